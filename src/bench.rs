@@ -0,0 +1,295 @@
+//! Micro benchmark of publish, subscribe and request/reply throughput against a running server,
+//! to compare client configurations empirically.
+//!
+//! ```no_run
+//! # async fn bench() -> Result<(), ratsio::error::RatsioError> {
+//! use ratsio::bench::{run, BenchOptions};
+//! let report = run(BenchOptions::builder().msgs(10_000usize).size(256usize).build().unwrap()).await?;
+//! println!("{}", report);
+//! # Ok(())
+//! # }
+//! ```
+use crate::error::RatsioError;
+use crate::nats_client::{AdaptiveFlush, FlushPolicy, NatsClient, NatsClientOptions};
+use crate::ops::{Publish, Subscribe};
+use futures::prelude::*;
+use std::convert::TryInto;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Bytes at the start of every published payload holding its send time, for the latencies.
+const STAMP_LEN: usize = 8;
+
+/// What to benchmark, see `run`
+#[derive(Debug, Clone, Builder, PartialEq)]
+#[builder(setter(into), default)]
+pub struct BenchOptions {
+    /// Options of the publishing and the subscribing client
+    pub nats_options: NatsClientOptions,
+    /// Subject to publish on, requests go to `<subject>.request`
+    pub subject: String,
+    /// Number of messages to publish
+    pub msgs: usize,
+    /// Payload size in bytes, at least 8 as the send time is stored in the payload
+    pub size: usize,
+    /// Number of requests, sent one after the other
+    pub requests: usize,
+    /// How long to wait for outstanding messages and replies before giving up on them
+    pub timeout: Duration,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            nats_options: NatsClientOptions::default(),
+            subject: String::from("ratsio.bench"),
+            msgs: 100_000,
+            size: 128,
+            requests: 1_000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl BenchOptions {
+    pub fn builder() -> BenchOptionsBuilder {
+        BenchOptionsBuilder::default()
+    }
+}
+
+/// Latency percentiles of a benchmark phase
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Latency {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latency {
+    /// Nearest rank percentiles of `samples`, `None` without samples.
+    pub fn from_samples(mut samples: Vec<Duration>) -> Option<Latency> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let rank = |percentile: usize| {
+            let index = (percentile * samples.len() + 99) / 100;
+            samples[index.max(1) - 1]
+        };
+        Some(Latency {
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+/// Outcome of one benchmark phase
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    /// Messages that made it through the phase
+    pub msgs: usize,
+    /// Payload bytes of those messages
+    pub bytes: usize,
+    pub elapsed: Duration,
+    pub latency: Option<Latency>,
+}
+
+impl BenchResult {
+    pub fn msgs_per_sec(&self) -> f64 {
+        self.msgs as f64 / secs(self.elapsed)
+    }
+
+    pub fn mb_per_sec(&self) -> f64 {
+        self.bytes as f64 / (1024.0 * 1024.0) / secs(self.elapsed)
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} msgs in {:?}, {:.0} msgs/sec, {:.2} MB/sec",
+            self.msgs,
+            self.elapsed,
+            self.msgs_per_sec(),
+            self.mb_per_sec()
+        )?;
+        if let Some(latency) = self.latency {
+            write!(
+                f,
+                ", latency p50 {:?} p90 {:?} p99 {:?} max {:?}",
+                latency.p50, latency.p90, latency.p99, latency.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Results of `run`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    /// Time to hand all messages to the server, without latencies
+    pub publish: BenchResult,
+    /// Messages received by the subscriber, latencies from publish to receipt
+    pub subscribe: BenchResult,
+    /// Round trips of sequential requests to an echo service
+    pub request: BenchResult,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "publish:   {}", self.publish)?;
+        writeln!(f, "subscribe: {}", self.subscribe)?;
+        write!(f, "request:   {}", self.request)
+    }
+}
+
+fn secs(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64().max(std::f64::EPSILON)
+}
+
+/// Connects a publishing and a subscribing client and measures publish, subscribe and
+/// request/reply throughput and latencies. Messages still missing after `timeout` are left
+/// out of the results instead of failing the benchmark.
+pub async fn run(options: BenchOptions) -> Result<BenchReport, RatsioError> {
+    let publisher = NatsClient::connect(options.nats_options.clone()).await?;
+    let subscriber = match NatsClient::connect(options.nats_options.clone()).await {
+        Ok(subscriber) => subscriber,
+        Err(err) => {
+            publisher.close();
+            return Err(err);
+        }
+    };
+    let report = measure(&options, &publisher, &subscriber).await;
+    publisher.close();
+    subscriber.close();
+    report
+}
+
+async fn measure(
+    options: &BenchOptions,
+    publisher: &NatsClient,
+    subscriber: &NatsClient,
+) -> Result<BenchReport, RatsioError> {
+    let size = options.size.max(STAMP_LEN);
+
+    let mut stream = subscriber
+        .subscribe(Subscribe::builder().subject(options.subject.clone()).build().unwrap())
+        .await?;
+    subscriber.flush().await?;
+
+    let (msgs, timeout) = (options.msgs, options.timeout);
+    let origin = Instant::now();
+    let receiving = tokio::spawn(async move {
+        let mut latencies = Vec::with_capacity(msgs);
+        let mut last = Duration::from_secs(0);
+        while latencies.len() < msgs {
+            match tokio::time::timeout(timeout, stream.next()).await {
+                Ok(Some(msg)) if msg.payload.len() >= STAMP_LEN => {
+                    last = origin.elapsed();
+                    let stamp = u64::from_le_bytes(msg.payload[..STAMP_LEN].try_into().unwrap());
+                    // Stamps from the future were not published by this run.
+                    if let Some(latency) = last.checked_sub(Duration::from_nanos(stamp)) {
+                        latencies.push(latency);
+                    }
+                }
+                Ok(Some(_)) => {}
+                _ => break,
+            }
+        }
+        (latencies, last)
+    });
+
+    let mut payload = vec![0u8; size];
+    for _ in 0..options.msgs {
+        let stamp = origin.elapsed().as_nanos() as u64;
+        payload[..STAMP_LEN].copy_from_slice(&stamp.to_le_bytes());
+        publisher
+            .publish(Publish::builder().subject(options.subject.clone()).payload(payload.clone()).build().unwrap())
+            .await?;
+    }
+    publisher.flush().await?;
+    let publish = BenchResult {
+        msgs: options.msgs,
+        bytes: options.msgs * size,
+        elapsed: origin.elapsed(),
+        latency: None,
+    };
+
+    let (latencies, elapsed) = receiving.await.map_err(|err| RatsioError::GenericError(err.to_string()))?;
+    let subscribe = BenchResult {
+        msgs: latencies.len(),
+        bytes: latencies.len() * size,
+        elapsed,
+        latency: Latency::from_samples(latencies),
+    };
+
+    let request_subject = format!("{}.request", options.subject);
+    let service = subscriber
+        .serve(request_subject.clone(), None, |msg| future::ok(msg.payload))
+        .await?;
+    subscriber.flush().await?;
+    let payload = vec![0u8; size];
+    let mut latencies = Vec::with_capacity(options.requests);
+    let started = Instant::now();
+    for _ in 0..options.requests {
+        let sent = Instant::now();
+        match publisher.request_timeout(request_subject.clone(), &payload, options.timeout).await {
+            Ok(_) => latencies.push(sent.elapsed()),
+            Err(RatsioError::RequestTimeout) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    let request = BenchResult {
+        msgs: latencies.len(),
+        bytes: latencies.len() * size,
+        elapsed: started.elapsed(),
+        latency: Latency::from_samples(latencies),
+    };
+    subscriber.unsubscribe_many(&[service]);
+
+    Ok(BenchReport { publish, subscribe, request })
+}
+
+/// Runs the benchmark once per policy of `policies`, or per policy of `flush_policies` if empty.
+pub async fn compare_flush_policies(
+    options: BenchOptions,
+    policies: &[FlushPolicy],
+) -> Result<Vec<(FlushPolicy, BenchReport)>, RatsioError> {
+    let policies = if policies.is_empty() { flush_policies() } else { policies.to_vec() };
+    let mut reports = Vec::with_capacity(policies.len());
+    for policy in policies {
+        let mut options = options.clone();
+        options.nats_options.flush_policy = policy;
+        reports.push((policy, run(options).await?));
+    }
+    Ok(reports)
+}
+
+/// The default policy, two fixed policies and the default adaptive policy.
+pub fn flush_policies() -> Vec<FlushPolicy> {
+    vec![
+        FlushPolicy::Immediate,
+        FlushPolicy::Fixed { max_batch: 64, max_delay: Duration::from_micros(500) },
+        FlushPolicy::Fixed { max_batch: 256, max_delay: Duration::from_millis(2) },
+        FlushPolicy::Adaptive(AdaptiveFlush::default()),
+    ]
+}
+
+#[test]
+fn latency_percentiles() {
+    let samples = (1..=100).rev().map(Duration::from_millis).collect();
+    let latency = Latency::from_samples(samples).unwrap();
+    assert_eq!(latency.p50, Duration::from_millis(50));
+    assert_eq!(latency.p90, Duration::from_millis(90));
+    assert_eq!(latency.p99, Duration::from_millis(99));
+    assert_eq!(latency.max, Duration::from_millis(100));
+
+    let latency = Latency::from_samples(vec![Duration::from_millis(7)]).unwrap();
+    assert_eq!(latency.p50, Duration::from_millis(7));
+    assert_eq!(latency.max, Duration::from_millis(7));
+    assert_eq!(Latency::from_samples(Vec::new()), None);
+}
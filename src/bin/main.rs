@@ -5,9 +5,7 @@ use chrono::Local;
 use env_logger::Builder;
 use futures::{
     prelude::*,
-};
-use futures::{
-    sync::{mpsc, oneshot},
+    channel::mpsc,
 };
 use log::LevelFilter;
 use ratsio::nats_client::*;
@@ -39,64 +37,32 @@ fn main() {
         .cluster_id("test-cluster")
         .client_id("main-1").build()
         .unwrap();
-    let (result_tx, result_rx) = mpsc::unbounded();
+    let (result_tx, mut result_rx) = mpsc::unbounded();
 
-    let (stan_client_tx, stan_client_rx) = oneshot::channel();
     let subject: String = "test.subject".into();
-    let subject1 = subject.clone();
-    let subject2 = subject.clone();
-    let program = StanClient::from_options(stan_options)
-        .and_then(move |stan_client| {
-            let sub = StanSubscribe::builder()
-                .subject(subject1.clone())
-                .start_position(StartPosition::First)
-                .build().unwrap();
-            stan_client
-                .subscribe(sub, SyncHandler(Box::new(move |stan_msg: StanMessage| {
-                    info!(target: "ratsio", " ------------------- GOT :::: {:?}", stan_msg);
-                    tokio::spawn(result_tx.clone().send(stan_msg).into_future()
-                        .map(|_| ()).map_err(|_| ()));
-                    Ok(())
-                })))
-                .and_then(move |_| Ok(stan_client))
-        })
-        .and_then(move |stan_client| {
-            let stan_msg = StanMessage::new(subject2.clone(), Vec::from(&b"hello"[..]));
-            stan_client.send(stan_msg).map(|_| stan_client)
-        })
-        .map_err(|_| ())
-        .and_then(|stan_client| {
-            stan_client_tx.send(stan_client)
-                .into_future()
-                .map(|_| ())
-                .map_err(|_| ())
-        }).map_err(|_| ());
-
-    runtime.spawn(program);
-
-    let stan_client = stan_client_rx.wait().expect(" No STAN Client");
-
-    match result_rx.wait().next().expect("Cannot wait for a result") {
-        Ok(stan_msg) => {
-            info!(target: "ratsio", " -------------------:::: {:?}", stan_msg);
-            assert_eq!(stan_msg.subject, subject);
-            assert_eq!(stan_msg.payload, Vec::from(&b"hello"[..]));
-        }
-        Err(_) => {
-            assert!(false);
-        }
-    };
+    runtime.block_on(async {
+        let stan_client = StanClient::from_options(stan_options).await.expect(" No STAN Client");
+        let sub = StanSubscribe::builder()
+            .subject(subject.clone())
+            .start_position(StartPosition::First)
+            .build().unwrap();
+        stan_client
+            .subscribe(sub, SyncHandler(Box::new(move |stan_msg: StanMessage| {
+                info!(target: "ratsio", " ------------------- GOT :::: {:?}", stan_msg);
+                let _ = result_tx.unbounded_send(stan_msg);
+                Ok(())
+            })))
+            .await
+            .expect(" Could not subscribe");
 
+        let stan_msg = StanMessage::new(subject.clone(), Vec::from(&b"hello"[..]));
+        stan_client.send(stan_msg).await.expect(" Could not send");
 
-    let (close_tx, close_rx) = oneshot::channel();
-    runtime.spawn(stan_client.close()
-        .and_then(|_| {
-            close_tx
-                .send(true).unwrap();
-            Ok(())
-        }).map_err(|_| ())
-    );
+        let stan_msg = result_rx.next().await.expect("Cannot wait for a result");
+        info!(target: "ratsio", " -------------------:::: {:?}", stan_msg);
+        assert_eq!(stan_msg.subject, subject);
+        assert_eq!(stan_msg.payload, Vec::from(&b"hello"[..]));
 
-    let _ = close_rx.wait().expect(" Could not close STAN Client");
-    let _ = runtime.shutdown_now().wait();
+        stan_client.close().await.expect(" Could not close STAN Client");
+    });
 }
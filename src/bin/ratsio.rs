@@ -0,0 +1,221 @@
+//! `ratsio` command line tool, built with the `cli` feature:
+//!
+//! ```text
+//! ratsio [OPTIONS] pub <subject> <payload> [--count <n>]
+//! ratsio [OPTIONS] sub <subject> [--queue <group>] [--count <n>]
+//! ratsio [OPTIONS] request <subject> <payload> [--timeout <ms>]
+//! ratsio [OPTIONS] bench <subject> [--msgs <n>] [--size <bytes>] [--requests <n>] [--flush-policies <bool>]
+//! ```
+//!
+//! Options start from the `NATS_*` environment variables, see `NatsClientOptions::from_env`, or
+//! from `--config <file>`, and are then overridden by `--server`, `--user`, `--password`,
+//! `--token` and `--creds`.
+use futures::prelude::*;
+use ratsio::bench::BenchOptions;
+use ratsio::error::RatsioError;
+use ratsio::nats_client::*;
+use ratsio::ops::{Publish, Subscribe};
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+const USAGE: &str = "Usage: ratsio [--server <urls>] [--config <file>] [--user <user>] [--password <password>]
+              [--token <token>] [--creds <file>] <command>
+
+Commands:
+  pub <subject> <payload> [--count <n>]       Publish a message, <n> times
+  sub <subject> [--queue <group>] [--count <n>]  Print received messages, exit after <n>
+  request <subject> <payload> [--timeout <ms>]   Send a request and print the reply
+  bench <subject> [--msgs <n>] [--size <bytes>] [--requests <n>] [--flush-policies <bool>]
+                                                 Measure publish, subscribe and request throughput,
+                                                 once per flush policy with --flush-policies true";
+
+/// Command line arguments, flags may appear anywhere after the program name.
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<(String, String)>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut flags = Vec::new();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(String::new());
+            }
+            if arg.starts_with("--") {
+                let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+                flags.push((arg[2..].to_string(), value));
+            } else {
+                positional.push(arg);
+            }
+        }
+        Ok(Args { positional, flags })
+    }
+
+    fn flag(&self, name: &str) -> Option<&str> {
+        self.flags.iter().rev().find(|(flag, _)| flag == name).map(|(_, value)| value.as_str())
+    }
+
+    fn number(&self, name: &str, default: u64) -> Result<u64, String> {
+        match self.flag(name) {
+            Some(value) => value.parse().map_err(|_| format!("--{} expects a number, got {}", name, value)),
+            None => Ok(default),
+        }
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&str, String> {
+        self.positional.get(index).map(String::as_str).ok_or_else(|| format!("missing <{}>", name))
+    }
+
+    fn options(&self) -> Result<NatsClientOptions, RatsioError> {
+        let mut opts = match self.flag("config") {
+            Some(path) => NatsClientOptions::from_file(path)?,
+            None => NatsClientOptions::from_env()?,
+        };
+        if let Some(servers) = self.flag("server") {
+            opts.cluster_uris = servers.split(',').map(str::trim).collect::<Vec<_>>().into();
+        }
+        if let Some(user) = self.flag("user") {
+            opts.username = user.to_string();
+        }
+        if let Some(password) = self.flag("password") {
+            opts.password = password.to_string();
+        }
+        if let Some(token) = self.flag("token") {
+            opts.auth_token = token.to_string();
+        }
+        if let Some(creds) = self.flag("creds") {
+            #[cfg(feature = "jwt")]
+            {
+                opts.user_jwt = Some(UserJWT::from_creds_file(creds)?);
+            }
+            #[cfg(not(feature = "jwt"))]
+            {
+                let _ = creds;
+                return Err(RatsioError::FeatureNotEnabled("jwt".into()));
+            }
+        }
+        // A diagnostic tool should report a bad server or credentials instead of retrying forever.
+        opts.ensure_connect = false;
+        Ok(opts)
+    }
+}
+
+fn print_message(subject: &str, payload: &[u8]) {
+    println!("[{}] {}", subject, String::from_utf8_lossy(payload));
+}
+
+async fn publish(client: &NatsClient, args: &Args) -> Result<(), String> {
+    let subject = args.positional(1, "subject")?;
+    let payload = args.positional(2, "payload")?;
+    let count = args.number("count", 1)?;
+    for _ in 0..count {
+        client
+            .publish(Publish::builder()
+                .subject(subject.into())
+                .payload(Vec::from(payload.as_bytes()))
+                .build()
+                .unwrap())
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    client.flush().await.map_err(|err| err.to_string())?;
+    println!("Published {} message(s) to {}", count, subject);
+    Ok(())
+}
+
+async fn subscribe(client: &NatsClient, args: &Args) -> Result<(), String> {
+    let subject = args.positional(1, "subject")?;
+    let count = args.number("count", 0)?;
+    let sub = Subscribe::builder()
+        .subject(subject.into())
+        .queue_group(args.flag("queue").map(String::from))
+        .build()
+        .unwrap();
+    let mut stream = client.subscribe(sub).await.map_err(|err| err.to_string())?;
+    println!("Listening on {}", subject);
+    let mut received = 0;
+    while let Some(msg) = stream.next().await {
+        print_message(&msg.subject, &msg.payload);
+        received += 1;
+        if received == count {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn request(client: &NatsClient, args: &Args) -> Result<(), String> {
+    let subject = args.positional(1, "subject")?;
+    let payload = args.positional(2, "payload")?;
+    let timeout = Duration::from_millis(args.number("timeout", 2000)?);
+    let started = Instant::now();
+    let reply = client
+        .request_timeout(subject.into(), payload.as_bytes(), timeout)
+        .await
+        .map_err(|err| err.to_string())?;
+    print_message(&reply.subject, &reply.payload);
+    println!("Received reply in {:?}", started.elapsed());
+    Ok(())
+}
+
+async fn bench(options: NatsClientOptions, args: &Args) -> Result<(), String> {
+    let options = BenchOptions::builder()
+        .nats_options(options)
+        .subject(args.positional(1, "subject")?)
+        .msgs(args.number("msgs", 100_000)? as usize)
+        .size(args.number("size", 128)? as usize)
+        .requests(args.number("requests", 1_000)? as usize)
+        .build()?;
+    if args.flag("flush-policies") == Some("true") {
+        let reports = ratsio::bench::compare_flush_policies(options, &[]).await.map_err(|err| err.to_string())?;
+        for (policy, report) in reports {
+            println!("{:?}\n{}\n", policy, report);
+        }
+        return Ok(());
+    }
+    let report = ratsio::bench::run(options).await.map_err(|err| err.to_string())?;
+    println!("{}", report);
+    Ok(())
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let options = args.options().map_err(|err| err.to_string())?;
+    let command = args.positional(0, "command")?;
+    if command == "bench" {
+        return bench(options, &args).await;
+    }
+    let client: Arc<NatsClient> = NatsClient::connect(options).await.map_err(|err| err.to_string())?;
+    match command {
+        "pub" => publish(&client, &args).await,
+        "sub" => subscribe(&client, &args).await,
+        "request" => request(&client, &args).await,
+        command => Err(format!("unknown command {}", command)),
+    }
+}
+
+fn main() {
+    let _ = env_logger::try_init();
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(ref args) if args.positional.is_empty() => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+        Ok(args) => args,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("error: {}", err);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let mut runtime = Runtime::new().unwrap();
+    if let Err(err) = runtime.block_on(run(args)) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,213 @@
+//! Source of the time used by the client timers, so ping, request and reconnect timing can be
+//! tested deterministically. Clients use `TokioClock` unless `NatsClientOptions::clock` is set.
+//!
+//! ```
+//! use futures::executor::block_on;
+//! use ratsio::clock::{MockClock, SharedClock};
+//! use std::time::Duration;
+//!
+//! let mock = MockClock::new();
+//! let clock = SharedClock::new(mock.clone());
+//! let reply = clock.timeout(Duration::from_secs(5), futures::future::pending::<()>());
+//! mock.advance(Duration::from_secs(5));
+//! assert_eq!(block_on(reply), None);
+//! ```
+use futures::{
+    channel::oneshot,
+    future::{self, Either},
+    pin_mut, Future,
+};
+use parking_lot::Mutex;
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+lazy_static! {
+    static ref WALL_CLOCK_ANCHOR: (SystemTime, Instant) = (SystemTime::now(), Instant::now());
+}
+
+/// The wall clock time, advanced from the first call on with the monotonic clock so that clock
+/// adjustments of the host don't make it jump. Used to stamp `Ratsio-Sent-At` headers.
+pub fn monotonic_wall_time() -> SystemTime {
+    let (wall, monotonic) = *WALL_CLOCK_ANCHOR;
+    wall + monotonic.elapsed()
+}
+
+/// A future resolving once a `Clock::delay` passed.
+pub type Delay = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn delay(&self, duration: Duration) -> Delay;
+}
+
+/// The time of the tokio runtime the client runs on.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay(&self, duration: Duration) -> Delay {
+        Box::pin(tokio::time::delay_for(duration))
+    }
+}
+
+/// A shareable `Clock`, see `NatsClientOptions::clock`.
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new<C: Clock + 'static>(clock: C) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    pub fn delay(&self, duration: Duration) -> Delay {
+        self.0.delay(duration)
+    }
+
+    /// Time passed since `earlier`, zero if `earlier` is later than now.
+    pub fn since(&self, earlier: Instant) -> Duration {
+        let now = self.now();
+        if now > earlier { now - earlier } else { Duration::from_millis(0) }
+    }
+
+    /// The output of `fut`, or `None` if it did not resolve within `duration`.
+    pub fn timeout<F: Future>(&self, duration: Duration, fut: F) -> impl Future<Output = Option<F::Output>> {
+        let delay = self.delay(duration);
+        async move {
+            pin_mut!(fut);
+            match future::select(fut, delay).await {
+                Either::Left((output, _)) => Some(output),
+                Either::Right(_) => None,
+            }
+        }
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock::new(TokioClock)
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedClock(clock)")
+    }
+}
+
+impl PartialEq for SharedClock {
+    fn eq(&self, other: &SharedClock) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug)]
+struct MockClockState {
+    now: Instant,
+    timers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+/// A clock that only moves when `advance`d, delays resolve once it was advanced past them.
+/// Clones share the time.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    state: Arc<Mutex<MockClockState>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        MockClock {
+            state: Arc::new(Mutex::new(MockClockState {
+                now: Instant::now(),
+                timers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Moves the time forward by `duration`, resolving the delays that passed in the order of
+    /// their deadlines.
+    pub fn advance(&self, duration: Duration) {
+        let mut due = {
+            let mut state = self.state.lock();
+            state.now += duration;
+            let now = state.now;
+            let (due, pending): (Vec<_>, Vec<_>) = state.timers.drain(..).partition(|(deadline, _)| *deadline <= now);
+            state.timers = pending;
+            due
+        };
+        due.sort_by_key(|(deadline, _)| *deadline);
+        for (_, tx) in due {
+            let _ = tx.send(());
+        }
+    }
+
+    /// Number of delays waiting for the time to be advanced, not counting dropped ones.
+    pub fn pending_delays(&self) -> usize {
+        let mut state = self.state.lock();
+        state.timers.retain(|(_, tx)| !tx.is_canceled());
+        state.timers.len()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().now
+    }
+
+    fn delay(&self, duration: Duration) -> Delay {
+        if duration == Duration::from_millis(0) {
+            return Box::pin(future::ready(()));
+        }
+        let (tx, rx) = oneshot::channel();
+        let mut state = self.state.lock();
+        let deadline = state.now + duration;
+        state.timers.push((deadline, tx));
+        Box::pin(async move {
+            // A dropped clock never advances again.
+            if rx.await.is_err() {
+                future::pending::<()>().await
+            }
+        })
+    }
+}
+
+#[test]
+fn mock_delays_resolve_when_advanced() {
+    use futures::{executor::block_on, FutureExt};
+
+    let mock = MockClock::new();
+    let clock = SharedClock::new(mock.clone());
+    let started = clock.now();
+    let mut short = clock.delay(Duration::from_secs(1));
+    let mut long = clock.delay(Duration::from_secs(10));
+    assert_eq!(mock.pending_delays(), 2);
+    assert!((&mut short).now_or_never().is_none());
+
+    mock.advance(Duration::from_secs(2));
+    assert!((&mut short).now_or_never().is_some());
+    assert!((&mut long).now_or_never().is_none());
+    assert_eq!(clock.since(started), Duration::from_secs(2));
+
+    let answered = clock.timeout(Duration::from_secs(5), future::ready(7));
+    assert_eq!(block_on(answered), Some(7));
+    mock.advance(Duration::from_secs(8));
+    assert!(long.now_or_never().is_some());
+    assert_eq!(mock.pending_delays(), 0);
+}
@@ -27,24 +27,19 @@ impl Decoder for OpCodec {
                 buf.split_to(len - remaining.len());
                 Ok(Some(item))
             },
-            Err(NomErr::Error(err)) => {
-                //scan for \r\n and recover there.
-                let txt = String::from(&(*String::from_utf8_lossy(&buf[..])));
-                error!(target: "ratsio", " Error parsing => {:?}\n{}", err, txt);
-                if let Some(offset) = buf[..].windows(2).position(|w| w == b"\r\n") {
-                    buf.split_to(offset);
-                    self.decode(buf)
-                }else{
-                    buf.split_to(len);
-                    Ok(None)
+            Err(NomErr::Error(err)) | Err(NomErr::Failure(err)) => {
+                let line = first_line(&buf[..]);
+                if carries_payload(&buf[..]) {
+                    // The size of the payload following the line is unknown, nothing after it
+                    // can be trusted.
+                    error!(target: "ratsio", " Malformed op => {:?}\n{}", err, line);
+                    buf.clear();
+                    return Err(RatsioError::ProtocolError(line));
                 }
-            },
-            Err(NomErr::Failure(err)) => {
-                //scan for \r\n and recover there.
-                let txt = String::from(&(*String::from_utf8_lossy(&buf[..])));
-                error!(target: "ratsio", " Failure parsing => {:?}\n{}", err, txt);
+                //scan for \r\n and recover after it.
+                error!(target: "ratsio", " Error parsing => {:?}\n{}", err, line);
                 if let Some(offset) = buf[..].windows(2).position(|w| w == b"\r\n") {
-                    buf.split_to(offset);
+                    buf.split_to(offset + 2);
                     self.decode(buf)
                 }else{
                     buf.split_to(len);
@@ -55,6 +50,21 @@ impl Decoder for OpCodec {
     }
 }
 
+/// Names of the ops whose arguments give the size of the payload following them.
+const PAYLOAD_OPS: [&[u8]; 4] = [b"MSG", b"HMSG", b"PUB", b"HPUB"];
+
+fn carries_payload(buf: &[u8]) -> bool {
+    let start = buf.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(buf.len());
+    let name = &buf[start..];
+    let end = name.iter().position(|b| b.is_ascii_whitespace()).unwrap_or(name.len());
+    PAYLOAD_OPS.iter().any(|op| name[..end].eq_ignore_ascii_case(op))
+}
+
+fn first_line(buf: &[u8]) -> String {
+    let end = buf.windows(2).position(|w| w == b"\r\n").unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).trim().to_string()
+}
+
 impl Encoder for OpCodec {
     type Item = Op;
     type Error = RatsioError;
@@ -71,3 +81,67 @@ impl Encoder for OpCodec {
         Ok(())
     }
 }
+
+#[test]
+fn encoder_rejects_protocol_injection() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const ALPHABET: &[char] = &['a', 'Z', '9', '.', '*', '>', '_', '-', ' ', '\t', '\r', '\n', '\u{0}', 'é'];
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let random_token = |rng: &mut StdRng| -> String {
+        let len = rng.gen_range(0, 12);
+        (0..len).map(|_| ALPHABET[rng.gen_range(0, ALPHABET.len())]).collect()
+    };
+    let mut codec = OpCodec::default();
+    for _ in 0..2000 {
+        let subject = random_token(&mut rng);
+        let reply_to = if rng.gen() { Some(random_token(&mut rng)) } else { None };
+        let payload: Vec<u8> = (0..rng.gen_range(0, 16)).map(|_| rng.gen()).collect();
+        let op = Op::PUB(Publish { subject: subject.clone(), reply_to: reply_to.clone(), payload, headers: None });
+        let valid = crate::subject::validate(&subject).is_ok()
+            && reply_to.as_ref().map_or(true, |reply_to| crate::subject::validate(reply_to).is_ok());
+
+        let mut buf = BytesMut::new();
+        match codec.encode(op.clone(), &mut buf) {
+            Ok(()) => {
+                assert!(valid, "encoded {:?}", op);
+                // A valid op reads back as exactly one op, the same.
+                assert_eq!(codec.decode(&mut buf).unwrap(), Some(op));
+                assert!(buf.is_empty());
+            }
+            Err(RatsioError::InvalidSubject(_)) => {
+                assert!(!valid, "rejected {:?}", op);
+                assert!(buf.is_empty());
+            }
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+}
+
+#[test]
+fn decoder_recovers_from_malformed_ops() {
+    let mut codec = OpCodec::default();
+
+    // Unknown lines are skipped up to the next op.
+    let mut buf = BytesMut::from(&b"BOGUS 1 2\r\nNOT AN OP\r\nPING\r\n"[..]);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(Op::PING));
+    assert!(buf.is_empty());
+
+    // Ops with a payload of unknown size fail the stream, which reconnects.
+    let corrupted: Vec<&[u8]> = vec![
+        b"MSG FOO.BAR 9 eleven\r\nHello World\r\nPING\r\n",
+        b"MSG FOO.BAR 11\r\nHello World\r\nPING\r\n",
+        b"msg FOO.BAR 9 INBOX.34 EXTRA 11\r\nHello World\r\n",
+        b"MSG \xff 9 11\r\nHello World\r\n",
+        b"HMSG FOO.BAR 9 45 34\r\nNATS/1.0\r\n\r\nHello World\r\n",
+        b"  HPUB FOO.BAR\r\nHello World\r\n",
+    ];
+    for input in corrupted {
+        let mut buf = BytesMut::from(input);
+        match codec.decode(&mut buf) {
+            Err(RatsioError::ProtocolError(line)) => assert!(!line.contains("Hello")),
+            other => panic!("decoded {:?} => {:?}", String::from_utf8_lossy(input), other),
+        }
+        assert!(buf.is_empty());
+    }
+}
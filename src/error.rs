@@ -1,4 +1,4 @@
-use std::io;
+use std::{fmt, io};
 
 macro_rules! from_error {
     ($type:ty, $target:ident, $targetvar:expr) => {
@@ -29,6 +29,7 @@ pub enum RatsioError {
     #[fail(display = "UTF8Error: {}", _0)]
     UTF8Error(::std::string::FromUtf8Error),
     /// Error on TLS handling
+    #[cfg(feature = "tls")]
     #[fail(display = "TlsError: {}", _0)]
     TlsError(::native_tls::Error),
     // Occurs when the host is not provided, removing the ability for TLS to function correctly for server identify verification
@@ -65,7 +66,142 @@ pub enum RatsioError {
     SubscriptionReachedMaxMsgs(u32),
 
     #[fail(display = "Stream Closed for {}", _0)]
-    StreamClosed(String)
+    StreamClosed(String),
+    /// No reply was received before the request timed out
+    #[fail(display = "RequestTimeout: no reply received in time")]
+    RequestTimeout,
+    /// A registered payload validator rejected a message on the given subject
+    #[fail(display = "PayloadValidationError: payload for {} rejected: {}", _0, _1)]
+    PayloadValidationError(String, String),
+    /// The op was not sent because there is no connection to a server, see
+    /// `NatsClientOptions::fail_when_disconnected`
+    #[fail(display = "NotConnected: no connection to a server, the op was dropped")]
+    NotConnected,
+    /// Options could not be loaded from the environment or a config file
+    #[fail(display = "ConfigError: {}", _0)]
+    ConfigError(String),
+    /// The server is too old for the requested feature, see `ServerInfo`
+    #[fail(display = "UnsupportedByServer: the server does not support {}", _0)]
+    UnsupportedByServer(String),
+    /// The server answered a request with a 503 status, nobody is subscribed to the subject
+    #[fail(display = "NoResponders: no subscribers for the request subject")]
+    NoResponders,
+    /// The options ask for something the crate was built without, names the missing cargo feature
+    #[fail(display = "FeatureNotEnabled: the `{}` feature is required", _0)]
+    FeatureNotEnabled(String),
+    /// No connection could be made within `NatsClientOptions::connect_timeout`
+    #[fail(display = "ConnectTimeout: no connection made in time")]
+    ConnectTimeout,
+    /// Writing to the connection did not complete within `NatsClientOptions::write_timeout`
+    #[fail(display = "WriteTimeout: the server is not reading from the connection")]
+    WriteTimeout,
+    /// A subscription with the sid is already registered, see `NatsClient::resubscribe` to replace it
+    #[fail(display = "DuplicateSid: sid {} is already subscribed", _0)]
+    DuplicateSid(String),
+    /// No subscription with the sid is registered
+    #[fail(display = "UnknownSid: no subscription with sid {}", _0)]
+    UnknownSid(String),
+    /// The client is closed and will not connect again
+    #[fail(display = "ClientClosed: the client is closed")]
+    ClientClosed,
+    /// Requests to the subject failed too often and are short-circuited for a while, see
+    /// `NatsClientOptions::circuit_breaker`
+    #[fail(display = "CircuitOpen: requests to {} are failing, not sending", _0)]
+    CircuitOpen(String),
+    /// A subject, reply subject or queue group is empty or contains whitespace or control
+    /// characters, which would corrupt the protocol stream
+    #[fail(display = "InvalidSubject: {:?} is not a valid subject", _0)]
+    InvalidSubject(String),
+    /// The server sent an op that could not be parsed, with the offending line. The connection
+    /// is re-established, since the rest of the stream can't be trusted.
+    #[fail(display = "ProtocolError: malformed op {:?}", _0)]
+    ProtocolError(String),
+    /// A JWT could not be decoded, verified or signed, see `AuthCallout`
+    #[fail(display = "JwtError: {}", _0)]
+    JwtError(String),
+    /// The service answered the request with an error, or a `serve` handler refuses to answer
+    #[fail(display = "ServiceError: {}", _0)]
+    ServiceError(ServiceError),
+    /// Frames of a `Channel` or replies of a `ReplyStream` went missing, it is broken
+    #[fail(display = "FramesLost: expected frame {}, received {}", expected, received)]
+    FramesLost { expected: u64, received: u64 },
+    /// The server refused a subscription, see `NatsClient::subscribe_confirmed`
+    #[fail(display = "SubscriptionRejected: {}", _0)]
+    SubscriptionRejected(SubscriptionError),
+    /// No route of a `MultiClusterClient` matches the subject and it has no default cluster
+    #[fail(display = "NoClusterForSubject: no cluster is routed {}", _0)]
+    NoClusterForSubject(String),
+    /// The client has `NatsClientOptions::max_subscriptions` subscriptions already
+    #[fail(display = "TooManySubscriptions: the client is limited to {} subscriptions", _0)]
+    TooManySubscriptions(usize),
+    /// The client published to `NatsClientOptions::max_distinct_publish_subjects` other subjects already
+    #[fail(display = "TooManyPublishSubjects: the client is limited to publishing to {} subjects, {} is not one of them", _0, _1)]
+    TooManyPublishSubjects(usize, String),
+}
+
+/// An error reply following the `Nats-Service-Error` / `Nats-Service-Error-Code` header
+/// convention of NATS micro services, see `NatsClient::serve`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceError {
+    /// Status code, HTTP like: 400 for bad requests, 500 for failures of the service
+    pub code: u16,
+    pub message: String,
+}
+
+impl ServiceError {
+    pub fn new(code: u16, message: &str) -> Self {
+        ServiceError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+impl From<ServiceError> for RatsioError {
+    fn from(err: ServiceError) -> Self {
+        RatsioError::ServiceError(err)
+    }
+}
+
+/// Connection level errors delivered on the streams of `NatsClient::subscribe_with_errors`
+#[derive(Clone, Debug, Fail, PartialEq)]
+pub enum SubscriptionError {
+    /// The connection to the server was lost, messages may be missed until it is re-established
+    #[fail(display = "Disconnected: the connection to the server was lost")]
+    Disconnected,
+    /// The server reported the client as a slow consumer and dropped messages for it
+    #[fail(display = "SlowConsumer: the server dropped messages")]
+    SlowConsumer,
+    /// The server refused the subscription to the given subject
+    #[fail(display = "PermissionViolation: not allowed to subscribe to {}", _0)]
+    PermissionViolation(String),
+}
+
+impl SubscriptionError {
+    /// Maps a `-ERR` message of the server to the subscription error it represents, if any.
+    pub(crate) fn from_server_error(msg: &str) -> Option<SubscriptionError> {
+        const SUB_VIOLATION: &str = "permissions violation for subscription to ";
+        let lower = msg.to_lowercase();
+        if lower.starts_with(SUB_VIOLATION) {
+            let target = msg[SUB_VIOLATION.len()..].trim();
+            let subject = if target.starts_with('"') {
+                target[1..].split('"').next()
+            } else {
+                target.split_whitespace().next()
+            };
+            subject.map(|subject| SubscriptionError::PermissionViolation(subject.to_string()))
+        } else if lower.contains("slow consumer") {
+            Some(SubscriptionError::SlowConsumer)
+        } else {
+            None
+        }
+    }
 }
 
 impl From<io::Error> for RatsioError {
@@ -92,6 +228,27 @@ impl From<RatsioError> for () {
 }
 
 from_error!(::std::string::FromUtf8Error, RatsioError, RatsioError::UTF8Error);
+#[cfg(feature = "tls")]
 from_error!(::native_tls::Error, RatsioError, RatsioError::TlsError);
 from_error!(String, RatsioError, RatsioError::GenericError);
 from_error!(::url::ParseError, RatsioError, RatsioError::UrlParseError);
+
+#[test]
+fn subscription_error_from_server_error() {
+    assert_eq!(
+        SubscriptionError::from_server_error(r#"Permissions Violation for Subscription to "foo.bar" using queue "q""#),
+        Some(SubscriptionError::PermissionViolation("foo.bar".into()))
+    );
+    assert_eq!(
+        SubscriptionError::from_server_error("Permissions Violation for Subscription to foo.>"),
+        Some(SubscriptionError::PermissionViolation("foo.>".into()))
+    );
+    assert_eq!(
+        SubscriptionError::from_server_error("Slow Consumer Detected"),
+        Some(SubscriptionError::SlowConsumer)
+    );
+    assert_eq!(
+        SubscriptionError::from_server_error(r#"Permissions Violation for Publish to "foo""#),
+        None
+    );
+}
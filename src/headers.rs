@@ -0,0 +1,255 @@
+//! NATS message headers, sent with `HPUB` and received with `HMSG` (NATS server 2.2+).
+//!
+//! The header block is a `NATS/1.0[ status[ description]]` line, followed by MIME style
+//! `Name: value` lines and terminated by an empty line.
+
+use crate::error::ServiceError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HEADER_LINE: &str = "NATS/1.0";
+
+/// Absolute deadline of a request, in milliseconds since the unix epoch.
+pub const NATS_DEADLINE: &str = "Nats-Deadline";
+
+/// Time a message was published, in milliseconds since the unix epoch. Stamped by ratsio
+/// publishers with `stamp_sent_at` enabled and used by the subscription `max_age`.
+pub const RATSIO_SENT_AT: &str = "Ratsio-Sent-At";
+
+/// Description of the error a service answered a request with.
+pub const NATS_SERVICE_ERROR: &str = "Nats-Service-Error";
+
+/// Code of the error a service answered a request with, see `ServiceError::code`.
+pub const NATS_SERVICE_ERROR_CODE: &str = "Nats-Service-Error-Code";
+
+/// Compression of the payload, `zstd` or `snappy`, see `nats_client::Compression`.
+pub const CONTENT_ENCODING: &str = "Content-Encoding";
+
+/// Public nkey of the publisher that signed the message, see `NatsClient::add_payload_signer`.
+pub const RATSIO_SIGNER: &str = "Ratsio-Signer";
+
+/// Base64url ed25519 signature of the subject, a NUL byte and the payload by `Ratsio-Signer`.
+pub const RATSIO_SIGNATURE: &str = "Ratsio-Signature";
+
+/// Why the signature of a received message could not be verified, set on messages delivered
+/// anyway, see `nats_client::OnUnverified::Flag`.
+pub const RATSIO_UNVERIFIED: &str = "Ratsio-Unverified";
+
+/// Control frames of a `Channel`: `open`, `accept` or `close`.
+pub const RATSIO_CHANNEL: &str = "Ratsio-Channel";
+
+/// Sequence of a `Channel` frame, starting at 1 in each direction.
+pub const RATSIO_CHANNEL_SEQ: &str = "Ratsio-Channel-Seq";
+
+/// Replies granted to the responder of a `NatsClient::stream_request`, sent with the request and
+/// again as the replies are consumed.
+pub const RATSIO_STREAM_CREDIT: &str = "Ratsio-Stream-Credit";
+
+/// Sequence of a streamed reply, starting at 1.
+pub const RATSIO_STREAM_SEQ: &str = "Ratsio-Stream-Seq";
+
+/// Marks the last reply of a stream, `end` from the responder or `cancel` from the caller.
+pub const RATSIO_STREAM_END: &str = "Ratsio-Stream-End";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Headers {
+    status: Option<u16>,
+    description: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers::default()
+    }
+
+    /// The inline status code, e.g. `503` for "no responders".
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// The inline status description, if any.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// First value of the header `name`, matched case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// All values of the header `name`, matched case-insensitively.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets the header `name`, replacing existing values.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Adds a value for the header `name`, keeping existing values.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.status.is_none()
+    }
+
+    /// Sets the `Nats-Deadline` header.
+    pub fn set_deadline(&mut self, deadline: SystemTime) {
+        self.insert_time(NATS_DEADLINE, deadline);
+    }
+
+    /// Parses the `Nats-Deadline` header.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.get_time(NATS_DEADLINE)
+    }
+
+    /// Sets the `Ratsio-Sent-At` header.
+    pub fn set_sent_at(&mut self, sent_at: SystemTime) {
+        self.insert_time(RATSIO_SENT_AT, sent_at);
+    }
+
+    /// Parses the `Ratsio-Sent-At` header.
+    pub fn sent_at(&self) -> Option<SystemTime> {
+        self.get_time(RATSIO_SENT_AT)
+    }
+
+    /// Sets the `Nats-Service-Error` and `Nats-Service-Error-Code` headers.
+    pub fn set_service_error(&mut self, err: &ServiceError) {
+        self.insert(NATS_SERVICE_ERROR, &err.message);
+        self.insert(NATS_SERVICE_ERROR_CODE, &err.code.to_string());
+    }
+
+    /// The error a service answered with, from the `Nats-Service-Error` headers. A reply with
+    /// only one of them is an error too, with code 500 or an empty message.
+    pub fn service_error(&self) -> Option<ServiceError> {
+        let message = self.get(NATS_SERVICE_ERROR);
+        let code = self.get(NATS_SERVICE_ERROR_CODE);
+        if message.is_none() && code.is_none() {
+            return None;
+        }
+        Some(ServiceError {
+            code: code.and_then(|code| code.trim().parse().ok()).unwrap_or(500),
+            message: message.unwrap_or_default().to_string(),
+        })
+    }
+
+    fn insert_time(&mut self, name: &str, time: SystemTime) {
+        let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        self.insert(name, &millis.to_string());
+    }
+
+    fn get_time(&self, name: &str) -> Option<SystemTime> {
+        self.get(name)
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    /// Serializes the header block, including the terminating empty line.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = String::from(HEADER_LINE);
+        if let Some(status) = self.status {
+            buf.push_str(&format!(" {}", status));
+            if let Some(ref description) = self.description {
+                buf.push_str(&format!(" {}", description));
+            }
+        }
+        buf.push_str("\r\n");
+        for (k, v) in &self.entries {
+            buf.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        buf.push_str("\r\n");
+        buf.into_bytes()
+    }
+
+    /// Parses a header block, lines that are not `Name: value` pairs are skipped.
+    pub fn from_bytes(buf: &[u8]) -> Headers {
+        let text = String::from_utf8_lossy(buf);
+        let mut lines = text.split("\r\n");
+        let mut headers = Headers::new();
+        if let Some(first) = lines.next() {
+            let mut parts = first.trim_start_matches(HEADER_LINE).trim().splitn(2, ' ');
+            headers.status = parts.next().and_then(|s| s.parse().ok());
+            headers.description = parts.next()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty());
+        }
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+            if let (Some(k), Some(v)) = (parts.next(), parts.next()) {
+                headers.append(k.trim(), v.trim());
+            }
+        }
+        headers
+    }
+}
+
+#[test]
+fn headers_round_trip() {
+    let mut headers = Headers::new();
+    headers.insert("Content-Type", "application/json");
+    headers.append("X-Tag", "a");
+    headers.append("X-Tag", "b");
+    let bytes = headers.to_bytes();
+    assert_eq!(&bytes[..], &b"NATS/1.0\r\nContent-Type: application/json\r\nX-Tag: a\r\nX-Tag: b\r\n\r\n"[..]);
+    let parsed = Headers::from_bytes(&bytes);
+    assert_eq!(parsed, headers);
+    assert_eq!(parsed.get("content-type"), Some("application/json"));
+    assert_eq!(parsed.get_all("x-tag").collect::<Vec<_>>(), vec!["a", "b"]);
+}
+
+#[test]
+fn headers_status_line() {
+    let parsed = Headers::from_bytes(b"NATS/1.0 503 No Responders\r\n\r\n");
+    assert_eq!(parsed.status(), Some(503));
+    assert_eq!(parsed.description(), Some("No Responders"));
+    assert!(parsed.get("anything").is_none());
+}
+
+#[test]
+fn headers_deadline() {
+    let mut headers = Headers::new();
+    let deadline = UNIX_EPOCH + Duration::from_millis(1_600_000_000_123);
+    headers.set_deadline(deadline);
+    assert_eq!(headers.get(NATS_DEADLINE), Some("1600000000123"));
+    assert_eq!(headers.deadline(), Some(deadline));
+}
+
+#[test]
+fn headers_sent_at() {
+    let mut headers = Headers::new();
+    assert_eq!(headers.sent_at(), None);
+    let sent_at = UNIX_EPOCH + Duration::from_millis(1_600_000_000_000);
+    headers.set_sent_at(sent_at);
+    assert_eq!(headers.get(RATSIO_SENT_AT), Some("1600000000000"));
+    assert_eq!(headers.sent_at(), Some(sent_at));
+}
+
+#[test]
+fn headers_service_error() {
+    let mut headers = Headers::new();
+    assert_eq!(headers.service_error(), None);
+    headers.set_service_error(&ServiceError::new(400, "unknown symbol"));
+    let parsed = Headers::from_bytes(&headers.to_bytes());
+    assert_eq!(parsed.get(NATS_SERVICE_ERROR_CODE), Some("400"));
+    assert_eq!(parsed.service_error(), Some(ServiceError::new(400, "unknown symbol")));
+
+    let mut message_only = Headers::new();
+    message_only.insert(NATS_SERVICE_ERROR, "failed");
+    assert_eq!(message_only.service_error(), Some(ServiceError::new(500, "failed")));
+}
@@ -0,0 +1,238 @@
+//! Decoding NATS operator, account and user JWTs into their claims, e.g. to warn before the
+//! credentials of a client expire. Only built with the `jwt` feature.
+//!
+//! ```no_run
+//! # fn check(user_jwt: &ratsio::nats_client::UserJWT) -> Result<(), ratsio::error::RatsioError> {
+//! let claims = user_jwt.claims()?;
+//! if let Some(left) = claims.expires_in() {
+//!     println!("{} expires in {:?}", claims.subject, left);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::error::RatsioError;
+use data_encoding::BASE64URL_NOPAD;
+use nkeys::KeyPair;
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const JWT_HEADER: &str = r#"{"typ":"JWT","alg":"ed25519-nkey"}"#;
+
+pub(crate) fn jwt_error<E: ToString>(err: E) -> RatsioError {
+    RatsioError::JwtError(err.to_string())
+}
+
+/// Signs `claims` with `key_pair` as a NATS JWT.
+pub(crate) fn encode_jwt(claims: &Value, key_pair: &KeyPair) -> Result<String, RatsioError> {
+    let body = serde_json::to_vec(claims).map_err(jwt_error)?;
+    let signed = format!("{}.{}", BASE64URL_NOPAD.encode(JWT_HEADER.as_bytes()), BASE64URL_NOPAD.encode(&body));
+    let sig = key_pair.sign(signed.as_bytes()).map_err(jwt_error)?;
+    Ok(format!("{}.{}", signed, BASE64URL_NOPAD.encode(&sig)))
+}
+
+/// The claims of a NATS JWT, once its signature was verified against the key of its issuer if
+/// `verify` is set.
+pub(crate) fn decode_jwt(jwt: &str, verify: bool) -> Result<Value, RatsioError> {
+    let parts: Vec<&str> = jwt.trim().split('.').collect();
+    if parts.len() != 3 {
+        return Err(jwt_error("not a JWT"));
+    }
+    let decode = |part: &str| -> Result<Value, RatsioError> {
+        let json = BASE64URL_NOPAD.decode(part.as_bytes()).map_err(jwt_error)?;
+        serde_json::from_slice(&json).map_err(jwt_error)
+    };
+    let header = decode(parts[0])?;
+    if !header["alg"].as_str().map_or(false, |alg| alg.starts_with("ed25519")) {
+        return Err(jwt_error(format!("unsupported algorithm {}", header["alg"])));
+    }
+    let claims = decode(parts[1])?;
+    let issuer = claims["iss"].as_str().ok_or_else(|| jwt_error("no issuer"))?;
+    if verify {
+        let sig = BASE64URL_NOPAD.decode(parts[2].as_bytes()).map_err(jwt_error)?;
+        KeyPair::from_public_key(issuer)
+            .and_then(|key| key.verify(format!("{}.{}", parts[0], parts[1]).as_bytes(), &sig))
+            .map_err(|_| jwt_error(format!("signature does not match the issuer {}", issuer)))?;
+    }
+    Ok(claims)
+}
+
+/// Subjects a user may publish or subscribe to, empty lists allow everything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubjectPermission {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl SubjectPermission {
+    fn from_claim(claim: &Value) -> Self {
+        let subjects = |list: &Value| {
+            list.as_array()
+                .map(|list| list.iter().filter_map(Value::as_str).map(String::from).collect())
+                .unwrap_or_default()
+        };
+        SubjectPermission {
+            allow: subjects(&claim["allow"]),
+            deny: subjects(&claim["deny"]),
+        }
+    }
+}
+
+/// The `pub` and `sub` permissions of a user, or the default permissions of an account's users.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Permissions {
+    pub publish: SubjectPermission,
+    pub subscribe: SubjectPermission,
+}
+
+/// Limits of a user or an account, `None` when not set or unlimited (-1).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// Most subscriptions
+    pub subs: Option<i64>,
+    /// Most bytes of messages
+    pub data: Option<i64>,
+    /// Largest payload
+    pub payload: Option<i64>,
+    /// Most connections of an account
+    pub conn: Option<i64>,
+    /// Most leaf node connections of an account
+    pub leaf: Option<i64>,
+    /// Most imports of an account
+    pub imports: Option<i64>,
+    /// Most exports of an account
+    pub exports: Option<i64>,
+}
+
+impl Limits {
+    fn from_claim(claim: &Value) -> Self {
+        let limit = |name: &str| claim[name].as_i64().filter(|limit| *limit >= 0);
+        Limits {
+            subs: limit("subs"),
+            data: limit("data"),
+            payload: limit("payload"),
+            conn: limit("conn"),
+            leaf: limit("leaf"),
+            imports: limit("imports"),
+            exports: limit("exports"),
+        }
+    }
+}
+
+/// The claims of a NATS JWT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Claims {
+    /// `jti`, the id of the JWT
+    pub id: String,
+    /// `iss`, the public key of the operator, account or signing key that issued it
+    pub issuer: String,
+    /// `sub`, the public key of the account or user it is about
+    pub subject: String,
+    pub name: Option<String>,
+    pub issued_at: Option<SystemTime>,
+    /// `exp`, `None` for JWTs that never expire
+    pub expires: Option<SystemTime>,
+    /// `nats.type`: `operator`, `account` or `user`
+    pub kind: String,
+    /// `nats.issuer_account`, the account of a user issued with one of its signing keys
+    pub issuer_account: Option<String>,
+    /// The permissions of a user, or the default permissions of an account
+    pub permissions: Permissions,
+    /// The limits of a user, or `nats.limits` of an account
+    pub limits: Limits,
+    /// All `nats` claims, for those not decoded into fields
+    pub nats: Value,
+}
+
+impl Claims {
+    /// Decodes `jwt` without checking its signature, e.g. to inspect the credentials a client
+    /// was given.
+    pub fn decode(jwt: &str) -> Result<Claims, RatsioError> {
+        Claims::from_value(decode_jwt(jwt, false)?)
+    }
+
+    /// Decodes `jwt`, checking it was issued and signed by one of `trusted_keys`: the operator
+    /// keys for account JWTs, the account (signing) keys for user JWTs.
+    pub fn decode_verified(jwt: &str, trusted_keys: &[&str]) -> Result<Claims, RatsioError> {
+        let claims = Claims::from_value(decode_jwt(jwt, true)?)?;
+        if !trusted_keys.contains(&claims.issuer.as_str()) {
+            return Err(jwt_error(format!("issuer {} is not trusted", claims.issuer)));
+        }
+        Ok(claims)
+    }
+
+    fn from_value(claims: Value) -> Result<Claims, RatsioError> {
+        let time = |name: &str| claims[name].as_u64().map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let string = |value: &Value| value.as_str().map(String::from);
+        let nats = claims["nats"].clone();
+        let kind = string(&nats["type"]).ok_or_else(|| jwt_error("no nats.type"))?;
+        let (permissions, limits) = if kind == "account" {
+            (&nats["default_permissions"], Limits::from_claim(&nats["limits"]))
+        } else {
+            (&nats, Limits::from_claim(&nats))
+        };
+        let permissions = Permissions {
+            publish: SubjectPermission::from_claim(&permissions["pub"]),
+            subscribe: SubjectPermission::from_claim(&permissions["sub"]),
+        };
+        Ok(Claims {
+            id: string(&claims["jti"]).unwrap_or_default(),
+            issuer: string(&claims["iss"]).ok_or_else(|| jwt_error("no issuer"))?,
+            subject: string(&claims["sub"]).ok_or_else(|| jwt_error("no subject"))?,
+            name: string(&claims["name"]),
+            issued_at: time("iat"),
+            expires: time("exp"),
+            kind,
+            issuer_account: string(&nats["issuer_account"]),
+            permissions,
+            limits,
+            nats,
+        })
+    }
+
+    /// Time left until the JWT expires, zero once it expired and `None` if it never does.
+    pub fn expires_in(&self) -> Option<Duration> {
+        self.expires.map(|expires| expires.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_in() == Some(Duration::from_secs(0))
+    }
+}
+
+#[test]
+fn decodes_user_claims() {
+    use serde_json::json;
+
+    let account = KeyPair::new_account();
+    let user = KeyPair::new_user();
+    let expires = SystemTime::now() + Duration::from_secs(3600);
+    let claims = json!({
+        "jti": "J1",
+        "iat": 1_600_000_000u64,
+        "exp": expires.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        "iss": account.public_key(),
+        "sub": user.public_key(),
+        "name": "alice",
+        "nats": {
+            "type": "user",
+            "version": 2,
+            "pub": { "allow": ["orders.>"] },
+            "sub": { "deny": ["admin.>"] },
+            "subs": -1,
+            "payload": 1024,
+        },
+    });
+    let jwt = encode_jwt(&claims, &account).unwrap();
+    let decoded = Claims::decode_verified(&jwt, &[&account.public_key()]).unwrap();
+    assert_eq!((decoded.kind.as_str(), decoded.name.as_deref()), ("user", Some("alice")));
+    assert_eq!(decoded.permissions.publish.allow, vec!["orders.>"]);
+    assert_eq!(decoded.permissions.subscribe.deny, vec!["admin.>"]);
+    assert_eq!((decoded.limits.subs, decoded.limits.payload), (None, Some(1024)));
+    assert!(decoded.expires_in().unwrap() > Duration::from_secs(3500));
+    assert!(!decoded.is_expired());
+
+    assert!(Claims::decode_verified(&jwt, &[&KeyPair::new_operator().public_key()]).is_err());
+    let forged = encode_jwt(&claims, &KeyPair::new_account()).unwrap();
+    assert!(Claims::decode_verified(&forged, &[&account.public_key()]).is_err());
+    assert_eq!(Claims::decode(&forged).unwrap().subject, user.public_key());
+}
@@ -18,10 +18,23 @@ extern crate log;
 //use protobuf::{CachedSize, UnknownFields};
 pub mod protocol;
 pub mod ops;
+pub mod headers;
 pub mod error;
+#[cfg(feature = "jwt")]
+pub mod jwt;
 pub mod codec;
 pub mod net;
 pub mod nats_client;
+#[cfg(feature = "streaming")]
 pub mod stan_client;
 pub mod prelude;
 pub mod nuid;
+pub mod subject;
+pub mod bench;
+pub mod payload_pool;
+pub mod payload_reader;
+pub mod clock;
+pub mod registry;
+mod redact;
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -0,0 +1,43 @@
+use crate::error::RatsioError;
+use crate::ops::{Message, Publish, Subscribe};
+use futures::{
+    future::BoxFuture,
+    prelude::*,
+    stream::BoxStream,
+};
+
+use super::NatsClient;
+
+/// The core operations of a client as an object safe trait, so services can take an
+/// `Arc<dyn NatsClientApi>` and be unit tested with a `MockNatsClient` instead of a server.
+pub trait NatsClientApi: Send + Sync {
+    fn publish(&self, cmd: Publish) -> BoxFuture<'static, Result<(), RatsioError>>;
+
+    /// The messages of the subscription, it ends when the stream is dropped.
+    fn subscribe(&self, cmd: Subscribe) -> BoxFuture<'static, Result<BoxStream<'static, Message>, RatsioError>>;
+
+    fn request(&self, subject: String, payload: Vec<u8>) -> BoxFuture<'static, Result<Message, RatsioError>>;
+
+    /// Resolves once every op sent so far was processed.
+    fn flush(&self) -> BoxFuture<'static, Result<(), RatsioError>>;
+}
+
+impl NatsClientApi for NatsClient {
+    fn publish(&self, cmd: Publish) -> BoxFuture<'static, Result<(), RatsioError>> {
+        NatsClient::publish(self, cmd).boxed()
+    }
+
+    fn subscribe(&self, cmd: Subscribe) -> BoxFuture<'static, Result<BoxStream<'static, Message>, RatsioError>> {
+        NatsClient::subscribe(self, cmd)
+            .map_ok(|subscription| subscription.boxed())
+            .boxed()
+    }
+
+    fn request(&self, subject: String, payload: Vec<u8>) -> BoxFuture<'static, Result<Message, RatsioError>> {
+        NatsClient::request(self, subject, &payload).boxed()
+    }
+
+    fn flush(&self) -> BoxFuture<'static, Result<(), RatsioError>> {
+        NatsClient::flush(self).boxed()
+    }
+}
@@ -0,0 +1,170 @@
+use super::NatsClientOptions;
+#[cfg(feature = "jwt")]
+use super::UserJWT;
+use crate::ops::{Connect, ServerInfo};
+use std::{fmt, sync::Arc};
+
+/// The credentials sent in CONNECT, see `Authenticator`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectAuthFields {
+    pub user: Option<String>,
+    pub pass: Option<String>,
+    pub auth_token: Option<String>,
+    /// User JWT, for NATS 2.x decentralized security
+    pub jwt: Option<String>,
+    /// The server nonce signed with the nkey of the user, base64url encoded
+    pub sig: Option<String>,
+}
+
+impl ConnectAuthFields {
+    pub(crate) fn apply(self, connect: &mut Connect) {
+        connect.user = self.user;
+        connect.pass = self.pass;
+        connect.auth_token = self.auth_token;
+        connect.jwt = self.jwt;
+        connect.sig = self.sig;
+    }
+}
+
+/// Supplies the credentials of the client, asked on every (re)connect with the INFO of the server,
+/// see `NatsClientOptions::authenticator`. Schemes like credentials issued by Vault or exchanged
+/// for an OIDC token can be plugged in, `materialize` is called on the IO tasks of the client so it
+/// should return credentials fetched and refreshed in the background.
+pub trait Authenticator: Send + Sync {
+    fn materialize(&self, server_info: &ServerInfo) -> ConnectAuthFields;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&ServerInfo) -> ConnectAuthFields + Send + Sync,
+{
+    fn materialize(&self, server_info: &ServerInfo) -> ConnectAuthFields {
+        (self)(server_info)
+    }
+}
+
+/// Sends the same credentials to every server.
+impl Authenticator for ConnectAuthFields {
+    fn materialize(&self, _server_info: &ServerInfo) -> ConnectAuthFields {
+        self.clone()
+    }
+}
+
+/// Username and password authentication.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserPassword {
+    pub user: String,
+    pub pass: String,
+}
+
+impl Authenticator for UserPassword {
+    fn materialize(&self, _server_info: &ServerInfo) -> ConnectAuthFields {
+        ConnectAuthFields {
+            user: Some(self.user.clone()),
+            pass: Some(self.pass.clone()),
+            ..ConnectAuthFields::default()
+        }
+    }
+}
+
+/// Token authentication.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token(pub String);
+
+impl Authenticator for Token {
+    fn materialize(&self, _server_info: &ServerInfo) -> ConnectAuthFields {
+        ConnectAuthFields {
+            auth_token: Some(self.0.clone()),
+            ..ConnectAuthFields::default()
+        }
+    }
+}
+
+/// User JWT authentication, the nonce of the server is signed on every connect. Without a `sig`
+/// if signing fails, the server then rejects the connection.
+#[cfg(feature = "jwt")]
+impl Authenticator for UserJWT {
+    fn materialize(&self, server_info: &ServerInfo) -> ConnectAuthFields {
+        let sig = self.sign(server_info.nonce.as_bytes())
+            .map_err(|err| error!(target: "ratsio", "Nonce signing callback failed: {}", err))
+            .ok();
+        ConnectAuthFields {
+            jwt: Some(self.jwt.clone()),
+            sig,
+            ..ConnectAuthFields::default()
+        }
+    }
+}
+
+/// A shareable `Authenticator`, see `NatsClientOptions::authenticator`.
+#[derive(Clone)]
+pub struct SharedAuthenticator(Arc<dyn Authenticator>);
+
+impl SharedAuthenticator {
+    pub fn new<A: Authenticator + 'static>(authenticator: A) -> Self {
+        SharedAuthenticator(Arc::new(authenticator))
+    }
+
+    pub fn materialize(&self, server_info: &ServerInfo) -> ConnectAuthFields {
+        self.0.materialize(server_info)
+    }
+}
+
+impl fmt::Debug for SharedAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedAuthenticator(authenticator)")
+    }
+}
+
+impl PartialEq for SharedAuthenticator {
+    fn eq(&self, other: &SharedAuthenticator) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl NatsClientOptions {
+    /// The credentials for a server, from the `authenticator` if set, otherwise from `username`,
+    /// `password`, `auth_token` and `user_jwt`.
+    pub(crate) fn connect_auth(&self, server_info: &ServerInfo) -> ConnectAuthFields {
+        if let Some(ref authenticator) = self.authenticator {
+            return authenticator.materialize(server_info);
+        }
+        let not_empty = |x: &String| !x.is_empty();
+        #[allow(unused_mut)]
+        let mut fields = ConnectAuthFields {
+            user: Some(self.username.clone()).filter(not_empty),
+            pass: Some(self.password.clone()).filter(not_empty),
+            auth_token: Some(self.auth_token.clone()).filter(not_empty),
+            ..ConnectAuthFields::default()
+        };
+        #[cfg(feature = "jwt")]
+        {
+            if let Some(ref user_jwt) = self.user_jwt {
+                let signed = user_jwt.materialize(server_info);
+                fields.jwt = signed.jwt;
+                fields.sig = signed.sig;
+            }
+        }
+        fields
+    }
+}
+
+#[test]
+fn authenticators_fill_connect() {
+    let info = ServerInfo { nonce: "nonce".into(), ..ServerInfo::default() };
+    let opts = NatsClientOptions {
+        username: "user".into(),
+        password: "secret".into(),
+        ..NatsClientOptions::default()
+    };
+    assert_eq!(opts.connect_auth(&info), UserPassword { user: "user".into(), pass: "secret".into() }.materialize(&info));
+
+    let rotating = SharedAuthenticator::new(|info: &ServerInfo| ConnectAuthFields {
+        auth_token: Some(format!("token-for-{}", info.nonce)),
+        ..ConnectAuthFields::default()
+    });
+    let opts = NatsClientOptions { authenticator: Some(rotating), ..opts };
+    let mut connect = Connect::default();
+    opts.connect_auth(&info).apply(&mut connect);
+    assert_eq!((connect.user, connect.auth_token), (None, Some(String::from("token-for-nonce"))));
+}
@@ -0,0 +1,196 @@
+use crate::error::RatsioError;
+use crate::jwt::{encode_jwt, jwt_error};
+use nkeys::KeyPair;
+use serde_json::{json, Map, Value};
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Subject the server sends authorization requests on, see `NatsClient::serve_auth_callout`.
+pub const AUTH_CALLOUT_SUBJECT: &str = "$SYS.REQ.USER.AUTH";
+
+/// The claims of a NATS JWT, once its signature was verified against the key of its issuer.
+fn decode_jwt(jwt: &str) -> Result<Value, RatsioError> {
+    crate::jwt::decode_jwt(jwt, true)
+}
+
+fn issued_at() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+/// An authorization request the server sends to the auth callout service for a connecting client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthorizationRequest {
+    /// Id of the server asking, the response is addressed to it
+    pub server_id: String,
+    /// The nkey the server generated for the client, the subject of the user JWT
+    pub user_nkey: String,
+    /// What the server knows of the client: `host`, `id`, `name`, `lang`, `version`...
+    pub client_info: Value,
+    /// The CONNECT options of the client, including `user`, `pass`, `auth_token` and `jwt`
+    pub connect_opts: Value,
+}
+
+impl AuthorizationRequest {
+    /// Decodes the request JWT, verifying it was signed by the server that sent it. Requests
+    /// encrypted with an xkey are not supported.
+    pub fn from_jwt(jwt: &str) -> Result<AuthorizationRequest, RatsioError> {
+        let claims = decode_jwt(jwt)?;
+        let nats = &claims["nats"];
+        if nats["type"] != "authorization_request" {
+            return Err(jwt_error(format!("not an authorization request => {}", nats["type"])));
+        }
+        let field = |value: &Value, name: &str| {
+            value.as_str().map(String::from).ok_or_else(|| jwt_error(format!("no {}", name)))
+        };
+        Ok(AuthorizationRequest {
+            server_id: field(&nats["server_id"]["id"], "server id")?,
+            user_nkey: field(&nats["user_nkey"], "user nkey")?,
+            client_info: nats["client_info"].clone(),
+            connect_opts: nats["connect_opts"].clone(),
+        })
+    }
+
+    /// The `user` of the CONNECT options.
+    pub fn user(&self) -> Option<&str> {
+        self.connect_opts["user"].as_str()
+    }
+
+    /// The `pass` of the CONNECT options.
+    pub fn password(&self) -> Option<&str> {
+        self.connect_opts["pass"].as_str()
+    }
+
+    /// The `auth_token` of the CONNECT options.
+    pub fn token(&self) -> Option<&str> {
+        self.connect_opts["auth_token"].as_str()
+    }
+}
+
+/// The user an authorization request is granted for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthorizedUser {
+    /// Name of the user, shown in the server monitoring
+    pub name: String,
+    /// Account the user is placed in, `$G` on servers without accounts
+    pub account: String,
+    /// Further `nats` claims of the user JWT, such as the `pub` and `sub` permissions and the
+    /// `subs`, `data` and `payload` limits
+    pub permissions: Map<String, Value>,
+}
+
+impl AuthorizedUser {
+    /// A user of `account` without permission limits.
+    pub fn new(name: &str, account: &str) -> Self {
+        AuthorizedUser {
+            name: name.to_string(),
+            account: account.to_string(),
+            permissions: Map::new(),
+        }
+    }
+}
+
+/// Signs the responses of an auth callout service with the key of the account the service
+/// runs in, see `NatsClient::serve_auth_callout`.
+#[derive(Clone)]
+pub struct AuthCallout {
+    seed: String,
+    issuer: String,
+}
+
+impl Debug for AuthCallout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AuthCallout {{ issuer: {} }}", self.issuer)
+    }
+}
+
+impl AuthCallout {
+    /// The seed of the account nkey, as configured in the `auth_callout.issuer` of the server.
+    pub fn new(account_seed: &str) -> Result<AuthCallout, RatsioError> {
+        let key_pair = KeyPair::from_seed(account_seed).map_err(jwt_error)?;
+        Ok(AuthCallout {
+            seed: account_seed.to_string(),
+            issuer: key_pair.public_key(),
+        })
+    }
+
+    /// The public key of the account.
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    fn key_pair(&self) -> Result<KeyPair, RatsioError> {
+        KeyPair::from_seed(&self.seed).map_err(jwt_error)
+    }
+
+    /// The user JWT granting `request` the account and permissions of `user`.
+    pub fn user_jwt(&self, request: &AuthorizationRequest, user: &AuthorizedUser) -> Result<String, RatsioError> {
+        let mut nats = user.permissions.clone();
+        nats.insert("type".into(), "user".into());
+        nats.insert("version".into(), 2.into());
+        let claims = json!({
+            "jti": crate::nuid::next(),
+            "iat": issued_at(),
+            "iss": self.issuer,
+            "sub": request.user_nkey,
+            "aud": user.account,
+            "name": user.name,
+            "nats": nats,
+        });
+        encode_jwt(&claims, &self.key_pair()?)
+    }
+
+    /// The response JWT to `request`, with the user JWT if it was granted and the reason
+    /// otherwise.
+    pub fn respond(&self, request: &AuthorizationRequest, result: Result<AuthorizedUser, String>) -> Result<String, RatsioError> {
+        let mut nats = json!({ "type": "authorization_response", "version": 2 });
+        match result {
+            Ok(user) => nats["jwt"] = self.user_jwt(request, &user)?.into(),
+            Err(reason) => nats["error"] = reason.into(),
+        }
+        let claims = json!({
+            "jti": crate::nuid::next(),
+            "iat": issued_at(),
+            "iss": self.issuer,
+            "sub": request.user_nkey,
+            "aud": request.server_id,
+            "nats": nats,
+        });
+        encode_jwt(&claims, &self.key_pair()?)
+    }
+}
+
+#[test]
+fn answers_authorization_requests() {
+    let server = KeyPair::new_server();
+    let user_nkey = KeyPair::new_user().public_key();
+    let request_claims = json!({
+        "iss": server.public_key(),
+        "sub": user_nkey,
+        "aud": "nats-authorization-request",
+        "nats": {
+            "type": "authorization_request",
+            "version": 2,
+            "server_id": { "name": "n1", "id": "NSERVER" },
+            "user_nkey": user_nkey,
+            "client_info": { "host": "127.0.0.1", "id": 7 },
+            "connect_opts": { "user": "alice", "pass": "secret" },
+        },
+    });
+    let request = AuthorizationRequest::from_jwt(&encode_jwt(&request_claims, &server).unwrap()).unwrap();
+    assert_eq!((request.server_id.as_str(), request.user(), request.password()), ("NSERVER", Some("alice"), Some("secret")));
+
+    let account = KeyPair::new_account();
+    let callout = AuthCallout::new(&account.seed().unwrap()).unwrap();
+    let response = decode_jwt(&callout.respond(&request, Ok(AuthorizedUser::new("alice", "APP"))).unwrap()).unwrap();
+    assert_eq!((response["sub"].as_str(), response["aud"].as_str()), (Some(user_nkey.as_str()), Some("NSERVER")));
+    let user = decode_jwt(response["nats"]["jwt"].as_str().unwrap()).unwrap();
+    assert_eq!((user["iss"].as_str(), user["aud"].as_str()), (Some(callout.issuer()), Some("APP")));
+    assert_eq!(user["nats"]["type"], "user");
+
+    let denied = decode_jwt(&callout.respond(&request, Err("unknown user".into())).unwrap()).unwrap();
+    assert_eq!(denied["nats"]["error"], "unknown user");
+
+    // Claims signed by another key than their issuer's are rejected.
+    let forged = encode_jwt(&request_claims, &KeyPair::new_server()).unwrap();
+    assert!(AuthorizationRequest::from_jwt(&forged).is_err());
+}
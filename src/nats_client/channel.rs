@@ -0,0 +1,207 @@
+use crate::error::RatsioError;
+use crate::headers::{Headers, RATSIO_CHANNEL, RATSIO_CHANNEL_SEQ};
+use crate::ops::{Message, Publish, Subscribe, UnSubscribe};
+use futures::{
+    future,
+    prelude::*,
+    task::{Context, Poll},
+    Stream,
+};
+use parking_lot::Mutex;
+use std::{pin::Pin, sync::Arc, time::Duration};
+
+use super::{NatsClient, Subscription};
+
+const OPEN: &str = "open";
+const ACCEPT: &str = "accept";
+const CLOSE: &str = "close";
+
+/// One end of a full-duplex stream of frames between two clients, each end receives on a private
+/// inbox the other end sends to. Opened with `Channel::open` towards a peer calling
+/// `Channel::accept`. Frames are numbered, the stream yields them in order and fails with
+/// `RatsioError::FramesLost` if one went missing, e.g. during a reconnect. It ends once the peer
+/// closed its end.
+///
+/// Dropping a channel without `close` does not tell the peer, its stream then only ends with the
+/// connection.
+pub struct Channel {
+    client: Arc<NatsClient>,
+    inbox: Subscription<Message>,
+    peer_inbox: String,
+    /// Sequence of the last frame sent, locked while the next one is queued so frames go out in order
+    sent: Mutex<u64>,
+    /// Sequence of the last frame received
+    received: u64,
+    done: bool,
+}
+
+impl Channel {
+    fn new(client: Arc<NatsClient>, inbox: Subscription<Message>, peer_inbox: String) -> Self {
+        Channel {
+            client,
+            inbox,
+            peer_inbox,
+            sent: Mutex::new(0),
+            received: 0,
+            done: false,
+        }
+    }
+
+    /// Opens a channel with the peer accepting channels on `peer_subject`. Fails with
+    /// `RatsioError::RequestTimeout` if the peer does not answer within `timeout`, and with
+    /// `RatsioError::NoResponders` if nobody accepts channels on the subject.
+    pub fn open(
+        client: Arc<NatsClient>,
+        peer_subject: &str,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Channel, RatsioError>> + Send {
+        let peer_subject = peer_subject.to_string();
+        async move {
+            let inbox = Publish::generate_reply_to();
+            let mut subscription = client.subscribe(Subscribe {
+                subject: inbox.clone(),
+                ..Subscribe::default()
+            }).await?;
+            client.publish(Publish {
+                subject: peer_subject.clone(),
+                payload: Vec::new(),
+                reply_to: Some(inbox),
+                headers: Some(control_headers(OPEN)),
+            }).await?;
+            let clock = client.opts.read().clock.clone();
+            let answer = match clock.timeout(timeout, subscription.next()).await {
+                Some(Some(answer)) => answer,
+                Some(None) => return Err(RatsioError::InnerBrokenChain),
+                None => return Err(RatsioError::RequestTimeout),
+            };
+            let headers = answer.headers.unwrap_or_default();
+            if headers.status() == Some(503) {
+                return Err(RatsioError::NoResponders);
+            }
+            match (headers.get(RATSIO_CHANNEL), answer.reply_to) {
+                (Some(ACCEPT), Some(peer_inbox)) => Ok(Channel::new(client, subscription, peer_inbox)),
+                _ => Err(RatsioError::GenericError(format!("{} did not accept the channel", peer_subject))),
+            }
+        }
+    }
+
+    /// Accepts the channels opened towards `subject`, in `queue_group` if given so peers are
+    /// spread over several acceptors. Other messages on the subject are ignored.
+    pub fn accept(
+        client: Arc<NatsClient>,
+        subject: &str,
+        queue_group: Option<String>,
+    ) -> impl Future<Output = Result<impl Stream<Item = Channel> + Send, RatsioError>> + Send {
+        let cmd = Subscribe {
+            subject: subject.to_string(),
+            queue_group,
+            ..Subscribe::default()
+        };
+        client.subscribe(cmd).map_ok(move |requests| {
+            requests.filter_map(move |request| {
+                let client = client.clone();
+                async move {
+                    let opening = request.headers.as_ref()
+                        .and_then(|headers| headers.get(RATSIO_CHANNEL)) == Some(OPEN);
+                    let peer_inbox = match request.reply_to {
+                        Some(peer_inbox) if opening => peer_inbox,
+                        _ => return None,
+                    };
+                    let inbox = Publish::generate_reply_to();
+                    // The SUB goes out before the answer, the peer's first frame can't miss the inbox.
+                    let accepted = client.subscribe(Subscribe { subject: inbox.clone(), ..Subscribe::default() })
+                        .and_then(|subscription| {
+                            client.publish_to_inbox(Publish {
+                                subject: peer_inbox.clone(),
+                                payload: Vec::new(),
+                                reply_to: Some(inbox),
+                                headers: Some(control_headers(ACCEPT)),
+                            }).map_ok(|_| subscription)
+                        }).await;
+                    match accepted {
+                        Ok(subscription) => Some(Channel::new(client, subscription, peer_inbox)),
+                        Err(err) => {
+                            warn!(target: client.log_target(), "Unable to accept channel from {} => {}", &peer_inbox, err);
+                            None
+                        }
+                    }
+                }
+            })
+        })
+    }
+
+    /// The inbox of the other end, the frames are sent to.
+    pub fn peer_inbox(&self) -> &str {
+        &self.peer_inbox
+    }
+
+    /// Sends a frame to the other end.
+    pub fn send(&self, payload: &[u8]) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        self.send_frame(payload.to_vec(), None)
+    }
+
+    /// Tells the other end no more frames follow, its stream ends. Frames still sent by the other
+    /// end are not received anymore.
+    pub fn close(&self) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        let _ = self.client.unsubscribe(UnSubscribe {
+            sid: self.inbox.sid().to_string(),
+            max_msgs: None,
+        });
+        self.send_frame(Vec::new(), Some(CLOSE))
+    }
+
+    fn send_frame(&self, payload: Vec<u8>, control: Option<&str>) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        let mut sent = self.sent.lock();
+        let mut headers = control.map(control_headers).unwrap_or_default();
+        headers.insert(RATSIO_CHANNEL_SEQ, &(*sent + 1).to_string());
+        let queued = self.client.publish_to_inbox(Publish {
+            subject: self.peer_inbox.clone(),
+            payload,
+            reply_to: None,
+            headers: Some(headers),
+        });
+        // Publishing queues the frame right away, the result only reports whether it was queued.
+        let queued = queued.now_or_never().unwrap_or(Ok(()));
+        if queued.is_ok() {
+            *sent += 1;
+        }
+        future::ready(queued)
+    }
+}
+
+impl Stream for Channel {
+    type Item = Result<Vec<u8>, RatsioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let frame = match self.inbox.poll_next_unpin(cx) {
+            Poll::Ready(Some(frame)) => frame,
+            Poll::Ready(None) => {
+                self.done = true;
+                return Poll::Ready(None);
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+        let headers = frame.headers.unwrap_or_default();
+        let seq = headers.get(RATSIO_CHANNEL_SEQ).and_then(|seq| seq.parse::<u64>().ok()).unwrap_or(0);
+        let expected = self.received + 1;
+        if seq != expected {
+            self.done = true;
+            return Poll::Ready(Some(Err(RatsioError::FramesLost { expected, received: seq })));
+        }
+        self.received = seq;
+        if headers.get(RATSIO_CHANNEL) == Some(CLOSE) {
+            self.done = true;
+            return Poll::Ready(None);
+        }
+        Poll::Ready(Some(Ok(frame.payload)))
+    }
+}
+
+fn control_headers(control: &str) -> Headers {
+    let mut headers = Headers::new();
+    headers.insert(RATSIO_CHANNEL, control);
+    headers
+}
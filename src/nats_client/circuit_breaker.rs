@@ -0,0 +1,86 @@
+use crate::clock::SharedClock;
+use crate::error::RatsioError;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Short-circuits requests to a subject whose last `failures` requests timed out or found no
+/// responders, see `NatsClientOptions::circuit_breaker`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CircuitBreakerOptions {
+    /// Consecutive failed requests after which the circuit of a subject opens
+    pub failures: u32,
+    /// How long requests fail with `RatsioError::CircuitOpen` once the circuit opened. The next
+    /// request after it is let through, its failure opens the circuit again right away.
+    pub cooldown: Duration,
+}
+
+#[derive(Debug, Default)]
+struct SubjectCircuit {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// The circuits of the subjects whose last requests failed.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    circuits: Mutex<HashMap<String, SubjectCircuit>>,
+    clock: SharedClock,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(clock: SharedClock) -> Self {
+        CircuitBreaker {
+            circuits: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    pub(crate) fn check(&self, subject: &str) -> Result<(), RatsioError> {
+        match self.circuits.lock().get(subject).and_then(|circuit| circuit.open_until) {
+            Some(open_until) if open_until > self.clock.now() => Err(RatsioError::CircuitOpen(subject.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts the outcome of a request, only timeouts and missing responders are failures.
+    pub(crate) fn record<T>(&self, options: &CircuitBreakerOptions, subject: &str, result: &Result<T, RatsioError>) {
+        let mut circuits = self.circuits.lock();
+        match result {
+            Err(RatsioError::RequestTimeout) | Err(RatsioError::NoResponders) => {
+                let circuit = circuits.entry(subject.to_string()).or_insert_with(SubjectCircuit::default);
+                circuit.failures += 1;
+                if circuit.failures >= options.failures {
+                    circuit.open_until = Some(self.clock.now() + options.cooldown);
+                }
+            }
+            Err(_) => {}
+            Ok(_) => {
+                circuits.remove(subject);
+            }
+        }
+    }
+}
+
+#[test]
+fn opens_after_consecutive_failures() {
+    let options = CircuitBreakerOptions {
+        failures: 2,
+        cooldown: Duration::from_secs(60),
+    };
+    let clock = crate::clock::MockClock::new();
+    let breaker = CircuitBreaker::new(SharedClock::new(clock.clone()));
+    let failed: Result<(), RatsioError> = Err(RatsioError::RequestTimeout);
+    breaker.record(&options, "svc", &failed);
+    breaker.record(&options, "svc", &Ok(()));
+    breaker.record(&options, "svc", &failed);
+    assert!(breaker.check("svc").is_ok());
+    breaker.record(&options, "svc", &Err::<(), _>(RatsioError::NoResponders));
+    match breaker.check("svc") {
+        Err(RatsioError::CircuitOpen(subject)) => assert_eq!(subject, "svc"),
+        other => panic!("expected an open circuit, got {:?}", other),
+    }
+    assert!(breaker.check("other").is_ok());
+    clock.advance(Duration::from_secs(60));
+    assert!(breaker.check("svc").is_ok());
+}
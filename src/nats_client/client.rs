@@ -1,391 +1,1319 @@
 use atomic_counter::AtomicCounter;
 use atomic_counter::ConsistentCounter;
 
-use crate::error::RatsioError;
+use crate::error::{RatsioError, SubscriptionError};
+use crate::headers::Headers;
 use crate::net::*;
 use crate::ops::{Message, Op, Publish, Subscribe, UnSubscribe};
 use futures::{
     future::{self, Either},
     prelude::*,
-    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    stream,
+    channel::mpsc::{self, UnboundedSender},
     Future, Stream,
 };
-use parking_lot::RwLock;
-use std::time::{Duration, Instant};
-use std::{collections::HashMap, sync::Arc};
-use tokio::time::Delay;
-use tokio::time::Interval;
+use parking_lot::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use super::*;
 
+/// Highest protocol level sent in CONNECT, 1 supports async INFO updates of the cluster topology.
+const CLIENT_PROTOCOL: u32 = 1;
+/// Client version sent in CONNECT.
+const CLIENT_VERSION: &str = "0.2.0";
+/// Number of ops a `NatsClient::raw_ops` stream may fall behind before ops are dropped.
+pub const RAW_OPS_BUFFER: usize = 256;
+
 impl NatsClientMultiplexer {
     fn new(
         stream: NatsStream,
         subs_map: Arc<RwLock<HashMap<String, SubscriptionSink>>>,
-        control_tx: mpsc::UnboundedSender<Op>,
+        control_tx: CountedSender<Op>,
+        read_batch_budget: usize,
+        supervisor: &Supervisor,
     ) -> Self {
-        let mltpx_subs_map = subs_map.clone();
-        let control_tx2 = control_tx.clone();
-        // Here we filter the incoming TCP stream Messages by subscription ID and sending it to the appropriate Sender
-        let multiplexer_fut = stream
-            .for_each(move |op| {
-                match op {
-                    Op::MSG(msg) => {
-                        if let Some(s) = (*mltpx_subs_map.read()).get(&msg.sid) {
-                            let _ = s.tx.unbounded_send(SinkMessage::Message(msg));
-                        }
-                    }
-                    // Forward the rest of the messages to the owning client
-                    op => {
-                        let _ = control_tx2.clone().unbounded_send(op);
-                    }
-                }
-
-                future::ok::<(), RatsioError>(())
-            })
-            .map(|_| ())
-            .from_err();
-
-        tokio::spawn(multiplexer_fut);
+        let batches = ReadBatches::new(stream, read_batch_budget);
+        let multiplexer_fut = multiplex(batches, subs_map.clone(), control_tx.clone());
+        supervisor.spawn_connection_task("multiplexer", multiplexer_fut);
 
         NatsClientMultiplexer {
             subs_map,
             control_tx,
+            log_target: supervisor.connection.log_target.clone(),
         }
     }
 
+    /// Registers a stream for the subscription, with `deliver_errors` connection level errors
+    /// concerning it are passed on as `Err` items, otherwise the stream only yields `Ok` messages.
+    /// Fails with `RatsioError::DuplicateSid` if the sid is registered already, and with
+    /// `RatsioError::TooManySubscriptions` if `max_subscriptions` (0 for no limit) are.
     pub fn for_sid(
         &self,
         cmd: Subscribe,
-    ) -> impl Stream<Item = Message, Error = RatsioError> + Send + Sync {
-        let (tx, rx) = mpsc::unbounded();
+        deliver_errors: bool,
+        max_subscriptions: usize,
+    ) -> Result<impl Stream<Item = Result<Message, SubscriptionError>> + Send + Sync, RatsioError> {
         let sid = cmd.sid.clone();
         let subject = cmd.subject.clone();
-        (*self.subs_map.write()).insert(
-            sid.clone(),
-            SubscriptionSink {
-                cmd,
-                tx,
-                max_count: None,
-                count: 0,
-            },
-        );
-
-        rx.map_err(|_| RatsioError::InnerBrokenChain)
-            .take_while(move |sink_msg| match sink_msg {
+        let queued = QueueDepth::default();
+        let log_target = self.log_target.clone();
+        let (tx, rx): (SinkSender, Pin<Box<dyn Stream<Item = SinkMessage> + Send + Sync>>) = match cmd.pending_limit {
+            Some(limit) => {
+                let pending = Arc::new(PendingMessages::new(limit, queued.clone()));
+                let guard = CloseOnDrop(pending.clone());
+                let rx = stream::poll_fn(move |cx| guard.0.poll_next(cx));
+                (SinkSender::Pending(PendingSender(pending)), Box::pin(rx))
+            }
+            None => {
+                let (tx, rx) = mpsc::unbounded();
+                let queued = queued.clone();
+                let rx = rx.inspect(move |sink_msg| {
+                    if let SinkMessage::Message(_) = sink_msg {
+                        queued.taken();
+                    }
+                });
+                (SinkSender::Unbounded(tx), Box::pin(rx))
+            }
+        };
+        {
+            // Checked under the same lock the sink is registered with, so concurrent subscribes
+            // with one sid can't both pass and replace each other's sink.
+            let mut subs_map = self.subs_map.write();
+            if subs_map.contains_key(&sid) {
+                return Err(RatsioError::DuplicateSid(sid));
+            }
+            if max_subscriptions > 0 && subs_map.len() >= max_subscriptions {
+                return Err(RatsioError::TooManySubscriptions(max_subscriptions));
+            }
+            subs_map.insert(sid.clone(), SubscriptionSink::new(cmd, tx, deliver_errors, queued));
+        }
+
+        Ok(rx.take_while(move |sink_msg| future::ready(match sink_msg {
                 SinkMessage::CLOSE => {
-                    warn!(target:"ratsio", "Closing sink for => {} / {}", &sid, &subject);
-                    Ok(false)
+                    warn!(target: log_target.as_str(), "Closing sink for => {} / {}", &sid, &subject);
+                    false
                 }
-                _ => Ok(true),
-            })
-            .filter_map(|sink_msg| match sink_msg {
-                SinkMessage::Message(msg) => Some(msg),
-                _ => None,
-            })
+                _ => true,
+            }))
+            .filter_map(move |sink_msg| future::ready(match sink_msg {
+                SinkMessage::Message(msg) => Some(Ok(msg)),
+                SinkMessage::Error(err) => Some(Err(err)),
+                SinkMessage::CLOSE => None,
+            })))
     }
 
     pub fn remove_sid(&self, sid: &str) {
+        // Dropping the sink ends the stream once the buffered messages are consumed.
         if (*self.subs_map.write()).remove(sid).is_some() {
-            debug!(target: "ratsio", "Removing sid {}", &sid);
+            debug!(target: self.log_target.as_str(), "Removing sid {}", &sid);
         }
     }
 }
 
 impl NatsClient {
+    /// The `log_label` of the client, to tell apart the events of clients in one process.
+    pub fn log_label(&self) -> String {
+        self.opts.read().log_label.clone()
+    }
+
+    /// The target of the internal logs of this client, see `NatsClientOptions::log_label`.
+    pub(crate) fn log_target(&self) -> &str {
+        &self.connection.log_target
+    }
+
     pub fn add_reconnect_handler(
         &self,
         hid: String,
         handler: Box<dyn Fn(Arc<NatsClient>) -> () + Send + Sync>,
     ) {
-        self.reconnect_handlers.write().insert(hid, handler);
+        let callback: ReconnectCallback = Arc::new(Mutex::new(move |client| {
+            handler(client);
+            future::ready(()).boxed()
+        }));
+        self.reconnect_handlers.write().insert(hid, callback);
+    }
+
+    /// Registers an async reconnect handler, the future it returns is spawned on every reconnect,
+    /// once CONNECT was sent (and subscriptions restored if `subscribe_on_reconnect` is set).
+    /// The handler is removed when the returned guard is dropped, see `ReconnectHandlerGuard::detach`.
+    pub fn on_reconnect<F, Fut>(&self, mut handler: F) -> ReconnectHandlerGuard
+    where
+        F: FnMut(Arc<NatsClient>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hid = crate::nuid::next();
+        let callback: ReconnectCallback =
+            Arc::new(Mutex::new(move |client| handler(client).boxed()));
+        self.reconnect_handlers.write().insert(hid.clone(), callback);
+        ReconnectHandlerGuard {
+            hid,
+            handlers: Arc::downgrade(&self.reconnect_handlers),
+        }
     }
 
     pub fn remove_reconnect_handler(&self, hid: &str) {
         self.reconnect_handlers.write().remove(hid);
     }
 
+    /// Sets a callback that is given the `Subscribe` command of every subscription restored after a
+    /// reconnect, it may return a changed command (e.g. another queue group) to subscribe with instead,
+    /// or `None` to end the subscription. The sid of the subscription is kept.
+    pub fn set_resubscribe_rewriter<F>(&self, rewrite: F)
+    where
+        F: Fn(Subscribe) -> Option<Subscribe> + Send + Sync + 'static,
+    {
+        *self.resubscribe_rewriter.write() = Some(Box::new(rewrite));
+    }
+
+    /// Registers a payload validator for all subjects matching `pattern` (wildcards allowed).
+    /// Publishes of invalid payloads fail with `RatsioError::PayloadValidationError`, invalid
+    /// received messages are dropped before they reach the subscription stream.
+    pub fn add_payload_validator(
+        &self,
+        pattern: String,
+        on: ValidateOn,
+        validator: Arc<dyn PayloadValidator>,
+    ) {
+        self.validators.write().add(pattern, on, validator);
+    }
+
+    /// Removes all payload validators registered for `pattern`.
+    pub fn remove_payload_validators(&self, pattern: &str) {
+        self.validators.write().remove(pattern);
+    }
+
+    /// Compresses the payloads of at least `min_size` bytes published to subjects matching
+    /// `pattern` (wildcards allowed), marked with a `Content-Encoding` header. The first policy
+    /// registered that matches a subject applies, so register `Compression::Never` for the
+    /// exceptions first. Received messages are decompressed whatever the policies, before the
+    /// payload validators see them. Payloads are sent uncompressed to servers without headers.
+    pub fn add_compression_policy(&self, pattern: String, compression: Compression, min_size: usize) {
+        self.compression.write().add(pattern, compression, min_size);
+    }
+
+    /// Removes all compression policies registered for `pattern`.
+    pub fn remove_compression_policies(&self, pattern: &str) {
+        self.compression.write().remove(pattern);
+    }
+
+    /// Signs the messages published to subjects matching `pattern` (wildcards allowed) with `key`,
+    /// into `Ratsio-Signer` and `Ratsio-Signature` headers. The subject is signed along with the
+    /// payload, so a copy republished on another subject does not verify. Compressed payloads are
+    /// signed as sent.
+    /// The first signer registered that matches a subject applies, publishes fail with
+    /// `RatsioError::UnsupportedByServer` on servers without headers.
+    #[cfg(feature = "jwt")]
+    pub fn add_payload_signer(&self, pattern: String, key: nkeys::KeyPair) {
+        self.signing.write().add_signer(pattern, key);
+    }
+
+    /// Removes all payload signers registered for `pattern`.
+    #[cfg(feature = "jwt")]
+    pub fn remove_payload_signers(&self, pattern: &str) {
+        self.signing.write().remove_signers(pattern);
+    }
+
+    /// Verifies the signatures of messages received on subjects matching `pattern` (wildcards
+    /// allowed), with the keys `resolver` trusts for their signers. Messages that are not signed,
+    /// or not by a trusted key, are dropped or flagged as `on_unverified` says, before they are
+    /// decompressed and validated. Replies to requests are not verified.
+    #[cfg(feature = "jwt")]
+    pub fn add_signature_verifier(
+        &self,
+        pattern: String,
+        resolver: Arc<dyn SignerKeyResolver>,
+        on_unverified: OnUnverified,
+    ) {
+        self.signing.write().add_verifier(pattern, resolver, on_unverified);
+    }
+
+    /// Removes all signature verifiers registered for `pattern`.
+    #[cfg(feature = "jwt")]
+    pub fn remove_signature_verifiers(&self, pattern: &str) {
+        self.signing.write().remove_verifiers(pattern);
+    }
+
     pub fn get_state(&self) -> NatsClientState {
         self.state.read().clone()
     }
-    /// Creates a client and initiates a connection to the server
+
+    /// Details of the current connection: server, socket addresses, TLS and protocol levels.
+    pub fn connection_info(&self) -> ConnectionInfo {
+        let (server_url, local_addr, remote_addr, tls) = {
+            let inner = self.connection.inner.read();
+            (inner.0.clone(), inner.1.local_addr(), inner.1.peer_addr(), inner.1.is_tls())
+        };
+        let server_info = self.server_info.read();
+        ConnectionInfo {
+            server_url,
+            local_addr,
+            remote_addr,
+            tls,
+            state: self.get_state(),
+            server_id: server_info.as_ref().map(|info| info.server_id.clone()),
+            server_version: server_info.as_ref().map(|info| info.version.clone()),
+            server_proto: server_info.as_ref().map(|info| info.proto),
+            server_features: server_info.as_ref().map(ServerInfo::features),
+            client_protocol: server_info.as_ref().map_or(CLIENT_PROTOCOL, |info| CLIENT_PROTOCOL.min(info.proto)),
+            client_version: CLIENT_VERSION.to_string(),
+            connect_version: self.connection.state.read().1,
+        }
+    }
+
+    /// Messages and payload bytes published per subject prefix, the prefixes with the most bytes first.
+    pub fn stats_by_subject(&self) -> Vec<SubjectStats> {
+        self.stats.lock().snapshot()
+    }
+
+    /// The `n` subject prefixes the most payload bytes were published on.
+    pub fn top_subjects(&self, n: usize) -> Vec<SubjectStats> {
+        let mut stats = self.stats_by_subject();
+        stats.truncate(n);
+        stats
+    }
+
+    /// Delivery statistics of the subscription `sid`, `None` if there is no such subscription.
+    pub fn subscription_stats(&self, sid: &str) -> Option<SubscriptionStats> {
+        self.receiver.read().subs_map.read().get(sid).map(|sink| sink.stats(sid))
+    }
+
+    /// Delivery statistics of all subscriptions, the ones that went the longest without a message
+    /// first, so dead or lagging subscriptions stand out.
+    pub fn stats_by_subscription(&self) -> Vec<SubscriptionStats> {
+        let mut stats: Vec<_> = self.receiver.read().subs_map.read()
+            .iter()
+            .map(|(sid, sink)| sink.stats(sid))
+            .collect();
+        stats.sort_by(|a, b| a.last_delivery.cmp(&b.last_delivery).then_with(|| a.sid.cmp(&b.sid)));
+        stats
+    }
+
+    /// A snapshot of the internal queue depths and task health.
+    pub fn runtime_metrics(&self) -> RuntimeMetrics {
+        let subscription_queues = self.receiver.read().subs_map.read()
+            .iter()
+            .map(|(sid, sink)| (sid.clone(), sink.queued.get()))
+            .collect();
+        let subscription_latencies = self.receiver.read().subs_map.read()
+            .iter()
+            .filter(|(_, sink)| sink.stats.latency.count > 0)
+            .map(|(sid, sink)| (sid.clone(), sink.stats.latency.clone()))
+            .collect();
+        RuntimeMetrics {
+            control_queue: self.control_tx.read().depth(),
+            subscription_queues,
+            subscription_latencies,
+            reconnect_queue: self.connection.reconnect_handler.depth(),
+            tasks: self.supervisor.tasks.alive(),
+        }
+    }
+
+    /// The state of the client as reported to health checks, see `NatsClientOptions::health_subject`.
+    /// Times the round trip of a PING to the server, waiting up to a second for the PONG.
+    pub fn health_report(&self) -> impl Future<Output = HealthReport> + Send {
+        let clock = self.opts.read().clock.clone();
+        let pinged_at = clock.now();
+        let pong = self.ping_fence.fence(&self.sender.read());
+        let (published_msgs, published_bytes) = self.stats_by_subject()
+            .iter()
+            .fold((0, 0), |(msgs, bytes), stats| (msgs + stats.msgs, bytes + stats.bytes));
+        let report = HealthReport {
+            name: self.opts.read().name.clone(),
+            state: format!("{:?}", self.get_state()),
+            uptime_ms: pinged_at.duration_since(self.started_at).as_millis() as u64,
+            server_id: self.server_info.read().as_ref().map(|info| info.server_id.clone()),
+            rtt_ms: None,
+            reconnects: self.connection.state.read().1,
+            subscriptions: self.receiver.read().subs_map.read().len(),
+            published_msgs,
+            published_bytes,
+            dropped_ops: self.dropped_ops(),
+            tasks: self.supervisor.tasks.alive(),
+        };
+        async move {
+            let answered = match pong {
+                Ok(pong) => clock.timeout(RTT_TIMEOUT, pong).await.map_or(false, |pong| pong.is_ok()),
+                Err(_) => false,
+            };
+            let rtt = Some(clock.now().duration_since(pinged_at)).filter(|_| answered);
+            HealthReport {
+                rtt_ms: rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                ..report
+            }
+        }
+    }
+
+    /// Answers the health checks on the `health_subject` with a `HealthReport`, if it is set.
+    fn answer_health_checks(client: &Arc<Self>) {
+        let subscription = match client.take_subscription(HEALTH_SID) {
+            Some(subscription) => subscription,
+            None => return,
+        };
+        let client = client.clone();
+        tokio::spawn(subscription.for_each(move |msg| {
+            let client = client.clone();
+            async move {
+                let reply_to = match msg.reply_to {
+                    Some(reply_to) => reply_to,
+                    None => return,
+                };
+                let report = client.health_report().await;
+                let payload = match serde_json::to_vec(&report) {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        error!(target: client.log_target(), "Unable to encode the health report => {}", err);
+                        return;
+                    }
+                };
+                // Sent like `serve` replies, not counted against `max_distinct_publish_subjects`.
+                let reply = Op::PUB(Publish { subject: reply_to, reply_to: None, payload, headers: None });
+                let _ = client.sender.read().send_with_priority(reply, Priority::High, true);
+            }
+        }));
+    }
+
+    /// Resets the publish statistics.
+    pub fn reset_stats(&self) {
+        self.stats.lock().clear();
+    }
+
+    /// Number of ops dropped because there was no connection to send them on, since the client was
+    /// created or `reset_dropped_ops` was called.
+    pub fn dropped_ops(&self) -> usize {
+        self.dropped_ops.get()
+    }
+
+    /// Resets the count of `dropped_ops`, returning the previous count.
+    pub fn reset_dropped_ops(&self) -> usize {
+        self.dropped_ops.reset()
+    }
+
+    /// Closes the client for good: the connection is dropped, subscription streams end and
+    /// `ClientEvent::Closed` is published.
+    pub fn close(&self) {
+        self.supervisor.close();
+    }
+
+    /// Closes the client gracefully: unsubscribes on the server so no new messages or requests
+    /// arrive, waits for the running `serve` handlers to reply, flushes and then closes. Waits
+    /// at most `NatsClientOptions::drain_timeout` for the handlers and again for the flush,
+    /// the client is closed in any case.
+    pub fn drain(&self) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        {
+            let sender = self.sender.read();
+            for sid in self.receiver.read().subs_map.read().keys() {
+                let _ = sender.send(Op::UNSUB(UnSubscribe { sid: sid.clone(), max_msgs: None }));
+            }
+        }
+        let (drain_timeout, clock) = {
+            let opts = self.opts.read();
+            (Duration::from_millis(opts.drain_timeout), opts.clock.clone())
+        };
+        let handlers_done = clock.timeout(drain_timeout, self.in_flight.idle());
+        let sender = self.sender.clone();
+        let ping_fence = self.ping_fence.clone();
+        let supervisor = self.supervisor.clone();
+        let log_target = self.log_target().to_string();
+        async move {
+            if handlers_done.await.is_none() {
+                warn!(target: log_target.as_str(), "Request handlers still running after the drain timeout");
+            }
+            let pong = ping_fence.fence(&sender.read());
+            let flushed = match pong {
+                Ok(pong) => match clock.timeout(drain_timeout, pong).await {
+                    Some(Ok(())) => Ok(()),
+                    Some(Err(_)) => Err(RatsioError::InnerBrokenChain),
+                    None => Err(RatsioError::RequestTimeout),
+                },
+                Err(err) => Err(err),
+            };
+            supervisor.close();
+            flushed
+        }
+    }
+
+    /// Drains the client once `signal` resolves, e.g. `tokio::signal::ctrl_c()`, so graceful
+    /// termination is a single call. Resolves once the client is closed, it can be spawned next to
+    /// the application.
     ///
-    /// Returns `impl Future<Item = Self, Error = RatsioError>`
+    /// ```no_run
+    /// # async fn run(client: std::sync::Arc<ratsio::nats_client::NatsClient>) {
+    /// tokio::spawn(client.shutdown_on(futures::future::pending::<()>()));
+    /// # }
+    /// ```
+    pub fn shutdown_on<S: Future + Send + 'static>(
+        self: &Arc<Self>,
+        signal: S,
+    ) -> impl Future<Output = Result<(), RatsioError>> + Send + 'static {
+        let client = self.clone();
+        async move {
+            signal.await;
+            info!(target: client.log_target(), "Shutdown signalled, draining");
+            client.drain().await
+        }
+    }
+
+    /// A stream of the `ClientEvent`s published from now on.
+    pub fn events(&self) -> impl Stream<Item = ClientEvent> + Send + Sync {
+        self.supervisor.events.subscribe()
+    }
+
+    /// The cluster of the server connected to, `None` until the first INFO arrived.
+    pub fn topology(&self) -> Option<Topology> {
+        self.server_info.read().as_ref().map(Topology::from_info)
+    }
+
+    /// A stream of the `Topology` changes from now on, e.g. servers joining and leaving the
+    /// cluster during a rolling upgrade.
+    pub fn topology_changes(&self) -> impl Stream<Item = TopologyChange> + Send + Sync {
+        self.events().filter_map(|event| match event {
+            ClientEvent::TopologyChanged(change) => future::ready(Some(change)),
+            _ => future::ready(None),
+        })
+    }
+
+    /// Resolves once the client is connected, right away if it already is. Fails with
+    /// `RatsioError::ConnectTimeout` if that takes longer than `timeout`, and with
+    /// `RatsioError::ClientClosed` if the client is closed meanwhile.
+    pub fn wait_until_connected(
+        &self,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<(), RatsioError>> + Send {
+        // Listening before the state is checked, so the event can't be missed in between.
+        let mut events = self.events();
+        let state = self.get_state();
+        let clock = self.opts.read().clock.clone();
+        let connected = async move {
+            match state {
+                NatsClientState::Connected => return Ok(()),
+                NatsClientState::Closed => return Err(RatsioError::ClientClosed),
+                _ => {}
+            }
+            while let Some(event) = events.next().await {
+                match event {
+                    ClientEvent::Connected => return Ok(()),
+                    ClientEvent::Closed => return Err(RatsioError::ClientClosed),
+                    _ => {}
+                }
+            }
+            Err(RatsioError::ClientClosed)
+        };
+        async move {
+            match timeout {
+                Some(timeout) => clock.timeout(timeout, connected)
+                    .await
+                    .unwrap_or(Err(RatsioError::ConnectTimeout)),
+                None => connected.await,
+            }
+        }
+    }
+
+    /// Sends a protocol op as is, for protocol extensions and diagnostics. No validation or
+    /// bookkeeping is done, e.g. a SUB sent this way does not deliver to any subscription stream.
+    pub fn send_op(&self, op: Op) -> Result<(), RatsioError> {
+        self.sender.read().send(op)
+    }
+
+    /// A stream of copies of the ops received from the server from now on, except MSGs.
+    /// Ops are dropped for a stream that falls more than `RAW_OPS_BUFFER` ops behind.
+    pub fn raw_ops(&self) -> impl Stream<Item = Op> + Send + Sync {
+        self.raw_ops.subscribe(RAW_OPS_BUFFER)
+    }
+
+    /// The latest connects and disconnects of the client with their reasons, oldest first. Keeps
+    /// `NatsClientOptions::connection_history_size` events.
+    pub fn connection_history(&self) -> Vec<ConnectionEvent> {
+        self.connection.history.events()
+    }
+
+    /// A copy of the options the client is currently running with.
+    pub fn options(&self) -> NatsClientOptions {
+        self.opts.read().clone()
+    }
+
+    /// Changes options of a running client without recreating it. Ping settings apply from the next
+    /// ping, reconnect settings and the server list from the next reconnect and credentials from the
+    /// next CONNECT sent to a server.
+    pub fn update_options(&self, patch: NatsClientOptionsPatch) {
+        let mut opts = self.opts.write();
+        if let Some(ping_interval) = patch.ping_interval {
+            opts.ping_interval = ping_interval;
+        }
+        if let Some(ping_max_out) = patch.ping_max_out {
+            opts.ping_max_out = ping_max_out;
+        }
+        if let Some(subscribe_on_reconnect) = patch.subscribe_on_reconnect {
+            opts.subscribe_on_reconnect = subscribe_on_reconnect;
+        }
+        if let Some(resubscribe_order) = patch.resubscribe_order {
+            opts.resubscribe_order = resubscribe_order;
+        }
+        if let Some(verify_subscriptions) = patch.verify_subscriptions {
+            opts.verify_subscriptions = verify_subscriptions;
+        }
+        if let Some(reconnect_handlers_blocking) = patch.reconnect_handlers_blocking {
+            opts.reconnect_handlers_blocking = reconnect_handlers_blocking;
+        }
+        if let Some(unsubscribe_on_drop) = patch.unsubscribe_on_drop {
+            opts.unsubscribe_on_drop = unsubscribe_on_drop;
+        }
+        if let Some(read_timeout) = patch.read_timeout {
+            opts.read_timeout = read_timeout;
+        }
+        if let Some(ensure_connect) = patch.ensure_connect {
+            opts.ensure_connect = ensure_connect;
+        }
+        if let Some(reconnect_timeout) = patch.reconnect_timeout {
+            opts.reconnect_timeout = reconnect_timeout;
+        }
+        if let Some(reconnect_jitter) = patch.reconnect_jitter {
+            opts.reconnect_jitter = reconnect_jitter;
+        }
+        if let Some(reconnect_jitter_tls) = patch.reconnect_jitter_tls {
+            opts.reconnect_jitter_tls = reconnect_jitter_tls;
+        }
+        *self.connection.reconnect_delay.write() = opts.reconnect_delay();
+        if let Some(request_inbox_ttl) = patch.request_inbox_ttl {
+            opts.request_inbox_ttl = request_inbox_ttl;
+        }
+        if let Some(drain_timeout) = patch.drain_timeout {
+            opts.drain_timeout = drain_timeout;
+        }
+        if let Some(authenticator) = patch.authenticator {
+            opts.authenticator = authenticator;
+        }
+        if let Some(username) = patch.username {
+            opts.username = username;
+        }
+        if let Some(password) = patch.password {
+            opts.password = password;
+        }
+        if let Some(auth_token) = patch.auth_token {
+            opts.auth_token = auth_token;
+        }
+        #[cfg(feature = "jwt")]
+        {
+            if let Some(user_jwt) = patch.user_jwt {
+                opts.user_jwt = user_jwt;
+            }
+        }
+        if let Some(cluster_uris) = patch.cluster_uris {
+            self.connection.server_pool.write().set_seeds(cluster_uris.urls().to_vec());
+            opts.cluster_uris = cluster_uris;
+        }
+        if let Some(prefer_seed_servers) = patch.prefer_seed_servers {
+            self.connection.server_pool.write().set_prefer_seeds(prefer_seed_servers);
+            opts.prefer_seed_servers = prefer_seed_servers;
+        }
+        if let Some(low_latency) = patch.low_latency {
+            self.low_latency.store(low_latency, Ordering::SeqCst);
+            self.connection.set_nodelay(low_latency);
+            opts.low_latency = low_latency;
+        }
+    }
+    /// Creates a client and initiates a connection to the server, giving up after `connect_timeout`
+    /// if it is set. With `dedicated_io_runtime` the client tasks are started on a runtime of their own,
+    /// with `connect_lazily` it resolves right away, see `NatsClient::new`.
+    ///
+    /// Returns `impl Future<Output = Result<Arc<Self>, RatsioError>>`
     pub fn connect(
         opts: NatsClientOptions,
-    ) -> impl Future<Item = Arc<Self>, Error = RatsioError> + Send + Sync {
-        loop_fn(opts, move |opts| {
-            let cont_opts = opts.clone();
-            NatsClient::create_client(opts)
-                .and_then(move |client| Ok(Loop::Break(client)))
-                .or_else(move |_err| {
-                    if cont_opts.ensure_connect {
-                        let when =
-                            Instant::now() + Duration::from_millis(cont_opts.reconnect_timeout);
-                        Either::Left(
-                            Delay::new(when)
-                                .and_then(move |_| Ok(Loop::Continue(cont_opts)))
-                                .map_err(|_| RatsioError::InnerBrokenChain),
-                        )
-                    } else {
-                        Either::Right(future::err(RatsioError::NoRouteToHostError))
+    ) -> impl Future<Output = Result<Arc<Self>, RatsioError>> + Send {
+        async move {
+            if opts.connect_lazily {
+                return NatsClient::new(opts);
+            }
+            if !opts.dedicated_io_runtime {
+                return NatsClient::connect_until_deadline(opts, None).await;
+            }
+            let io_runtime = IoRuntime::start(NatsClient::io_thread_name(&opts))?;
+            io_runtime
+                .spawn(NatsClient::connect_until_deadline(opts, Some(io_runtime.clone())))
+                .await
+                .map_err(|_| RatsioError::InnerBrokenChain)?
+        }
+    }
+
+    /// Creates a client without waiting for a connection. It starts `Disconnected` and keeps trying to
+    /// connect in the background like it reconnects, regardless of `ensure_connect`, so the application
+    /// does not wait for the servers to be up. Subscriptions are sent once connected, publishes are held
+    /// until then, up to `lazy_publish_buffer`. Must be called within a tokio runtime.
+    pub fn new(opts: NatsClientOptions) -> Result<Arc<Self>, RatsioError> {
+        if !opts.dedicated_io_runtime {
+            return Ok(NatsClient::create_lazy_client(opts, None));
+        }
+        let io_runtime = IoRuntime::start(NatsClient::io_thread_name(&opts))?;
+        Ok(io_runtime.enter(|| NatsClient::create_lazy_client(opts, Some(io_runtime.clone()))))
+    }
+
+    fn io_thread_name(opts: &NatsClientOptions) -> String {
+        match opts.log_label.as_str() {
+            "" => String::from("ratsio-io"),
+            label => format!("ratsio-io-{}", label),
+        }
+    }
+
+    fn connect_until_deadline(
+        opts: NatsClientOptions,
+        io_runtime: Option<Arc<IoRuntime>>,
+    ) -> impl Future<Output = Result<Arc<Self>, RatsioError>> + Send {
+        let connect_timeout = opts.connect_timeout;
+        let clock = opts.clock.clone();
+        let connecting = async move {
+            loop {
+                match NatsClient::create_client(opts.clone(), io_runtime.clone()).await {
+                    Ok(client) => return Ok(client),
+                    Err(err) => {
+                        if !opts.ensure_connect {
+                            return Err(RatsioError::NoRouteToHostError);
+                        }
+                        debug!(target: opts.log_target().as_str(), "Unable to connect, retrying => {}", err);
+                        opts.clock.delay(opts.reconnect_delay().next(opts.tls_required)).await;
                     }
-                })                
-        })
+                }
+            }
+        };
+        async move {
+            if connect_timeout == 0 {
+                return connecting.await;
+            }
+            clock.timeout(Duration::from_millis(connect_timeout), connecting)
+                .await
+                .unwrap_or(Err(RatsioError::ConnectTimeout))
+        }
     }
     /// Create nats client with options
     /// Called internally depending on the user options.
     fn create_client(
         opts: NatsClientOptions,
-    ) -> impl Future<Item = Arc<Self>, Error = RatsioError> + Send + Sync {
+        io_runtime: Option<Arc<IoRuntime>>,
+    ) -> impl Future<Output = Result<Arc<Self>, RatsioError>> + Send {
         let tls_required = opts.tls_required;
-        let recon_opts = opts.clone();
         let cluster_uris = opts.cluster_uris.0.clone();
-        let (reconnect_handler_tx, reconnect_handler_rx) = mpsc::unbounded();
-        NatsConnection::create_connection(reconnect_handler_tx.clone(),
-                                          opts.reconnect_timeout, &cluster_uris[..], tls_required)
-            .and_then(move |connection| {
-                debug!(target:"ratsio", "Creating NATS client, got a connection.");
-                let connection = Arc::new(connection);
-                let stream_conn = connection.clone();
-                let ping_conn = connection.clone();
-                let (sink, stream): (NatsSink, NatsStream) = NatsConnSinkStream {
-                    inner: connection.inner.clone(),
-                    state: connection.state.clone(),
-                    reconnect_trigger: Box::new(move || {
-                        NatsConnection::trigger_reconnect(stream_conn.clone());
-                    }),
-                }.split();
-
-                let (control_tx, control_rx) = mpsc::unbounded();
-                let subs_map: Arc<RwLock<HashMap<String, SubscriptionSink>>> =
-                    Arc::new(RwLock::new(HashMap::default()));
-                let recon_subs_map = subs_map.clone();
-
-                let receiver = NatsClientMultiplexer::new(stream, subs_map.clone(), control_tx.clone());
-                let sender = NatsClientSender::new(sink);
-
-                let (unsub_tx, unsub_rx) = mpsc::unbounded();
-
-                let ping_interval = u64::from(opts.ping_interval);
-                let ping_max_out = usize::from(opts.ping_max_out);
-                                
-                let client = Arc::new(NatsClient {
-                    connection: connection.clone(),
-                    sender: Arc::new(RwLock::new(sender)),
-                    server_info: Arc::new(RwLock::new(None)),
-                    unsub_receiver: Box::new(unsub_rx.map_err(|_| RatsioError::InnerBrokenChain)),
-                    receiver: Arc::new(RwLock::new(receiver)),
-                    control_tx: Arc::new(RwLock::new(control_tx)),
-                    state: Arc::new(RwLock::new(NatsClientState::Connecting)),
-                    opts,
-                    reconnect_handlers: Arc::new(RwLock::new(HashMap::default())),                    
-                });
+        let (reconnect_handler_tx, reconnect_handler_rx) = counted();
+        NatsConnection::create_connection(reconnect_handler_tx.clone(), opts.reconnect_delay(),
+                                          &cluster_uris[..], opts.server_list.clone(), tls_required,
+                                          opts.tls_identity_provider.clone(), opts.tls_hostname.clone(),
+                                          opts.address_family,
+                                          opts.log_target(), opts.clock.clone(),
+                                          ConnectionHistory::new(opts.connection_history_size),
+                                          opts.server_pool_file.clone().map(PathBuf::from))
+            .map_ok(move |connection| {
+                debug!(target: connection.log_target.as_str(), "Creating NATS client, got a connection.");
+                NatsClient::start(opts, io_runtime, connection, reconnect_handler_rx, PublishBuffer::disabled())
+            })
+    }
 
-                let ping_client = client.clone();
-                let ping_attempts = Arc::new(ConsistentCounter::new(0));
-                let pong_reset = ping_attempts.clone();
-                let recon_ping_attempts = ping_attempts.clone();
-                NatsClient::control_receiver(control_rx, unsub_tx.clone(), client.clone(), pong_reset);
-
-
-                //Send pings to server to check if we're still connected.
-                tokio::spawn(Interval::new_interval(Duration::from_secs(ping_interval))
-                    .for_each(move |_| {
-                        if *ping_client.state.read() == NatsClientState::Connected {
-                            trace!(target: "ratsio", " Send {:?}", Op::PING);
-                            ping_client.sender.read().send(Op::PING);
-                            let attempts = ping_attempts.inc();
-                            if attempts >= 1 {
-                                debug!(target: "ratsio", "Skipped a ping.");
-                            }
+    /// Creates a client on a connection that is not made yet, see `NatsClient::new`.
+    fn create_lazy_client(opts: NatsClientOptions, io_runtime: Option<Arc<IoRuntime>>) -> Arc<Self> {
+        let (reconnect_handler_tx, reconnect_handler_rx) = counted();
+        let connection = NatsConnection::unconnected(reconnect_handler_tx, opts.reconnect_delay(),
+                                                     opts.cluster_uris.0.clone(), opts.server_list.clone(),
+                                                     opts.tls_required, opts.tls_identity_provider.clone(),
+                                                     opts.tls_hostname.clone(), opts.address_family,
+                                                     opts.log_target(), opts.clock.clone(),
+                                                     ConnectionHistory::new(opts.connection_history_size),
+                                                     opts.server_pool_file.clone().map(PathBuf::from));
+        let publish_buffer = PublishBuffer::new(opts.lazy_publish_buffer);
+        let client = NatsClient::start(opts, io_runtime, connection, reconnect_handler_rx, publish_buffer);
+        NatsConnection::trigger_reconnect(client.connection.clone());
+        client
+    }
 
-                            if attempts > ping_max_out {
-                                error!(target: "ratsio", "Pings are not responded to, we may be down.");
-                                *ping_client.state.write() = NatsClientState::Disconnected;
-                                NatsConnection::trigger_reconnect(ping_conn.clone());
-                            }
-                        }
-                        Ok(())
-                    }).map_err(|_| ()));
-
-                let recon_client = client.clone();
-                tokio::spawn(reconnect_handler_rx.for_each(move |conn| {
-                    *recon_client.state.write() = NatsClientState::Reconnecting;
-                    if !recon_opts.subscribe_on_reconnect {
-                        let _: Vec<_> = recon_subs_map.read().iter().map(|(_, sink)| {
-                            let _ = sink.tx.unbounded_send(SinkMessage::CLOSE);
-                            debug!(target:"ratsio", "Closing sink for => {:?}", &sink.cmd.subject);
-                        }).collect();
-                        recon_subs_map.write().clear();
+    /// Starts the client tasks on `connection`, later connections are handed over on
+    /// `reconnect_handler_rx`.
+    fn start(
+        opts: NatsClientOptions,
+        io_runtime: Option<Arc<IoRuntime>>,
+        connection: NatsConnection,
+        reconnect_handler_rx: CountedReceiver<Arc<NatsConnection>>,
+        publish_buffer: PublishBuffer,
+    ) -> Arc<Self> {
+        connection.server_pool.write().set_prefer_seeds(opts.prefer_seed_servers);
+        if opts.standby_connection {
+            connection.standby.set_max_age(Some(Duration::from_millis(opts.standby_max_age)));
+        }
+        connection.set_nodelay(opts.low_latency);
+        let barrier_buffer = PublishBuffer::released(opts.lazy_publish_buffer);
+        let started_at = opts.clock.now();
+        let connection = Arc::new(connection);
+        let stream_conn = connection.clone();
+        let ping_conn = connection.clone();
+        let events = EventEmitter::new(connection.log_target.clone());
+        let stream_events = events.clone();
+        let (sink, stream): (NatsSink, NatsStream) = NatsConnSinkStream {
+            inner: connection.inner.clone(),
+            state: connection.state.clone(),
+            last_read: connection.last_read.clone(),
+            reconnect_trigger: Box::new(move |reason: &ConnectionEventReason| {
+                if let ConnectionEventReason::ProtocolError(line) = reason {
+                    stream_events.emit(ClientEvent::ProtocolError { line: line.clone() });
+                }
+                NatsConnection::trigger_reconnect(stream_conn.clone());
+            }),
+            log_target: connection.log_target.clone(),
+            clock: connection.clock.clone(),
+            history: connection.history.clone(),
+        }.split();
+
+        let (control_tx, control_rx) = counted();
+        let subs_map: Arc<RwLock<HashMap<String, SubscriptionSink>>> =
+            Arc::new(RwLock::new(HashMap::default()));
+        let recon_subs_map = subs_map.clone();
+        let stats = Arc::new(Mutex::new(SubjectStatsRegistry::new(
+            opts.stats_subject_depth,
+            opts.stats_max_subjects,
+        )));
+        let recon_stats = stats.clone();
+        // A lazily connected client has no connection yet, it connects like it reconnects.
+        let state = Arc::new(RwLock::new(if publish_buffer.is_buffering() {
+            NatsClientState::Disconnected
+        } else {
+            NatsClientState::Connecting
+        }));
+        let supervisor = Supervisor {
+            connection: connection.clone(),
+            state: state.clone(),
+            subs_map: subs_map.clone(),
+            events,
+            io_runtime,
+            tasks: TaskHealth::default(),
+        };
+
+        let receiver = NatsClientMultiplexer::new(stream, subs_map.clone(), control_tx.clone(),
+                                                  opts.read_batch_budget, &supervisor);
+        let dropped_ops = Arc::new(ConsistentCounter::new(0));
+        let recon_dropped_ops = dropped_ops.clone();
+        let low_latency = Arc::new(AtomicBool::new(opts.low_latency));
+        let sender = NatsClientSender::new(sink, stats.clone(), dropped_ops.clone(),
+                                           opts.fail_when_disconnected, low_latency.clone(),
+                                           opts.write_timeout, opts.flush_policy, &supervisor);
+
+        let (unsub_tx, unsub_rx) = mpsc::unbounded();
+        let circuit_breaker = Arc::new(CircuitBreaker::new(opts.clock.clone()));
+
+
+        let client = Arc::new(NatsClient {
+            connection: connection.clone(),
+            sender: Arc::new(RwLock::new(sender)),
+            server_info: Arc::new(RwLock::new(None)),
+            unsub_receiver: unsub_rx,
+            receiver: Arc::new(RwLock::new(receiver)),
+            control_tx: Arc::new(RwLock::new(control_tx)),
+            state,
+            opts: Arc::new(RwLock::new(opts)),
+            reconnect_handlers: Arc::new(RwLock::new(HashMap::default())),
+            validators: Arc::new(RwLock::new(ValidatorRegistry::default())),
+            compression: Arc::new(RwLock::new(CompressionPolicies::default())),
+            #[cfg(feature = "jwt")]
+            signing: Arc::new(RwLock::new(SigningPolicies::default())),
+            stats,
+            dropped_ops,
+            supervisor: supervisor.clone(),
+            resubscribe_rewriter: Arc::new(RwLock::new(None)),
+            raw_ops: RawOpsTap::new(connection.log_target.clone()),
+            ping_fence: Arc::new(PingFence::default()),
+            circuit_breaker,
+            low_latency,
+            in_flight: Arc::new(InFlight::default()),
+            publish_buffer: Arc::new(publish_buffer),
+            barrier_buffer: Arc::new(barrier_buffer),
+            preregistered: Mutex::new(HashMap::default()),
+            resumable_requests: Arc::new(ResumableRequests::default()),
+            publish_subjects: Mutex::new(HashSet::new()),
+            started_at,
+        });
+        client.register_subscriptions();
+        NatsClient::answer_health_checks(&client);
+
+        let ping_client = client.clone();
+        let idle_client = client.clone();
+        let ping_attempts = Arc::new(ConsistentCounter::new(0));
+        let pong_reset = ping_attempts.clone();
+        let recon_ping_attempts = ping_attempts.clone();
+        let server_url = connection.inner.read().0.clone();
+        NatsClient::control_receiver(control_rx, unsub_tx.clone(), client.clone(), pong_reset, server_url);
+
+
+        //Send pings to server to check if we're still connected.
+        //Interval and max out are read on every tick so `update_options` applies to a running client.
+        supervisor.spawn_client_task("ping", async move {
+            loop {
+                let (ping_interval, clock) = {
+                    let opts = ping_client.opts.read();
+                    (u64::from(opts.ping_interval), opts.clock.clone())
+                };
+                clock.delay(Duration::from_secs(ping_interval)).await;
+                if *ping_client.state.read() == NatsClientState::Connected {
+                    trace!(target: ping_client.log_target(), " Send {:?}", Op::PING);
+                    let _ = ping_client.ping_fence.ping(&ping_client.sender.read());
+                    let attempts = ping_attempts.inc();
+                    if attempts >= 1 {
+                        debug!(target: ping_client.log_target(), "Skipped a ping.");
                     }
 
-                    let _ = recon_client.control_tx.read().unbounded_send(Op::CLOSE);
-                    recon_ping_attempts.reset();
-                    let stream_conn = conn.clone();
-                    let (sink, stream): (NatsSink, NatsStream) = NatsConnSinkStream {
-                        inner: conn.inner.clone(),
-                        state: conn.state.clone(),
-                        reconnect_trigger: Box::new(move || {
-                            NatsConnection::trigger_reconnect(stream_conn.clone());
-                        }),
-                    }.split();
-
-                    let (control_tx, control_rx) = mpsc::unbounded();
-                    let receiver = NatsClientMultiplexer::new(stream, recon_subs_map.clone(), control_tx.clone());
-                    let sender = NatsClientSender::new(sink);
-
-                    NatsClient::control_receiver(control_rx, unsub_tx.clone(), recon_client.clone(),
-                                                 recon_ping_attempts.clone());
-
-                    *recon_client.sender.write() = sender;
-                    *recon_client.receiver.write() = receiver;
-                    *recon_client.control_tx.write() = control_tx;
-                    *recon_client.state.write() = NatsClientState::Connected;
-
-                    if let Err(e) = NatsClient::connect(recon_opts.clone()).wait() {
-                        error!(target: "ratsio", "Failed to send connect op {:?}", e)
+                    let ping_max_out = usize::from(ping_client.opts.read().ping_max_out);
+                    if attempts > ping_max_out {
+                        error!(target: ping_client.log_target(), "Pings are not responded to, we may be down.");
+                        ping_conn.record_event(ConnectionEventReason::Stale);
+                        *ping_client.state.write() = NatsClientState::Disconnected;
+                        NatsConnection::trigger_reconnect(ping_conn.clone());
                     }
+                }
+            }
+        });
 
-                    if recon_opts.subscribe_on_reconnect {
-                        let subs_sender = recon_client.sender.clone();
-                        let subs_fut_list: Vec<_> = recon_subs_map.read().iter().map(|(_, sink)| {
-                            subs_sender.read()
-                                .send(Op::SUB(sink.cmd.clone()))
-                                .map_err(|err| {
-                                    //TODO ----------
-                                    error!(target: "ratsio", "Error re-subscribing {:?}", err);
-                                })
-                        }).collect();
-
-                        tokio::spawn(future::join_all(subs_fut_list).map(|_| ()));
+        //Reconnect when nothing was read from the server for `read_timeout`, catches
+        //half-open connections before `ping_max_out` pings went unanswered.
+        supervisor.spawn_client_task("read_timeout", async move {
+            loop {
+                let (read_timeout, clock) = {
+                    let opts = idle_client.opts.read();
+                    (Duration::from_millis(opts.read_timeout), opts.clock.clone())
+                };
+                if read_timeout == Duration::from_millis(0) {
+                    clock.delay(Duration::from_secs(1)).await;
+                    continue;
+                }
+                let idle = idle_client.connection.read_idle();
+                if idle < read_timeout {
+                    clock.delay(read_timeout - idle).await;
+                    continue;
+                }
+                if *idle_client.state.read() == NatsClientState::Connected {
+                    error!(target: idle_client.log_target(), "Nothing read from the server for {:?}, connection is stale.",
+                           idle);
+                    idle_client.connection.record_event(ConnectionEventReason::Stale);
+                    *idle_client.state.write() = NatsClientState::Disconnected;
+                    NatsConnection::trigger_reconnect(idle_client.connection.clone());
+                }
+                clock.delay(read_timeout).await;
+            }
+        });
+
+        //Keep a fresh standby connection to switch to, see `standby_connection`.
+        let standby_client = client.clone();
+        supervisor.spawn_client_task("standby", async move {
+            loop {
+                if *standby_client.state.read() == NatsClientState::Closed {
+                    break;
+                }
+                let clock = standby_client.opts.read().clock.clone();
+                match standby_client.connection.standby.max_age() {
+                    Some(max_age) => {
+                        NatsConnection::warm_standby(standby_client.connection.clone()).await;
+                        clock.delay(std::cmp::max(max_age / 2, Duration::from_millis(100))).await;
                     }
+                    None => clock.delay(Duration::from_secs(1)).await,
+                }
+            }
+        });
 
-                    let cb_client = recon_client.clone();
-                    recon_client.reconnect_handlers.read().iter()
-                        .for_each(move |(_, handler)| {
-                            (*handler)(cb_client.clone());
-                        });
-                    Ok(())
-                }));
-                future::ok(client)
-            })
+        let recon_client = client.clone();
+        supervisor.spawn_client_task("reconnect", reconnect_handler_rx.for_each(move |conn| {
+            if *recon_client.state.read() == NatsClientState::Closed {
+                return future::ready(());
+            }
+            *recon_client.state.write() = NatsClientState::Reconnecting;
+            let subscribe_on_reconnect = recon_client.opts.read().subscribe_on_reconnect;
+            // The subscriptions made before the first connection of a lazy client are all sent.
+            let first_connect = recon_client.publish_buffer.is_buffering();
+            if recon_client.opts.read().resubscribe_barrier {
+                recon_client.barrier_buffer.hold();
+            }
+            recon_subs_map.write().retain(|_, sink| {
+                if first_connect {
+                    return true;
+                }
+                sink.send_error(SubscriptionError::Disconnected);
+                let resubscribe = sink.cmd.resubscribe.unwrap_or(subscribe_on_reconnect);
+                if !resubscribe {
+                    sink.close();
+                    debug!(target: recon_client.log_target(), "Closing sink for => {:?}", &sink.cmd.subject);
+                }
+                resubscribe
+            });
+
+            let _ = recon_client.control_tx.read().unbounded_send(Op::CLOSE);
+            recon_ping_attempts.reset();
+            recon_client.ping_fence.reset();
+            let stream_conn = conn.clone();
+            let stream_events = recon_client.supervisor.events.clone();
+            let (sink, stream): (NatsSink, NatsStream) = NatsConnSinkStream {
+                inner: conn.inner.clone(),
+                state: conn.state.clone(),
+                last_read: conn.last_read.clone(),
+                reconnect_trigger: Box::new(move |reason: &ConnectionEventReason| {
+                    if let ConnectionEventReason::ProtocolError(line) = reason {
+                        stream_events.emit(ClientEvent::ProtocolError { line: line.clone() });
+                    }
+                    NatsConnection::trigger_reconnect(stream_conn.clone());
+                }),
+                log_target: conn.log_target.clone(),
+                clock: conn.clock.clone(),
+                history: conn.history.clone(),
+            }.split();
+
+            let (control_tx, control_rx) = counted();
+            let supervisor = &recon_client.supervisor;
+            let read_batch_budget = recon_client.opts.read().read_batch_budget;
+            let receiver = NatsClientMultiplexer::new(stream, recon_subs_map.clone(), control_tx.clone(),
+                                                      read_batch_budget, supervisor);
+            let (fail_when_disconnected, write_timeout, flush_policy) = {
+                let opts = recon_client.opts.read();
+                (opts.fail_when_disconnected, opts.write_timeout, opts.flush_policy)
+            };
+            let low_latency = recon_client.low_latency.clone();
+            conn.set_nodelay(low_latency.load(Ordering::SeqCst));
+            let sender = NatsClientSender::new(sink, recon_stats.clone(), recon_dropped_ops.clone(),
+                                               fail_when_disconnected, low_latency, write_timeout, flush_policy,
+                                               supervisor);
+
+            let server_url = conn.inner.read().0.clone();
+            NatsClient::control_receiver(control_rx, unsub_tx.clone(), recon_client.clone(),
+                                         recon_ping_attempts.clone(), server_url);
+
+            *recon_client.sender.write() = sender;
+            *recon_client.receiver.write() = receiver;
+            *recon_client.control_tx.write() = control_tx;
+            // The client stays `Reconnecting` until the server INFO arrives on the new
+            // connection, see `NatsClient::reconnected`.
+            future::ready(())
+        }));
+        client
     }
 
     fn control_receiver(
-        control_rx: UnboundedReceiver<Op>,
+        control_rx: CountedReceiver<Op>,
         unsub_tx: UnboundedSender<Op>,
         client: Arc<NatsClient>,
         pong_reset: Arc<ConsistentCounter>,
+        server_url: NatsUrl,
     ) {
+        let supervisor = client.supervisor.clone();
         let control_fut = control_rx
             .take_while(|op| {
-                match op {
-                    Op::CLOSE => Ok(false),
-                    _ => Ok(true),
-                }
+                future::ready(match op {
+                    Op::CLOSE => false,
+                    _ => true,
+                })
             })
             .for_each(move |op| {
+                client.raw_ops.emit(&op);
                 match op {
                     Op::PING => {
                         pong_reset.reset();
-                        tokio::spawn(client.sender.read().send(Op::PONG)
-                            .map(|_| {
-                                debug!(target: "ratsio", "Sent {:?}", Op::PONG);
-                            })
-                            .map_err(|err| {
-                                error!(target: "ratsio", "Error could not send pong to server: {:?}", err);
-                            }));
+                        if !client.opts.read().manual_ping_mode {
+                            match client.sender.read().send(Op::PONG) {
+                                Ok(_) => debug!(target: client.log_target(), "Sent {:?}", Op::PONG),
+                                Err(err) => error!(target: client.log_target(), "Error could not send pong to server: {:?}", err),
+                            }
+                        }
                         let _ = unsub_tx.unbounded_send(op.clone());
                     }
                     Op::PONG => {
-                        debug!(target: "ratsio", " Received {:?}", Op::PONG);
+                        debug!(target: client.log_target(), " Received {:?}", Op::PONG);
                         pong_reset.reset();
+                        client.ping_fence.pong();
                     }
                     Op::INFO(server_info) => {
-                        pong_reset.reset();                        
-                        *client.server_info.write() = Some(server_info.clone());
-                        let mut reconnect_hosts = server_info.connect_urls.clone();
-                        for host in client.connection.init_hosts.clone() {
-                            reconnect_hosts.push(host);
+                        pong_reset.reset();
+                        let previous_info = client.server_info.write().replace(server_info.clone());
+                        if server_info.ldm && !previous_info.as_ref().map_or(false, |info| info.ldm) {
+                            warn!(target: client.log_target(), "Server is in lame duck mode");
+                            client.connection.record_event(ConnectionEventReason::LameDuck);
+                        }
+                        client.connection.server_pool.write().set_discovered(&server_info.connect_urls);
+                        client.connection.save_server_pool();
+                        if let Some(ref previous_info) = previous_info {
+                            let change = TopologyChange::between(Topology::from_info(previous_info),
+                                                                 Topology::from_info(&server_info));
+                            if let Some(change) = change {
+                                info!(target: client.log_target(), "Cluster topology changed, joined {:?}, left {:?}",
+                                      change.joined, change.left);
+                                client.supervisor.events.emit(ClientEvent::TopologyChanged(change));
+                            }
+                        }
+                        let state = client.get_state();
+                        let connecting = state != NatsClientState::Connected && state != NatsClientState::Closed;
+                        let auth_changed = !connecting && previous_info
+                            .map(|info| info.auth_required != server_info.auth_required)
+                            .unwrap_or(false);
+                        if auth_changed {
+                            info!(target: client.log_target(), "Server auth_required changed to {}", server_info.auth_required);
+                            client.supervisor.events.emit(ClientEvent::AuthRequiredChanged {
+                                auth_required: server_info.auth_required,
+                            });
+                        }
+                        // Answer the first INFO of a connection with CONNECT, required so we can sign the
+                        // server-supplied nonce if using JWT security. Later INFOs are topology updates, unless
+                        // the server started to require auth (config reload), then we authenticate again.
+                        if connecting || (auth_changed && server_info.auth_required) {
+                            let connect = Self::generate_connect(&client, &server_info, &server_url);
+                            debug!(target: client.log_target(), "Sending CONNECT...");
+                            let _ = client
+                                .sender
+                                .read()
+                                .send(Op::CONNECT(connect));
+                        }
+                        if connecting {
+                            *client.state.write() = NatsClientState::Connected;
+                            let events = client.supervisor.events.clone();
+                            if state != NatsClientState::Reconnecting {
+                                client.send_registered_subscriptions();
+                                events.emit(ClientEvent::Connected);
+                            } else if client.opts.read().reconnect_handlers_blocking {
+                                let handlers = NatsClient::reconnected(&client);
+                                tokio::spawn(handlers.map(move |_| events.emit(ClientEvent::Connected)));
+                            } else {
+                                tokio::spawn(NatsClient::reconnected(&client));
+                                events.emit(ClientEvent::Connected);
+                            }
                         }
-                        *client.connection.reconnect_hosts.write() = reconnect_hosts;  
-                        let connect = Self::generate_connect(&client, &server_info);
-                        // Now send a CONNECT protocol message in response to the INFO, required so 
-                        // we can sign the server-supplied nonce if using JWT security.                        
-                        debug!("Sending CONNECT...");
-                        client
-                            .sender
-                            .read()
-                            .send(Op::CONNECT(connect)); 
-                        *client.state.write() = NatsClientState::Connected;
                     }
                     Op::ERR(msg) => {
-                        error!(target: "ratsio", "NATS Server - Error - {}", msg);
+                        error!(target: client.log_target(), "NATS Server - Error - {}", msg);
+                        let lower = msg.to_lowercase();
+                        if lower.contains("authorization") || lower.contains("authentication") {
+                            client.connection.record_event(ConnectionEventReason::Auth(msg.clone()));
+                        }
+                        if let Some(err) = SubscriptionError::from_server_error(&msg) {
+                            let receiver = client.receiver.read();
+                            for sink in receiver.subs_map.write().values_mut() {
+                                match err {
+                                    SubscriptionError::PermissionViolation(ref subject)
+                                        if *subject != sink.cmd.subject => {}
+                                    SubscriptionError::PermissionViolation(_) => {
+                                        sink.rejected = Some(err.clone());
+                                        sink.send_error(err.clone());
+                                    }
+                                    _ => sink.send_error(err.clone()),
+                                }
+                            }
+                        }
                     }
                     Op::CLOSE => {
-                        warn!(target: "ratsio", "Stream closed");
+                        warn!(target: client.log_target(), "Stream closed");
                     }
                     op => {
                         let _ = unsub_tx.unbounded_send(op.clone());
                         pong_reset.reset();
                     }
                 };
-                Ok(())
+                future::ready(())
+            });
+        supervisor.spawn_connection_task("control", control_fut);
+    }
+
+    /// Restores subscriptions and starts the reconnect handlers, once CONNECT was sent on a new connection.
+    /// On the first connection of a lazy client the buffered publishes are sent instead of running
+    /// the handlers, with `resubscribe_barrier` only once the server answered a PING sent behind the
+    /// subscriptions. Each handler runs on its own task, the returned future resolves once all finished.
+    fn reconnected(client: &Arc<Self>) -> impl Future<Output = ()> + Send {
+        let (first_connect, barrier) = {
+            let rewrite = client.resubscribe_rewriter.read();
+            let subs_sender = client.sender.read();
+            let receiver = client.receiver.read();
+            let mut cmds = Vec::new();
+            receiver.subs_map.write().retain(|_, sink| {
+                if let Some(ref rewrite) = *rewrite {
+                    match rewrite(sink.cmd.clone()) {
+                        Some(cmd) => sink.cmd = Subscribe { sid: sink.cmd.sid.clone(), ..cmd },
+                        None => {
+                            sink.close();
+                            debug!(target: client.log_target(), "Not re-subscribing => {:?}", &sink.cmd.subject);
+                            return false;
+                        }
+                    }
+                }
+                if !sink.paused_on_server {
+                    cmds.push(sink.cmd.clone());
+                }
+                true
+            });
+            client.opts.read().resubscribe_order.order(&mut cmds);
+            // Flushed once all are queued, ahead of the publishes queued meanwhile.
+            let count = cmds.len();
+            for (idx, cmd) in cmds.into_iter().enumerate() {
+                if let Err(err) = subs_sender.send_with_priority(Op::SUB(cmd), Priority::High, idx + 1 == count) {
+                    error!(target: client.log_target(), "Error re-subscribing {:?}", err);
+                }
+            }
+            // Requests of `request_idempotent` whose reply may have gone to the lost connection.
+            for (sid, cmd) in client.resumable_requests.pending() {
+                if !receiver.subs_map.read().contains_key(&sid) {
+                    continue;
+                }
+                debug!(target: client.log_target(), "Sending request on {} again after reconnect", &cmd.subject);
+                let resent = subs_sender.send(Op::UNSUB(UnSubscribe { sid, max_msgs: Some(1) }))
+                    .and_then(|_| subs_sender.send(Op::PUB(cmd)));
+                if let Err(err) = resent {
+                    error!(target: client.log_target(), "Error sending a request again {:?}", err);
+                }
+            }
+            let barrier = if client.opts.read().resubscribe_barrier {
+                Some(client.ping_fence.fence(&subs_sender))
+            } else {
+                None
+            };
+            (client.publish_buffer.is_buffering(), barrier)
+        };
+        match barrier {
+            None => client.release_held_publishes(),
+            Some(Ok(pong)) => {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    if pong.await.is_ok() {
+                        debug!(target: client.log_target(), "Subscriptions restored, sending the held publishes");
+                    }
+                    client.release_held_publishes();
+                });
+            }
+            Some(Err(err)) => {
+                error!(target: client.log_target(), "Error sending the resubscribe barrier {:?}", err);
+                client.release_held_publishes();
+            }
+        }
+
+        // Handlers are collected first, so they may (de)register handlers themselves.
+        let handlers: Vec<ReconnectCallback> = if first_connect {
+            Vec::new()
+        } else {
+            client.reconnect_handlers.read().values().cloned().collect()
+        };
+        let running: Vec<_> = handlers.into_iter().map(|handler| {
+            let client = client.clone();
+            // A panicking handler only ends its own task.
+            tokio::spawn(async move {
+                let handler_fut = (*handler.lock())(client);
+                handler_fut.await
             })
-            .into_future()
-            .map(|_| ())
-            .map_err(|_| ());
-        tokio::spawn(control_fut);
+        }).collect();
+        let log_target = client.log_target().to_string();
+        future::join_all(running).map(move |results| {
+            for err in results.into_iter().filter_map(Result::err) {
+                error!(target: log_target.as_str(), "Reconnect handler failed => {}", err);
+            }
+        })
+    }
+
+    /// Sends the publishes held until the first connection and those held by the resubscribe barrier,
+    /// in this order.
+    fn release_held_publishes(&self) {
+        let sender = self.sender.read();
+        let mut send = |op: Op, priority: Priority, flush: bool| {
+            if let Err(err) = sender.send_with_priority(op, priority, flush) {
+                error!(target: self.log_target(), "Error sending a buffered publish {:?}", err);
+            }
+        };
+        self.publish_buffer.release(&mut send);
+        self.barrier_buffer.release(&mut send);
     }
 
     // Refactored the original connect method into a function that takes a ServerInfo 
     // struct and generates an appropriate Connect message in response.
-    fn generate_connect(client: &Arc<Self>, server_info: &ServerInfo) -> Connect {
+    fn generate_connect(client: &Self, server_info: &ServerInfo, server_url: &NatsUrl) -> Connect {
+        let opts = client.opts.read().clone();
         let not_empty = |x: &String| !x.is_empty();
-        let mut sig: Option<String> = None;
-        let mut jwt: Option<String> = None;
-            
-        if let Some(ref jwtopt) = client.opts.user_jwt {
-            jwt = Some(jwtopt.jwt.clone());
-            debug!("User JWT option detected");
-
-            let res = (jwtopt.signer)(server_info.nonce.as_bytes());
-            match res {
-                Ok(sigbytes) => {
-                    sig = Some(data_encoding::BASE64URL_NOPAD.encode(sigbytes.as_slice()))
-                }
-                Err(e) => error!("Nonce signing callback failed: {}", e),
-            }
-        }    
-
+        let features = server_info.features();
         let mut connect = Connect {
-            verbose: client.opts.verbose,
-            pedantic: client.opts.pedantic,
-            tls_required: client.opts.tls_required,
-            auth_token: Some(client.opts.auth_token.clone()).filter(not_empty),
-            user: Some(client.opts.username.clone()).filter(not_empty),
-            pass: Some(client.opts.password.clone()).filter(not_empty),
-            name: Some(client.opts.name.clone()).filter(not_empty),
+            verbose: opts.verbose,
+            pedantic: opts.pedantic,
+            tls_required: opts.tls_required,
+            auth_token: None,
+            user: None,
+            pass: None,
+            name: Some(opts.name.clone()).filter(not_empty),
             lang: "rust".to_string(),
-            version: "0.2.0".to_string(),
-            protocol: 1,
-            echo: client.opts.echo,
-            sig: sig,
-            jwt: jwt,
+            version: CLIENT_VERSION.to_string(),
+            protocol: CLIENT_PROTOCOL.min(server_info.proto),
+            echo: opts.echo,
+            sig: None,
+            jwt: None,
+            headers: features.contains(ServerFeatures::HEADERS),
+            no_responders: features.contains(ServerFeatures::NO_RESPONDERS),
         };
+        opts.connect_auth(server_info).apply(&mut connect);
 
-        let node_url = (*client.connection.inner.read()).0.clone();
-        if let Some(password) = node_url.password() {
-            connect.pass = Some(password.to_string());
+        if let Some(credentials) = opts.server_credentials.get(&server_url.host_and_port()) {
+            if credentials.username.is_some() {
+                connect.user = credentials.username.clone();
+            }
+            if credentials.password.is_some() {
+                connect.pass = credentials.password.clone();
+            }
+            if credentials.auth_token.is_some() {
+                connect.auth_token = credentials.auth_token.clone();
+            }
+        }
+        if let Some(ref password) = server_url.password {
+            connect.pass = Some(password.clone());
         }
-        if !node_url.username().is_empty() {
-            connect.user = Some(node_url.username().to_string());
+        if let Some(ref username) = server_url.username {
+            connect.user = Some(username.clone());
         }
         connect
 
     }
             
+    /// Switches verbose mode (`+OK` after every op) on the live connection by sending CONNECT again,
+    /// and for later connections.
+    pub fn set_verbose(&self, verbose: bool) -> Result<(), RatsioError> {
+        self.opts.write().verbose = verbose;
+        self.send_connect()
+    }
+
+    /// Switches pedantic mode (stricter checks by the server) on the live connection by sending
+    /// CONNECT again, and for later connections.
+    pub fn set_pedantic(&self, pedantic: bool) -> Result<(), RatsioError> {
+        self.opts.write().pedantic = pedantic;
+        self.send_connect()
+    }
+
+    /// Switches between low latency mode, `TCP_NODELAY` and a flush after every op, and throughput
+    /// mode with coalesced flushes, see `NatsClientOptions::low_latency`. Applies to the ops sent
+    /// from now on.
+    pub fn set_low_latency(&self, low_latency: bool) {
+        self.update_options(NatsClientOptionsPatch {
+            low_latency: Some(low_latency),
+            ..Default::default()
+        });
+    }
+
+    /// Sends CONNECT with the current options, unless no INFO was received yet; the options are
+    /// then used by the CONNECT answering it.
+    fn send_connect(&self) -> Result<(), RatsioError> {
+        let server_info = self.server_info.read().clone();
+        match server_info {
+            Some(ref server_info) => {
+                let server_url = self.connection.inner.read().0.clone();
+                let connect = Self::generate_connect(self, server_info, &server_url);
+                debug!(target: self.log_target(), "Sending CONNECT...");
+                self.sender.read().send(Op::CONNECT(connect))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The protocol features of the server, `None` until its INFO was received.
+    pub fn server_features(&self) -> Option<ServerFeatures> {
+        self.server_info.read().as_ref().map(ServerInfo::features)
+    }
+
+    /// Whether the server announced support for headers in its INFO, assumed until the first
+    /// INFO was received.
+    fn supports_headers(&self) -> bool {
+        self.server_features().map_or(true, |features| features.contains(ServerFeatures::HEADERS))
+    }
+
     /// Send a PUB command to the server
     ///
     /// Returns `impl Future<Output = Result<(), RatsioError>>`
@@ -393,14 +1321,134 @@ impl NatsClient {
         &self,
         cmd: Publish,
     ) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
-        if let Some(ref server_info) = *self.server_info.read() {
-            if cmd.payload.len() > server_info.max_payload {
-                return Either::Left(future::err(RatsioError::MaxPayloadOverflow(
-                    server_info.max_payload,
-                )));
+        let low_latency = self.low_latency.load(Ordering::SeqCst);
+        self.publish_with_low_latency(cmd, low_latency)
+    }
+
+    /// Publishes like `publish`, flushing right after the message with `low_latency` and along with
+    /// the ops queued behind it otherwise, whatever `NatsClientOptions::low_latency` is set to.
+    ///
+    /// Returns `impl Future<Output = Result<(), RatsioError>>`
+    pub fn publish_with_low_latency(
+        &self,
+        cmd: Publish,
+        low_latency: bool,
+    ) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        self.publish_op(cmd, low_latency, Priority::Low, true)
+    }
+
+    /// Publishes like `publish`, in the sender lane of `priority`. High priority messages are
+    /// written before the low priority messages queued at the time, such as request replies
+    /// that must not wait behind bulk publishes.
+    ///
+    /// Returns `impl Future<Output = Result<(), RatsioError>>`
+    pub fn publish_with_priority(
+        &self,
+        cmd: Publish,
+        priority: Priority,
+    ) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        let low_latency = self.low_latency.load(Ordering::SeqCst);
+        self.publish_op(cmd, low_latency, priority, true)
+    }
+
+    /// Publishes like `publish` to the private inbox of a peer, not counted against
+    /// `max_distinct_publish_subjects` since every peer has inboxes of its own.
+    pub(crate) fn publish_to_inbox(&self, cmd: Publish) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        let low_latency = self.low_latency.load(Ordering::SeqCst);
+        self.publish_op(cmd, low_latency, Priority::Low, false)
+    }
+
+    fn publish_op(
+        &self,
+        mut cmd: Publish,
+        low_latency: bool,
+        priority: Priority,
+        count_subject: bool,
+    ) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        if let Err(err) = self.validators.read().validate_publish(&cmd.subject, &cmd.payload) {
+            return Either::Left(future::err(err));
+        }
+        let headers_supported = self.supports_headers();
+        if !headers_supported && cmd.headers.as_ref().map_or(false, |headers| !headers.is_empty()) {
+            return Either::Left(future::err(RatsioError::UnsupportedByServer("headers".into())));
+        }
+        if headers_supported {
+            if let Err(err) = self.compression.read().compress(&cmd.subject, &mut cmd.payload, &mut cmd.headers) {
+                return Either::Left(future::err(err));
             }
         }
-        Either::Right(self.sender.read().send(Op::PUB(cmd)))
+        #[cfg(feature = "jwt")]
+        {
+            if let Err(err) = self.signing.read().sign(&cmd.subject, &cmd.payload, headers_supported, &mut cmd.headers) {
+                return Either::Left(future::err(err));
+            }
+        }
+        if headers_supported && self.opts.read().stamp_sent_at {
+            cmd.headers.get_or_insert_with(Headers::new).set_sent_at(crate::clock::monotonic_wall_time());
+        }
+        if let Err(err) = self.check_max_payload(&cmd) {
+            return Either::Left(future::err(err));
+        }
+        // Counted last, a publish failing the checks above does not take up a subject.
+        if count_subject {
+            if let Err(err) = self.count_publish_subject(&cmd.subject) {
+                return Either::Left(future::err(err));
+            }
+        }
+        // Held until the first connection of a lazy client, or the resubscribe barrier, so it goes
+        // out after the subscriptions.
+        match self.publish_buffer.push(Op::PUB(cmd), priority).or_else(|op| self.barrier_buffer.push(op, priority)) {
+            Ok(()) => Either::Left(future::ok(())),
+            Err(op) => Either::Right(future::ready(self.sender.read().send_with_priority(op, priority, low_latency))),
+        }
+    }
+
+    /// Fails with `RatsioError::MaxPayloadOverflow` if `cmd` and its headers exceed the `max_payload`
+    /// of the server, which would close the connection.
+    fn check_max_payload(&self, cmd: &Publish) -> Result<(), RatsioError> {
+        match *self.server_info.read() {
+            Some(ref server_info) if cmd.total_size() > server_info.max_payload => {
+                Err(RatsioError::MaxPayloadOverflow(server_info.max_payload))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts `subject` against `max_distinct_publish_subjects`, failing for a new subject past it.
+    fn count_publish_subject(&self, subject: &str) -> Result<(), RatsioError> {
+        let max_subjects = self.opts.read().max_distinct_publish_subjects;
+        if max_subjects == 0 {
+            return Ok(());
+        }
+        let mut subjects = self.publish_subjects.lock();
+        if subjects.contains(subject) {
+            return Ok(());
+        }
+        if subjects.len() >= max_subjects {
+            return Err(RatsioError::TooManyPublishSubjects(max_subjects, subject.to_string()));
+        }
+        subjects.insert(subject.to_string());
+        Ok(())
+    }
+
+    /// A handle publishing in batches of `PUBLISHER_BATCH` without taking the locks of `publish`,
+    /// for producers on many threads. Give each thread a clone of its own.
+    pub fn publisher(&self) -> PublisherHandle {
+        self.publisher_with_batch_size(PUBLISHER_BATCH)
+    }
+
+    /// Like `publisher`, queuing the publishes in batches of `batch_size`. A `batch_size` of 1
+    /// queues every publish right away.
+    pub fn publisher_with_batch_size(&self, batch_size: usize) -> PublisherHandle {
+        PublisherHandle::new(self, batch_size)
+    }
+
+    /// Answer a server PING, only needed when `manual_ping_mode` is enabled.
+    ///
+    /// Returns `impl Future<Output = Result<(), RatsioError>>`
+    pub fn send_pong(&self) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        trace!(target: self.log_target(), " Send {:?}", Op::PONG);
+        future::ready(self.sender.read().send(Op::PONG))
     }
 
     /// Send a UNSUB command to the server and de-register stream in the multiplexer
@@ -415,52 +1463,335 @@ impl NatsClient {
                 s.max_count = Some(max);
             }
         }
-        self.sender.read().send(Op::UNSUB(cmd))
+        future::ready(self.sender.read().send(Op::UNSUB(cmd)))
     }
 
     /// Send a SUB command and register subscription stream in the multiplexer and return that `Stream` in a future
     ///
-    /// Returns `impl Future<Item = impl Stream<Item = Message, Error = RatsioError>>`
+    /// Returns `impl Future<Output = Result<Subscription<Message>, RatsioError>>`
     pub fn subscribe(
         &self,
         cmd: Subscribe,
+    ) -> impl Future<Output = Result<Subscription<Message>, RatsioError>> + Send + Sync {
+        if self.opts.read().verify_subscriptions {
+            Either::Left(self.subscribe_confirmed(cmd))
+        } else {
+            Either::Right(self.subscription(cmd, true))
+        }
+    }
+
+    /// The stream of the subscription `sid` of `NatsClientOptions::subscriptions`, once. Messages
+    /// received before are buffered in it.
+    pub fn take_subscription(&self, sid: &str) -> Option<Subscription<Message>> {
+        self.preregistered.lock().remove(sid)
+    }
+
+    /// `NatsClientOptions::subscriptions`, and the subscription of the `health_subject` if set.
+    fn registered_subscribes(&self) -> Vec<Subscribe> {
+        let opts = self.opts.read();
+        let mut cmds = opts.subscriptions.clone();
+        if let Some(ref subject) = opts.health_subject {
+            cmds.push(Subscribe {
+                subject: subject.clone(),
+                sid: HEALTH_SID.to_string(),
+                resubscribe: Some(true),
+                ..Subscribe::default()
+            });
+        }
+        cmds
+    }
+
+    /// Registers the streams of `NatsClientOptions::subscriptions`, before the client connects.
+    fn register_subscriptions(&self) {
+        let cmds = self.registered_subscribes();
+        for cmd in cmds {
+            let sid = cmd.sid.clone();
+            match self.subscription(cmd, false).now_or_never() {
+                Some(Ok(subscription)) => {
+                    self.preregistered.lock().insert(sid, subscription);
+                }
+                Some(Err(err)) => error!(target: self.log_target(), "Unable to register subscription {} => {}", &sid, err),
+                None => {}
+            }
+        }
+    }
+
+    /// Sends the SUBs of `NatsClientOptions::subscriptions`, right after the first CONNECT. Later
+    /// connections restore them like any subscription.
+    fn send_registered_subscriptions(&self) {
+        let cmds = self.registered_subscribes();
+        let receiver = self.receiver.read();
+        let subs_map = receiver.subs_map.read();
+        let sender = self.sender.read();
+        let cmds: Vec<_> = cmds.into_iter()
+            .filter_map(|cmd| subs_map.get(&cmd.sid).map(|sink| sink.cmd.clone()))
+            .collect();
+        let count = cmds.len();
+        for (idx, cmd) in cmds.into_iter().enumerate() {
+            if let Err(err) = sender.send_with_priority(Op::SUB(cmd), Priority::High, idx + 1 == count) {
+                error!(target: self.log_target(), "Error subscribing {:?}", err);
+            }
+        }
+    }
+
+    /// Subscribes like `subscribe`, only registering the stream unless `send_sub` is set.
+    fn subscription(
+        &self,
+        cmd: Subscribe,
+        send_sub: bool,
+    ) -> impl Future<Output = Result<Subscription<Message>, RatsioError>> + Send + Sync {
+        let sid = cmd.sid.clone();
+        let sender = self.sender.clone();
+        let receiver = self.receiver.clone();
+        let (unsubscribe_on_drop, clock) = {
+            let opts = self.opts.read();
+            (opts.unsubscribe_on_drop, opts.clock.clone())
+        };
+        self.subscribe_stream(cmd, false, send_sub).map_ok(move |stream| {
+            let stream = stream.filter_map(|item| future::ready(item.ok()));
+            Subscription::new(sid, stream, sender, receiver, unsubscribe_on_drop, clock)
+        })
+    }
+
+    /// Replaces the subscription `sid` with `cmd`, e.g. to change its subject or queue group. Its
+    /// stream stays open and yields the messages of `cmd` from now on, the sid of `cmd` is ignored.
+    /// Subscriptions restored after a reconnect use `cmd` too.
+    pub fn resubscribe(&self, sid: &str, cmd: Subscribe) -> Result<(), RatsioError> {
+        let receiver = self.receiver.read();
+        let mut subs_map = receiver.subs_map.write();
+        let sink = subs_map.get_mut(sid).ok_or_else(|| RatsioError::UnknownSid(sid.to_string()))?;
+        sink.cmd = Subscribe { sid: sid.to_string(), ..cmd };
+        if sink.paused_on_server {
+            return Ok(());
+        }
+        let sender = self.sender.read();
+        sender.send(Op::UNSUB(UnSubscribe { sid: sid.to_string(), max_msgs: None }))?;
+        sender.send(Op::SUB(sink.cmd.clone()))
+    }
+
+    /// Resolves once the server has processed every op sent so far, by round tripping a PING.
+    /// Fails with `RatsioError::InnerBrokenChain` if the connection is lost before.
+    ///
+    /// Returns `impl Future<Output = Result<(), RatsioError>>`
+    pub fn flush(&self) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        future::ready(self.ping_fence.fence(&self.sender.read()))
+            .and_then(|pong| pong.map_err(|_| RatsioError::InnerBrokenChain))
+    }
+
+    /// Subscribes like `subscribe`, resolving only once the server has processed the SUB, so
+    /// messages published right after are not missed. A PING is sent after the SUB and its PONG
+    /// awaited, which works whether or not `verbose` is enabled. Fails with
+    /// `RatsioError::InnerBrokenChain` if the connection is lost before the PONG arrived, and with
+    /// `RatsioError::SubscriptionRejected` if the server answered the SUB with a permissions violation.
+    ///
+    /// Returns `impl Future<Output = Result<Subscription<Message>, RatsioError>>`
+    pub fn subscribe_confirmed(
+        &self,
+        cmd: Subscribe,
+    ) -> impl Future<Output = Result<Subscription<Message>, RatsioError>> + Send + Sync {
+        let subscribed = self.subscription(cmd, true);
+        let flushed = self.flush();
+        let receiver = self.receiver.clone();
+        subscribed.and_then(move |subscription| flushed.map(move |flushed| {
+            flushed?;
+            // The server answers a refused SUB with -ERR before the PONG.
+            let rejected = receiver.read().subs_map.read()
+                .get(subscription.sid())
+                .and_then(|sink| sink.rejected.clone());
+            match rejected {
+                Some(err) => {
+                    if let Some(sink) = receiver.read().subs_map.write().remove(subscription.sid()) {
+                        sink.close();
+                    }
+                    Err(RatsioError::SubscriptionRejected(err))
+                }
+                None => Ok(subscription),
+            }
+        }))
+    }
+
+    /// Subscribes like `subscribe`, but the stream also yields the connection level errors that
+    /// concern the subscription: disconnects, the server dropping messages because the client is a
+    /// slow consumer and permission violations for the subscribed subject.
+    ///
+    /// Returns `impl Future<Output = Result<Subscription<Result<Message, SubscriptionError>>, RatsioError>>`
+    pub fn subscribe_with_errors(
+        &self,
+        cmd: Subscribe,
+    ) -> impl Future<Output = Result<Subscription<Result<Message, SubscriptionError>>, RatsioError>> + Send + Sync {
+        let sid = cmd.sid.clone();
+        let sender = self.sender.clone();
+        let receiver = self.receiver.clone();
+        let (unsubscribe_on_drop, clock) = {
+            let opts = self.opts.read();
+            (opts.unsubscribe_on_drop, opts.clock.clone())
+        };
+        self.subscribe_stream(cmd, true, true)
+            .map_ok(move |stream| Subscription::new(sid, stream, sender, receiver, unsubscribe_on_drop, clock))
+    }
+
+    fn subscribe_stream(
+        &self,
+        cmd: Subscribe,
+        deliver_errors: bool,
+        send_sub: bool,
     ) -> impl Future<
-        Output = impl Stream<Item = Message> + Send + Sync,
+        Output = Result<impl Stream<Item = Result<Message, SubscriptionError>> + Send + Sync, RatsioError>,
     > + Send
                  + Sync {
         let receiver = self.receiver.clone();
         let subs_receiver = self.receiver.clone();
         let sid = cmd.sid.clone();
-        debug!(target: "ratsio", "Subscription for {} / {}", &cmd.subject, &sid);
-        let subs_cmd = cmd.clone();
-        self.sender.read().send(Op::SUB(cmd)).then(move |_| {
-            let stream = receiver.read().for_sid(subs_cmd).then(move |msg| {
+        debug!(target: self.log_target(), "Subscription for {} / {}", &cmd.subject, &sid);
+        let log_target = self.log_target().to_string();
+        let validators = self.validators.clone();
+        #[cfg(feature = "jwt")]
+        let signing = self.signing.clone();
+        let max_age = cmd.max_age;
+        let ttl = cmd.ttl;
+        let clock = self.opts.read().clock.clone();
+        let max_decompressed_size = self.opts.read().max_decompressed_size;
+        let ttl_sender = self.sender.clone();
+        let max_subscriptions = self.opts.read().max_subscriptions;
+        // The sink is registered before SUB goes out, messages arriving before the stream is
+        // first polled are buffered for it.
+        let registered = self.receiver.read().for_sid(cmd.clone(), deliver_errors, max_subscriptions);
+        let registered = registered.and_then(|stream| {
+            if send_sub {
+                if let Err(err) = self.sender.read().send(Op::SUB(cmd)) {
+                    self.receiver.read().remove_sid(&sid);
+                    return Err(err);
+                }
+            }
+            Ok(stream)
+        });
+        future::ready(registered).map_ok(move |stream| {
+            let stale_target = log_target.clone();
+            let delete_target = log_target.clone();
+            if let Some(ttl) = ttl {
+                subscription::unsubscribe_after(sid.clone(), ttl, &clock, ttl_sender, receiver.clone());
+            }
+            let receive_target = log_target.clone();
+            // Counted before any message is dropped here, the server counts them all towards `max_msgs`.
+            let stream = stream.map(move |item| {
+                if item.is_err() {
+                    return item;
+                }
                 let lock = subs_receiver.read();
                 let mut stx = lock.subs_map.write();
-                let mut delete = None;
+                let mut delete = false;
 
                 if let Some(s) = stx.get_mut(&sid) {
                     if let Some(max_count) = s.max_count {
                         s.count += 1;
-                        if s.count >= max_count {
-                            delete = Some(max_count);
-                        }
+                        delete = s.count >= max_count;
                     }
                 }
 
-                if let Some(count) = delete.take() {
-                    if stx.remove(&sid).is_some() {
-                        debug!(target: "ratsio", "Deleting subscription for {}", &sid);
+                // Dropping the sink ends the stream once the buffered messages are consumed.
+                if delete && stx.remove(&sid).is_some() {
+                    debug!(target: delete_target.as_str(), "Deleting subscription for {}", &sid);
+                }
+                item
+            }).filter_map(move |item| {
+                let mut msg = match item {
+                    Ok(msg) => msg,
+                    Err(err) => return future::ready(Some(Err(err))),
+                };
+                #[cfg(feature = "jwt")]
+                {
+                    if let Err(reason) = signing.read().verify(&mut msg) {
+                        warn!(target: receive_target.as_str(), "Dropping unverified message on {} => {}", &msg.subject, reason);
+                        return future::ready(None);
+                    }
+                }
+                match super::compression::decompress(&mut msg, max_decompressed_size) {
+                    Ok(()) => future::ready(Some(Ok(msg))),
+                    Err(err) => {
+                        error!(target: receive_target.as_str(), "Dropping message on {} => {}", &msg.subject, err);
+                        future::ready(None)
                     }
-                    return Err(RatsioError::SubscriptionReachedMaxMsgs(count));
                 }
-                Ok(msg)
+            }).filter(move |item| {
+                let msg = match item {
+                    Ok(msg) => msg,
+                    Err(_) => return future::ready(true),
+                };
+                let valid = match validators.read().validate_receive(&msg.subject, &msg.payload) {
+                    Ok(_) => true,
+                    Err(err) => {
+                        error!(target: log_target.as_str(), "Dropping message => {}", err);
+                        false
+                    }
+                };
+                future::ready(valid)
+            }).filter(move |item| {
+                let msg = match item {
+                    Ok(msg) => msg,
+                    Err(_) => return future::ready(true),
+                };
+                let stale = match (max_age, msg.sent_at()) {
+                    (Some(max_age), Some(sent_at)) => sent_at
+                        .elapsed()
+                        .map(|age| age > max_age)
+                        .unwrap_or(false),
+                    _ => false,
+                };
+                if stale {
+                    debug!(target: stale_target.as_str(), "Dropping stale message on {}", &msg.subject);
+                }
+                future::ready(!stale)
             });
 
-            future::ok(stream)
+            stream
+        })
+    }
+
+    /// Subscribes to each of `subjects` and merges the subscriptions into a single stream of
+    /// `(subscribed subject, message)` pairs.
+    ///
+    /// Returns `impl Future<Output = Result<MultiSubscription, RatsioError>>`
+    pub fn subscribe_many<S: AsRef<str>>(
+        &self,
+        subjects: &[S],
+    ) -> impl Future<Output = Result<MultiSubscription, RatsioError>> + Send + Sync {
+        let cmds: Vec<Subscribe> = subjects.iter()
+            .map(|subject| Subscribe {
+                subject: subject.as_ref().to_string(),
+                ..Subscribe::default()
+            })
+            .collect();
+        let sids = cmds.iter().map(|cmd| cmd.sid.clone()).collect::<Vec<_>>();
+        let subs_futures = cmds.into_iter()
+            .map(|cmd| {
+                let subject = cmd.subject.clone();
+                self.subscribe(cmd).map_ok(move |stream| {
+                    let tagged: super::TaggedStream = Box::pin(stream.map(move |msg| (subject.clone(), msg)));
+                    tagged
+                })
+            })
+            .collect::<Vec<_>>();
+        let sender = self.sender.clone();
+        let receiver = self.receiver.clone();
+        future::try_join_all(subs_futures).map_ok(move |streams| MultiSubscription {
+            sids,
+            stream: stream::select_all(streams),
+            sender,
+            receiver,
         })
     }
 
+    /// Unsubscribes all `sids`, e.g. those of a `MultiSubscription`, and de-registers their streams.
+    pub fn unsubscribe_many(&self, sids: &[String]) {
+        for sid in sids {
+            let _ = self.sender.read().send(Op::UNSUB(UnSubscribe {
+                sid: sid.clone(),
+                max_msgs: None,
+            }));
+            self.receiver.read().remove_sid(sid);
+        }
+    }
+
     /// Performs a request to the server following the Request/Reply pattern. 
     /// Returns a future containing the MSG that will be replied at some point by a third party    
     pub fn request(
@@ -468,11 +1799,120 @@ impl NatsClient {
         subject: String,
         payload: &[u8],
     ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
-        if let Some(ref server_info) = *self.server_info.read() {
-            if payload.len() > server_info.max_payload {
-                return Either::Left(future::err(RatsioError::MaxPayloadOverflow(
-                    server_info.max_payload,
-                )));
+        self.request_with_headers(subject, payload, None)
+    }
+
+    /// Performs a request like `request`, but fails with `RatsioError::RequestTimeout` if no reply
+    /// arrives within `timeout`. With `propagate_deadlines` set, the deadline is sent along in a
+    /// `Nats-Deadline` header, see `Message::deadline`.
+    pub fn request_timeout(
+        &self,
+        subject: String,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
+        self.request_within(subject, payload, timeout, false)
+    }
+
+    /// Performs a request like `request_timeout`, sending it again after a reconnect until a reply
+    /// arrived, so a connection lost between the request and its reply does not end in a timeout.
+    /// Only for idempotent requests: the responder may have handled the request already, its
+    /// reply went to the lost connection.
+    pub fn request_idempotent(
+        &self,
+        subject: String,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
+        self.request_within(subject, payload, timeout, true)
+    }
+
+    fn request_within(
+        &self,
+        subject: String,
+        payload: &[u8],
+        timeout: Duration,
+        resume: bool,
+    ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
+        let headers = if self.opts.read().propagate_deadlines && self.supports_headers() {
+            let mut headers = Headers::new();
+            headers.set_deadline(SystemTime::now() + timeout);
+            Some(headers)
+        } else {
+            None
+        };
+        // Replies are recorded by `send_request`, timeouts only show here.
+        let circuit = self.request_circuit(&subject);
+        let clock = self.opts.read().clock.clone();
+        clock.timeout(timeout, self.send_request(subject, payload, headers, None, resume))
+            .map(move |result| match result {
+                Some(reply) => reply,
+                None => {
+                    let result = Err(RatsioError::RequestTimeout);
+                    if let Some((ref options, ref breaker, ref subject)) = circuit {
+                        breaker.record(options, subject, &result);
+                    }
+                    result
+                }
+            })
+    }
+
+    /// Performs a request like `request`, sending `headers` along with the payload.
+    pub fn request_with_headers(
+        &self,
+        subject: String,
+        payload: &[u8],
+        headers: Option<Headers>,
+    ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
+        self.send_request(subject, payload, headers, None, false)
+    }
+
+    /// Performs a request like `request`, sending the request a second time if no reply arrived
+    /// within `hedge_after`. The first reply is returned, the inbox only takes one message so the
+    /// server drops the other reply.
+    pub fn request_hedged(
+        &self,
+        subject: String,
+        payload: &[u8],
+        hedge_after: Duration,
+    ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
+        self.send_request(subject, payload, None, Some(hedge_after), false)
+    }
+
+    fn send_request(
+        &self,
+        subject: String,
+        payload: &[u8],
+        headers: Option<Headers>,
+        hedge_after: Option<Duration>,
+        resume: bool,
+    ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
+        // Checked before the inbox is subscribed.
+        if let Err(err) = crate::subject::validate(&subject) {
+            return Either::Left(future::err(err));
+        }
+        if let Err(err) = self.validators.read().validate_publish(&subject, payload) {
+            return Either::Left(future::err(err));
+        }
+        let circuit = self.request_circuit(&subject);
+        if let Some((_, ref breaker, ref subject)) = circuit {
+            if let Err(err) = breaker.check(subject) {
+                return Either::Left(future::err(err));
+            }
+        }
+
+        let headers_supported = self.supports_headers();
+        if !headers_supported && headers.as_ref().map_or(false, |headers| !headers.is_empty()) {
+            return Either::Left(future::err(RatsioError::UnsupportedByServer("headers".into())));
+        }
+        let mut headers = headers;
+        if headers_supported && self.opts.read().stamp_sent_at {
+            headers.get_or_insert_with(Headers::new).set_sent_at(crate::clock::monotonic_wall_time());
+        }
+        #[cfg(feature = "jwt")]
+        {
+            if let Err(err) = self.signing.read().sign(&subject, payload, headers_supported, &mut headers) {
+                return Either::Left(future::err(err));
             }
         }
 
@@ -481,48 +1921,293 @@ impl NatsClient {
             subject,
             payload: Vec::from(&payload[..]),
             reply_to: Some(inbox.clone()),
+            headers,
         };
+        if let Err(err) = self.check_max_payload(&pub_cmd) {
+            return Either::Left(future::err(err));
+        }
+        // Counted last, a request failing the checks above does not take up a subject.
+        if let Err(err) = self.count_publish_subject(&pub_cmd.subject) {
+            return Either::Left(future::err(err));
+        }
 
         let sub_cmd = Subscribe {
-            queue_group: None,
-            sid: Subscribe::generate_sid(),
             subject: inbox,
+            // The inbox must be back for a request sent again after a reconnect.
+            resubscribe: Some(true).filter(|_| resume),
+            ..Subscribe::default()
         };
 
         let sid = sub_cmd.sid.clone();
+        let hedge_sid = sid.clone();
+        let expiry_sid = sid.clone();
 
         let unsub_cmd = UnSubscribe {
             sid: sub_cmd.sid.clone(),
             max_msgs: Some(1),
         };
 
-        let unsub_sender = self.sender.clone();
-        let pub_sender = self.sender.clone();
+        let resume_guard = if resume {
+            Some(self.resumable_requests.track(sid.clone(), pub_cmd.clone()))
+        } else {
+            None
+        };
         let receiver = self.receiver.clone();
-        let stream = self
-            .receiver
-            .read()
-            .for_sid(sub_cmd.clone())
+        // Request inboxes are not counted against `max_subscriptions`.
+        let stream = match self.receiver.read().for_sid(sub_cmd.clone(), false, 0) {
+            Ok(stream) => stream,
+            Err(err) => return Either::Left(future::err(err)),
+        };
+        let stream = stream
+            .filter_map(|item| future::ready(item.ok()))
             .take(1)
             .into_future()
-            .map_err(|(e, _)| e)
-            .and_then(move |(message, _)| {
-                match message {
+            .map(move |(message, _)| {
+                drop(resume_guard);
+                let result = match message {
                     Some(m) => {
                         receiver.read().remove_sid(&sid);
-                        Ok(m)
+                        let no_responders = m.headers.as_ref()
+                            .map_or(false, |headers| headers.status() == Some(503));
+                        if no_responders {
+                            Err(RatsioError::NoResponders)
+                        } else if let Some(err) = m.service_error() {
+                            Err(RatsioError::ServiceError(err))
+                        } else {
+                            Ok(m)
+                        }
                     },
                     None => Err(RatsioError::InnerBrokenChain)
+                };
+                if let Some((ref options, ref breaker, ref subject)) = circuit {
+                    breaker.record(options, subject, &result);
+                }
+                result
+            });
+
+        let hedge_cmd = hedge_after.map(|hedge_after| (hedge_after, pub_cmd.clone()));
+        let sent = {
+            let sender = self.sender.read();
+            sender.send(Op::SUB(sub_cmd))
+                .and_then(|_| sender.send(Op::UNSUB(unsub_cmd)))
+                .and_then(|_| sender.send(Op::PUB(pub_cmd)))
+        };
+        match sent {
+            Err(_) => self.receiver.read().remove_sid(&expiry_sid),
+            Ok(_) => self.expire_request_inbox(expiry_sid),
+        }
+        if let (Ok(_), Some((hedge_after, hedge_cmd))) = (&sent, hedge_cmd) {
+            let sender = self.sender.clone();
+            let receiver = self.receiver.clone();
+            let log_target = self.log_target().to_string();
+            let hedge_delay = self.opts.read().clock.delay(hedge_after);
+            tokio::spawn(async move {
+                hedge_delay.await;
+                // The sid is removed once the reply arrived.
+                let pending = receiver.read().subs_map.read().contains_key(&hedge_sid);
+                if pending {
+                    debug!(target: log_target.as_str(), "No reply on {} after {:?}, hedging", &hedge_sid, hedge_after);
+                    let _ = sender.read().send(Op::PUB(hedge_cmd));
                 }
             });
+        }
+        Either::Right(future::ready(sent).and_then(move |_| stream))
+    }
+
+    /// What the outcome of a request to `subject` is recorded with, `None` without `circuit_breaker`.
+    fn request_circuit(&self, subject: &str) -> Option<(CircuitBreakerOptions, Arc<CircuitBreaker>, String)> {
+        self.opts.read().circuit_breaker
+            .map(|options| (options, self.circuit_breaker.clone(), subject.to_string()))
+    }
+
+    /// Removes the inbox subscription `sid` of a request once `request_inbox_ttl` passed without a
+    /// reply, e.g. because the caller dropped the request future.
+    fn expire_request_inbox(&self, sid: String) {
+        let (ttl, clock) = {
+            let opts = self.opts.read();
+            (opts.request_inbox_ttl, opts.clock.clone())
+        };
+        if ttl == 0 {
+            return;
+        }
+        let sender = self.sender.clone();
+        let receiver = self.receiver.clone();
+        let log_target = self.log_target().to_string();
+        tokio::spawn(async move {
+            clock.delay(Duration::from_millis(ttl)).await;
+            let receiver = receiver.read();
+            // The sid is removed once the reply arrived.
+            if receiver.subs_map.read().contains_key(&sid) {
+                debug!(target: log_target.as_str(), "No reply on {} after {}ms, removing the inbox", &sid, ttl);
+                let _ = sender.read().send(Op::UNSUB(UnSubscribe { sid: sid.clone(), max_msgs: None }));
+                receiver.remove_sid(&sid);
+            }
+        });
+    }
+
+    /// Subscribes to `subject`, in `queue_group` if given, and answers every request with the
+    /// payload produced by `handler`, at `Priority::High`. Requests whose `Message::deadline` has
+    /// already passed are skipped, replies that are ready only after the deadline are not sent.
+    /// A handler failing with `RatsioError::ServiceError` answers with the error in the
+    /// `Nats-Service-Error` headers, other failures are only logged.
+    ///
+    /// Returns the sid of the service subscription.
+    pub fn serve<F, Fut>(
+        &self,
+        subject: String,
+        queue_group: Option<String>,
+        handler: F,
+    ) -> impl Future<Output = Result<String, RatsioError>> + Send + Sync
+    where
+        F: Fn(Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<u8>, RatsioError>> + Send + 'static,
+    {
+        let cmd = Subscribe {
+            subject,
+            queue_group,
+            ..Subscribe::default()
+        };
+        let sid = cmd.sid.clone();
+        let sender = self.sender.clone();
+        let handler = Arc::new(handler);
+        let in_flight = self.in_flight.clone();
+        let log_target = self.log_target().to_string();
+        self.subscribe(cmd).map_ok(move |stream| {
+            tokio::spawn(stream.for_each(move |msg| {
+                let expired = |deadline: Option<SystemTime>| {
+                    deadline.map(|d| d <= SystemTime::now()).unwrap_or(false)
+                };
+                let reply_to = match msg.reply_to.clone() {
+                    Some(reply_to) => reply_to,
+                    None => {
+                        debug!(target: log_target.as_str(), "Ignoring request without reply subject on {}", &msg.subject);
+                        return future::ready(());
+                    }
+                };
+                let deadline = msg.deadline();
+                if expired(deadline) {
+                    debug!(target: log_target.as_str(), "Skipping request on {}, its deadline has passed", &msg.subject);
+                    return future::ready(());
+                }
+                let sender = sender.clone();
+                let log_target = log_target.clone();
+                let running = in_flight.enter();
+                tokio::spawn((*handler)(msg).map(move |result| {
+                    // Done once the reply is queued, see `drain`.
+                    let _running = running;
+                    match result {
+                        Ok(_) | Err(RatsioError::ServiceError(_)) if expired(deadline) => {
+                            debug!(target: log_target.as_str(), "Dropping reply to {}, its deadline has passed", &reply_to);
+                        }
+                        Ok(payload) => {
+                            let reply = Op::PUB(Publish {
+                                subject: reply_to,
+                                reply_to: None,
+                                payload,
+                                headers: None,
+                            });
+                            let sender = sender.read();
+                            let flush = sender.low_latency.load(Ordering::SeqCst);
+                            let _ = sender.send_with_priority(reply, Priority::High, flush);
+                        }
+                        Err(RatsioError::ServiceError(service_error)) => {
+                            debug!(target: log_target.as_str(), "Answering {} with {}", &reply_to, service_error);
+                            let mut headers = Headers::new();
+                            headers.set_service_error(&service_error);
+                            let reply = Op::PUB(Publish {
+                                subject: reply_to,
+                                reply_to: None,
+                                payload: Vec::new(),
+                                headers: Some(headers),
+                            });
+                            let sender = sender.read();
+                            let flush = sender.low_latency.load(Ordering::SeqCst);
+                            let _ = sender.send_with_priority(reply, Priority::High, flush);
+                        }
+                        Err(err) => {
+                            error!(target: log_target.as_str(), "Request handler for {} failed => {}", &reply_to, err);
+                        }
+                    }
+                }));
+                future::ready(())
+            }));
+            sid
+        })
+    }
 
-        Either::Right(
-            self.sender
-                .read()
-                .send(Op::SUB(sub_cmd))
-                .then(move |_| unsub_sender.read().send(Op::UNSUB(unsub_cmd)))
-                .then(move |_| pub_sender.read().send(Op::PUB(pub_cmd)))
-                .then(move |_| stream),
-        )
+    /// Performs a request answered with a stream of replies, such as a server-streaming RPC
+    /// served with `serve_stream`. The responder is granted `STREAM_CREDIT` replies up front and
+    /// more as the stream is consumed, so a slow caller holds the responder back instead of
+    /// piling up replies in its subscription.
+    pub fn stream_request(
+        self: &Arc<Self>,
+        subject: String,
+        payload: &[u8],
+    ) -> impl Future<Output = Result<ReplyStream, RatsioError>> + Send {
+        self.stream_request_with_credit(subject, payload, STREAM_CREDIT)
+    }
+
+    /// Performs a request like `stream_request`, granting the responder `credit` replies at a
+    /// time instead of `STREAM_CREDIT`.
+    pub fn stream_request_with_credit(
+        self: &Arc<Self>,
+        subject: String,
+        payload: &[u8],
+        credit: u32,
+    ) -> impl Future<Output = Result<ReplyStream, RatsioError>> + Send {
+        ReplyStream::request(self.clone(), subject, payload, credit)
+    }
+
+    /// Answers the `stream_request`s on `subject` with the replies of the stream `handler` returns
+    /// for each, in `queue_group` if given. The responder sends no more replies than the caller
+    /// granted, stops once the caller cancels and gives up on callers that granted no credit for
+    /// a while. A failing stream ends with its error, as `ServiceError` unless it is one already.
+    ///
+    /// Returns the sid of the service subscription.
+    pub fn serve_stream<F, S>(
+        self: &Arc<Self>,
+        subject: String,
+        queue_group: Option<String>,
+        handler: F,
+    ) -> impl Future<Output = Result<String, RatsioError>> + Send
+    where
+        F: Fn(Message) -> S + Send + Sync + 'static,
+        S: Stream<Item = Result<Vec<u8>, RatsioError>> + Send + 'static,
+    {
+        stream_rpc::serve(self.clone(), subject, queue_group, handler)
+    }
+
+    /// Runs an auth callout service: answers the authorization requests of the server on
+    /// `AUTH_CALLOUT_SUBJECT` with the user `authorize` grants, or the reason it refuses, signed
+    /// by `callout`. Requests that can't be decoded or verified are not answered.
+    ///
+    /// Returns the sid of the service subscription.
+    #[cfg(feature = "jwt")]
+    pub fn serve_auth_callout<F, Fut>(
+        &self,
+        callout: AuthCallout,
+        queue_group: Option<String>,
+        authorize: F,
+    ) -> impl Future<Output = Result<String, RatsioError>> + Send + Sync
+    where
+        F: Fn(AuthorizationRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<AuthorizedUser, String>> + Send + 'static,
+    {
+        let callout = Arc::new(callout);
+        self.serve(AUTH_CALLOUT_SUBJECT.into(), queue_group, move |msg: Message| {
+            let request = msg.as_str()
+                .map_err(|err| RatsioError::JwtError(err.to_string()))
+                .and_then(AuthorizationRequest::from_jwt);
+            match request {
+                Ok(request) => {
+                    let callout = callout.clone();
+                    let authorized = authorize(request.clone());
+                    async move {
+                        callout.respond(&request, authorized.await).map(String::into_bytes)
+                    }.boxed()
+                }
+                Err(err) => future::err(err).boxed(),
+            }
+        })
     }
 }
@@ -0,0 +1,185 @@
+use crate::error::RatsioError;
+use crate::headers::{Headers, CONTENT_ENCODING};
+use crate::ops::Message;
+
+/// How payloads published to subjects matching a pattern are compressed, see
+/// `NatsClient::add_compression_policy`. Needs the `compression` feature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// zstd at `level`, 1 (fastest) to 22, 3 is zstd's default
+    Zstd { level: i32 },
+    Snappy,
+    /// Never compressed, e.g. for latency sensitive subjects under a broader compressed pattern
+    Never,
+}
+
+impl Compression {
+    /// The `Content-Encoding` of payloads compressed this way.
+    pub fn encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::Zstd { .. } => Some(ZSTD),
+            Compression::Snappy => Some(SNAPPY),
+            Compression::Never => None,
+        }
+    }
+}
+
+const ZSTD: &str = "zstd";
+const SNAPPY: &str = "snappy";
+
+/// Default of `NatsClientOptions::max_decompressed_size`.
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug)]
+struct CompressionPolicy {
+    pattern: String,
+    compression: Compression,
+    /// Smaller payloads are sent as they are
+    min_size: usize,
+}
+
+/// Compression policies registered on a client, the first one matching a subject applies.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CompressionPolicies {
+    policies: Vec<CompressionPolicy>,
+}
+
+impl CompressionPolicies {
+    pub(crate) fn add(&mut self, pattern: String, compression: Compression, min_size: usize) {
+        self.policies.push(CompressionPolicy { pattern, compression, min_size });
+    }
+
+    pub(crate) fn remove(&mut self, pattern: &str) {
+        self.policies.retain(|policy| policy.pattern != pattern);
+    }
+
+    /// Compresses `payload` if the policy for `subject` asks for it, marking it in `headers`.
+    pub(crate) fn compress(
+        &self,
+        subject: &str,
+        payload: &mut Vec<u8>,
+        headers: &mut Option<Headers>,
+    ) -> Result<(), RatsioError> {
+        let policy = match self.policies.iter().find(|policy| crate::subject::matches(&policy.pattern, subject)) {
+            Some(policy) if payload.len() >= policy.min_size => policy,
+            _ => return Ok(()),
+        };
+        let encoding = match policy.compression.encoding() {
+            Some(encoding) => encoding,
+            None => return Ok(()),
+        };
+        *payload = encode(policy.compression, payload)?;
+        headers.get_or_insert_with(Headers::new).insert(CONTENT_ENCODING, encoding);
+        Ok(())
+    }
+}
+
+/// Decompresses the payload of `msg` if it has a `Content-Encoding` header, which is removed.
+/// Messages without one, or with an encoding this build can't decode, are left as they are.
+/// Fails if the payload would decompress to more than `max_size` bytes.
+pub(crate) fn decompress(msg: &mut Message, max_size: usize) -> Result<(), RatsioError> {
+    let encoding = match msg.headers.as_ref().and_then(|headers| headers.get(CONTENT_ENCODING)) {
+        Some(encoding) if decodable(encoding) => encoding.to_string(),
+        _ => return Ok(()),
+    };
+    msg.payload = decode(&encoding, &msg.payload, max_size)?;
+    if let Some(ref mut headers) = msg.headers {
+        headers.remove(CONTENT_ENCODING);
+    }
+    Ok(())
+}
+
+fn decodable(encoding: &str) -> bool {
+    cfg!(feature = "compression") && (encoding == ZSTD || encoding == SNAPPY)
+}
+
+#[cfg(feature = "compression")]
+fn too_large(max_size: usize) -> RatsioError {
+    RatsioError::GenericError(format!("Decompressed payload exceeds {} bytes", max_size))
+}
+
+#[cfg(feature = "compression")]
+fn encode(compression: Compression, payload: &[u8]) -> Result<Vec<u8>, RatsioError> {
+    match compression {
+        Compression::Zstd { level } => Ok(zstd::encode_all(payload, level)?),
+        Compression::Snappy => snap::raw::Encoder::new()
+            .compress_vec(payload)
+            .map_err(|err| RatsioError::GenericError(format!("snappy => {}", err))),
+        Compression::Never => Ok(payload.to_vec()),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn encode(_compression: Compression, _payload: &[u8]) -> Result<Vec<u8>, RatsioError> {
+    Err(RatsioError::FeatureNotEnabled("compression".into()))
+}
+
+#[cfg(feature = "compression")]
+fn decode(encoding: &str, payload: &[u8], max_size: usize) -> Result<Vec<u8>, RatsioError> {
+    use std::io::Read;
+
+    match encoding {
+        ZSTD => {
+            let mut decoded = Vec::new();
+            zstd::stream::read::Decoder::new(payload)?
+                .take(max_size as u64 + 1)
+                .read_to_end(&mut decoded)?;
+            if decoded.len() > max_size {
+                return Err(too_large(max_size));
+            }
+            Ok(decoded)
+        }
+        SNAPPY => {
+            let snappy_error = |err: snap::Error| RatsioError::GenericError(format!("snappy => {}", err));
+            if snap::raw::decompress_len(payload).map_err(snappy_error)? > max_size {
+                return Err(too_large(max_size));
+            }
+            snap::raw::Decoder::new().decompress_vec(payload).map_err(snappy_error)
+        }
+        _ => Err(RatsioError::GenericError(format!("Unsupported content encoding => {}", encoding))),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn decode(_encoding: &str, _payload: &[u8], _max_size: usize) -> Result<Vec<u8>, RatsioError> {
+    Err(RatsioError::FeatureNotEnabled("compression".into()))
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compresses_by_subject() {
+    let mut policies = CompressionPolicies::default();
+    policies.add("md.>".into(), Compression::Never, 0);
+    policies.add(">".into(), Compression::Zstd { level: 3 }, 16);
+    let plain = b"log line, log line, log line, log line".to_vec();
+
+    let (mut payload, mut headers) = (plain.clone(), None);
+    policies.compress("md.quotes", &mut payload, &mut headers).unwrap();
+    assert_eq!((payload, headers), (plain.clone(), None));
+
+    let (mut payload, mut headers) = (b"short".to_vec(), None);
+    policies.compress("logs.app", &mut payload, &mut headers).unwrap();
+    assert_eq!(headers, None);
+
+    let (mut payload, mut headers) = (plain.clone(), None);
+    policies.compress("logs.app", &mut payload, &mut headers).unwrap();
+    assert_ne!(payload, plain);
+    let mut msg = Message { subject: "logs.app".into(), payload, headers, ..Message::default() };
+    let compressed = msg.clone();
+    decompress(&mut msg, MAX_DECOMPRESSED_SIZE).unwrap();
+    assert_eq!(msg.payload, plain);
+    assert_eq!(msg.headers.unwrap().get(CONTENT_ENCODING), None);
+
+    let mut bomb = compressed;
+    assert!(decompress(&mut bomb, plain.len() - 1).is_err(), "decompressed size not bounded");
+}
+
+#[test]
+fn passes_unknown_encodings_through() {
+    let mut headers = Headers::new();
+    headers.insert(CONTENT_ENCODING, "gzip");
+    let mut msg = Message { payload: b"gzipped".to_vec(), headers: Some(headers), ..Message::default() };
+    decompress(&mut msg, MAX_DECOMPRESSED_SIZE).unwrap();
+    assert_eq!(msg.payload, b"gzipped".to_vec());
+    assert_eq!(msg.headers.unwrap().get(CONTENT_ENCODING), Some("gzip"));
+}
@@ -0,0 +1,124 @@
+use crate::ops::Op;
+use super::TopologyChange;
+use futures::channel::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// Events about the lifecycle of a client, see `NatsClient::events`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    /// An internal task ended unexpectedly. If it was `restarted` the connection was
+    /// re-established, otherwise the client is closed.
+    TaskFailed {
+        task: String,
+        reason: String,
+        restarted: bool,
+    },
+    /// An INFO update changed whether the server requires authentication. When it is
+    /// required now, CONNECT is sent again with the configured credentials.
+    AuthRequiredChanged { auth_required: bool },
+    /// The server sent an op that could not be parsed, `line` is the offending line. The
+    /// connection is re-established, since the rest of the stream can't be trusted.
+    ProtocolError { line: String },
+    /// The client is connected, CONNECT was sent in answer to the INFO of a new connection
+    /// and, after a reconnect, the subscriptions were restored.
+    Connected,
+    /// The client is closed, no messages are sent or delivered anymore.
+    Closed,
+    /// An INFO changed the cluster members, or the client is connected to another server now.
+    TopologyChanged(TopologyChange),
+}
+
+/// Fans out `ClientEvent`s to every stream handed out by `NatsClient::events`.
+#[derive(Clone, Debug)]
+pub(crate) struct EventEmitter {
+    listeners: Arc<RwLock<Vec<UnboundedSender<ClientEvent>>>>,
+    log_target: String,
+}
+
+impl EventEmitter {
+    pub(crate) fn new(log_target: String) -> Self {
+        EventEmitter {
+            listeners: Arc::new(RwLock::new(Vec::new())),
+            log_target,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> UnboundedReceiver<ClientEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.listeners.write().push(tx);
+        rx
+    }
+
+    /// Listeners whose stream was dropped are removed.
+    pub(crate) fn emit(&self, event: ClientEvent) {
+        debug!(target: self.log_target.as_str(), "Client event {:?}", event);
+        self.listeners
+            .write()
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+/// Copies of the non-MSG ops received from the server, see `NatsClient::raw_ops`.
+/// Listeners that fall behind miss ops instead of holding up the client.
+#[derive(Clone, Debug)]
+pub(crate) struct RawOpsTap {
+    listeners: Arc<RwLock<Vec<Sender<Op>>>>,
+    log_target: String,
+}
+
+impl RawOpsTap {
+    pub(crate) fn new(log_target: String) -> Self {
+        RawOpsTap {
+            listeners: Arc::new(RwLock::new(Vec::new())),
+            log_target,
+        }
+    }
+
+    pub(crate) fn subscribe(&self, buffer: usize) -> Receiver<Op> {
+        let (tx, rx) = mpsc::channel(buffer);
+        self.listeners.write().push(tx);
+        rx
+    }
+
+    pub(crate) fn emit(&self, op: &Op) {
+        if self.listeners.read().is_empty() {
+            return;
+        }
+        let mut listeners = self.listeners.write();
+        for tx in listeners.iter_mut() {
+            if let Err(err) = tx.try_send(op.clone()) {
+                if err.is_full() {
+                    trace!(target: self.log_target.as_str(), "Raw ops listener is behind, dropping {:?}", op);
+                }
+            }
+        }
+        listeners.retain(|tx| !tx.is_closed());
+    }
+}
+
+#[test]
+fn emits_to_live_listeners() {
+    use futures::StreamExt;
+
+    let emitter = EventEmitter::new(String::from("ratsio"));
+    let mut rx = emitter.subscribe();
+    drop(emitter.subscribe());
+    emitter.emit(ClientEvent::Closed);
+    assert_eq!(emitter.listeners.read().len(), 1);
+    assert_eq!(futures::executor::block_on(rx.next()), Some(ClientEvent::Closed));
+}
+
+#[test]
+fn raw_ops_drop_when_behind() {
+    use futures::StreamExt;
+
+    let tap = RawOpsTap::new(String::from("ratsio"));
+    let mut rx = tap.subscribe(0);
+    tap.emit(&Op::PING);
+    tap.emit(&Op::PONG);
+    drop(tap.subscribe(1));
+    tap.emit(&Op::OK);
+    assert_eq!(tap.listeners.read().len(), 1);
+    assert_eq!(futures::executor::block_on(rx.next()), Some(Op::PING));
+}
@@ -0,0 +1,82 @@
+use crate::error::RatsioError;
+use crate::ops::Op;
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+
+use super::NatsClientSender;
+
+#[derive(Debug, Default)]
+struct FenceState {
+    /// PINGs sent on the current connection
+    sent: u64,
+    /// PONGs received on the current connection
+    received: u64,
+    /// Waiters with the number of the PING whose PONG they wait for
+    waiters: VecDeque<(u64, oneshot::Sender<()>)>,
+}
+
+/// Counts the PINGs sent by the client and the PONGs answering them. The server handles ops in
+/// order, so once the PONG to a PING arrived every op sent before that PING was processed.
+#[derive(Debug, Default)]
+pub(crate) struct PingFence {
+    state: Mutex<FenceState>,
+}
+
+impl PingFence {
+    /// Sends a PING. All PINGs of the client must be sent through here to be counted.
+    pub(crate) fn ping(&self, sender: &NatsClientSender) -> Result<(), RatsioError> {
+        let mut state = self.state.lock();
+        sender.send(Op::PING)?;
+        state.sent += 1;
+        Ok(())
+    }
+
+    /// Sends a PING, the receiver resolves once it is answered and fails if the connection is
+    /// lost before.
+    pub(crate) fn fence(&self, sender: &NatsClientSender) -> Result<oneshot::Receiver<()>, RatsioError> {
+        let (tx, rx) = oneshot::channel();
+        let mut state = self.state.lock();
+        sender.send(Op::PING)?;
+        state.sent += 1;
+        let ping = state.sent;
+        state.waiters.push_back((ping, tx));
+        Ok(rx)
+    }
+
+    pub(crate) fn pong(&self) {
+        let mut state = self.state.lock();
+        state.received += 1;
+        while state.waiters.front().map_or(false, |(ping, _)| *ping <= state.received) {
+            if let Some((_, tx)) = state.waiters.pop_front() {
+                let _ = tx.send(());
+            }
+        }
+    }
+
+    /// Starts counting anew on a new connection, pending waiters fail.
+    pub(crate) fn reset(&self) {
+        *self.state.lock() = FenceState::default();
+    }
+}
+
+#[test]
+fn pongs_resolve_waiters_in_order() {
+    let fence = PingFence::default();
+    let (tx1, mut rx1) = oneshot::channel();
+    let (tx2, mut rx2) = oneshot::channel();
+    {
+        let mut state = fence.state.lock();
+        state.sent = 3;
+        state.waiters.push_back((2, tx1));
+        state.waiters.push_back((3, tx2));
+    }
+    // The PONG to a PING sent before the fence does not resolve it.
+    fence.pong();
+    assert_eq!(rx1.try_recv(), Ok(None));
+    fence.pong();
+    assert_eq!(rx1.try_recv(), Ok(Some(())));
+    assert_eq!(rx2.try_recv(), Ok(None));
+    fence.reset();
+    assert!(rx2.try_recv().is_err());
+}
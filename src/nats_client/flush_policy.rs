@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+/// When the sender flushes the ops it wrote to the connection, see `NatsClientOptions::flush_policy`.
+/// Ops queued with a flush, e.g. in `low_latency` mode, are flushed right away under every policy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlushPolicy {
+    /// Flush as soon as no more ops are queued
+    Immediate,
+    /// Once no more ops are queued, wait for more until `max_batch` ops are written or `max_delay`
+    /// passed, for fewer and larger writes at the cost of latency
+    Fixed { max_batch: usize, max_delay: Duration },
+    /// Flush as soon as no more ops are queued while the queue stays shallow, and wait for more ops
+    /// like `Fixed` once it backs up, the longer the deeper it was recently
+    Adaptive(AdaptiveFlush),
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy::Immediate
+    }
+}
+
+/// Limits of `FlushPolicy::Adaptive`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveFlush {
+    /// Most ops written with one flush
+    pub max_batch: usize,
+    /// Longest wait for more ops, taken once `max_batch` ops were queued at a time
+    pub max_delay: Duration,
+}
+
+impl Default for AdaptiveFlush {
+    fn default() -> Self {
+        AdaptiveFlush {
+            max_batch: 256,
+            max_delay: Duration::from_millis(2),
+        }
+    }
+}
+
+/// The flush policy of a sender task, with the queue depth it saw recently.
+#[derive(Debug)]
+pub(crate) struct FlushState {
+    policy: FlushPolicy,
+    /// Moving average of the ops found queued at once
+    depth: f64,
+}
+
+impl FlushState {
+    pub(crate) fn new(policy: FlushPolicy) -> Self {
+        FlushState { policy, depth: 0.0 }
+    }
+
+    /// Records that `queued` ops were waiting when the sender got to them.
+    pub(crate) fn queued(&mut self, queued: usize) {
+        self.depth = self.depth * 0.75 + queued as f64 * 0.25;
+    }
+
+    /// How many ops to write in all and how long to wait for them before flushing, once `written`
+    /// ops were written and none are queued anymore. `None` to flush right away.
+    pub(crate) fn linger(&self, written: usize) -> Option<(usize, Duration)> {
+        match self.policy {
+            FlushPolicy::Immediate => None,
+            FlushPolicy::Fixed { max_batch, max_delay } => Some((max_batch, max_delay)).filter(|_| written < max_batch),
+            FlushPolicy::Adaptive(limits) => {
+                if self.depth < 2.0 {
+                    return None;
+                }
+                let target = ((self.depth * 2.0) as usize).min(limits.max_batch);
+                let share = (self.depth / limits.max_batch as f64).min(1.0);
+                Some((target, limits.max_delay.mul_f64(share))).filter(|_| written < target)
+            }
+        }
+    }
+}
+
+#[test]
+fn adaptive_flush_follows_queue_depth() {
+    let limits = AdaptiveFlush { max_batch: 100, max_delay: Duration::from_millis(10) };
+    let mut state = FlushState::new(FlushPolicy::Adaptive(limits));
+    state.queued(1);
+    assert_eq!(state.linger(1), None, "shallow queue flushes right away");
+
+    for _ in 0..20 {
+        state.queued(50);
+    }
+    let (target, delay) = state.linger(50).unwrap();
+    assert_eq!(target, 99);
+    assert!(delay > Duration::from_millis(4) && delay < Duration::from_millis(5));
+    assert_eq!(state.linger(99), None);
+
+    for _ in 0..20 {
+        state.queued(1);
+    }
+    assert_eq!(state.linger(1), None, "drained queue flushes right away again");
+
+    let fixed = FlushState::new(FlushPolicy::Fixed { max_batch: 10, max_delay: Duration::from_millis(1) });
+    assert_eq!(fixed.linger(1), Some((10, Duration::from_millis(1))));
+    assert_eq!(fixed.linger(10), None);
+}
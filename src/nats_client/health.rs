@@ -0,0 +1,59 @@
+use std::{collections::BTreeMap, time::Duration};
+
+/// Sid of the subscription answering health checks, see `NatsClientOptions::health_subject`.
+pub(crate) const HEALTH_SID: &str = "ratsio-health";
+
+/// Longest wait for the PONG timing the round trip of a health check.
+pub(crate) const RTT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The state of a client, sent as JSON to health checks on `NatsClientOptions::health_subject`
+/// and returned by `NatsClient::health_report`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// `NatsClientOptions::name`
+    pub name: String,
+    /// `Connected`, `Reconnecting`...
+    pub state: String,
+    pub uptime_ms: u64,
+    pub server_id: Option<String>,
+    /// Round trip time of a PING to the server, `None` if it was not answered within a second
+    pub rtt_ms: Option<f64>,
+    /// Connections made since the first one
+    pub reconnects: u64,
+    pub subscriptions: usize,
+    /// Messages and payload bytes published, see `NatsClient::stats_by_subject`
+    pub published_msgs: u64,
+    pub published_bytes: u64,
+    /// See `NatsClient::dropped_ops`
+    pub dropped_ops: usize,
+    /// Whether each internal task is running, by name
+    pub tasks: BTreeMap<String, bool>,
+}
+
+impl HealthReport {
+    /// Whether the client is connected and its tasks are running.
+    pub fn is_healthy(&self) -> bool {
+        self.state == "Connected" && self.rtt_ms.is_some() && self.tasks.values().all(|alive| *alive)
+    }
+}
+
+#[test]
+fn health_report_round_trips_as_json() {
+    let report = HealthReport {
+        name: "billing".into(),
+        state: "Connected".into(),
+        uptime_ms: 60_000,
+        server_id: Some("N1".into()),
+        rtt_ms: Some(0.4),
+        reconnects: 1,
+        subscriptions: 3,
+        published_msgs: 10,
+        published_bytes: 420,
+        dropped_ops: 0,
+        tasks: vec![("sender".to_string(), true)].into_iter().collect(),
+    };
+    let json = serde_json::to_string(&report).unwrap();
+    assert_eq!(serde_json::from_str::<HealthReport>(&json).unwrap(), report);
+    assert!(report.is_healthy());
+    assert!(!HealthReport { rtt_ms: None, ..report }.is_healthy());
+}
@@ -0,0 +1,71 @@
+use futures::{channel::oneshot, Future};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+struct InFlightState {
+    running: usize,
+    idle_waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// Counts the request handlers started by `NatsClient::serve` that did not finish yet, so
+/// `NatsClient::drain` can wait for their replies.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    state: Mutex<InFlightState>,
+}
+
+/// A running handler, it is done once the guard is dropped.
+#[derive(Debug)]
+pub(crate) struct InFlightGuard(Arc<InFlight>);
+
+impl InFlight {
+    pub(crate) fn enter(self: &Arc<Self>) -> InFlightGuard {
+        self.state.lock().running += 1;
+        InFlightGuard(self.clone())
+    }
+
+    /// Resolves once no handler is running.
+    pub(crate) fn idle(&self) -> impl Future<Output = ()> {
+        let mut state = self.state.lock();
+        let waiter = if state.running == 0 {
+            None
+        } else {
+            let (tx, rx) = oneshot::channel();
+            state.idle_waiters.push(tx);
+            Some(rx)
+        };
+        async move {
+            if let Some(rx) = waiter {
+                let _ = rx.await;
+            }
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.running -= 1;
+        if state.running == 0 {
+            for tx in state.idle_waiters.drain(..) {
+                let _ = tx.send(());
+            }
+        }
+    }
+}
+
+#[test]
+fn idle_once_handlers_are_done() {
+    use futures::FutureExt;
+
+    let in_flight = Arc::new(InFlight::default());
+    assert!(in_flight.idle().now_or_never().is_some());
+    let first = in_flight.enter();
+    let second = in_flight.enter();
+    let mut idle = in_flight.idle().boxed();
+    drop(first);
+    assert!((&mut idle).now_or_never().is_none());
+    drop(second);
+    assert!(idle.now_or_never().is_some());
+}
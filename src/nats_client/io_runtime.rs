@@ -0,0 +1,61 @@
+use crate::error::RatsioError;
+use futures::{channel::oneshot, Future};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::thread;
+use tokio::runtime::{Builder, Handle};
+use tokio::task::JoinHandle;
+
+/// A single threaded runtime on a thread of its own, running the IO and timer tasks of a client
+/// with `NatsClientOptions::dedicated_io_runtime`. It stops when the client is closed or dropped.
+#[derive(Debug)]
+pub(crate) struct IoRuntime {
+    handle: Handle,
+    shutdown: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl IoRuntime {
+    pub(crate) fn start(thread_name: String) -> Result<Arc<IoRuntime>, RatsioError> {
+        let mut runtime = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .thread_name(thread_name.as_str())
+            .build()?;
+        let handle = runtime.handle().clone();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        thread::Builder::new().name(thread_name).spawn(move || {
+            let _ = runtime.block_on(shutdown_rx);
+        })?;
+        Ok(Arc::new(IoRuntime {
+            handle,
+            shutdown: Mutex::new(Some(shutdown_tx)),
+        }))
+    }
+
+    /// Spawns `fut` on the runtime, the tasks it spawns in turn run there too.
+    pub(crate) fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle.spawn(fut)
+    }
+
+    /// Runs `f` in the context of the runtime, the tasks it spawns run there.
+    pub(crate) fn enter<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.handle.enter(f)
+    }
+
+    /// Stops the runtime thread, its pending tasks are dropped.
+    pub(crate) fn shutdown(&self) {
+        if let Some(shutdown_tx) = self.shutdown.lock().take() {
+            let _ = shutdown_tx.send(());
+        }
+    }
+}
+
+impl Drop for IoRuntime {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
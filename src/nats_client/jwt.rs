@@ -0,0 +1,91 @@
+use crate::error::RatsioError;
+use std::{fmt::Debug, fs, path::Path, sync::Arc};
+
+/// An alias representing the requirements for the nonce signing callback function
+pub type SignerCallback =
+    Arc<dyn Fn(&[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> + Send + Sync>;
+
+/// An option that indicates client JWT authentication should be used. Takes a callback that
+/// will be used to sign the nonce the server supplies. For security reasons, ensure that
+/// you keep the seed in memory only as long as is necessary. Because of the tokio wrappings
+/// used by this client, the callback must be wrapped in an Arc of the signer callback function type.
+#[derive(Clone)]
+pub struct UserJWT {
+    pub(super) jwt: String,
+    signer: SignerCallback,
+}
+
+impl Debug for UserJWT {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "UserJWT {{ jwt: {}, signer: (func) }}", crate::redact::REDACTED)
+    }
+}
+
+impl PartialEq for UserJWT {
+    fn eq(&self, other: &UserJWT) -> bool {
+        self.jwt == other.jwt
+    }
+}
+
+impl UserJWT {
+    /// Creates a new UserJWT option from an encoded JWT and a callback to be invoked to sign
+    /// the server-provided nonce
+    pub fn new(jwt: String, signer: SignerCallback) -> UserJWT {
+        UserJWT { jwt, signer }
+    }
+
+    /// Reads the user JWT and nkey seed from a `.creds` file as generated by `nsc`.
+    /// The seed is kept in memory to sign the nonces of later (re)connects.
+    pub fn from_creds_file<P: AsRef<Path>>(path: P) -> Result<UserJWT, RatsioError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let creds_error = |what: &str| {
+            RatsioError::ConfigError(format!("{} => {}", path.display(), what))
+        };
+        let jwt = creds_block(&contents, "NATS USER JWT").ok_or_else(|| creds_error("no user JWT"))?;
+        let seed = creds_block(&contents, "USER NKEY SEED").ok_or_else(|| creds_error("no nkey seed"))?;
+        nkeys::KeyPair::from_seed(&seed).map_err(|err| creds_error(&err.to_string()))?;
+
+        let signer: SignerCallback = Arc::new(move |nonce: &[u8]| {
+            let key_pair = nkeys::KeyPair::from_seed(&seed).map_err(|err| err.to_string())?;
+            Ok(key_pair.sign(nonce).map_err(|err| err.to_string())?)
+        });
+        Ok(UserJWT::new(jwt, signer))
+    }
+
+    /// The claims of the JWT, decoded without checking its signature, e.g. to refresh the
+    /// credentials before they expire.
+    pub fn claims(&self) -> Result<crate::jwt::Claims, RatsioError> {
+        crate::jwt::Claims::decode(&self.jwt)
+    }
+
+    /// The `sig` of the CONNECT message for the server supplied `nonce`.
+    pub(crate) fn sign(&self, nonce: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+        let sigbytes = (self.signer)(nonce)?;
+        Ok(data_encoding::BASE64URL_NOPAD.encode(sigbytes.as_slice()))
+    }
+}
+
+/// The first line after the `-----BEGIN <name>-----` marker of a creds file.
+fn creds_block(contents: &str, name: &str) -> Option<String> {
+    let mut lines = contents.lines().map(str::trim);
+    lines.find(|line| line.starts_with("-----BEGIN") && line.contains(name))?;
+    lines.find(|line| !line.is_empty()).map(String::from)
+}
+
+#[test]
+fn reads_creds_file() {
+    let key_pair = nkeys::KeyPair::new_user();
+    let creds = format!(
+        "-----BEGIN NATS USER JWT-----\neyJhbGciOi\n------END NATS USER JWT------\n\n\
+         -----BEGIN USER NKEY SEED-----\n{}\n------END USER NKEY SEED------\n",
+        key_pair.seed().unwrap()
+    );
+    let path = std::env::temp_dir().join(format!("ratsio-{}.creds", crate::nuid::next()));
+    fs::write(&path, creds).unwrap();
+    let user_jwt = UserJWT::from_creds_file(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(user_jwt.jwt, "eyJhbGciOi");
+    let sig = (user_jwt.signer)(b"nonce").unwrap();
+    assert!(key_pair.verify(b"nonce", &sig).is_ok());
+}
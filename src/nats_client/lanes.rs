@@ -0,0 +1,115 @@
+use crate::ops::Op;
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future,
+    task::{Context, Poll},
+    StreamExt,
+};
+
+/// The lane an op is queued in by `NatsClientSender`. High priority ops are written before the
+/// low priority ops queued at the time, so a flood of publishes can't hold back the PONGs the
+/// server waits for. Ops of the same lane keep their order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Low,
+}
+
+impl Priority {
+    /// PUBs and PINGs are low priority, every other op is high priority. PINGs stay in order
+    /// with the publishes, so their PONG still tells that the publishes before were processed.
+    pub fn of(op: &Op) -> Priority {
+        match op {
+            Op::PUB(_) | Op::PING => Priority::Low,
+            _ => Priority::High,
+        }
+    }
+}
+
+/// Ops to send, and whether to flush right after
+pub(crate) type QueuedOp = (Op, bool);
+
+/// Sending halves of the lanes.
+#[derive(Clone, Debug)]
+pub(crate) struct LaneSenders {
+    high: UnboundedSender<QueuedOp>,
+    low: UnboundedSender<QueuedOp>,
+}
+
+impl LaneSenders {
+    pub(crate) fn send(&self, queued: QueuedOp, priority: Priority) -> Result<(), QueuedOp> {
+        let lane = match priority {
+            Priority::High => &self.high,
+            Priority::Low => &self.low,
+        };
+        lane.unbounded_send(queued).map_err(|err| err.into_inner())
+    }
+}
+
+/// Receiving halves of the lanes, read by the sender task.
+#[derive(Debug)]
+pub(crate) struct Lanes {
+    high: UnboundedReceiver<QueuedOp>,
+    low: UnboundedReceiver<QueuedOp>,
+}
+
+pub(crate) fn lanes() -> (LaneSenders, Lanes) {
+    let (high_tx, high_rx) = mpsc::unbounded();
+    let (low_tx, low_rx) = mpsc::unbounded();
+    (LaneSenders { high: high_tx, low: low_tx }, Lanes { high: high_rx, low: low_rx })
+}
+
+impl Lanes {
+    /// The next op, high priority first. `None` once both lanes are closed and empty.
+    pub(crate) async fn next(&mut self) -> Option<QueuedOp> {
+        future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<QueuedOp>> {
+        let high_closed = match self.high.poll_next_unpin(cx) {
+            Poll::Ready(Some(queued)) => return Poll::Ready(Some(queued)),
+            Poll::Ready(None) => true,
+            Poll::Pending => false,
+        };
+        match self.low.poll_next_unpin(cx) {
+            Poll::Ready(Some(queued)) => Poll::Ready(Some(queued)),
+            Poll::Ready(None) if high_closed => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
+    }
+
+    /// The next op already queued, high priority first.
+    pub(crate) fn try_next(&mut self) -> Option<QueuedOp> {
+        if let Ok(Some(queued)) = self.high.try_next() {
+            return Some(queued);
+        }
+        match self.low.try_next() {
+            Ok(Some(queued)) => Some(queued),
+            _ => None,
+        }
+    }
+
+    /// Closes both lanes, the ops queued can still be taken with `try_next`.
+    pub(crate) fn close(&mut self) {
+        self.high.close();
+        self.low.close();
+    }
+}
+
+#[test]
+fn high_priority_ops_go_first() {
+    use crate::ops::Publish;
+    use futures::executor::block_on;
+
+    let (senders, mut lanes) = lanes();
+    let publish = Op::PUB(Publish::builder().subject("bulk".into()).build().unwrap());
+    senders.send((publish.clone(), false), Priority::of(&publish)).unwrap();
+    senders.send((Op::PING, false), Priority::of(&Op::PING)).unwrap();
+    senders.send((Op::PONG, true), Priority::of(&Op::PONG)).unwrap();
+    assert_eq!(block_on(lanes.next()), Some((Op::PONG, true)));
+    assert_eq!(lanes.try_next(), Some((publish, false)));
+    lanes.close();
+    assert_eq!(block_on(lanes.next()), Some((Op::PING, false)));
+    assert_eq!(block_on(lanes.next()), None);
+    assert!(senders.send((Op::PONG, false), Priority::High).is_err());
+}
@@ -0,0 +1,157 @@
+use crate::error::RatsioError;
+use crate::ops::{Message, Publish, Subscribe};
+use crate::subject;
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    future::{self, BoxFuture},
+    prelude::*,
+    stream::BoxStream,
+};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+
+use super::NatsClientApi;
+
+/// The scripted outcome of a request to a `MockNatsClient`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MockReply {
+    /// A reply with the payload
+    Payload(Vec<u8>),
+    /// Fails with `RatsioError::NoResponders`
+    NoResponders,
+    /// Fails with `RatsioError::RequestTimeout`
+    Timeout,
+}
+
+#[derive(Default)]
+struct MockState {
+    published: Vec<Publish>,
+    expected_requests: VecDeque<(String, MockReply)>,
+    requests: Vec<(String, Vec<u8>)>,
+    subscriptions: Vec<(Subscribe, UnboundedSender<Message>)>,
+}
+
+/// A `NatsClientApi` without a server for unit tests. Published messages are recorded and
+/// delivered to the matching subscriptions, requests are answered with the replies scripted by
+/// `expect_request` in order.
+///
+/// ```
+/// use futures::executor::block_on;
+/// use ratsio::nats_client::{MockNatsClient, MockReply, NatsClientApi};
+///
+/// let client = MockNatsClient::new();
+/// client.expect_request("prices.get", MockReply::Payload(b"42".to_vec()));
+/// let reply = block_on(client.request("prices.get".into(), b"AAPL".to_vec())).unwrap();
+/// assert_eq!(reply.payload, b"42");
+/// assert!(client.unmet_expectations().is_empty());
+/// ```
+#[derive(Default)]
+pub struct MockNatsClient {
+    state: Mutex<MockState>,
+}
+
+impl MockNatsClient {
+    pub fn new() -> Self {
+        MockNatsClient::default()
+    }
+
+    /// The next request must go to `subject`, it is answered with `reply`.
+    pub fn expect_request(&self, subject: &str, reply: MockReply) {
+        self.state.lock().expected_requests.push_back((subject.to_string(), reply));
+    }
+
+    /// Delivers `msg` to the subscriptions matching its subject, as if it came from the server.
+    pub fn deliver(&self, msg: Message) {
+        let mut state = self.state.lock();
+        for (cmd, tx) in &state.subscriptions {
+            if subject::matches(&cmd.subject, &msg.subject) {
+                let _ = tx.unbounded_send(Message { sid: cmd.sid.clone(), ..msg.clone() });
+            }
+        }
+        state.subscriptions.retain(|(_, tx)| !tx.is_closed());
+    }
+
+    /// The messages published so far.
+    pub fn published(&self) -> Vec<Publish> {
+        self.state.lock().published.clone()
+    }
+
+    /// Subjects and payloads of the requests made so far.
+    pub fn requests(&self) -> Vec<(String, Vec<u8>)> {
+        self.state.lock().requests.clone()
+    }
+
+    /// Subjects of the expected requests that were not made.
+    pub fn unmet_expectations(&self) -> Vec<String> {
+        self.state.lock().expected_requests.iter().map(|(subject, _)| subject.clone()).collect()
+    }
+}
+
+impl NatsClientApi for MockNatsClient {
+    fn publish(&self, cmd: Publish) -> BoxFuture<'static, Result<(), RatsioError>> {
+        let msg = Message {
+            subject: cmd.subject.clone(),
+            sid: String::new(),
+            reply_to: cmd.reply_to.clone(),
+            payload: cmd.payload.clone(),
+            headers: cmd.headers.clone(),
+        };
+        self.state.lock().published.push(cmd);
+        self.deliver(msg);
+        future::ok(()).boxed()
+    }
+
+    fn subscribe(&self, cmd: Subscribe) -> BoxFuture<'static, Result<BoxStream<'static, Message>, RatsioError>> {
+        let (tx, rx) = mpsc::unbounded();
+        self.state.lock().subscriptions.push((cmd, tx));
+        future::ok(rx.boxed()).boxed()
+    }
+
+    fn request(&self, subject: String, payload: Vec<u8>) -> BoxFuture<'static, Result<Message, RatsioError>> {
+        let mut state = self.state.lock();
+        state.requests.push((subject.clone(), payload));
+        let expected = state.expected_requests.front().map_or(false, |(expected, _)| *expected == subject);
+        let reply = if expected {
+            state.expected_requests.pop_front().map(|(_, reply)| reply)
+        } else {
+            None
+        };
+        let result = match reply {
+            Some(MockReply::Payload(payload)) => Ok(Message {
+                subject: Publish::generate_reply_to(),
+                sid: String::new(),
+                reply_to: None,
+                payload,
+                headers: None,
+            }),
+            Some(MockReply::Timeout) => Err(RatsioError::RequestTimeout),
+            Some(MockReply::NoResponders) | None => Err(RatsioError::NoResponders),
+        };
+        future::ready(result).boxed()
+    }
+
+    fn flush(&self) -> BoxFuture<'static, Result<(), RatsioError>> {
+        future::ok(()).boxed()
+    }
+}
+
+#[test]
+fn mock_delivers_published_messages() {
+    use futures::executor::block_on;
+
+    let client = MockNatsClient::new();
+    let sub = Subscribe::builder().subject("orders.*".into()).build().unwrap();
+    let mut stream = block_on(client.subscribe(sub)).unwrap();
+    let publish = Publish::builder().subject("orders.new".into()).payload(b"1".to_vec()).build().unwrap();
+    block_on(client.publish(publish)).unwrap();
+    block_on(client.publish(Publish::builder().subject("payments.new".into()).build().unwrap())).unwrap();
+    assert_eq!(block_on(stream.next()).map(|msg| msg.payload), Some(b"1".to_vec()));
+    assert_eq!(client.published().len(), 2);
+
+    client.expect_request("prices.get", MockReply::Timeout);
+    match block_on(client.request("quotes.get".into(), Vec::new())) {
+        Err(RatsioError::NoResponders) => {}
+        other => panic!("unexpected request was answered => {:?}", other),
+    }
+    assert_eq!(client.unmet_expectations(), vec![String::from("prices.get")]);
+}
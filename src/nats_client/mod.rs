@@ -1,150 +1,679 @@
-use crate::error::RatsioError;
+use crate::clock::SharedClock;
+use crate::error::{RatsioError, SubscriptionError};
 use crate::net::*;
-use crate::ops::{Connect, Message, Op, ServerInfo, Subscribe};
+use crate::ops::{Connect, Message, Op, ServerFeatures, ServerInfo, Subscribe};
+use crate::redact::{optional_secret, secret};
+pub use crate::net::{
+    AddressFamily, ConnectionEvent, ConnectionEventReason, IdentityCallback, NatsUrl, ServerList, ServerListProvider,
+    TlsIdentityProvider,
+};
 use futures::{
+    future::BoxFuture,
     prelude::*,
     stream,
-    task::Poll,
-    channel::mpsc::{self, UnboundedSender},
-    Future, Stream,
+    task::{Context, Poll},
+    channel::mpsc::{self, UnboundedReceiver},
+    Stream,
 };
-use parking_lot::RwLock;
+use atomic_counter::{AtomicCounter, ConsistentCounter};
+use parking_lot::{Mutex, RwLock};
 use std::fmt::Debug;
-use std::{collections::HashMap, sync::Arc};
+use std::net::SocketAddr;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
+};
 
 type NatsSink = stream::SplitSink<NatsConnSinkStream, Op>;
 type NatsStream = stream::SplitStream<NatsConnSinkStream>;
 
+mod api;
+mod auth;
+#[cfg(feature = "jwt")]
+mod auth_callout;
+mod channel;
+mod circuit_breaker;
 mod client;
+mod compression;
+mod config;
+mod events;
+mod fence;
+mod flush_policy;
+mod health;
+mod in_flight;
+mod io_runtime;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod lanes;
+mod mock;
+mod multi_cluster;
+mod pending;
+mod publish_buffer;
+mod publisher;
+mod read_batch;
+mod resumable;
+mod router;
+#[cfg(feature = "tower")]
+mod service;
+#[cfg(feature = "jwt")]
+mod signing;
+mod stats;
+mod stream_rpc;
+mod subscription;
+mod supervisor;
+mod topology;
+mod validation;
+mod work_queue;
+
+pub use self::api::NatsClientApi;
+pub use self::auth::{Authenticator, ConnectAuthFields, SharedAuthenticator, Token, UserPassword};
+#[cfg(feature = "jwt")]
+pub use self::auth_callout::{AuthCallout, AuthorizationRequest, AuthorizedUser, AUTH_CALLOUT_SUBJECT};
+pub use self::channel::Channel;
+pub use self::circuit_breaker::CircuitBreakerOptions;
+use self::circuit_breaker::CircuitBreaker;
+pub use self::client::RAW_OPS_BUFFER;
+pub use self::compression::{Compression, MAX_DECOMPRESSED_SIZE};
+use self::compression::CompressionPolicies;
+pub use self::events::ClientEvent;
+#[cfg(feature = "jwt")]
+pub use self::jwt::{SignerCallback, UserJWT};
+pub use self::lanes::Priority;
+use self::lanes::LaneSenders;
+use self::events::{EventEmitter, RawOpsTap};
+use self::fence::PingFence;
+pub use self::flush_policy::{AdaptiveFlush, FlushPolicy};
+use self::flush_policy::FlushState;
+pub use self::health::HealthReport;
+use self::health::{HEALTH_SID, RTT_TIMEOUT};
+use self::in_flight::InFlight;
+use self::io_runtime::IoRuntime;
+pub use self::mock::{MockNatsClient, MockReply};
+pub use self::multi_cluster::MultiClusterClient;
+pub use self::pending::SlowConsumerStats;
+use self::pending::{CloseOnDrop, PendingMessages, PendingSender};
+use self::publish_buffer::PublishBuffer;
+pub use self::publisher::{PublisherHandle, PUBLISHER_BATCH};
+use self::read_batch::ReadBatches;
+use self::resumable::ResumableRequests;
+pub use self::read_batch::READ_BATCH_BUDGET;
+pub use self::router::{HeaderMatch, SubjectRouter};
+#[cfg(feature = "tower")]
+pub use self::service::{Request, RequestService};
+#[cfg(feature = "jwt")]
+pub use self::signing::{OnUnverified, SignerKeyResolver, TrustedSigners};
+#[cfg(feature = "jwt")]
+use self::signing::SigningPolicies;
+pub use self::stats::{
+    LatencyHistogram, RuntimeMetrics, SubjectStats, SubscriptionStats, LATENCY_BUCKETS_MS, OTHER_SUBJECTS,
+};
+pub use self::stream_rpc::{ReplyStream, STREAM_CREDIT};
+pub use self::subscription::{BroadcastReceiver, Subscription};
+use self::stats::SubjectStatsRegistry;
+use self::supervisor::{Supervisor, TaskHealth};
+pub use self::topology::{Topology, TopologyChange};
+pub use self::validation::{JsonValidator, PayloadValidator, ValidateOn};
+#[cfg(feature = "protobuf")]
+pub use self::validation::ProtobufValidator;
+use self::validation::ValidatorRegistry;
+pub use self::work_queue::{Job, WorkQueue, WorkQueueCoordinator, WorkQueueOptions, WORK_QUEUE_PREFIX};
 
 #[derive(Clone, Debug)]
 pub struct NatsClientSender {
-    tx: UnboundedSender<Op>,
+    /// Ops to send, and whether to flush right after
+    tx: LaneSenders,
+    stats: Arc<Mutex<SubjectStatsRegistry>>,
+    connection: Arc<NatsConnection>,
+    dropped_ops: Arc<ConsistentCounter>,
+    fail_when_disconnected: bool,
+    /// Flush after every op, see `NatsClientOptions::low_latency`
+    low_latency: Arc<AtomicBool>,
 }
 
 impl NatsClientSender {
-    fn new(sink: NatsSink) -> Self {
-        let (tx, rx) = mpsc::unbounded::<Op>();
-        let work = sink.send_all(&mut rx);
-        tokio::spawn(work);
+    fn new(
+        sink: NatsSink,
+        stats: Arc<Mutex<SubjectStatsRegistry>>,
+        dropped_ops: Arc<ConsistentCounter>,
+        fail_when_disconnected: bool,
+        low_latency: Arc<AtomicBool>,
+        write_timeout: u64,
+        flush_policy: FlushPolicy,
+        supervisor: &Supervisor,
+    ) -> Self {
+        let (tx, mut rx) = lanes::lanes();
+        let task_dropped_ops = dropped_ops.clone();
+        let log_target = supervisor.connection.log_target.clone();
+        let connection = supervisor.connection.clone();
+        let state = supervisor.state.clone();
+        let work = async move {
+            let mut sink = sink;
+            let clock = connection.clock.clone();
+            let write_timeout = Duration::from_millis(write_timeout);
+            let mut flush_state = FlushState::new(flush_policy);
+            let sent: Result<(), RatsioError> = async {
+                while let Some((op, mut flush)) = rx.next().await {
+                    write_within(&clock, write_timeout, sink.feed(op)).await?;
+                    let mut written = 1;
+                    // Ops queued meanwhile are written along and flushed together.
+                    while !flush {
+                        match rx.try_next() {
+                            Some((op, flush_now)) => {
+                                write_within(&clock, write_timeout, sink.feed(op)).await?;
+                                written += 1;
+                                flush = flush_now;
+                            }
+                            None => break,
+                        }
+                    }
+                    flush_state.queued(written);
+                    if let Some((target, delay)) = flush_state.linger(written).filter(|_| !flush) {
+                        let deadline = clock.now() + delay;
+                        while !flush && written < target {
+                            let remaining = deadline.saturating_duration_since(clock.now());
+                            match clock.timeout(remaining, rx.next()).await {
+                                Some(Some((op, flush_now))) => {
+                                    write_within(&clock, write_timeout, sink.feed(op)).await?;
+                                    written += 1;
+                                    flush = flush_now;
+                                }
+                                _ => break,
+                            }
+                        }
+                    }
+                    write_within(&clock, write_timeout, sink.flush()).await?;
+                }
+                sink.close().await
+            }.await;
+            if let Err(RatsioError::WriteTimeout) = sent {
+                if connection.is_connected() {
+                    error!(target: log_target.as_str(), "Write did not complete within {:?}, connection is stale.",
+                           write_timeout);
+                    connection.record_event(ConnectionEventReason::WriteTimeout);
+                    *state.write() = NatsClientState::Disconnected;
+                    NatsConnection::trigger_reconnect(connection.clone());
+                }
+            }
+            if let Err(err) = sent {
+                debug!(target: log_target.as_str(), "Sender closed => {}", err);
+                // The op that failed, and everything queued behind it, is lost with the connection.
+                task_dropped_ops.inc();
+                rx.close();
+                while rx.try_next().is_some() {
+                    task_dropped_ops.inc();
+                }
+            }
+        };
+        supervisor.spawn_connection_task("sender", work);
+
+        NatsClientSender {
+            tx,
+            stats,
+            connection: supervisor.connection.clone(),
+            dropped_ops,
+            fail_when_disconnected,
+            low_latency,
+        }
+    }
+    /// Queues an OP to be sent to the server. Without a connection the op is dropped and counted in
+    /// `NatsClient::dropped_ops`, it fails with `RatsioError::NotConnected` if `fail_when_disconnected` is set.
+    pub fn send(&self, op: Op) -> Result<(), RatsioError> {
+        self.send_with_flush(op, self.low_latency.load(Ordering::SeqCst))
+    }
 
-        NatsClientSender { tx }
+    /// Queues an OP like `send`, flushing right after it if `flush` is set and along with the
+    /// ops queued behind it otherwise.
+    pub fn send_with_flush(&self, op: Op, flush: bool) -> Result<(), RatsioError> {
+        let priority = Priority::of(&op);
+        self.send_with_priority(op, priority, flush)
     }
-    /// Sends an OP to the server
-    pub fn send(&self, op: Op) -> impl Future<Output = ()> {
-        //let _verbose = self.verbose.clone();
+
+    /// Queues an OP like `send_with_flush`, in the lane of `priority` rather than the lane
+    /// `Priority::of` the op.
+    pub fn send_with_priority(&self, op: Op, priority: Priority, flush: bool) -> Result<(), RatsioError> {
+        // Failing to encode the op later would break the connection.
+        op.validate()?;
+        if !self.connection.is_connected() {
+            self.dropped_ops.inc();
+            trace!(target: self.connection.log_target.as_str(), "Not connected, dropping {:?}", op);
+            return if self.fail_when_disconnected {
+                Err(RatsioError::NotConnected)
+            } else {
+                Ok(())
+            };
+        }
+        let published = match op {
+            Op::PUB(ref cmd) => Some((cmd.subject.clone(), cmd.payload.len())),
+            _ => None,
+        };
         self.tx
-            .unbounded_send(op)
-            .map_err(|_| RatsioError::InnerBrokenChain)
-            .into_future()
+            .send((op, flush), priority)
+            .map_err(|_| RatsioError::InnerBrokenChain)?;
+        if let Some((subject, bytes)) = published {
+            self.stats.lock().record(&subject, bytes);
+        }
+        Ok(())
+    }
+
+    /// Queues the ops of `batch` in the low priority lane, flushing after the last. The connection
+    /// is checked and the stats recorded once for the whole batch. Fails with
+    /// `RatsioError::InnerBrokenChain` if the lanes of this sender were closed with its connection,
+    /// the ops not queued are left in `batch`.
+    pub(crate) fn send_batch(&self, batch: &mut VecDeque<Op>) -> Result<(), RatsioError> {
+        if !self.connection.is_connected() {
+            self.dropped_ops.add(batch.len());
+            trace!(target: self.connection.log_target.as_str(), "Not connected, dropping {} ops", batch.len());
+            batch.clear();
+            return if self.fail_when_disconnected {
+                Err(RatsioError::NotConnected)
+            } else {
+                Ok(())
+            };
+        }
+        let mut published = Vec::with_capacity(batch.len());
+        let mut result = Ok(());
+        while let Some(op) = batch.pop_front() {
+            let record = match op {
+                Op::PUB(ref cmd) => Some((cmd.subject.clone(), cmd.payload.len())),
+                _ => None,
+            };
+            let flush = batch.is_empty();
+            if let Err((op, _)) = self.tx.send((op, flush), Priority::Low) {
+                batch.push_front(op);
+                result = Err(RatsioError::InnerBrokenChain);
+                break;
+            }
+            published.extend(record);
+        }
+        let mut stats = self.stats.lock();
+        for (subject, bytes) in published {
+            stats.record(&subject, bytes);
+        }
+        result
+    }
+}
+
+/// Waits for `write` to complete, failing with `RatsioError::WriteTimeout` after `timeout` unless it is 0.
+async fn write_within<F>(clock: &SharedClock, timeout: Duration, write: F) -> Result<(), RatsioError>
+where
+    F: Future<Output = Result<(), RatsioError>>,
+{
+    if timeout == Duration::from_millis(0) {
+        write.await
+    } else {
+        clock.timeout(timeout, write).await.unwrap_or(Err(RatsioError::WriteTimeout))
     }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) enum SinkMessage {
     Message(Message),
+    Error(SubscriptionError),
     CLOSE,
 }
 
-#[derive(Debug, Clone)]
+/// Where a sink passes the messages of its stream on to.
+#[derive(Debug)]
+pub(crate) enum SinkSender {
+    /// Without a `Subscribe::pending_limit`, the messages are buffered until the stream takes them
+    Unbounded(mpsc::UnboundedSender<SinkMessage>),
+    /// Bounded by the `Subscribe::pending_limit`
+    Pending(PendingSender),
+}
+
+impl SinkSender {
+    fn send(&self, item: SinkMessage) {
+        match self {
+            SinkSender::Unbounded(tx) => {
+                let _ = tx.unbounded_send(item);
+            }
+            SinkSender::Pending(pending) => pending.0.push_control(item),
+        }
+    }
+
+    fn pending(&self) -> Option<&Arc<PendingMessages>> {
+        match self {
+            SinkSender::Unbounded(_) => None,
+            SinkSender::Pending(pending) => Some(&pending.0),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub(crate) struct SubscriptionSink {
     cmd: Subscribe,
-    tx: mpsc::UnboundedSender<SinkMessage>,
+    tx: SinkSender,
     max_count: Option<u32>,
     count: u32,
+    /// Whether the stream takes `SubscriptionError`s, see `NatsClient::subscribe_with_errors`
+    deliver_errors: bool,
+    /// Unsubscribed on the server by `Subscription::pause`, not restored on reconnect until resumed
+    paused_on_server: bool,
+    /// The latest `Subscribe::replay_buffer` messages, replayed to new forks
+    replay: VecDeque<Message>,
+    /// Streams created with `Subscription::fork`
+    forks: Vec<mpsc::UnboundedSender<SinkMessage>>,
+    /// Delivery counters of the stream, sid and subject are filled in by `stats`
+    stats: SubscriptionStats,
+    /// Messages sent to the stream and not taken from it yet
+    queued: QueueDepth,
+    /// The server refused the subscription, see `NatsClient::subscribe_confirmed`
+    rejected: Option<SubscriptionError>,
+}
+
+impl SubscriptionSink {
+    pub(crate) fn new(cmd: Subscribe, tx: SinkSender, deliver_errors: bool, queued: QueueDepth) -> Self {
+        SubscriptionSink {
+            cmd,
+            tx,
+            max_count: None,
+            count: 0,
+            deliver_errors,
+            paused_on_server: false,
+            replay: VecDeque::new(),
+            forks: Vec::new(),
+            stats: SubscriptionStats::default(),
+            queued,
+            rejected: None,
+        }
+    }
+
+    /// A sink without a pending limit, with the receiver of its stream.
+    #[cfg(test)]
+    fn for_test(cmd: Subscribe) -> (Self, UnboundedReceiver<SinkMessage>) {
+        let (tx, rx) = mpsc::unbounded();
+        (SubscriptionSink::new(cmd, SinkSender::Unbounded(tx), false, QueueDepth::default()), rx)
+    }
+
+    /// Passes `msg` on to the stream and its forks. Returns the pending messages of the stream
+    /// if the multiplexer has to wait for it to catch up, see `SlowConsumerPolicy::Block`.
+    pub(crate) fn deliver(&mut self, msg: Message) -> Option<Arc<PendingMessages>> {
+        if self.cmd.replay_buffer > 0 {
+            if self.replay.len() >= self.cmd.replay_buffer {
+                self.replay.pop_front();
+            }
+            self.replay.push_back(msg.clone());
+        }
+        self.forks.retain(|fork| fork.unbounded_send(SinkMessage::Message(msg.clone())).is_ok());
+        let bytes = msg.payload.len();
+        let sent_at = msg.sent_at();
+        let delivered = match self.tx {
+            SinkSender::Unbounded(ref tx) => {
+                // Counted before it is sent, the stream may take it right away.
+                self.queued.queued();
+                let sent = tx.unbounded_send(SinkMessage::Message(msg)).is_ok();
+                if !sent {
+                    self.queued.taken();
+                }
+                sent
+            }
+            SinkSender::Pending(ref pending) => pending.0.push(msg),
+        };
+        if !delivered {
+            self.stats.dropped += 1;
+            return None;
+        }
+        self.stats.record_delivery(bytes, sent_at);
+        match self.tx.pending() {
+            Some(pending) if pending.must_block() => Some(pending.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn stats(&self, sid: &str) -> SubscriptionStats {
+        SubscriptionStats {
+            sid: sid.to_string(),
+            subject: self.cmd.subject.clone(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Registers a fork, it first receives the messages in the replay buffer.
+    pub(crate) fn fork(&mut self) -> mpsc::UnboundedReceiver<SinkMessage> {
+        let (tx, rx) = mpsc::unbounded();
+        for msg in &self.replay {
+            let _ = tx.unbounded_send(SinkMessage::Message(msg.clone()));
+        }
+        self.forks.push(tx);
+        rx
+    }
+
+    pub(crate) fn send_error(&self, err: SubscriptionError) {
+        if self.deliver_errors {
+            self.tx.send(SinkMessage::Error(err));
+        }
+    }
+
+    pub(crate) fn slow_consumer_stats(&self) -> SlowConsumerStats {
+        self.tx.pending().map(|pending| pending.stats()).unwrap_or_default()
+    }
+
+    /// Releases a `SlowConsumerPolicy::Block` while the subscription is paused, see `Subscription::pause`.
+    pub(crate) fn set_paused(&self, paused: bool) {
+        if let Some(pending) = self.tx.pending() {
+            pending.set_paused(paused);
+        }
+    }
+
+    /// Ends the subscription stream and its forks.
+    pub(crate) fn close(&self) {
+        self.tx.send(SinkMessage::CLOSE);
+        for fork in &self.forks {
+            let _ = fork.unbounded_send(SinkMessage::CLOSE);
+        }
+    }
+}
+
+/// Passes the messages of each batch of ops read from the connection on to the sinks of their
+/// subscriptions and the other ops on to the client, under a single lock of the subscriptions
+/// per batch. A `SlowConsumerPolicy::Block` sink that is full holds up the next op until its
+/// stream caught up or is paused, PINGs, PONGs and INFOs behind it included.
+fn multiplex<S>(
+    batches: S,
+    subs_map: Arc<RwLock<HashMap<String, SubscriptionSink>>>,
+    control_tx: CountedSender<Op>,
+) -> impl Future<Output = ()> + Send
+where
+    S: Stream<Item = Vec<Op>> + Send,
+{
+    batches.for_each(move |ops| {
+        let subs_map = subs_map.clone();
+        let control_tx = control_tx.clone();
+        async move {
+            let mut ops = ops.into_iter();
+            loop {
+                let blocked = {
+                    let mut subs_map = subs_map.write();
+                    let mut blocked = None;
+                    for op in &mut ops {
+                        match op {
+                            Op::MSG(msg) => {
+                                blocked = subs_map.get_mut(&msg.sid).and_then(|s| s.deliver(msg));
+                                if blocked.is_some() {
+                                    break;
+                                }
+                            }
+                            // Forward the rest of the messages to the owning client
+                            op => {
+                                let _ = control_tx.unbounded_send(op);
+                            }
+                        }
+                    }
+                    blocked
+                };
+                match blocked {
+                    // Backpressure, the next op is only handled once the stream caught up.
+                    Some(pending) => future::poll_fn(|cx| pending.poll_capacity(cx)).await,
+                    None => break,
+                }
+            }
+        }
+    })
 }
 
 #[derive(Debug)]
 pub struct NatsClientMultiplexer {
-    control_tx: mpsc::UnboundedSender<Op>,
+    control_tx: CountedSender<Op>,
     subs_map: Arc<RwLock<HashMap<String, SubscriptionSink>>>,
+    log_target: String,
+}
+
+type TaggedStream = Pin<Box<dyn Stream<Item = (String, Message)> + Send + Sync>>;
+
+/// Merged stream of several subscriptions created with `NatsClient::subscribe_many`.
+/// Each message is tagged with the subject of the subscription it was received on. Dropping it
+/// drops the individual subscriptions, which unsubscribe like `Subscription` does.
+pub struct MultiSubscription {
+    sids: Vec<String>,
+    stream: stream::SelectAll<TaggedStream>,
+    sender: Arc<RwLock<NatsClientSender>>,
+    receiver: Arc<RwLock<NatsClientMultiplexer>>,
+}
+
+impl MultiSubscription {
+    /// The sids of the individual subscriptions.
+    pub fn sids(&self) -> &[String] {
+        &self.sids
+    }
+
+    /// Unsubscribes all the subscriptions, the stream ends once the buffered messages are consumed.
+    pub fn unsubscribe(&self) {
+        for sid in &self.sids {
+            subscription::unsubscribe(sid, &self.sender, &self.receiver);
+        }
+    }
+}
+
+impl Debug for MultiSubscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "MultiSubscription {{ sids: {:?} }}", self.sids)
+    }
+}
+
+impl Stream for MultiSubscription {
+    type Item = (String, Message);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
 }
 
 /// UriVec allows ergonomic use of NatsClientOptions.
 /// ``` rust
+/// use ratsio::nats_client::UriVec;
+///
 /// ratsio::prelude::NatsClientOptions::builder()
-///    .cluster_uris("localhost:4222")
+///    .cluster_uris(UriVec::parse(&["localhost:4222", "other_location:4222"]).unwrap())
 ///    .build();
 /// ```
-/// or
-/// ``` rust
-/// ratsio::prelude::NatsClientOptions::builder()
-///    .cluster_uris(vec!("localhost:4222", "other_location:4222"))
-///    .build();
-/// ```
-#[derive(Clone, Debug, PartialEq)]
-pub struct UriVec(Vec<String>);
+/// Urls are parsed into `NatsUrl`s up front, with `UriVec::parse` or from `NatsUrl`s. The
+/// conversions from raw strings are deprecated, they skip (and log) urls that cannot be parsed.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct UriVec(Vec<NatsUrl>);
+
+impl UriVec {
+    /// Parses `urls`, failing with the error of the first one that is not valid.
+    pub fn parse<S: AsRef<str>>(urls: &[S]) -> Result<UriVec, RatsioError> {
+        urls.iter()
+            .map(|url| url.as_ref().parse())
+            .collect::<Result<Vec<NatsUrl>, _>>()
+            .map(UriVec)
+    }
+
+    pub fn urls(&self) -> &[NatsUrl] {
+        &self.0
+    }
+}
+
+impl From<Vec<NatsUrl>> for UriVec {
+    fn from(xs: Vec<NatsUrl>) -> Self {
+        UriVec(xs)
+    }
+}
+
+impl From<NatsUrl> for UriVec {
+    fn from(x: NatsUrl) -> Self {
+        UriVec(vec![x])
+    }
+}
 
+/// Deprecated, skips (and logs) invalid urls, use `UriVec::parse`.
 impl From<Vec<&str>> for UriVec {
     fn from(xs: Vec<&str>) -> Self {
-        UriVec(xs.into_iter().map(|x| x.into()).collect())
+        UriVec(NatsUrl::parse_all(&xs))
     }
 }
 
+/// Deprecated, skips (and logs) invalid urls, use `UriVec::parse`.
 impl From<Vec<String>> for UriVec {
     fn from(xs: Vec<String>) -> Self {
-        UriVec(xs)
+        UriVec(NatsUrl::parse_all(&xs))
     }
 }
 
+/// Deprecated, skips (and logs) invalid urls, use `UriVec::parse`.
 impl From<String> for UriVec {
     fn from(x: String) -> Self {
-        UriVec(vec![x])
+        UriVec(NatsUrl::parse_all(&[x]))
     }
 }
 
+/// Deprecated, skips (and logs) invalid urls, use `UriVec::parse`.
 impl From<&str> for UriVec {
     fn from(x: &str) -> Self {
-        UriVec(vec![x.to_owned()])
+        UriVec(NatsUrl::parse_all(&[x]))
     }
 }
 
-/// An alias representing the requirements for the nonce signing callback function
-pub type SignerCallback =
-    Arc<dyn Fn(&[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> + Send + Sync>;
-
-/// An option that indicates client JWT authentication should be used. Takes a callback that
-/// will be used to sign the nonce the server supplies. For security reasons, ensure that
-/// you keep the seed in memory only as long as is necessary. Because of the tokio wrappings
-/// used by this client, the callback must be wrapped in an Arc of the signer callback function type.
-#[derive(Clone)]
-pub struct UserJWT {
-    jwt: String,
-    signer: SignerCallback,
+/// Credentials for one server, see `NatsClientOptions::server_credentials`. Fields left as `None`
+/// fall back to the client wide options.
+#[derive(Clone, Default, PartialEq)]
+pub struct ServerCredentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub auth_token: Option<String>,
 }
 
-impl Debug for UserJWT {
+impl Debug for ServerCredentials {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "UserJWT {{ jwt: {}, signer: (func) }}", self.jwt)
-    }
-}
-
-impl PartialEq for UserJWT {
-    fn eq(&self, other: &UserJWT) -> bool {
-        self.jwt == other.jwt
-    }
-}
-
-impl UserJWT {
-    /// Creates a new UserJWT option from an encoded JWT and a callback to be invoked to sign
-    /// the server-provided nonce
-    pub fn new(jwt: String, signer: SignerCallback) -> UserJWT {
-        UserJWT { jwt, signer }
+        f.debug_struct("ServerCredentials")
+            .field("username", &self.username)
+            .field("password", &optional_secret(&self.password))
+            .field("auth_token", &optional_secret(&self.auth_token))
+            .finish()
     }
 }
 
-/// Options that are to be given to the client for initialization
-#[derive(Debug, Clone, Builder, PartialEq)]
+/// Options that are to be given to the client for initialization. Credentials are redacted from
+/// its `Debug` output, `summary` describes the options in one line for logs.
+#[derive(Clone, Builder, PartialEq)]
 #[builder(setter(into), default)]
 pub struct NatsClientOptions {
+    /// Supplies the credentials on every (re)connect, replacing `username`, `password`, `auth_token`
+    /// and `user_jwt`, which are shorthands for the built-in authenticators.
+    pub authenticator: Option<SharedAuthenticator>,
     /// Cluster username, can be overwritten by host url nats://<username>:<password>@<host>:<port>
     pub username: String,
     /// Cluster password, can be overwritten by host url nats://<username>:<password>@<host>:<port>
     pub password: String,
     /// Cluster auth_token
     pub auth_token: String,
+    /// Credentials for single servers, by `host:port`, overriding `username`, `password` and
+    /// `auth_token` when connected to that server. Credentials in the server url take precedence.
+    pub server_credentials: HashMap<String, ServerCredentials>,
     /// Whether TLS is required.
     pub tls_required: bool,
+    /// Supplies the client certificate for mutual TLS, asked again on every (re)connect.
+    pub tls_identity_provider: Option<TlsIdentityProvider>,
+    /// Name sent as SNI and matched against the server certificate, instead of the host of the
+    /// server url. Needed when dialing an IP, or a TCP load balancer whose certificate is issued
+    /// for another name.
+    pub tls_hostname: Option<String>,
     /// verbosity, default true
     pub verbose: bool,
     /// pedantic, default false
@@ -153,51 +682,518 @@ pub struct NatsClientOptions {
     pub echo: bool,
     /// Optional client name
     pub name: String,
+    /// Tells the log output of clients in one process apart, internal logs go to the target
+    /// `ratsio::<log_label>` instead of `ratsio`, so `RUST_LOG=ratsio` still enables them.
+    pub log_label: String,
 
-    /// Cluster URI in the IP:PORT format
+    /// Cluster URIs, `nats://[user:password@]host[:port]` or `tls://...`
     pub cluster_uris: UriVec,
+    /// Supplies the servers before every connect and reconnect, replacing `cluster_uris`.
+    /// `cluster_uris` are still used when the provider fails or returns no servers.
+    pub server_list: Option<ServerList>,
+    /// Reconnect to the `cluster_uris` before the servers gossiped in INFO `connect_urls`, which are
+    /// often unreachable from the client in leafnode or gateway topologies.
+    pub prefer_seed_servers: bool,
+    /// File the gossiped servers and how connecting to them went are saved to, and loaded from on
+    /// start, so a restarted client reaches the cluster through them while the seeds are down.
+    pub server_pool_file: Option<String>,
+    /// Keep a connection to a second server of the pool open, and switch to it right away when the
+    /// current connection is lost instead of dialing. For latency critical clients of a cluster.
+    pub standby_connection: bool,
+    /// Milliseconds a standby connection is kept before it is replaced, below the `auth_timeout`
+    /// of the servers as they drop connections that don't send CONNECT in time.
+    pub standby_max_age: u64,
+    /// IP family to try first when a server resolves to both IPv4 and IPv6 addresses
+    pub address_family: AddressFamily,
 
     /// Ping interfval in seconds
     pub ping_interval: u16,
     /// No of unsuccessful pings before the connection is deemed disconnected.
     pub ping_max_out: u16,
-    /// If we should re-subscribe all subscriptions on re-connection.
+    /// Do not answer server PINGs automatically. PINGs are still forwarded on the client `Stream`,
+    /// the application is responsible for answering them with `NatsClient::send_pong`.
+    pub manual_ping_mode: bool,
+    /// Reconnect when nothing, not even a PONG, was read from the server for this many milliseconds.
+    /// Should be well above `ping_interval`, 0 disables the check.
+    pub read_timeout: u64,
+    /// Reconnect when writing to or flushing the connection takes longer than this many
+    /// milliseconds, so a server that stopped reading doesn't stall the client. 0 disables it.
+    pub write_timeout: u64,
+    /// If we should re-subscribe all subscriptions on re-connection, `Subscribe::resubscribe` overrides
+    /// it per subscription and `NatsClient::set_resubscribe_rewriter` can change the replayed commands.
     /// If you don't want re-subscription, add a reconnect_handler and do your thing there.
     pub subscribe_on_reconnect: bool,
+    /// When the ops written to the connection are flushed, `FlushPolicy::Adaptive` trades latency
+    /// for throughput only while publishes back up. Ignored in `low_latency` mode.
+    pub flush_policy: FlushPolicy,
+    /// Order subscriptions are restored in on reconnect, ahead of the publishes queued meanwhile
+    pub resubscribe_order: ResubscribeOrder,
+    /// After a reconnect, hold the publishes until the server answered a PING sent behind the
+    /// restored subscriptions, so the client publishes nothing its own subscriptions would miss.
+    /// Up to `lazy_publish_buffer` publishes are held, further ones are sent right away.
+    pub resubscribe_barrier: bool,
+    /// Have `NatsClient::subscribe` wait for the server like `NatsClient::subscribe_confirmed`, and
+    /// fail when the server refuses the subscription instead of handing out a stream that never
+    /// yields a message.
+    pub verify_subscriptions: bool,
+    /// Subscriptions made right after the CONNECT of the first connection, ahead of any other op,
+    /// so no message published once the client is connected is missed. Restored on reconnects like
+    /// other subscriptions, their streams are taken with `NatsClient::take_subscription`.
+    pub subscriptions: Vec<Subscribe>,
+    /// Emit `ClientEvent::Connected` after a reconnect only once all reconnect handlers finished,
+    /// for applications restoring their own state in the handlers. The handlers run on their own
+    /// tasks either way, so they never hold up resubscribing or pings.
+    pub reconnect_handlers_blocking: bool,
+    /// Unsubscribe when a `Subscription` is dropped, so subscriptions of tasks that ended without
+    /// cleaning up don't stay on the server. Applies to subscriptions created afterwards,
+    /// `Subscription::set_unsubscribe_on_drop` overrides it per subscription.
+    pub unsubscribe_on_drop: bool,
     /// If connect fails, keep trying, forever,
     pub ensure_connect: bool,
+    /// Milliseconds `NatsClient::connect` may take in total, including the retries of `ensure_connect`,
+    /// before failing with `RatsioError::ConnectTimeout`. 0 for no deadline.
+    pub connect_timeout: u64,
+    /// `NatsClient::connect` resolves right away with a `Disconnected` client that connects in the
+    /// background, like `NatsClient::new`, instead of waiting for the first connection.
+    pub connect_lazily: bool,
+    /// Number of publishes a lazily connected client holds until its first connection, further
+    /// publishes are handled like publishes without a connection.
+    pub lazy_publish_buffer: usize,
     /// Time between connection retries
     pub reconnect_timeout: u64,
+    /// Up to this many milliseconds are randomly added to `reconnect_timeout`, so clients that lost
+    /// the same server do not all reconnect at once
+    pub reconnect_jitter: u64,
+    /// Like `reconnect_jitter`, for TLS connections whose handshakes are more expensive for the server
+    pub reconnect_jitter_tls: u64,
+    /// Time source of the ping, read timeout, request timeout and reconnect timers, replaced by a
+    /// `MockClock` in tests. The time of the tokio runtime by default.
+    pub clock: SharedClock,
     /// When using NATS 2.x decentralized security, supply a user JWT for authN/authZ
+    #[cfg(feature = "jwt")]
     pub user_jwt: Option<UserJWT>,
+    /// Run the IO and timer tasks of the client on a single threaded runtime of its own, so they
+    /// are not held up by a busy application runtime.
+    pub dedicated_io_runtime: bool,
+    /// Most ops the multiplexer handles per wakeup of the reader, after a full batch it yields to
+    /// the other tasks of the executor once, so a busy connection does not starve them. Applies from
+    /// the next connection.
+    pub read_batch_budget: usize,
+    /// Milliseconds after which the inbox subscription of a request that got no reply is removed,
+    /// so abandoned requests do not keep their sids forever. The request then fails with
+    /// `RatsioError::InnerBrokenChain`, 0 keeps the inboxes until a reply arrives.
+    pub request_inbox_ttl: u64,
+    /// Milliseconds `NatsClient::drain` waits for the running `serve` handlers, and then for the
+    /// server to confirm the replies were received, before closing the client anyway.
+    pub drain_timeout: u64,
+    /// Fail requests to a subject right away with `RatsioError::CircuitOpen` for a while, once
+    /// too many of them timed out or found no responders in a row.
+    pub circuit_breaker: Option<CircuitBreakerOptions>,
+    /// Send the deadline of `request_timeout` calls along in a `Nats-Deadline` header,
+    /// so responders can give up on requests that can no longer be answered in time.
+    pub propagate_deadlines: bool,
+    /// Stamp published messages with a `Ratsio-Sent-At` header, needed by subscribers
+    /// that drop stale messages with `Subscribe::max_age` and for the latency histograms of
+    /// `SubscriptionStats::latency`. Requires NATS server 2.2+.
+    pub stamp_sent_at: bool,
+    /// Set `TCP_NODELAY` and flush after every op, for the lowest latency. Otherwise ops queued
+    /// together are written with a single flush, for throughput. Can be changed at runtime with
+    /// `NatsClient::set_low_latency` and overridden per message with `NatsClient::publish_with_low_latency`.
+    pub low_latency: bool,
+    /// Number of connects and disconnects kept for `NatsClient::connection_history`, 0 keeps none.
+    pub connection_history_size: usize,
+    /// Fail sends with `RatsioError::NotConnected` while there is no connection, instead of dropping
+    /// the ops silently. Dropped ops are counted in `NatsClient::dropped_ops` either way.
+    pub fail_when_disconnected: bool,
+    /// Number of subject tokens publish statistics are grouped by, 0 for full subjects.
+    pub stats_subject_depth: usize,
+    /// Maximum number of subject prefixes publish statistics are kept for, further prefixes are
+    /// counted under `OTHER_SUBJECTS`. 0 disables the statistics.
+    pub stats_max_subjects: usize,
+    /// Most subscriptions at a time, further subscribes fail with `RatsioError::TooManySubscriptions`.
+    /// The inboxes of requests count towards it but are never refused. 0 for no limit.
+    pub max_subscriptions: usize,
+    /// Most distinct subjects published to, publishes and requests to further subjects fail with
+    /// `RatsioError::TooManyPublishSubjects`. Guards against subjects made per entity by mistake,
+    /// `PublisherHandle` publishes and replies or frames sent to private inboxes are not counted.
+    /// 0 for no limit.
+    pub max_distinct_publish_subjects: usize,
+    /// Most bytes a compressed payload may decompress to, larger ones are dropped.
+    pub max_decompressed_size: usize,
+    /// Subject health checks are answered on with a JSON `HealthReport`, conventionally
+    /// `_HEALTH.<name>`, so fleet tooling can check clients over NATS. No health checks if not set.
+    pub health_subject: Option<String>,
 }
 
 impl Default for NatsClientOptions {
     fn default() -> Self {
         NatsClientOptions {
+            authenticator: None,
             username: String::new(),
             password: String::new(),
             tls_required: false,
+            tls_identity_provider: None,
+            tls_hostname: None,
             auth_token: String::new(),
+            server_credentials: HashMap::new(),
             verbose: true,
             pedantic: false,
             echo: true,
             name: String::new(),
-            cluster_uris: UriVec(Vec::new()),
+            log_label: String::new(),
+            cluster_uris: UriVec::default(),
+            server_list: None,
+            prefer_seed_servers: false,
+            server_pool_file: None,
+            standby_connection: false,
+            standby_max_age: 1500,
+            address_family: AddressFamily::Any,
             ping_interval: 5,
             ping_max_out: 3,
+            manual_ping_mode: false,
+            read_timeout: 0,
+            write_timeout: 0,
             subscribe_on_reconnect: true,
+            flush_policy: FlushPolicy::Immediate,
+            resubscribe_order: ResubscribeOrder::QueueGroupsFirst,
+            resubscribe_barrier: false,
+            verify_subscriptions: false,
+            subscriptions: Vec::new(),
+            reconnect_handlers_blocking: false,
+            unsubscribe_on_drop: true,
             ensure_connect: true,
+            connect_timeout: 0,
+            connect_lazily: false,
+            lazy_publish_buffer: 1024,
             reconnect_timeout: 1000,
+            reconnect_jitter: 100,
+            reconnect_jitter_tls: 1000,
+            clock: SharedClock::default(),
+            #[cfg(feature = "jwt")]
             user_jwt: None,
+            dedicated_io_runtime: false,
+            read_batch_budget: READ_BATCH_BUDGET,
+            request_inbox_ttl: 300_000,
+            drain_timeout: 30_000,
+            circuit_breaker: None,
+            propagate_deadlines: false,
+            stamp_sent_at: false,
+            low_latency: false,
+            connection_history_size: 64,
+            fail_when_disconnected: false,
+            stats_subject_depth: 0,
+            stats_max_subjects: 1000,
+            max_subscriptions: 0,
+            max_distinct_publish_subjects: 0,
+            max_decompressed_size: MAX_DECOMPRESSED_SIZE,
+            health_subject: None,
         }
     }
 }
 
+impl Debug for NatsClientOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Destructured in full so new fields can't be left out by mistake
+        let NatsClientOptions {
+            authenticator,
+            username,
+            password,
+            auth_token,
+            server_credentials,
+            tls_required,
+            tls_identity_provider,
+            tls_hostname,
+            verbose,
+            pedantic,
+            echo,
+            name,
+            log_label,
+            cluster_uris,
+            server_list,
+            prefer_seed_servers,
+            server_pool_file,
+            standby_connection,
+            standby_max_age,
+            address_family,
+            ping_interval,
+            ping_max_out,
+            manual_ping_mode,
+            read_timeout,
+            write_timeout,
+            subscribe_on_reconnect,
+            flush_policy,
+            resubscribe_order,
+            resubscribe_barrier,
+            verify_subscriptions,
+            subscriptions,
+            reconnect_handlers_blocking,
+            unsubscribe_on_drop,
+            ensure_connect,
+            connect_timeout,
+            connect_lazily,
+            lazy_publish_buffer,
+            reconnect_timeout,
+            reconnect_jitter,
+            reconnect_jitter_tls,
+            clock,
+            #[cfg(feature = "jwt")]
+            user_jwt,
+            dedicated_io_runtime,
+            read_batch_budget,
+            request_inbox_ttl,
+            drain_timeout,
+            circuit_breaker,
+            propagate_deadlines,
+            stamp_sent_at,
+            low_latency,
+            connection_history_size,
+            fail_when_disconnected,
+            stats_subject_depth,
+            stats_max_subjects,
+            max_subscriptions,
+            max_distinct_publish_subjects,
+            max_decompressed_size,
+            health_subject,
+        } = self;
+        let mut debug = f.debug_struct("NatsClientOptions");
+        debug.field("authenticator", &authenticator);
+        debug.field("username", &username);
+        debug.field("password", &secret(password));
+        debug.field("auth_token", &secret(auth_token));
+        debug.field("server_credentials", &server_credentials);
+        debug.field("tls_required", &tls_required);
+        debug.field("tls_identity_provider", &tls_identity_provider);
+        debug.field("tls_hostname", &tls_hostname);
+        debug.field("verbose", &verbose);
+        debug.field("pedantic", &pedantic);
+        debug.field("echo", &echo);
+        debug.field("name", &name);
+        debug.field("log_label", &log_label);
+        debug.field("cluster_uris", &cluster_uris);
+        debug.field("server_list", &server_list);
+        debug.field("prefer_seed_servers", &prefer_seed_servers);
+        debug.field("server_pool_file", &server_pool_file);
+        debug.field("standby_connection", &standby_connection);
+        debug.field("standby_max_age", &standby_max_age);
+        debug.field("address_family", &address_family);
+        debug.field("ping_interval", &ping_interval);
+        debug.field("ping_max_out", &ping_max_out);
+        debug.field("manual_ping_mode", &manual_ping_mode);
+        debug.field("read_timeout", &read_timeout);
+        debug.field("write_timeout", &write_timeout);
+        debug.field("subscribe_on_reconnect", &subscribe_on_reconnect);
+        debug.field("flush_policy", &flush_policy);
+        debug.field("resubscribe_order", &resubscribe_order);
+        debug.field("resubscribe_barrier", &resubscribe_barrier);
+        debug.field("verify_subscriptions", &verify_subscriptions);
+        debug.field("subscriptions", &subscriptions);
+        debug.field("reconnect_handlers_blocking", &reconnect_handlers_blocking);
+        debug.field("unsubscribe_on_drop", &unsubscribe_on_drop);
+        debug.field("ensure_connect", &ensure_connect);
+        debug.field("connect_timeout", &connect_timeout);
+        debug.field("connect_lazily", &connect_lazily);
+        debug.field("lazy_publish_buffer", &lazy_publish_buffer);
+        debug.field("reconnect_timeout", &reconnect_timeout);
+        debug.field("reconnect_jitter", &reconnect_jitter);
+        debug.field("reconnect_jitter_tls", &reconnect_jitter_tls);
+        debug.field("clock", &clock);
+        #[cfg(feature = "jwt")]
+        debug.field("user_jwt", &optional_secret(user_jwt));
+        debug.field("dedicated_io_runtime", &dedicated_io_runtime);
+        debug.field("read_batch_budget", &read_batch_budget);
+        debug.field("request_inbox_ttl", &request_inbox_ttl);
+        debug.field("drain_timeout", &drain_timeout);
+        debug.field("circuit_breaker", &circuit_breaker);
+        debug.field("propagate_deadlines", &propagate_deadlines);
+        debug.field("stamp_sent_at", &stamp_sent_at);
+        debug.field("low_latency", &low_latency);
+        debug.field("connection_history_size", &connection_history_size);
+        debug.field("fail_when_disconnected", &fail_when_disconnected);
+        debug.field("stats_subject_depth", &stats_subject_depth);
+        debug.field("stats_max_subjects", &stats_max_subjects);
+        debug.field("max_subscriptions", &max_subscriptions);
+        debug.field("max_distinct_publish_subjects", &max_distinct_publish_subjects);
+        debug.field("max_decompressed_size", &max_decompressed_size);
+        debug.field("health_subject", &health_subject);
+        debug.finish()
+    }
+}
+
 impl NatsClientOptions {
     pub fn builder() -> NatsClientOptionsBuilder {
         NatsClientOptionsBuilder::default()
     }
+
+    /// A one line description of the options, without credentials, for logs.
+    pub fn summary(&self) -> String {
+        let servers: Vec<_> = self.cluster_uris.0.iter().map(NatsUrl::summary).collect();
+        format!(
+            "name: {:?}, servers: [{}], auth: {}, tls: {}, ping: {}s x{}, reconnect: {}ms{}",
+            self.name,
+            servers.join(", "),
+            self.auth_method(),
+            self.tls_required,
+            self.ping_interval,
+            self.ping_max_out,
+            self.reconnect_timeout,
+            if self.server_list.is_some() { ", server list" } else { "" },
+        )
+    }
+
+    /// How the client authenticates, in the order the credentials take precedence.
+    fn auth_method(&self) -> &'static str {
+        #[cfg(feature = "jwt")]
+        let jwt = self.user_jwt.is_some();
+        #[cfg(not(feature = "jwt"))]
+        let jwt = false;
+        if self.authenticator.is_some() {
+            "authenticator"
+        } else if jwt {
+            "jwt"
+        } else if !self.auth_token.is_empty() {
+            "token"
+        } else if !self.username.is_empty() {
+            "user and password"
+        } else {
+            "none"
+        }
+    }
+
+    pub(crate) fn reconnect_delay(&self) -> ReconnectDelay {
+        ReconnectDelay {
+            reconnect_timeout: self.reconnect_timeout,
+            jitter: self.reconnect_jitter,
+            jitter_tls: self.reconnect_jitter_tls,
+        }
+    }
+
+    /// The target of the internal logs of a client with these options, see `log_label`.
+    pub(crate) fn log_target(&self) -> String {
+        if self.log_label.is_empty() {
+            String::from("ratsio")
+        } else {
+            format!("ratsio::{}", self.log_label)
+        }
+    }
+}
+
+/// Changes to apply to the options of a running client with `NatsClient::update_options`,
+/// fields left as `None` are not changed.
+/// ``` rust
+/// ratsio::nats_client::NatsClientOptionsPatch {
+///     ping_interval: Some(10),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Default, PartialEq)]
+pub struct NatsClientOptionsPatch {
+    pub ping_interval: Option<u16>,
+    pub ping_max_out: Option<u16>,
+    pub read_timeout: Option<u64>,
+    pub subscribe_on_reconnect: Option<bool>,
+    pub resubscribe_order: Option<ResubscribeOrder>,
+    pub verify_subscriptions: Option<bool>,
+    pub reconnect_handlers_blocking: Option<bool>,
+    pub unsubscribe_on_drop: Option<bool>,
+    pub ensure_connect: Option<bool>,
+    pub reconnect_timeout: Option<u64>,
+    pub reconnect_jitter: Option<u64>,
+    pub reconnect_jitter_tls: Option<u64>,
+    pub request_inbox_ttl: Option<u64>,
+    pub drain_timeout: Option<u64>,
+    pub authenticator: Option<Option<SharedAuthenticator>>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub auth_token: Option<String>,
+    #[cfg(feature = "jwt")]
+    pub user_jwt: Option<Option<UserJWT>>,
+    pub cluster_uris: Option<UriVec>,
+    pub prefer_seed_servers: Option<bool>,
+    pub low_latency: Option<bool>,
+}
+
+impl Debug for NatsClientOptionsPatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let NatsClientOptionsPatch {
+            ping_interval,
+            ping_max_out,
+            read_timeout,
+            subscribe_on_reconnect,
+            resubscribe_order,
+            verify_subscriptions,
+            reconnect_handlers_blocking,
+            unsubscribe_on_drop,
+            ensure_connect,
+            reconnect_timeout,
+            reconnect_jitter,
+            reconnect_jitter_tls,
+            request_inbox_ttl,
+            drain_timeout,
+            authenticator,
+            username,
+            password,
+            auth_token,
+            #[cfg(feature = "jwt")]
+            user_jwt,
+            cluster_uris,
+            prefer_seed_servers,
+            low_latency,
+        } = self;
+        let mut debug = f.debug_struct("NatsClientOptionsPatch");
+        debug.field("ping_interval", &ping_interval);
+        debug.field("ping_max_out", &ping_max_out);
+        debug.field("read_timeout", &read_timeout);
+        debug.field("subscribe_on_reconnect", &subscribe_on_reconnect);
+        debug.field("resubscribe_order", &resubscribe_order);
+        debug.field("verify_subscriptions", &verify_subscriptions);
+        debug.field("reconnect_handlers_blocking", &reconnect_handlers_blocking);
+        debug.field("unsubscribe_on_drop", &unsubscribe_on_drop);
+        debug.field("ensure_connect", &ensure_connect);
+        debug.field("reconnect_timeout", &reconnect_timeout);
+        debug.field("reconnect_jitter", &reconnect_jitter);
+        debug.field("reconnect_jitter_tls", &reconnect_jitter_tls);
+        debug.field("request_inbox_ttl", &request_inbox_ttl);
+        debug.field("drain_timeout", &drain_timeout);
+        debug.field("authenticator", &authenticator);
+        debug.field("username", &username);
+        debug.field("password", &password.as_deref().map(secret));
+        debug.field("auth_token", &auth_token.as_deref().map(secret));
+        #[cfg(feature = "jwt")]
+        debug.field("user_jwt", &user_jwt.as_ref().map(optional_secret));
+        debug.field("cluster_uris", &cluster_uris);
+        debug.field("prefer_seed_servers", &prefer_seed_servers);
+        debug.field("low_latency", &low_latency);
+        debug.finish()
+    }
+}
+
+/// Order subscriptions are restored in on reconnect, see `NatsClientOptions::resubscribe_order`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResubscribeOrder {
+    /// Queue subscriptions first, so the other members of their queue groups take over this
+    /// member's share of the messages for as short as possible
+    QueueGroupsFirst,
+    /// Plain subscriptions first, e.g. when missing none of their messages matters most
+    QueueGroupsLast,
+    /// In no particular order
+    Any,
+}
+
+impl Default for ResubscribeOrder {
+    fn default() -> Self {
+        ResubscribeOrder::QueueGroupsFirst
+    }
+}
+
+impl ResubscribeOrder {
+    /// Sorts the subscriptions, keeping their order otherwise.
+    pub(crate) fn order(self, cmds: &mut Vec<Subscribe>) {
+        match self {
+            ResubscribeOrder::QueueGroupsFirst => cmds.sort_by_key(|cmd| cmd.queue_group.is_none()),
+            ResubscribeOrder::QueueGroupsLast => cmds.sort_by_key(|cmd| cmd.queue_group.is_some()),
+            ResubscribeOrder::Any => {}
+        }
+    }
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -206,31 +1202,132 @@ pub enum NatsClientState {
     Connected,
     Reconnecting,
     Disconnected,
+    /// Closed after an internal task failed, see `ClientEvent::TaskFailed`.
+    Closed,
+}
+
+/// A snapshot of the connection a client is using, see `NatsClient::connection_info`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionInfo {
+    /// The server url the connection was made to
+    pub server_url: NatsUrl,
+    pub local_addr: Option<SocketAddr>,
+    pub remote_addr: Option<SocketAddr>,
+    /// Whether the connection is made over TLS
+    pub tls: bool,
+    pub state: NatsClientState,
+    /// Id of the server, `None` until its INFO was received
+    pub server_id: Option<String>,
+    /// Version of the server, `None` until its INFO was received
+    pub server_version: Option<String>,
+    /// Protocol level announced by the server in INFO, `None` until INFO was received
+    pub server_proto: Option<u32>,
+    /// Features announced by the server in INFO, `None` until INFO was received
+    pub server_features: Option<ServerFeatures>,
+    /// Protocol level the client announces in CONNECT, at most the level of the server
+    pub client_protocol: u32,
+    /// Client version sent in CONNECT
+    pub client_version: String,
+    /// Incremented on every successful reconnect
+    pub connect_version: u64,
+}
+
+type ResubscribeRewriter = Box<dyn Fn(Subscribe) -> Option<Subscribe> + Send + Sync>;
+type ReconnectCallback =
+    Arc<Mutex<dyn FnMut(Arc<NatsClient>) -> BoxFuture<'static, ()> + Send>>;
+type HandlerMap = HashMap<String, ReconnectCallback>;
+
+/// Returned by `NatsClient::on_reconnect`, deregisters the reconnect handler when dropped.
+#[must_use = "the reconnect handler is removed as soon as the guard is dropped"]
+pub struct ReconnectHandlerGuard {
+    hid: String,
+    handlers: Weak<RwLock<HandlerMap>>,
+}
+
+impl ReconnectHandlerGuard {
+    /// The handler id, usable with `NatsClient::remove_reconnect_handler`.
+    pub fn id(&self) -> &str {
+        &self.hid
+    }
+
+    /// Keeps the handler registered for as long as the client lives.
+    pub fn detach(mut self) {
+        self.handlers = Weak::new();
+    }
 }
 
-type HandlerMap = HashMap<String, Box<dyn Fn(Arc<NatsClient>) -> () + Send + Sync>>;
+impl Debug for ReconnectHandlerGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ReconnectHandlerGuard {{ hid: {} }}", self.hid)
+    }
+}
+
+impl Drop for ReconnectHandlerGuard {
+    fn drop(&mut self) {
+        if let Some(handlers) = self.handlers.upgrade() {
+            handlers.write().remove(&self.hid);
+        }
+    }
+}
 
 /// The NATS Client. What you'll be using mostly. All the async handling is made internally except for
 /// the system messages that are forwarded on the `Stream` that the client implements
 pub struct NatsClient {
     connection: Arc<NatsConnection>,
 
-    /// Backup of options
-    opts: NatsClientOptions,
+    /// Options, can be changed at runtime through `update_options`
+    opts: Arc<RwLock<NatsClientOptions>>,
     /// Server info
     server_info: Arc<RwLock<Option<ServerInfo>>>,
     /// Stream of the messages that are not caught for subscriptions (only system messages like PING/PONG should be here)
-    unsub_receiver: Box<dyn Stream<Item = Result<Op, RatsioError>> + Send + Sync>,
+    unsub_receiver: UnboundedReceiver<Op>,
     /// Sink part to send commands
     pub sender: Arc<RwLock<NatsClientSender>>,
     /// Subscription multiplexer
     pub receiver: Arc<RwLock<NatsClientMultiplexer>>,
 
     /// For control Ops (PING, PONG, CLOSE, SERVER_INFO) and misc operations.
-    control_tx: Arc<RwLock<UnboundedSender<Op>>>,
+    control_tx: Arc<RwLock<CountedSender<Op>>>,
 
     state: Arc<RwLock<NatsClientState>>,
     reconnect_handlers: Arc<RwLock<HandlerMap>>,
+    /// Payload validators, checked on publish and/or receive
+    validators: Arc<RwLock<ValidatorRegistry>>,
+    /// Compression of published payloads by subject
+    compression: Arc<RwLock<CompressionPolicies>>,
+    /// Signing of published and verification of received payloads by subject
+    #[cfg(feature = "jwt")]
+    signing: Arc<RwLock<SigningPolicies>>,
+    /// Publish statistics per subject prefix
+    stats: Arc<Mutex<SubjectStatsRegistry>>,
+    /// Ops dropped because there was no connection
+    dropped_ops: Arc<ConsistentCounter>,
+    /// Watches internal tasks and publishes `ClientEvent`s
+    supervisor: Supervisor,
+    /// Rewrites the subscriptions restored after a reconnect
+    resubscribe_rewriter: Arc<RwLock<Option<ResubscribeRewriter>>>,
+    /// Copies of the received non-MSG ops for `raw_ops`
+    raw_ops: RawOpsTap,
+    /// Counts PINGs and PONGs, see `subscribe_confirmed`
+    ping_fence: Arc<PingFence>,
+    /// Failing request subjects, see `NatsClientOptions::circuit_breaker`
+    circuit_breaker: Arc<CircuitBreaker>,
+    /// Shared with the senders, see `NatsClientOptions::low_latency`
+    low_latency: Arc<AtomicBool>,
+    /// Running `serve` handlers, see `drain`
+    in_flight: Arc<InFlight>,
+    /// Publishes made before the first connection of a lazily connected client
+    publish_buffer: Arc<PublishBuffer>,
+    /// Publishes made while the subscriptions are restored, see `resubscribe_barrier`
+    barrier_buffer: Arc<PublishBuffer>,
+    /// Streams of `NatsClientOptions::subscriptions` not taken yet, see `take_subscription`
+    preregistered: Mutex<HashMap<String, Subscription<Message>>>,
+    /// Requests sent again after a reconnect, see `request_idempotent`
+    resumable_requests: Arc<ResumableRequests>,
+    /// Subjects published to, counted against `max_distinct_publish_subjects`
+    publish_subjects: Mutex<HashSet<String>>,
+    /// When the client was created, for the uptime of `health_report`
+    started_at: Instant,
 }
 
 impl ::std::fmt::Debug for NatsClient {
@@ -247,10 +1344,158 @@ impl ::std::fmt::Debug for NatsClient {
 impl Stream for NatsClient {
     type Item = Op;
 
-    fn poll_next(self: Pin<&mut Self>,
-        cx: &mut Context<'_>,) -> Poll<Option<Self::Item>> {
-        self.unsub_receiver
-            .poll_next()
-            .map_err(|_| RatsioError::InnerBrokenChain)
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.unsub_receiver.poll_next_unpin(cx)
+    }
+}
+
+#[test]
+fn reconnect_guard_deregisters_on_drop() {
+    let handlers: Arc<RwLock<HandlerMap>> = Arc::new(RwLock::new(HashMap::default()));
+    let callback: ReconnectCallback = Arc::new(Mutex::new(|_| future::ready(()).boxed()));
+    handlers.write().insert("a".into(), callback.clone());
+    handlers.write().insert("b".into(), callback);
+
+    let guard = ReconnectHandlerGuard { hid: "a".into(), handlers: Arc::downgrade(&handlers) };
+    drop(guard);
+    assert!(!handlers.read().contains_key("a"));
+
+    let guard = ReconnectHandlerGuard { hid: "b".into(), handlers: Arc::downgrade(&handlers) };
+    guard.detach();
+    assert!(handlers.read().contains_key("b"));
+}
+
+#[test]
+fn forks_get_replayed_messages() {
+    use futures::executor::block_on_stream;
+
+    let (mut sink, rx) = SubscriptionSink::for_test(Subscribe { replay_buffer: 2, ..Default::default() });
+    let msg = |payload: &[u8]| Message { payload: payload.to_vec(), ..Default::default() };
+    sink.deliver(msg(b"1"));
+    sink.deliver(msg(b"2"));
+    sink.deliver(msg(b"3"));
+    let fork = sink.fork();
+    sink.deliver(msg(b"4"));
+    sink.close();
+
+    let payloads = |rx: mpsc::UnboundedReceiver<SinkMessage>| {
+        block_on_stream(rx)
+            .filter_map(|sink_msg| match sink_msg {
+                SinkMessage::Message(msg) => Some(msg.payload),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    drop(sink);
+    assert_eq!(payloads(fork), vec![b"2".to_vec(), b"3".to_vec(), b"4".to_vec()]);
+    assert_eq!(payloads(rx).len(), 4);
+}
+
+#[test]
+fn sinks_count_deliveries() {
+    let (mut sink, rx) = SubscriptionSink::for_test(Subscribe { subject: "orders.*".into(), ..Default::default() });
+    let msg = |payload: &[u8]| Message { payload: payload.to_vec(), ..Default::default() };
+    sink.deliver(msg(b"12"));
+    sink.deliver(msg(b"345"));
+    drop(rx);
+    sink.deliver(msg(b"6"));
+
+    let stats = sink.stats("7");
+    assert_eq!((stats.sid.as_str(), stats.subject.as_str()), ("7", "orders.*"));
+    assert_eq!((stats.delivered, stats.dropped, stats.bytes), (2, 1, 5));
+    assert!(stats.last_delivery.is_some());
+    assert_eq!(sink.queued.get(), 2);
+}
+
+#[test]
+fn multiplexer_blocks_until_the_stream_catches_up_or_pauses() {
+    use crate::ops::{PendingLimit, SlowConsumerPolicy};
+    use futures::{executor::block_on_stream, task::noop_waker_ref};
+
+    let limit = PendingLimit { max_msgs: 1, policy: SlowConsumerPolicy::Block };
+    let pending = Arc::new(PendingMessages::new(limit, QueueDepth::default()));
+    let cmd = Subscribe { sid: "1".into(), pending_limit: Some(limit), ..Default::default() };
+    let (mut sink, _) = SubscriptionSink::for_test(cmd);
+    sink.tx = SinkSender::Pending(PendingSender(pending.clone()));
+    let subs_map = Arc::new(RwLock::new(HashMap::new()));
+    subs_map.write().insert("1".to_string(), sink);
+    let (control_tx, control_rx) = counted();
+    let msg = |payload: &[u8]| Op::MSG(Message { sid: "1".into(), payload: payload.to_vec(), ..Default::default() });
+    let batches = stream::iter(vec![vec![msg(b"1"), msg(b"2"), Op::PING], vec![Op::PONG]]);
+    let mut multiplexer = Box::pin(multiplex(batches, subs_map.clone(), control_tx));
+
+    assert_eq!((&mut multiplexer).now_or_never(), None, "blocked behind the first message");
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert!(pending.poll_next(&mut cx).is_ready());
+    assert_eq!((&mut multiplexer).now_or_never(), None, "blocked behind the second message");
+
+    // Pausing lifts the block, the ops behind it get through.
+    subs_map.read()["1"].set_paused(true);
+    assert_eq!((&mut multiplexer).now_or_never(), Some(()));
+    drop(multiplexer);
+    assert_eq!(block_on_stream(control_rx).collect::<Vec<_>>(), vec![Op::PING, Op::PONG]);
+    assert_eq!(pending.stats().blocked, 2);
+}
+
+#[test]
+fn parses_uri_vecs() {
+    let uris = UriVec::parse(&["nats://a:4222", "tls://b"]).unwrap();
+    assert_eq!(uris.urls().iter().map(|url| url.port).collect::<Vec<_>>(), vec![4222, 4222]);
+    assert!(UriVec::parse(&["nats://a:4222", "ftp://b"]).is_err());
+    assert_eq!(UriVec::from(vec!["nats://a:4222", "ftp://b"]).urls().len(), 1);
+}
+
+#[test]
+fn registers_each_sid_once() {
+    let receiver = NatsClientMultiplexer {
+        control_tx: counted().0,
+        subs_map: Arc::new(RwLock::new(HashMap::new())),
+        log_target: "ratsio".into(),
+    };
+    let cmd = |sid: &str| Subscribe { sid: sid.into(), subject: sid.into(), ..Default::default() };
+    assert!(receiver.for_sid(cmd("1"), false, 2).is_ok());
+    match receiver.for_sid(Subscribe { subject: "other".into(), ..cmd("1") }, false, 2) {
+        Err(RatsioError::DuplicateSid(sid)) => assert_eq!(sid, "1"),
+        _ => panic!("duplicate sid registered"),
+    }
+    assert_eq!(receiver.subs_map.read()["1"].cmd.subject, "1", "the first sink is kept");
+    assert!(receiver.for_sid(cmd("2"), false, 2).is_ok());
+    match receiver.for_sid(cmd("3"), false, 2) {
+        Err(RatsioError::TooManySubscriptions(2)) => {}
+        _ => panic!("subscription limit not enforced"),
+    }
+}
+
+#[test]
+fn resubscribes_queue_groups_first() {
+    let cmd = |sid: &str, queue_group: Option<&str>| Subscribe {
+        sid: sid.into(),
+        queue_group: queue_group.map(String::from),
+        ..Default::default()
+    };
+    let sids = |order: ResubscribeOrder| {
+        let mut cmds = vec![cmd("1", None), cmd("2", Some("workers")), cmd("3", None), cmd("4", Some("workers"))];
+        order.order(&mut cmds);
+        cmds.into_iter().map(|cmd| cmd.sid).collect::<Vec<_>>()
+    };
+    assert_eq!(sids(ResubscribeOrder::QueueGroupsFirst), vec!["2", "4", "1", "3"]);
+    assert_eq!(sids(ResubscribeOrder::QueueGroupsLast), vec!["1", "3", "2", "4"]);
+    assert_eq!(sids(ResubscribeOrder::Any), vec!["1", "2", "3", "4"]);
+}
+
+#[test]
+fn writes_time_out() {
+    use crate::clock::MockClock;
+    use futures::executor::block_on;
+
+    let mock = MockClock::new();
+    let clock = SharedClock::new(mock.clone());
+    let mut wedged = write_within(&clock, Duration::from_millis(100), future::pending()).boxed();
+    assert!((&mut wedged).now_or_never().is_none());
+    mock.advance(Duration::from_millis(100));
+    match block_on(wedged) {
+        Err(RatsioError::WriteTimeout) => {}
+        other => panic!("expected a write timeout, got {:?}", other),
     }
+    assert!(block_on(write_within(&clock, Duration::from_millis(0), future::ready(Ok(())))).is_ok());
 }
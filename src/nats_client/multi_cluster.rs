@@ -0,0 +1,231 @@
+use crate::error::RatsioError;
+use crate::ops::{Message, Publish, Subscribe};
+use futures::{
+    future::{self, BoxFuture, Either},
+    prelude::*,
+    stream::BoxStream,
+};
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+
+use super::{NatsClient, NatsClientApi, NatsClientOptions, Subscription};
+
+/// Clients of several independent clusters behind one publish/subscribe/request API, each
+/// subject goes to the cluster its route maps it to, e.g. `eu.>` to the EU cluster.
+///
+/// ```no_run
+/// # async fn multi(eu: ratsio::nats_client::NatsClientOptions, us: ratsio::nats_client::NatsClientOptions)
+/// #     -> Result<(), ratsio::error::RatsioError> {
+/// use ratsio::nats_client::MultiClusterClient;
+/// let client = MultiClusterClient::connect(vec![("eu".into(), eu), ("us".into(), us)]).await?
+///     .route("eu.>", "eu")?
+///     .default_cluster("us")?;
+/// client.request("eu.orders.get".into(), b"42").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct MultiClusterClient {
+    clusters: BTreeMap<String, Arc<NatsClient>>,
+    routes: Routes,
+}
+
+/// Which cluster a subject goes to, apart from the clients of the clusters.
+#[derive(Clone, Debug, Default)]
+struct Routes {
+    /// Subject patterns and the cluster they go to, the first matching one applies
+    routes: Vec<(String, String)>,
+    /// Cluster of the subjects no route matches
+    default_cluster: Option<String>,
+}
+
+impl Routes {
+    fn cluster_for(&self, subject: &str) -> Result<&str, RatsioError> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| crate::subject::matches(pattern, subject))
+            .map(|(_, cluster)| cluster)
+            .or_else(|| self.default_cluster.as_ref())
+            .map(String::as_str)
+            .ok_or_else(|| RatsioError::NoClusterForSubject(subject.to_string()))
+    }
+}
+
+impl Debug for MultiClusterClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "MultiClusterClient {{ clusters: {:?}, routes: {:?}, default_cluster: {:?} }}",
+            self.clusters.keys().collect::<Vec<_>>(),
+            self.routes.routes,
+            self.routes.default_cluster
+        )
+    }
+}
+
+impl MultiClusterClient {
+    /// Routes to the clients of `clusters`, by cluster name. No subject is routed until routes
+    /// or a default cluster are added.
+    pub fn new(clusters: BTreeMap<String, Arc<NatsClient>>) -> Self {
+        MultiClusterClient {
+            clusters,
+            routes: Routes::default(),
+        }
+    }
+
+    /// Connects a client to each cluster, failing if one of them can't connect. The clusters that
+    /// did connect are closed then.
+    pub async fn connect(clusters: Vec<(String, NatsClientOptions)>) -> Result<Self, RatsioError> {
+        let connecting = clusters
+            .into_iter()
+            .map(|(name, opts)| NatsClient::connect(opts).map_ok(|client| (name, client)));
+        let mut clients = BTreeMap::new();
+        let mut failed = None;
+        for result in future::join_all(connecting).await {
+            match result {
+                Ok((name, client)) => {
+                    clients.insert(name, client);
+                }
+                Err(err) => {
+                    failed.get_or_insert(err);
+                }
+            }
+        }
+        match failed {
+            Some(err) => {
+                for client in clients.values() {
+                    client.close();
+                }
+                Err(err)
+            }
+            None => Ok(MultiClusterClient::new(clients)),
+        }
+    }
+
+    /// Routes the subjects matching `pattern` (wildcards allowed) to `cluster`. Routes are tried
+    /// in the order they were added.
+    pub fn route(mut self, pattern: &str, cluster: &str) -> Result<Self, RatsioError> {
+        crate::subject::validate(pattern)?;
+        self.check_cluster(cluster)?;
+        self.routes.routes.push((pattern.to_string(), cluster.to_string()));
+        Ok(self)
+    }
+
+    /// Routes the subjects no route matches to `cluster`, instead of failing them with
+    /// `RatsioError::NoClusterForSubject`.
+    pub fn default_cluster(mut self, cluster: &str) -> Result<Self, RatsioError> {
+        self.check_cluster(cluster)?;
+        self.routes.default_cluster = Some(cluster.to_string());
+        Ok(self)
+    }
+
+    fn check_cluster(&self, cluster: &str) -> Result<(), RatsioError> {
+        if self.clusters.contains_key(cluster) {
+            Ok(())
+        } else {
+            Err(RatsioError::ConfigError(format!("unknown cluster {}", cluster)))
+        }
+    }
+
+    /// The client of the cluster `name`.
+    pub fn cluster(&self, name: &str) -> Option<&Arc<NatsClient>> {
+        self.clusters.get(name)
+    }
+
+    /// The name of the cluster `subject` is routed to. Subscription subjects are routed as if
+    /// their wildcards were literal tokens, so `eu.*` goes where `eu.>` is routed to.
+    pub fn cluster_name_for(&self, subject: &str) -> Result<&str, RatsioError> {
+        self.routes.cluster_for(subject)
+    }
+
+    /// The client `subject` is routed to.
+    pub fn client_for(&self, subject: &str) -> Result<&Arc<NatsClient>, RatsioError> {
+        let name = self.cluster_name_for(subject)?;
+        Ok(&self.clusters[name])
+    }
+
+    pub fn publish(&self, cmd: Publish) -> impl Future<Output = Result<(), RatsioError>> + Send + Sync {
+        match self.client_for(&cmd.subject) {
+            Ok(client) => Either::Left(client.publish(cmd)),
+            Err(err) => Either::Right(future::err(err)),
+        }
+    }
+
+    /// Subscribes on the cluster `cmd.subject` is routed to.
+    pub fn subscribe(
+        &self,
+        cmd: Subscribe,
+    ) -> impl Future<Output = Result<Subscription<Message>, RatsioError>> + Send + Sync {
+        match self.client_for(&cmd.subject) {
+            Ok(client) => Either::Left(client.subscribe(cmd)),
+            Err(err) => Either::Right(future::err(err)),
+        }
+    }
+
+    /// Sends the request to the cluster `subject` is routed to, the reply comes back on it.
+    pub fn request(
+        &self,
+        subject: String,
+        payload: &[u8],
+    ) -> impl Future<Output = Result<Message, RatsioError>> + Send + Sync {
+        match self.client_for(&subject) {
+            Ok(client) => Either::Left(client.request(subject, payload)),
+            Err(err) => Either::Right(future::err(err)),
+        }
+    }
+
+    /// Resolves once every cluster processed the ops sent so far.
+    pub fn flush(&self) -> impl Future<Output = Result<(), RatsioError>> + Send {
+        future::try_join_all(self.clusters.values().map(|client| client.flush())).map_ok(|_| ())
+    }
+
+    /// Closes the clients of all clusters.
+    pub fn close(&self) {
+        for client in self.clusters.values() {
+            client.close();
+        }
+    }
+}
+
+impl NatsClientApi for MultiClusterClient {
+    fn publish(&self, cmd: Publish) -> BoxFuture<'static, Result<(), RatsioError>> {
+        MultiClusterClient::publish(self, cmd).boxed()
+    }
+
+    fn subscribe(&self, cmd: Subscribe) -> BoxFuture<'static, Result<BoxStream<'static, Message>, RatsioError>> {
+        MultiClusterClient::subscribe(self, cmd)
+            .map_ok(|subscription| subscription.boxed())
+            .boxed()
+    }
+
+    fn request(&self, subject: String, payload: Vec<u8>) -> BoxFuture<'static, Result<Message, RatsioError>> {
+        MultiClusterClient::request(self, subject, &payload).boxed()
+    }
+
+    fn flush(&self) -> BoxFuture<'static, Result<(), RatsioError>> {
+        MultiClusterClient::flush(self).boxed()
+    }
+}
+
+#[test]
+fn routes_in_order_then_to_the_default() {
+    let mut routes = Routes {
+        routes: vec![
+            ("eu.orders.>".to_string(), "eu-orders".to_string()),
+            ("eu.>".to_string(), "eu".to_string()),
+            ("*.orders.*".to_string(), "orders".to_string()),
+        ],
+        default_cluster: None,
+    };
+    assert_eq!(routes.cluster_for("eu.orders.new").unwrap(), "eu-orders");
+    assert_eq!(routes.cluster_for("eu.users.new").unwrap(), "eu");
+    assert_eq!(routes.cluster_for("us.orders.new").unwrap(), "orders");
+    assert_eq!(routes.cluster_for("eu.*").unwrap(), "eu");
+    match routes.cluster_for("us.users.new") {
+        Err(RatsioError::NoClusterForSubject(subject)) => assert_eq!(subject, "us.users.new"),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    routes.default_cluster = Some("us".to_string());
+    assert_eq!(routes.cluster_for("us.users.new").unwrap(), "us");
+    assert_eq!(routes.cluster_for("eu.orders.new").unwrap(), "eu-orders");
+}
@@ -0,0 +1,266 @@
+use crate::net::QueueDepth;
+use crate::ops::{Message, PendingLimit, SlowConsumerPolicy};
+use atomic_counter::{AtomicCounter, ConsistentCounter};
+use futures::task::{AtomicWaker, Context, Poll};
+use parking_lot::Mutex;
+use std::{collections::VecDeque, sync::Arc};
+
+use super::SinkMessage;
+
+/// How often the `Subscribe::pending_limit` of a subscription was hit, per policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlowConsumerStats {
+    /// Messages dropped on arrival with `SlowConsumerPolicy::DropNewest`, or with
+    /// `SlowConsumerPolicy::Block` while the subscription is paused
+    pub dropped_newest: usize,
+    /// Buffered messages dropped with `SlowConsumerPolicy::DropOldest`
+    pub dropped_oldest: usize,
+    /// Times reading from the connection was held up with `SlowConsumerPolicy::Block`
+    pub blocked: usize,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    /// Messages, errors and the end of the stream, in the order the stream takes them
+    items: VecDeque<SinkMessage>,
+    /// Messages in `items`, bounded by `PendingLimit::max_msgs`
+    messages: usize,
+    /// The sink is gone, the stream ends once the buffered items are taken
+    ended: bool,
+    /// The stream was dropped, nothing will be taken anymore
+    closed: bool,
+    /// The subscription is paused, `Block` does not hold up the connection meanwhile
+    paused: bool,
+}
+
+/// The bounded buffer between the multiplexer and a subscription stream with a
+/// `Subscribe::pending_limit`. The messages beyond the limit are dropped here, so a slow stream
+/// never holds more than `max_msgs` of them.
+#[derive(Debug)]
+pub(crate) struct PendingMessages {
+    limit: PendingLimit,
+    buffer: Mutex<Buffer>,
+    /// Shared with the sink, see `NatsClient::runtime_metrics`
+    queued: QueueDepth,
+    /// The multiplexer, waiting for the stream to catch up
+    capacity: AtomicWaker,
+    /// The stream, waiting for items
+    readable: AtomicWaker,
+    dropped_newest: ConsistentCounter,
+    dropped_oldest: ConsistentCounter,
+    blocked: ConsistentCounter,
+}
+
+impl PendingMessages {
+    pub(crate) fn new(limit: PendingLimit, queued: QueueDepth) -> Self {
+        PendingMessages {
+            limit,
+            buffer: Mutex::new(Buffer::default()),
+            queued,
+            capacity: AtomicWaker::new(),
+            readable: AtomicWaker::new(),
+            dropped_newest: ConsistentCounter::new(0),
+            dropped_oldest: ConsistentCounter::new(0),
+            blocked: ConsistentCounter::new(0),
+        }
+    }
+
+    /// Buffers a message for the stream, false if it is dropped instead.
+    pub(crate) fn push(&self, msg: Message) -> bool {
+        {
+            let mut buffer = self.buffer.lock();
+            if buffer.closed || buffer.ended {
+                return false;
+            }
+            if buffer.messages >= self.limit.max_msgs {
+                match self.limit.policy {
+                    SlowConsumerPolicy::DropOldest => {
+                        let oldest = buffer.items.iter().position(|item| match item {
+                            SinkMessage::Message(_) => true,
+                            _ => false,
+                        });
+                        if let Some(oldest) = oldest {
+                            buffer.items.remove(oldest);
+                            buffer.messages -= 1;
+                            self.queued.taken();
+                        }
+                        self.dropped_oldest.inc();
+                    }
+                    SlowConsumerPolicy::Block if !buffer.paused => {}
+                    _ => {
+                        self.dropped_newest.inc();
+                        return false;
+                    }
+                }
+            }
+            buffer.items.push_back(SinkMessage::Message(msg));
+            buffer.messages += 1;
+            self.queued.queued();
+        }
+        self.readable.wake();
+        true
+    }
+
+    /// Buffers an error or the end of the stream, whatever the limit.
+    pub(crate) fn push_control(&self, item: SinkMessage) {
+        {
+            let mut buffer = self.buffer.lock();
+            if buffer.closed {
+                return;
+            }
+            buffer.items.push_back(item);
+        }
+        self.readable.wake();
+    }
+
+    /// Whether the multiplexer has to wait before delivering the next message, counted as blocked.
+    pub(crate) fn must_block(&self) -> bool {
+        let full = self.limit.policy == SlowConsumerPolicy::Block && !self.has_capacity(&self.buffer.lock());
+        if full {
+            self.blocked.inc();
+        }
+        full
+    }
+
+    pub(crate) fn poll_capacity(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.capacity.register(cx.waker());
+        if self.has_capacity(&self.buffer.lock()) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn has_capacity(&self, buffer: &Buffer) -> bool {
+        buffer.closed || buffer.ended || buffer.paused || buffer.messages < self.limit.max_msgs
+    }
+
+    /// The next item for the stream, `None` once the sink is gone and all items were taken.
+    pub(crate) fn poll_next(&self, cx: &mut Context<'_>) -> Poll<Option<SinkMessage>> {
+        self.readable.register(cx.waker());
+        let item = {
+            let mut buffer = self.buffer.lock();
+            match buffer.items.pop_front() {
+                Some(item) => {
+                    if let SinkMessage::Message(_) = item {
+                        buffer.messages -= 1;
+                        self.queued.taken();
+                    }
+                    item
+                }
+                None if buffer.ended => return Poll::Ready(None),
+                None => return Poll::Pending,
+            }
+        };
+        self.capacity.wake();
+        Poll::Ready(Some(item))
+    }
+
+    /// While paused, `Block` drops the newest messages instead of holding up the connection
+    /// for a stream that is not read.
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.buffer.lock().paused = paused;
+        self.capacity.wake();
+    }
+
+    /// The sink is gone, no more messages are buffered.
+    fn end(&self) {
+        self.buffer.lock().ended = true;
+        self.readable.wake();
+        self.capacity.wake();
+    }
+
+    /// The stream is gone, the buffered messages are dropped and nothing is held up for it anymore.
+    fn close(&self) {
+        {
+            let mut buffer = self.buffer.lock();
+            buffer.closed = true;
+            for _ in 0..buffer.messages {
+                self.queued.taken();
+            }
+            buffer.messages = 0;
+            buffer.items.clear();
+        }
+        self.capacity.wake();
+    }
+
+    pub(crate) fn stats(&self) -> SlowConsumerStats {
+        SlowConsumerStats {
+            dropped_newest: self.dropped_newest.get(),
+            dropped_oldest: self.dropped_oldest.get(),
+            blocked: self.blocked.get(),
+        }
+    }
+}
+
+/// The end of the `PendingMessages` held by the sink, the stream ends once it is dropped.
+#[derive(Debug)]
+pub(crate) struct PendingSender(pub(crate) Arc<PendingMessages>);
+
+impl Drop for PendingSender {
+    fn drop(&mut self) {
+        self.0.end();
+    }
+}
+
+/// Closes the `PendingMessages` of a stream when the stream is dropped.
+pub(crate) struct CloseOnDrop(pub(crate) Arc<PendingMessages>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+#[test]
+fn pending_policies() {
+    use futures::task::noop_waker_ref;
+
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let limit = |policy| PendingLimit { max_msgs: 2, policy };
+    let msg = |payload: &[u8]| Message { payload: payload.to_vec(), ..Message::default() };
+    let payloads = |pending: &PendingMessages| {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut payloads = Vec::new();
+        while let Poll::Ready(Some(SinkMessage::Message(msg))) = pending.poll_next(&mut cx) {
+            payloads.push(msg.payload);
+        }
+        payloads
+    };
+
+    let queued = QueueDepth::default();
+    let pending = PendingMessages::new(limit(SlowConsumerPolicy::DropNewest), queued.clone());
+    assert!(pending.push(msg(b"1")) && pending.push(msg(b"2")));
+    assert!(!pending.push(msg(b"3")));
+    assert_eq!(payloads(&pending), vec![b"1".to_vec(), b"2".to_vec()]);
+    assert_eq!((pending.stats().dropped_newest, queued.get()), (1, 0));
+
+    // Messages 1 and 2 are buffered, 3 and 4 push them out and the buffer stays at 2.
+    let queued = QueueDepth::default();
+    let pending = PendingMessages::new(limit(SlowConsumerPolicy::DropOldest), queued.clone());
+    assert!((1..=4).all(|n| pending.push(msg(n.to_string().as_bytes()))));
+    assert_eq!(queued.get(), 2);
+    assert_eq!(payloads(&pending), vec![b"3".to_vec(), b"4".to_vec()]);
+    assert_eq!(pending.stats().dropped_oldest, 2);
+
+    let pending = PendingMessages::new(limit(SlowConsumerPolicy::Block), QueueDepth::default());
+    assert!(pending.push(msg(b"1")) && !pending.must_block());
+    assert!(pending.push(msg(b"2")) && pending.must_block());
+    assert_eq!(pending.poll_capacity(&mut cx), Poll::Pending);
+    pending.set_paused(true);
+    assert!(!pending.must_block());
+    assert!(!pending.push(msg(b"3")), "paused, the newest is dropped");
+    pending.set_paused(false);
+    assert_eq!(payloads(&pending).len(), 2);
+    assert_eq!((pending.stats().blocked, pending.stats().dropped_newest), (1, 1));
+
+    // The stream ends once the sink is gone and the buffered messages were taken.
+    let ended = Arc::new(PendingMessages::new(limit(SlowConsumerPolicy::Block), QueueDepth::default()));
+    ended.push(msg(b"1"));
+    drop(PendingSender(ended.clone()));
+    assert_eq!(payloads(&ended), vec![b"1".to_vec()]);
+    assert!(match ended.poll_next(&mut cx) {
+        Poll::Ready(None) => true,
+        _ => false,
+    });
+}
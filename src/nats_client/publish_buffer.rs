@@ -0,0 +1,106 @@
+use super::lanes::Priority;
+use crate::ops::Op;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+
+/// Holds the publishes of a client created with `NatsClientOptions::connect_lazily` until its
+/// first connection, so they are sent after the subscriptions made meanwhile. Also holds the
+/// publishes made while subscriptions are restored, see `NatsClientOptions::resubscribe_barrier`.
+#[derive(Debug)]
+pub(crate) struct PublishBuffer {
+    /// `None` once the buffered ops were sent, or for clients that connected right away
+    ops: Mutex<Option<VecDeque<(Op, Priority)>>>,
+    capacity: usize,
+}
+
+impl PublishBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        PublishBuffer {
+            ops: Mutex::new(Some(VecDeque::new())),
+            capacity,
+        }
+    }
+
+    /// A buffer that takes nothing, for clients that are connected from the start.
+    pub(crate) fn disabled() -> Self {
+        PublishBuffer {
+            ops: Mutex::new(None),
+            capacity: 0,
+        }
+    }
+
+    /// A buffer that takes nothing until `hold` is called, then up to `capacity` ops.
+    pub(crate) fn released(capacity: usize) -> Self {
+        PublishBuffer {
+            ops: Mutex::new(None),
+            capacity,
+        }
+    }
+
+    /// Buffers the ops pushed from now on until the next `release`.
+    pub(crate) fn hold(&self) {
+        self.ops.lock().get_or_insert_with(VecDeque::new);
+    }
+
+    /// Whether the client is still waiting for its first connection.
+    pub(crate) fn is_buffering(&self) -> bool {
+        self.ops.lock().is_some()
+    }
+
+    /// Buffers `op` until the first connection, gives it back once connected or when the buffer is full.
+    pub(crate) fn push(&self, op: Op, priority: Priority) -> Result<(), Op> {
+        match *self.ops.lock() {
+            Some(ref mut ops) if ops.len() < self.capacity => {
+                ops.push_back((op, priority));
+                Ok(())
+            }
+            _ => Err(op),
+        }
+    }
+
+    /// Ends buffering and hands the buffered ops to `send` in order, returns whether the client was
+    /// waiting for its first connection. Ops pushed meanwhile are given back, so they go out after.
+    pub(crate) fn release<F: FnMut(Op, Priority, bool)>(&self, mut send: F) -> bool {
+        let mut guard = self.ops.lock();
+        match guard.take() {
+            Some(ops) => {
+                let count = ops.len();
+                for (idx, (op, priority)) in ops.into_iter().enumerate() {
+                    send(op, priority, idx + 1 == count);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn buffers_until_released() {
+    let buffer = PublishBuffer::new(1);
+    assert!(buffer.is_buffering());
+    assert_eq!(buffer.push(Op::PING, Priority::Low), Ok(()));
+    assert_eq!(buffer.push(Op::PONG, Priority::High), Err(Op::PONG));
+    let mut sent = Vec::new();
+    assert!(buffer.release(|op, _, flush| sent.push((op, flush))));
+    assert_eq!(sent, vec![(Op::PING, true)]);
+    assert!(!buffer.is_buffering());
+    assert_eq!(buffer.push(Op::PING, Priority::Low), Err(Op::PING));
+    assert!(!buffer.release(|_, _, _| panic!("nothing buffered")));
+
+    assert!(!PublishBuffer::disabled().is_buffering());
+}
+
+#[test]
+fn holds_again_until_released() {
+    let buffer = PublishBuffer::released(2);
+    assert_eq!(buffer.push(Op::PING, Priority::Low), Err(Op::PING));
+    buffer.hold();
+    assert_eq!(buffer.push(Op::PING, Priority::Low), Ok(()));
+    buffer.hold();
+    assert!(buffer.is_buffering());
+    let mut sent = Vec::new();
+    assert!(buffer.release(|op, _, _| sent.push(op)));
+    assert_eq!(sent, vec![Op::PING]);
+    assert_eq!(buffer.push(Op::PING, Priority::Low), Err(Op::PING));
+}
@@ -0,0 +1,143 @@
+use crate::error::RatsioError;
+use crate::ops::{Op, Publish, ServerFeatures, ServerInfo};
+use atomic_counter::AtomicCounter;
+use parking_lot::RwLock;
+use std::{collections::VecDeque, sync::Arc};
+
+use super::{NatsClient, NatsClientSender};
+#[cfg(feature = "jwt")]
+use super::SigningPolicies;
+
+/// Number of publishes a `PublisherHandle` collects before queuing them, see `NatsClient::publisher`.
+pub const PUBLISHER_BATCH: usize = 64;
+
+/// A publish path for producers on many threads, see `NatsClient::publisher`. Each handle holds
+/// the writer queue of the current connection and collects publishes in a batch of its own, so
+/// publishing takes no lock shared with other handles until the batch is queued. The batch is
+/// queued once full, on `flush` and when the handle is dropped.
+///
+/// Publishes are signed and checked against the `max_payload` and header support of the server
+/// like those of `NatsClient::publish`, as known when the handle was created or moved to a new
+/// connection. Unlike there, payload validators, compression, `stamp_sent_at` and
+/// `max_distinct_publish_subjects` do not apply, and publishes of a lazily connected client made
+/// before its first connection are dropped instead of buffered.
+pub struct PublisherHandle {
+    /// Sender of the current connection, replaced on reconnect
+    current: Arc<RwLock<NatsClientSender>>,
+    sender: NatsClientSender,
+    server_info: Arc<RwLock<Option<ServerInfo>>>,
+    #[cfg(feature = "jwt")]
+    signing: Arc<RwLock<SigningPolicies>>,
+    /// `max_payload` of the server, `None` until its INFO was received
+    max_payload: Option<usize>,
+    /// Whether the server supports headers, assumed until its INFO was received
+    headers_supported: bool,
+    batch: VecDeque<Op>,
+    batch_size: usize,
+}
+
+impl PublisherHandle {
+    pub(crate) fn new(client: &NatsClient, batch_size: usize) -> Self {
+        let current = client.sender.clone();
+        let sender = current.read().clone();
+        let mut handle = PublisherHandle {
+            current,
+            sender,
+            server_info: client.server_info.clone(),
+            #[cfg(feature = "jwt")]
+            signing: client.signing.clone(),
+            max_payload: None,
+            headers_supported: true,
+            batch: VecDeque::with_capacity(batch_size),
+            batch_size: batch_size.max(1),
+        };
+        handle.refresh_server_limits();
+        handle
+    }
+
+    fn refresh_server_limits(&mut self) {
+        let server_info = self.server_info.read();
+        self.max_payload = server_info.as_ref().map(|info| info.max_payload);
+        self.headers_supported = server_info
+            .as_ref()
+            .map_or(true, |info| info.features().contains(ServerFeatures::HEADERS));
+    }
+
+    /// Adds `cmd` to the batch, queuing the batch once it holds `batch_size` publishes.
+    pub fn publish(&mut self, mut cmd: Publish) -> Result<(), RatsioError> {
+        if !self.headers_supported && cmd.headers.as_ref().map_or(false, |headers| !headers.is_empty()) {
+            return Err(RatsioError::UnsupportedByServer("headers".into()));
+        }
+        self.sign(&mut cmd)?;
+        if let Some(max_payload) = self.max_payload {
+            if cmd.total_size() > max_payload {
+                return Err(RatsioError::MaxPayloadOverflow(max_payload));
+            }
+        }
+        let op = Op::PUB(cmd);
+        op.validate()?;
+        self.batch.push_back(op);
+        if self.batch.len() >= self.batch_size {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "jwt")]
+    fn sign(&self, cmd: &mut Publish) -> Result<(), RatsioError> {
+        self.signing.read().sign(&cmd.subject, &cmd.payload, self.headers_supported, &mut cmd.headers)
+    }
+
+    #[cfg(not(feature = "jwt"))]
+    fn sign(&self, _cmd: &mut Publish) -> Result<(), RatsioError> {
+        Ok(())
+    }
+
+    /// Queues the publishes of the batch, flushing the connection after the last.
+    pub fn flush(&mut self) -> Result<(), RatsioError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        match self.sender.send_batch(&mut self.batch) {
+            Err(RatsioError::InnerBrokenChain) => {
+                // The connection was replaced, continue with its sender.
+                self.sender = self.current.read().clone();
+                self.refresh_server_limits();
+                let result = self.sender.send_batch(&mut self.batch);
+                self.sender.dropped_ops.add(self.batch.len());
+                self.batch.clear();
+                result
+            }
+            result => result,
+        }
+    }
+
+    /// Publishes collected and not queued yet.
+    pub fn pending(&self) -> usize {
+        self.batch.len()
+    }
+}
+
+impl Clone for PublisherHandle {
+    /// A handle with an empty batch of its own.
+    fn clone(&self) -> Self {
+        PublisherHandle {
+            current: self.current.clone(),
+            sender: self.sender.clone(),
+            server_info: self.server_info.clone(),
+            #[cfg(feature = "jwt")]
+            signing: self.signing.clone(),
+            max_payload: self.max_payload,
+            headers_supported: self.headers_supported,
+            batch: VecDeque::with_capacity(self.batch_size),
+            batch_size: self.batch_size,
+        }
+    }
+}
+
+impl Drop for PublisherHandle {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,71 @@
+use futures::{
+    task::{Context, Poll},
+    Stream,
+};
+use std::pin::Pin;
+
+/// Default of `NatsClientOptions::read_batch_budget`.
+pub const READ_BATCH_BUDGET: usize = 128;
+
+/// Collects all ops that are ready, up to `budget`, so the multiplexer handles every complete
+/// frame in the read buffer per wakeup instead of one op per iteration.
+pub(crate) struct ReadBatches<S> {
+    inner: S,
+    budget: usize,
+    /// The last batch used up the budget
+    yield_next: bool,
+    done: bool,
+}
+
+impl<S> ReadBatches<S> {
+    pub(crate) fn new(inner: S, budget: usize) -> Self {
+        ReadBatches {
+            inner,
+            budget: budget.max(1),
+            yield_next: false,
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for ReadBatches<S> {
+    type Item = Vec<S::Item>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        if self.yield_next {
+            self.yield_next = false;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let mut batch = Vec::new();
+        while batch.len() < self.budget {
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(item)) => batch.push(item),
+                Poll::Ready(None) => {
+                    self.done = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+        self.yield_next = batch.len() == self.budget;
+        if !batch.is_empty() {
+            Poll::Ready(Some(batch))
+        } else if self.done {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[test]
+fn batches_ready_items_up_to_budget() {
+    use futures::{executor::block_on, stream, StreamExt};
+
+    let batches: Vec<Vec<u32>> = block_on(ReadBatches::new(stream::iter(0..5), 2).collect());
+    assert_eq!(batches, vec![vec![0, 1], vec![2, 3], vec![4]]);
+}
@@ -0,0 +1,42 @@
+use crate::ops::Publish;
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+
+/// Requests of `NatsClient::request_idempotent` waiting for their reply, by the sid of their
+/// inbox, sent again once the client reconnected.
+#[derive(Debug, Default)]
+pub(crate) struct ResumableRequests {
+    requests: Mutex<HashMap<String, Publish>>,
+}
+
+/// A tracked request, it is forgotten once the guard is dropped with the request future.
+#[derive(Debug)]
+pub(crate) struct ResumeGuard(Arc<ResumableRequests>, String);
+
+impl ResumableRequests {
+    pub(crate) fn track(self: &Arc<Self>, sid: String, cmd: Publish) -> ResumeGuard {
+        self.requests.lock().insert(sid.clone(), cmd);
+        ResumeGuard(self.clone(), sid)
+    }
+
+    /// The requests still waiting, with the sid of their inbox.
+    pub(crate) fn pending(&self) -> Vec<(String, Publish)> {
+        self.requests.lock().iter().map(|(sid, cmd)| (sid.clone(), cmd.clone())).collect()
+    }
+}
+
+impl Drop for ResumeGuard {
+    fn drop(&mut self) {
+        self.0.requests.lock().remove(&self.1);
+    }
+}
+
+#[test]
+fn forgets_requests_with_their_guard() {
+    let requests = Arc::new(ResumableRequests::default());
+    let cmd = Publish::builder().subject("svc.get".into()).build().unwrap();
+    let guard = requests.track("7".into(), cmd.clone());
+    assert_eq!(requests.pending(), vec![("7".to_string(), cmd)]);
+    drop(guard);
+    assert!(requests.pending().is_empty());
+}
@@ -0,0 +1,182 @@
+use crate::ops::Message;
+use crate::subject;
+use futures::{future::BoxFuture, prelude::*};
+use std::sync::Arc;
+
+type RouteHandler = Arc<dyn Fn(Message) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Conditions on the headers of a message, all of them must hold for a route to be taken.
+/// Header names are matched case-insensitively.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeaderMatch {
+    equals: Vec<(String, String)>,
+    present: Vec<String>,
+}
+
+impl HeaderMatch {
+    pub fn new() -> Self {
+        HeaderMatch::default()
+    }
+
+    /// The header `name` has `value` among its values, e.g. `Content-Type: application/json`.
+    pub fn equals(mut self, name: &str, value: &str) -> Self {
+        self.equals.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The header `name` is set, whatever its value.
+    pub fn present(mut self, name: &str) -> Self {
+        self.present.push(name.to_string());
+        self
+    }
+
+    fn matches(&self, msg: &Message) -> bool {
+        if self.equals.is_empty() && self.present.is_empty() {
+            return true;
+        }
+        let headers = match msg.headers {
+            Some(ref headers) => headers,
+            None => return false,
+        };
+        self.equals.iter().all(|(name, value)| headers.get_all(name).any(|v| v == value))
+            && self.present.iter().all(|name| headers.get(name).is_some())
+    }
+}
+
+struct Route {
+    pattern: String,
+    headers: HeaderMatch,
+    handler: RouteHandler,
+}
+
+/// Dispatches the messages of one subscription, typically on a wildcard subject, to handlers by
+/// subject pattern and headers, so payloads don't have to be inspected to pick a deserializer.
+/// Routes are tried in the order they were added, the first match handles the message.
+///
+/// ```no_run
+/// # async fn route(client: std::sync::Arc<ratsio::nats_client::NatsClient>) -> Result<(), ratsio::error::RatsioError> {
+/// use futures::prelude::*;
+/// use ratsio::nats_client::{HeaderMatch, SubjectRouter};
+/// use ratsio::ops::Subscribe;
+///
+/// let stream = client.subscribe(Subscribe::builder().subject("orders.>".into()).build().unwrap()).await?;
+/// let router = SubjectRouter::new()
+///     .route_with_headers("orders.*", HeaderMatch::new().equals("Content-Type", "application/json"), |msg| {
+///         future::ready(println!("json order {:?}", msg.as_str()))
+///     })
+///     .route("orders.>", |msg| future::ready(println!("other order on {}", msg.subject)));
+/// router.run(stream).await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct SubjectRouter {
+    routes: Vec<Route>,
+    fallback: Option<RouteHandler>,
+}
+
+impl SubjectRouter {
+    pub fn new() -> Self {
+        SubjectRouter::default()
+    }
+
+    /// Routes messages on subjects matching the (possibly wildcarded) `pattern` to `handler`.
+    pub fn route<F, Fut>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.route_with_headers(pattern, HeaderMatch::new(), handler)
+    }
+
+    /// Like `route`, only for messages whose headers satisfy `headers`.
+    pub fn route_with_headers<F, Fut>(mut self, pattern: &str, headers: HeaderMatch, handler: F) -> Self
+    where
+        F: Fn(Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.routes.push(Route {
+            pattern: pattern.to_string(),
+            headers,
+            handler: Arc::new(move |msg| handler(msg).boxed()),
+        });
+        self
+    }
+
+    /// Handles the messages no route matched, they are dropped otherwise.
+    pub fn fallback<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.fallback = Some(Arc::new(move |msg| handler(msg).boxed()));
+        self
+    }
+
+    /// The future of the handler `msg` is routed to, `None` if it is dropped.
+    pub fn dispatch(&self, msg: Message) -> Option<BoxFuture<'static, ()>> {
+        let handler = self.routes.iter()
+            .find(|route| subject::matches(&route.pattern, &msg.subject) && route.headers.matches(&msg))
+            .map(|route| &route.handler)
+            .or_else(|| self.fallback.as_ref());
+        match handler {
+            Some(handler) => Some(handler(msg)),
+            None => {
+                debug!(target: "ratsio", "No route for message on {}, dropping it", &msg.subject);
+                None
+            }
+        }
+    }
+
+    /// Dispatches every message of `stream`, handling one message at a time.
+    pub async fn run<S: Stream<Item = Message> + Unpin>(self, mut stream: S) {
+        while let Some(msg) = stream.next().await {
+            if let Some(handled) = self.dispatch(msg) {
+                handled.await;
+            }
+        }
+    }
+}
+
+#[test]
+fn routes_by_subject_and_headers() {
+    use crate::headers::Headers;
+    use std::sync::Mutex;
+
+    let routed = Arc::new(Mutex::new(Vec::new()));
+    let record = |name: &'static str| {
+        let routed = routed.clone();
+        move |_: Message| {
+            routed.lock().unwrap().push(name);
+            future::ready(())
+        }
+    };
+    let router = SubjectRouter::new()
+        .route_with_headers("orders.*", HeaderMatch::new().equals("content-type", "application/json"), record("json"))
+        .route_with_headers("orders.*", HeaderMatch::new().present("Tenant"), record("tenant"))
+        .route("orders.*", record("plain"));
+    let message = |subject: &str, headers: &[(&str, &str)]| {
+        let mut msg = Message {
+            subject: subject.to_string(),
+            sid: String::from("1"),
+            reply_to: None,
+            payload: Vec::new(),
+            headers: None,
+        };
+        if !headers.is_empty() {
+            let mut h = Headers::new();
+            for (name, value) in headers {
+                h.append(name, value);
+            }
+            msg.headers = Some(h);
+        }
+        msg
+    };
+
+    let dispatch = |msg| router.dispatch(msg).map(futures::executor::block_on).is_some();
+    assert!(dispatch(message("orders.new", &[("Content-Type", "application/json")])));
+    assert!(dispatch(message("orders.new", &[("Content-Type", "text/plain"), ("tenant", "acme")])));
+    assert!(dispatch(message("orders.new", &[])));
+    assert!(!dispatch(message("payments.new", &[])));
+    assert_eq!(*routed.lock().unwrap(), vec!["json", "tenant", "plain"]);
+}
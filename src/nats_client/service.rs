@@ -0,0 +1,77 @@
+//! `tower::Service` adapters over NATS request/reply, so timeouts, retries, load shedding and
+//! metrics middleware from the tower ecosystem can be composed around NATS RPC.
+//!
+//! ```
+//! use bytes::Bytes;
+//! use futures::executor::block_on;
+//! use ratsio::nats_client::{MockNatsClient, MockReply, Request, RequestService};
+//! use std::sync::Arc;
+//! use tower_service::Service;
+//!
+//! let client = Arc::new(MockNatsClient::new());
+//! client.expect_request("prices.get", MockReply::Payload(b"42".to_vec()));
+//! let mut service = RequestService::new(client);
+//! let reply = block_on(service.call(Request::new("prices.get", Bytes::from_static(b"AAPL")))).unwrap();
+//! assert_eq!(reply.payload, b"42");
+//! ```
+use crate::error::RatsioError;
+use crate::ops::Message;
+use futures::{
+    future::BoxFuture,
+    task::{Context, Poll},
+};
+use std::{fmt, sync::Arc};
+use tower_service::Service;
+
+use super::NatsClientApi;
+
+/// A request to `subject`, the `body` is sent as payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request<T> {
+    pub subject: String,
+    pub body: T,
+}
+
+impl<T> Request<T> {
+    pub fn new(subject: &str, body: T) -> Self {
+        Request {
+            subject: subject.to_string(),
+            body,
+        }
+    }
+}
+
+/// Sends each `Request` with `NatsClientApi::request` and resolves to the reply. Always ready,
+/// the client queues the requests itself, put a `ConcurrencyLimit` or `LoadShed` in front to
+/// bound them. Requests fail like `NatsClient::request`, wrap the service in a tower `Timeout`
+/// to give up on slow replies.
+#[derive(Clone)]
+pub struct RequestService {
+    client: Arc<dyn NatsClientApi>,
+}
+
+impl RequestService {
+    pub fn new<C: NatsClientApi + 'static>(client: Arc<C>) -> Self {
+        RequestService { client }
+    }
+}
+
+impl fmt::Debug for RequestService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RequestService(client)")
+    }
+}
+
+impl<T: AsRef<[u8]>> Service<Request<T>> for RequestService {
+    type Response = Message;
+    type Error = RatsioError;
+    type Future = BoxFuture<'static, Result<Message, RatsioError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), RatsioError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<T>) -> Self::Future {
+        self.client.request(request.subject, request.body.as_ref().to_vec())
+    }
+}
@@ -0,0 +1,184 @@
+use crate::error::RatsioError;
+use crate::headers::{Headers, RATSIO_SIGNATURE, RATSIO_SIGNER, RATSIO_UNVERIFIED};
+use crate::ops::Message;
+use data_encoding::BASE64URL_NOPAD;
+use nkeys::KeyPair;
+use std::sync::Arc;
+
+/// Resolves the key the signature of a received message is checked with, see
+/// `NatsClient::add_signature_verifier`.
+pub trait SignerKeyResolver: Send + Sync {
+    /// The public nkey to check the signature of a message on `subject` with, given the public key
+    /// its publisher claims in `Ratsio-Signer`. `None` if that signer is not trusted for `subject`.
+    fn resolve(&self, subject: &str, signer: &str) -> Option<String>;
+}
+
+impl<F> SignerKeyResolver for F
+where
+    F: Fn(&str, &str) -> Option<String> + Send + Sync,
+{
+    fn resolve(&self, subject: &str, signer: &str) -> Option<String> {
+        (self)(subject, signer)
+    }
+}
+
+/// Trusts the signers in a fixed list of public nkeys, on all subjects.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedSigners {
+    keys: Vec<String>,
+}
+
+impl TrustedSigners {
+    pub fn new(keys: Vec<String>) -> Self {
+        TrustedSigners { keys }
+    }
+}
+
+impl SignerKeyResolver for TrustedSigners {
+    fn resolve(&self, _subject: &str, signer: &str) -> Option<String> {
+        self.keys.iter().find(|key| *key == signer).cloned()
+    }
+}
+
+/// What happens to received messages whose signature is missing, not trusted or does not match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnUnverified {
+    /// Dropped before they reach the subscription stream
+    Reject,
+    /// Delivered with a `Ratsio-Unverified` header giving the reason
+    Flag,
+}
+
+#[derive(Clone)]
+struct PayloadSigner {
+    pattern: String,
+    key: Arc<KeyPair>,
+}
+
+#[derive(Clone)]
+struct SignatureVerifier {
+    pattern: String,
+    resolver: Arc<dyn SignerKeyResolver>,
+    on_unverified: OnUnverified,
+}
+
+/// Signers and verifiers registered on a client, the first one matching a subject applies.
+#[derive(Clone, Default)]
+pub(crate) struct SigningPolicies {
+    signers: Vec<PayloadSigner>,
+    verifiers: Vec<SignatureVerifier>,
+}
+
+impl SigningPolicies {
+    pub(crate) fn add_signer(&mut self, pattern: String, key: KeyPair) {
+        self.signers.push(PayloadSigner { pattern, key: Arc::new(key) });
+    }
+
+    pub(crate) fn remove_signers(&mut self, pattern: &str) {
+        self.signers.retain(|signer| signer.pattern != pattern);
+    }
+
+    pub(crate) fn add_verifier(
+        &mut self,
+        pattern: String,
+        resolver: Arc<dyn SignerKeyResolver>,
+        on_unverified: OnUnverified,
+    ) {
+        self.verifiers.push(SignatureVerifier { pattern, resolver, on_unverified });
+    }
+
+    pub(crate) fn remove_verifiers(&mut self, pattern: &str) {
+        self.verifiers.retain(|verifier| verifier.pattern != pattern);
+    }
+
+    /// Signs `subject` and `payload` into `headers` if a signer is registered for `subject`. Signed payloads
+    /// need headers, publishing them unsigned would only get them rejected.
+    pub(crate) fn sign(
+        &self,
+        subject: &str,
+        payload: &[u8],
+        headers_supported: bool,
+        headers: &mut Option<Headers>,
+    ) -> Result<(), RatsioError> {
+        let signer = match self.signers.iter().find(|signer| crate::subject::matches(&signer.pattern, subject)) {
+            Some(signer) => signer,
+            None => return Ok(()),
+        };
+        if !headers_supported {
+            return Err(RatsioError::UnsupportedByServer("headers".into()));
+        }
+        let sig = signer.key.sign(&signed_bytes(subject, payload))
+            .map_err(|err| RatsioError::GenericError(format!("Unable to sign the payload => {}", err)))?;
+        let headers = headers.get_or_insert_with(Headers::new);
+        headers.insert(RATSIO_SIGNER, &signer.key.public_key());
+        headers.insert(RATSIO_SIGNATURE, &BASE64URL_NOPAD.encode(&sig));
+        Ok(())
+    }
+
+    /// Checks the signature of `msg` if a verifier is registered for its subject. `Err(reason)` if
+    /// it is to be dropped, flagged messages are marked with a `Ratsio-Unverified` header.
+    pub(crate) fn verify(&self, msg: &mut Message) -> Result<(), String> {
+        let verifier = match self.verifiers.iter().find(|verifier| crate::subject::matches(&verifier.pattern, &msg.subject)) {
+            Some(verifier) => verifier,
+            None => return Ok(()),
+        };
+        let reason = match check_signature(verifier.resolver.as_ref(), msg) {
+            Ok(()) => return Ok(()),
+            Err(reason) => reason,
+        };
+        match verifier.on_unverified {
+            OnUnverified::Reject => Err(reason),
+            OnUnverified::Flag => {
+                msg.headers.get_or_insert_with(Headers::new).insert(RATSIO_UNVERIFIED, &reason);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What is signed, the subject along with the payload so a signed message can't be replayed on
+/// another subject.
+fn signed_bytes(subject: &str, payload: &[u8]) -> Vec<u8> {
+    let mut signed = Vec::with_capacity(subject.len() + 1 + payload.len());
+    signed.extend_from_slice(subject.as_bytes());
+    signed.push(0);
+    signed.extend_from_slice(payload);
+    signed
+}
+
+fn check_signature(resolver: &dyn SignerKeyResolver, msg: &Message) -> Result<(), String> {
+    let header = |name| msg.headers.as_ref().and_then(|headers| headers.get(name)).ok_or("not signed");
+    let signer = header(RATSIO_SIGNER)?;
+    let sig = BASE64URL_NOPAD.decode(header(RATSIO_SIGNATURE)?.as_bytes()).map_err(|err| err.to_string())?;
+    let key = resolver.resolve(&msg.subject, signer)
+        .ok_or_else(|| format!("signer {} is not trusted on {}", signer, msg.subject))?;
+    KeyPair::from_public_key(&key)
+        .and_then(|key| key.verify(&signed_bytes(&msg.subject, &msg.payload), &sig))
+        .map_err(|_| format!("signature does not match the key of {}", signer))
+}
+
+#[test]
+fn verifies_signed_payloads() {
+    let key = KeyPair::new_account();
+    let trusted = TrustedSigners::new(vec![key.public_key()]);
+    let mut policies = SigningPolicies::default();
+    policies.add_signer("orders.>".into(), key);
+    policies.add_verifier("orders.>".into(), Arc::new(trusted), OnUnverified::Reject);
+    policies.add_verifier(">".into(), Arc::new(|_: &str, _: &str| -> Option<String> { None }), OnUnverified::Flag);
+
+    let mut headers = None;
+    policies.sign("orders.new", b"42", true, &mut headers).unwrap();
+    let mut msg = Message { subject: "orders.new".into(), payload: b"42".to_vec(), headers, ..Message::default() };
+    assert_eq!(policies.verify(&mut msg), Ok(()));
+
+    let mut replayed = Message { subject: "orders.cancel".into(), ..msg.clone() };
+    assert!(policies.verify(&mut replayed).is_err(), "a copy on another subject verified");
+
+    msg.payload = b"43".to_vec();
+    assert!(policies.verify(&mut msg).is_err());
+    assert!(policies.sign("orders.new", b"42", false, &mut None).is_err());
+
+    let mut unsigned = Message { subject: "logs.app".into(), payload: b"42".to_vec(), ..Message::default() };
+    assert_eq!(policies.verify(&mut unsigned), Ok(()));
+    assert_eq!(unsigned.headers.unwrap().get(RATSIO_UNVERIFIED), Some("not signed"));
+}
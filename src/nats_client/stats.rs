@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+
+/// Subject under which messages are counted once `stats_max_subjects` prefixes are tracked.
+pub const OTHER_SUBJECTS: &str = ">";
+
+/// Upper bounds of the `LatencyHistogram` buckets, in milliseconds.
+pub const LATENCY_BUCKETS_MS: &[u64] = &[1, 2, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+/// Time from publish to delivery of the messages of a subscription that were stamped by their
+/// publisher, see `NatsClientOptions::stamp_sent_at`. Measured against the clock of the
+/// subscriber, so it is only as accurate as the clocks of the hosts are in sync, messages that
+/// appear to come from the future count as instant.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyHistogram {
+    /// Messages by bucket of `LATENCY_BUCKETS_MS`, plus one bucket for the slower ones
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: Duration,
+    pub max: Duration,
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&mut self, latency: Duration) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        }
+        let millis = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS.iter().position(|bound| millis < *bound).unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos((self.sum.as_nanos() / u128::from(self.count)) as u64))
+    }
+
+    /// The upper bound of the bucket holding the `percentile`th latency, the `max` for the
+    /// slowest bucket. `None` without messages.
+    pub fn percentile(&self, percentile: u8) -> Option<Duration> {
+        let rank = (u64::from(percentile.min(100)) * self.count + 99) / 100;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                return Some(LATENCY_BUCKETS_MS.get(bucket).map_or(self.max, |bound| Duration::from_millis(*bound)));
+            }
+        }
+        None
+    }
+}
+
+/// Messages and payload bytes published on a subject prefix, see `NatsClient::stats_by_subject`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubjectStats {
+    pub subject: String,
+    pub msgs: u64,
+    pub bytes: u64,
+}
+
+/// Messages received on a subscription, see `NatsClient::subscription_stats`. Counted for the
+/// stream of the subscription, not for its forks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubscriptionStats {
+    pub sid: String,
+    pub subject: String,
+    /// Messages passed on to the stream
+    pub delivered: u64,
+    /// Messages dropped, over the `Subscribe::pending_limit` or because the stream was dropped
+    pub dropped: u64,
+    /// Payload bytes of the delivered messages
+    pub bytes: u64,
+    /// When the last message was delivered, `None` if none was yet
+    pub last_delivery: Option<SystemTime>,
+    /// Latencies of the delivered messages that carry a `Ratsio-Sent-At` header
+    pub latency: LatencyHistogram,
+}
+
+impl SubscriptionStats {
+    pub(crate) fn record_delivery(&mut self, bytes: usize, sent_at: Option<SystemTime>) {
+        let now = SystemTime::now();
+        self.delivered += 1;
+        self.bytes += bytes as u64;
+        self.last_delivery = Some(now);
+        if let Some(sent_at) = sent_at {
+            self.latency.record(now.duration_since(sent_at).unwrap_or_default());
+        }
+    }
+}
+
+/// Depths of the internal queues of a client and the health of its tasks, see
+/// `NatsClient::runtime_metrics`. A queue that keeps growing, or a task that is not alive, tells
+/// where messages stopped flowing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuntimeMetrics {
+    /// Ops read from the server and not handled yet by the control task: PING, PONG, INFO, ERR...
+    pub control_queue: usize,
+    /// Messages passed on to each subscription stream and not taken from it yet, by sid
+    pub subscription_queues: BTreeMap<String, usize>,
+    /// Publish to delivery latencies of the subscriptions that received stamped messages, by sid
+    pub subscription_latencies: BTreeMap<String, LatencyHistogram>,
+    /// New connections not taken over by the reconnect task yet
+    pub reconnect_queue: usize,
+    /// Whether each internal task is running, by name: `sender`, `multiplexer` and `control` run
+    /// while connected, `ping`, `read_timeout` and `reconnect` as long as the client
+    pub tasks: BTreeMap<String, bool>,
+}
+
+/// Publish counters per subject prefix, shared by the senders of a client across reconnects.
+#[derive(Debug, Default)]
+pub(crate) struct SubjectStatsRegistry {
+    depth: usize,
+    max_subjects: usize,
+    counts: HashMap<String, (u64, u64)>,
+}
+
+impl SubjectStatsRegistry {
+    /// `depth` is the number of subject tokens making up a prefix, 0 for the full subject.
+    /// At most `max_subjects` prefixes are tracked, 0 disables the statistics.
+    pub(crate) fn new(depth: usize, max_subjects: usize) -> Self {
+        SubjectStatsRegistry {
+            depth,
+            max_subjects,
+            counts: HashMap::default(),
+        }
+    }
+
+    fn prefix<'a>(&self, subject: &'a str) -> &'a str {
+        if self.depth == 0 {
+            return subject;
+        }
+        match subject.match_indices('.').nth(self.depth - 1) {
+            Some((idx, _)) => &subject[..idx],
+            None => subject,
+        }
+    }
+
+    pub(crate) fn record(&mut self, subject: &str, bytes: usize) {
+        if self.max_subjects == 0 {
+            return;
+        }
+        let prefix = self.prefix(subject);
+        let key = if self.counts.contains_key(prefix) || self.counts.len() < self.max_subjects {
+            prefix
+        } else {
+            OTHER_SUBJECTS
+        };
+        let entry = self.counts.entry(key.to_string()).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += bytes as u64;
+    }
+
+    /// All tracked prefixes, the ones with the most bytes first.
+    pub(crate) fn snapshot(&self) -> Vec<SubjectStats> {
+        let mut stats: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(subject, (msgs, bytes))| SubjectStats {
+                subject: subject.clone(),
+                msgs: *msgs,
+                bytes: *bytes,
+            })
+            .collect();
+        stats.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.subject.cmp(&b.subject)));
+        stats
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+#[test]
+fn counts_by_prefix() {
+    let mut registry = SubjectStatsRegistry::new(2, 10);
+    registry.record("orders.eu.created", 10);
+    registry.record("orders.eu.deleted", 5);
+    registry.record("orders", 1);
+    let stats = registry.snapshot();
+    assert_eq!(stats[0], SubjectStats { subject: "orders.eu".into(), msgs: 2, bytes: 15 });
+    assert_eq!(stats[1], SubjectStats { subject: "orders".into(), msgs: 1, bytes: 1 });
+}
+
+#[test]
+fn caps_tracked_subjects() {
+    let mut registry = SubjectStatsRegistry::new(0, 2);
+    registry.record("a", 1);
+    registry.record("b", 1);
+    registry.record("c", 3);
+    registry.record("d", 3);
+    registry.record("a", 1);
+    let stats = registry.snapshot();
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats[0], SubjectStats { subject: OTHER_SUBJECTS.into(), msgs: 2, bytes: 6 });
+    assert_eq!(stats[1], SubjectStats { subject: "a".into(), msgs: 2, bytes: 2 });
+
+    let mut disabled = SubjectStatsRegistry::new(0, 0);
+    disabled.record("a", 1);
+    assert!(disabled.snapshot().is_empty());
+}
+
+#[test]
+fn latency_histogram_percentiles() {
+    let mut histogram = LatencyHistogram::default();
+    assert_eq!(histogram.percentile(50), None);
+    assert_eq!(histogram.mean(), None);
+    for millis in 0..90 {
+        histogram.record(Duration::from_millis(millis % 4));
+    }
+    for _ in 0..10 {
+        histogram.record(Duration::from_secs(20));
+    }
+    assert_eq!(histogram.count, 100);
+    assert_eq!(histogram.percentile(50), Some(Duration::from_millis(5)));
+    assert_eq!(histogram.percentile(99), Some(Duration::from_secs(20)));
+    assert_eq!(histogram.max, Duration::from_secs(20));
+    assert!(histogram.mean().unwrap() > Duration::from_secs(2));
+}
@@ -0,0 +1,344 @@
+use crate::error::{RatsioError, ServiceError};
+use crate::headers::{Headers, RATSIO_STREAM_CREDIT, RATSIO_STREAM_END, RATSIO_STREAM_SEQ};
+use crate::ops::{Message, Op, Publish, Subscribe, UnSubscribe};
+use futures::{
+    prelude::*,
+    task::{Context, Poll},
+    Stream,
+};
+use std::{
+    pin::Pin,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use super::{NatsClient, Priority, Subscription};
+
+/// Replies a `ReplyStream` grants its responder up front, see `NatsClient::stream_request`.
+pub const STREAM_CREDIT: u32 = 64;
+
+/// How long a responder waits for more credit before it gives up on a caller that went away.
+const CREDIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The replies to a `NatsClient::stream_request`, in the order the responder sent them. Each
+/// reply is numbered, the stream fails with `RatsioError::FramesLost` if one went missing, with
+/// `RatsioError::NoResponders` if nobody serves the subject and with `RatsioError::ServiceError`
+/// if the responder's stream failed. It ends with the responder's end-of-stream reply.
+///
+/// The responder sends no more than the credit granted, the stream grants more as the replies
+/// are consumed. Dropping the stream before its end cancels it at the responder.
+pub struct ReplyStream {
+    client: Arc<NatsClient>,
+    inbox: Subscription<Message>,
+    /// Private inbox of the responder the credit goes to, announced with its first reply
+    responder_inbox: Option<String>,
+    window: CreditWindow,
+    /// Sequence of the last reply received
+    received: u64,
+    done: bool,
+}
+
+impl ReplyStream {
+    pub(crate) fn request(
+        client: Arc<NatsClient>,
+        subject: String,
+        payload: &[u8],
+        credit: u32,
+    ) -> impl Future<Output = Result<ReplyStream, RatsioError>> + Send {
+        let payload = payload.to_vec();
+        let credit = credit.max(1);
+        async move {
+            let inbox = Publish::generate_reply_to();
+            let subscription = client.subscribe(Subscribe {
+                subject: inbox.clone(),
+                ..Subscribe::default()
+            }).await?;
+            client.publish(Publish {
+                subject,
+                payload,
+                reply_to: Some(inbox),
+                headers: Some(credit_headers(credit)),
+            }).await?;
+            Ok(ReplyStream {
+                client,
+                inbox: subscription,
+                responder_inbox: None,
+                window: CreditWindow::new(credit),
+                received: 0,
+                done: false,
+            })
+        }
+    }
+
+    /// Stops receiving, telling the responder to stop sending if the stream did not end yet.
+    pub fn cancel(&mut self) {
+        if self.done {
+            return;
+        }
+        self.finish();
+        if let Some(responder_inbox) = self.responder_inbox.take() {
+            let mut headers = Headers::new();
+            headers.insert(RATSIO_STREAM_END, "cancel");
+            let _ = send(&self.client, responder_inbox, Vec::new(), headers);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        let _ = self.client.unsubscribe(UnSubscribe {
+            sid: self.inbox.sid().to_string(),
+            max_msgs: None,
+        });
+    }
+}
+
+impl Stream for ReplyStream {
+    type Item = Result<Message, RatsioError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let reply = match self.inbox.poll_next_unpin(cx) {
+            Poll::Ready(Some(reply)) => reply,
+            Poll::Ready(None) => {
+                self.done = true;
+                return Poll::Ready(None);
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+        let headers = reply.headers.clone().unwrap_or_default();
+        if headers.status() == Some(503) {
+            self.finish();
+            return Poll::Ready(Some(Err(RatsioError::NoResponders)));
+        }
+        if reply.reply_to.is_some() {
+            self.responder_inbox = reply.reply_to.clone();
+        }
+        let seq = headers.get(RATSIO_STREAM_SEQ).and_then(|seq| seq.parse::<u64>().ok()).unwrap_or(0);
+        let expected = self.received + 1;
+        if seq != expected {
+            self.cancel();
+            return Poll::Ready(Some(Err(RatsioError::FramesLost { expected, received: seq })));
+        }
+        self.received = seq;
+        if headers.get(RATSIO_STREAM_END).is_some() {
+            self.finish();
+            return Poll::Ready(headers.service_error().map(|err| Err(RatsioError::ServiceError(err))));
+        }
+        if let (Some(grant), Some(responder_inbox)) = (self.window.consumed(), self.responder_inbox.clone()) {
+            let _ = send(&self.client, responder_inbox, Vec::new(), credit_headers(grant));
+        }
+        Poll::Ready(Some(Ok(reply)))
+    }
+}
+
+impl Drop for ReplyStream {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Answers the stream requests on `subject` with the replies of `handler`, see
+/// `NatsClient::serve_stream`.
+pub(crate) fn serve<F, S>(
+    client: Arc<NatsClient>,
+    subject: String,
+    queue_group: Option<String>,
+    handler: F,
+) -> impl Future<Output = Result<String, RatsioError>> + Send
+where
+    F: Fn(Message) -> S + Send + Sync + 'static,
+    S: Stream<Item = Result<Vec<u8>, RatsioError>> + Send + 'static,
+{
+    let cmd = Subscribe {
+        subject,
+        sid: Subscribe::generate_sid(),
+        queue_group,
+        ..Subscribe::default()
+    };
+    let sid = cmd.sid.clone();
+    let handler = Arc::new(handler);
+    let subscribed = client.subscribe(cmd);
+    async move {
+        let requests = subscribed.await?;
+        tokio::spawn(requests.for_each(move |request| {
+            let credit = request.headers.as_ref()
+                .and_then(|headers| headers.get(RATSIO_STREAM_CREDIT))
+                .and_then(|credit| credit.parse::<u32>().ok());
+            match (request.reply_to.clone(), credit) {
+                (Some(reply_to), Some(credit)) => {
+                    let running = client.in_flight.enter();
+                    let replies = (*handler)(request);
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        let _running = running;
+                        respond(client, reply_to, credit, replies).await
+                    });
+                }
+                _ => debug!(target: client.log_target(), "Ignoring request without stream credit on {}", &request.subject),
+            }
+            future::ready(())
+        }));
+        Ok(sid)
+    }
+}
+
+/// Sends `replies` to the caller's `reply_to` as long as it grants credit, then the end-of-stream
+/// reply.
+async fn respond<S>(client: Arc<NatsClient>, reply_to: String, credit: u32, replies: S)
+where
+    S: Stream<Item = Result<Vec<u8>, RatsioError>> + Send + 'static,
+{
+    let credit_inbox = Publish::generate_reply_to();
+    let mut grants = match client.subscribe(Subscribe { subject: credit_inbox.clone(), ..Subscribe::default() }).await {
+        Ok(grants) => grants,
+        Err(err) => {
+            warn!(target: client.log_target(), "Unable to answer stream request from {} => {}", &reply_to, err);
+            return;
+        }
+    };
+    let clock = client.opts.read().clock.clone();
+    let mut replies = replies.boxed();
+    let (mut credit, mut seq) = (u64::from(credit), 0u64);
+    let end = loop {
+        // Credit and cancellations that arrived meanwhile.
+        let mut cancelled = false;
+        while let Some(Some(grant)) = grants.next().now_or_never() {
+            cancelled |= grant_credit(&mut credit, &grant);
+        }
+        if credit == 0 && !cancelled {
+            match clock.timeout(CREDIT_TIMEOUT, grants.next()).await {
+                Some(Some(grant)) => cancelled = grant_credit(&mut credit, &grant),
+                _ => {
+                    debug!(target: client.log_target(), "No credit from {}, ending the stream", &reply_to);
+                    break None;
+                }
+            }
+        }
+        if cancelled {
+            debug!(target: client.log_target(), "Stream to {} cancelled by the caller", &reply_to);
+            break None;
+        }
+        if credit == 0 {
+            continue;
+        }
+        let payload = match replies.next().await {
+            Some(Ok(payload)) => payload,
+            Some(Err(RatsioError::ServiceError(err))) => break Some(Some(err)),
+            Some(Err(err)) => break Some(Some(ServiceError::new(500, &err.to_string()))),
+            None => break Some(None),
+        };
+        seq += 1;
+        credit -= 1;
+        let mut headers = Headers::new();
+        headers.insert(RATSIO_STREAM_SEQ, &seq.to_string());
+        if let Err(err) = send_reply(&client, &reply_to, &credit_inbox, payload, headers) {
+            warn!(target: client.log_target(), "Unable to send stream reply to {} => {}", &reply_to, err);
+            break None;
+        }
+    };
+    if let Some(err) = end {
+        let mut headers = Headers::new();
+        headers.insert(RATSIO_STREAM_SEQ, &(seq + 1).to_string());
+        headers.insert(RATSIO_STREAM_END, "end");
+        if let Some(ref err) = err {
+            headers.set_service_error(err);
+        }
+        let _ = send_reply(&client, &reply_to, &credit_inbox, Vec::new(), headers);
+    }
+    let _ = client.unsubscribe(UnSubscribe {
+        sid: grants.sid().to_string(),
+        max_msgs: None,
+    });
+}
+
+/// Adds the credit granted by `grant` to `credit`, true if the caller cancelled instead.
+fn grant_credit(credit: &mut u64, grant: &Message) -> bool {
+    let headers = grant.headers.clone().unwrap_or_default();
+    if headers.get(RATSIO_STREAM_END).is_some() {
+        return true;
+    }
+    *credit += headers.get(RATSIO_STREAM_CREDIT).and_then(|credit| credit.parse::<u64>().ok()).unwrap_or(0);
+    false
+}
+
+fn send_reply(
+    client: &NatsClient,
+    reply_to: &str,
+    credit_inbox: &str,
+    payload: Vec<u8>,
+    headers: Headers,
+) -> Result<(), RatsioError> {
+    send_publish(client, Publish {
+        subject: reply_to.to_string(),
+        payload,
+        reply_to: Some(credit_inbox.to_string()),
+        headers: Some(headers),
+    })
+}
+
+fn send(client: &NatsClient, subject: String, payload: Vec<u8>, headers: Headers) -> Result<(), RatsioError> {
+    send_publish(client, Publish {
+        subject,
+        payload,
+        reply_to: None,
+        headers: Some(headers),
+    })
+}
+
+/// Queues `cmd` ahead of the bulk publishes like `serve` replies, not counted against the
+/// distinct publish subjects since every stream has its own inboxes.
+fn send_publish(client: &NatsClient, cmd: Publish) -> Result<(), RatsioError> {
+    let sender = client.sender.read();
+    let flush = sender.low_latency.load(Ordering::SeqCst);
+    sender.send_with_priority(Op::PUB(cmd), Priority::High, flush)
+}
+
+fn credit_headers(credit: u32) -> Headers {
+    let mut headers = Headers::new();
+    headers.insert(RATSIO_STREAM_CREDIT, &credit.to_string());
+    headers
+}
+
+/// Credit a caller grants again once half of its window was consumed, so the responder rarely
+/// runs dry while the caller keeps up.
+#[derive(Debug)]
+struct CreditWindow {
+    window: u32,
+    consumed: u32,
+}
+
+impl CreditWindow {
+    fn new(window: u32) -> Self {
+        CreditWindow { window, consumed: 0 }
+    }
+
+    /// Records a consumed reply, the credit to grant if it is time to.
+    fn consumed(&mut self) -> Option<u32> {
+        self.consumed += 1;
+        if self.consumed < (self.window / 2).max(1) {
+            return None;
+        }
+        Some(std::mem::replace(&mut self.consumed, 0))
+    }
+}
+
+#[test]
+fn grants_credit_every_half_window() {
+    let mut window = CreditWindow::new(4);
+    assert_eq!(window.consumed(), None);
+    assert_eq!(window.consumed(), Some(2));
+    assert_eq!(window.consumed(), None);
+    assert_eq!(window.consumed(), Some(2));
+
+    let mut single = CreditWindow::new(1);
+    assert_eq!((single.consumed(), single.consumed()), (Some(1), Some(1)));
+
+    let (mut credit, mut grant) = (0, Message::default());
+    grant.headers = Some(credit_headers(3));
+    assert!(!grant_credit(&mut credit, &grant));
+    assert_eq!(credit, 3);
+    grant.headers.as_mut().unwrap().insert(RATSIO_STREAM_END, "cancel");
+    assert!(grant_credit(&mut credit, &grant));
+}
@@ -0,0 +1,330 @@
+use crate::clock::SharedClock;
+use crate::error::RatsioError;
+use crate::ops::{Message, Op, SharedMessage, UnSubscribe};
+use futures::{
+    channel::mpsc,
+    future,
+    prelude::*,
+    task::{AtomicWaker, Context, Poll},
+    Stream,
+};
+use parking_lot::RwLock;
+use std::{
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::{NatsClientMultiplexer, NatsClientSender, SinkMessage, SlowConsumerStats};
+
+/// The stream of a subscription created with `NatsClient::subscribe` or `NatsClient::subscribe_with_errors`.
+/// It can be paused without giving up the subscription, see `Subscription::pause`. Dropping it
+/// unsubscribes, unless disabled with `NatsClientOptions::unsubscribe_on_drop` or
+/// `Subscription::set_unsubscribe_on_drop`.
+pub struct Subscription<T> {
+    sid: String,
+    stream: Pin<Box<dyn Stream<Item = T> + Send + Sync>>,
+    paused: AtomicBool,
+    unsubscribe_on_drop: AtomicBool,
+    waker: AtomicWaker,
+    sender: Arc<RwLock<NatsClientSender>>,
+    receiver: Arc<RwLock<NatsClientMultiplexer>>,
+    clock: SharedClock,
+}
+
+impl<T> Subscription<T> {
+    pub(crate) fn new<S>(
+        sid: String,
+        stream: S,
+        sender: Arc<RwLock<NatsClientSender>>,
+        receiver: Arc<RwLock<NatsClientMultiplexer>>,
+        unsubscribe_on_drop: bool,
+        clock: SharedClock,
+    ) -> Self
+    where
+        S: Stream<Item = T> + Send + Sync + 'static,
+    {
+        Subscription {
+            sid,
+            stream: Box::pin(stream),
+            paused: AtomicBool::new(false),
+            unsubscribe_on_drop: AtomicBool::new(unsubscribe_on_drop),
+            waker: AtomicWaker::new(),
+            sender,
+            receiver,
+            clock,
+        }
+    }
+
+    /// The sid of the subscription, usable with `NatsClient::unsubscribe`.
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    /// Stops delivering messages, they are buffered until `resume` is called. With `stop_server_delivery`
+    /// the server is asked to stop sending messages too, messages published meanwhile are missed.
+    ///
+    /// A paused subscription with `SlowConsumerPolicy::Block` does not hold up the connection,
+    /// the messages beyond its `pending_limit` are dropped as with `SlowConsumerPolicy::DropNewest`
+    /// until it is resumed.
+    pub fn pause(&self, stop_server_delivery: bool) -> Result<(), RatsioError> {
+        self.paused.store(true, Ordering::SeqCst);
+        let receiver = self.receiver.read();
+        let mut subs_map = receiver.subs_map.write();
+        if let Some(sink) = subs_map.get_mut(&self.sid) {
+            sink.set_paused(true);
+            if stop_server_delivery && !sink.paused_on_server {
+                sink.paused_on_server = true;
+                self.sender.read().send(Op::UNSUB(UnSubscribe {
+                    sid: self.sid.clone(),
+                    max_msgs: None,
+                }))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delivers the buffered messages again, subscribing again with the same sid if the server
+    /// delivery was stopped.
+    pub fn resume(&self) -> Result<(), RatsioError> {
+        {
+            let receiver = self.receiver.read();
+            let mut subs_map = receiver.subs_map.write();
+            if let Some(sink) = subs_map.get_mut(&self.sid) {
+                sink.set_paused(false);
+                if sink.paused_on_server {
+                    sink.paused_on_server = false;
+                    self.sender.read().send(Op::SUB(sink.cmd.clone()))?;
+                }
+            }
+        }
+        self.paused.store(false, Ordering::SeqCst);
+        self.waker.wake();
+        Ok(())
+    }
+
+    /// Another stream of the messages of this subscription, starting with the latest
+    /// `Subscribe::replay_buffer` messages. Forks get the messages before `max_age` and payload
+    /// validation, are not paused with the subscription and end when it does.
+    pub fn fork(&self) -> impl Stream<Item = Message> + Send + Sync {
+        let rx = match self.receiver.read().subs_map.write().get_mut(&self.sid) {
+            Some(sink) => sink.fork(),
+            None => mpsc::unbounded().1,
+        };
+        rx.take_while(|sink_msg| future::ready(match sink_msg {
+                SinkMessage::CLOSE => false,
+                _ => true,
+            }))
+            .filter_map(|sink_msg| future::ready(match sink_msg {
+                SinkMessage::Message(msg) => Some(msg),
+                _ => None,
+            }))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Whether dropping this handle unsubscribes, overriding `NatsClientOptions::unsubscribe_on_drop`.
+    /// Disable it to keep the subscription, e.g. for its forks, and end it with `NatsClient::unsubscribe`.
+    pub fn set_unsubscribe_on_drop(&self, unsubscribe_on_drop: bool) {
+        self.unsubscribe_on_drop.store(unsubscribe_on_drop, Ordering::SeqCst);
+    }
+
+    /// Unsubscribes once `duration` passed, ending the stream, unless the subscription ended before.
+    pub fn unsubscribe_after(&self, duration: Duration) {
+        unsubscribe_after(self.sid.clone(), duration, &self.clock, self.sender.clone(), self.receiver.clone());
+    }
+
+    /// How often the `Subscribe::pending_limit` was hit, all zero without a limit.
+    pub fn slow_consumer_stats(&self) -> SlowConsumerStats {
+        match self.receiver.read().subs_map.read().get(&self.sid) {
+            Some(sink) => sink.slow_consumer_stats(),
+            None => SlowConsumerStats::default(),
+        }
+    }
+
+    /// Hands the items of this subscription to any number of local consumers, without subscribing on
+    /// the server again. Each `BroadcastReceiver` buffers up to `capacity` items, items are dropped for
+    /// receivers that fall further behind. Clones receive the items from the time they are cloned.
+    pub fn split_broadcast(self, capacity: usize) -> BroadcastReceiver<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        Broadcast::spawn(self, capacity)
+    }
+}
+
+impl Subscription<Message> {
+    /// Hands the messages of this subscription to any number of local consumers like
+    /// `split_broadcast`, sharing each message between them instead of copying it per consumer.
+    pub fn split_broadcast_shared(self, capacity: usize) -> BroadcastReceiver<SharedMessage> {
+        Broadcast::spawn(self.map(SharedMessage::new), capacity)
+    }
+}
+
+#[derive(Clone)]
+struct Broadcast<T> {
+    capacity: usize,
+    /// `None` once the subscription ended
+    listeners: Arc<RwLock<Option<Vec<mpsc::Sender<T>>>>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Broadcast<T> {
+    /// Emits the items of `stream` until it ends or no receivers are left.
+    fn spawn<S: Stream<Item = T> + Unpin + Send + 'static>(mut stream: S, capacity: usize) -> BroadcastReceiver<T> {
+        let broadcast = Broadcast {
+            capacity,
+            listeners: Arc::new(RwLock::new(Some(Vec::new()))),
+        };
+        let receiver = broadcast.subscribe();
+        let task_broadcast = broadcast.clone();
+        tokio::spawn(async move {
+            while let Some(item) = stream.next().await {
+                if !task_broadcast.emit(item) {
+                    break;
+                }
+            }
+            // Dropping the senders ends the receivers.
+            task_broadcast.listeners.write().take();
+        });
+        receiver
+    }
+}
+
+impl<T: Clone> Broadcast<T> {
+    fn subscribe(&self) -> BroadcastReceiver<T> {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        if let Some(ref mut listeners) = *self.listeners.write() {
+            listeners.push(tx);
+        }
+        BroadcastReceiver { rx, broadcast: self.clone() }
+    }
+
+    /// Returns false when no receivers are left.
+    fn emit(&self, item: T) -> bool {
+        let mut listeners = self.listeners.write();
+        let listeners = match *listeners {
+            Some(ref mut listeners) => listeners,
+            None => return false,
+        };
+        for tx in listeners.iter_mut() {
+            if let Err(err) = tx.try_send(item.clone()) {
+                if err.is_full() {
+                    trace!(target: "ratsio", "Broadcast receiver is behind, dropping an item");
+                }
+            }
+        }
+        listeners.retain(|tx| !tx.is_closed());
+        !listeners.is_empty()
+    }
+}
+
+/// A local consumer of a subscription, see `Subscription::split_broadcast` and
+/// `Subscription::split_broadcast_shared`.
+pub struct BroadcastReceiver<T> {
+    rx: mpsc::Receiver<T>,
+    broadcast: Broadcast<T>,
+}
+
+impl<T: Clone> Clone for BroadcastReceiver<T> {
+    fn clone(&self) -> Self {
+        self.broadcast.subscribe()
+    }
+}
+
+impl<T> fmt::Debug for BroadcastReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BroadcastReceiver {{ capacity: {} }}", self.broadcast.capacity)
+    }
+}
+
+impl<T> Stream for BroadcastReceiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Subscription {{ sid: {}, paused: {} }}", self.sid, self.is_paused())
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if self.unsubscribe_on_drop.load(Ordering::SeqCst) {
+            unsubscribe(&self.sid, &self.sender, &self.receiver);
+        }
+    }
+}
+
+/// Unsubscribes on the server, unless paused there, and ends the stream.
+pub(crate) fn unsubscribe(sid: &str, sender: &RwLock<NatsClientSender>, receiver: &RwLock<NatsClientMultiplexer>) {
+    let receiver = receiver.read();
+    // Gone already if it was unsubscribed or reached its `max_msgs`.
+    let paused_on_server = match receiver.subs_map.read().get(sid) {
+        Some(sink) => sink.paused_on_server,
+        None => return,
+    };
+    if !paused_on_server {
+        let _ = sender.read().send(Op::UNSUB(UnSubscribe {
+            sid: sid.to_string(),
+            max_msgs: None,
+        }));
+    }
+    receiver.remove_sid(sid);
+}
+
+/// Unsubscribes `sid` once `duration` passed on `clock`, see `Subscribe::ttl`.
+pub(crate) fn unsubscribe_after(
+    sid: String,
+    duration: Duration,
+    clock: &SharedClock,
+    sender: Arc<RwLock<NatsClientSender>>,
+    receiver: Arc<RwLock<NatsClientMultiplexer>>,
+) {
+    let expired = clock.delay(duration);
+    tokio::spawn(async move {
+        expired.await;
+        unsubscribe(&sid, &sender, &receiver);
+    });
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.is_paused() {
+            self.waker.register(cx.waker());
+            // `resume` may have run before the waker was registered.
+            if self.is_paused() {
+                return Poll::Pending;
+            }
+        }
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+#[test]
+fn broadcast_drops_for_lagging_receivers() {
+    use futures::executor::block_on;
+
+    let broadcast = Broadcast { capacity: 0, listeners: Arc::new(RwLock::new(Some(Vec::new()))) };
+    let mut fast = broadcast.subscribe();
+    let slow = fast.clone();
+    assert!(broadcast.emit(1));
+    assert_eq!(block_on(fast.next()), Some(1));
+    assert!(broadcast.emit(2));
+    assert!(broadcast.emit(3));
+    drop(slow);
+    assert!(broadcast.emit(4));
+    broadcast.listeners.write().take();
+    assert_eq!(block_on(fast.collect::<Vec<_>>()), vec![2]);
+}
@@ -0,0 +1,117 @@
+use crate::net::{ConnectionEventReason, NatsConnection};
+use futures::prelude::*;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use super::{ClientEvent, EventEmitter, IoRuntime, NatsClientState, SubscriptionSink};
+
+/// Number of running instances of each internal task, by name. Connection tasks of a lost
+/// connection may still be ending when those of the next one start.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TaskHealth(Arc<Mutex<BTreeMap<&'static str, usize>>>);
+
+impl TaskHealth {
+    fn started(&self, task: &'static str) {
+        *self.0.lock().entry(task).or_insert(0) += 1;
+    }
+
+    fn ended(&self, task: &'static str) {
+        if let Some(running) = self.0.lock().get_mut(task) {
+            *running = running.saturating_sub(1);
+        }
+    }
+
+    /// Whether each task that was started is running.
+    pub(crate) fn alive(&self) -> BTreeMap<String, bool> {
+        self.0.lock().iter().map(|(task, running)| (task.to_string(), *running > 0)).collect()
+    }
+}
+
+/// Watches the internal tasks of a client. Connection tasks (sender, multiplexer, control
+/// receiver) that panic are restarted by re-establishing the connection, client tasks (pings,
+/// read timeout, reconnects) are expected to run forever, the client is closed if one ends.
+#[derive(Clone, Debug)]
+pub(crate) struct Supervisor {
+    pub(crate) connection: Arc<NatsConnection>,
+    pub(crate) state: Arc<RwLock<NatsClientState>>,
+    pub(crate) subs_map: Arc<RwLock<HashMap<String, SubscriptionSink>>>,
+    pub(crate) events: EventEmitter,
+    /// The runtime of the client tasks with `NatsClientOptions::dedicated_io_runtime`
+    pub(crate) io_runtime: Option<Arc<IoRuntime>>,
+    pub(crate) tasks: TaskHealth,
+}
+
+impl Supervisor {
+    /// Spawns a task bound to the current connection, ending normally when the connection is lost.
+    pub(crate) fn spawn_connection_task<F>(&self, task: &'static str, fut: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.started(task);
+        let handle = tokio::spawn(fut);
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let result = handle.await;
+            supervisor.tasks.ended(task);
+            if let Err(err) = result {
+                if *supervisor.state.read() == NatsClientState::Closed {
+                    return;
+                }
+                error!(target: supervisor.connection.log_target.as_str(), "Task {} failed => {}, reconnecting",
+                       task, err);
+                supervisor.events.emit(ClientEvent::TaskFailed {
+                    task: task.to_string(),
+                    reason: err.to_string(),
+                    restarted: true,
+                });
+                supervisor.connection.record_event(ConnectionEventReason::TaskFailed(format!("{} => {}", task, err)));
+                NatsConnection::trigger_reconnect(supervisor.connection.clone());
+            }
+        });
+    }
+
+    /// Spawns a task that must run as long as the client does.
+    pub(crate) fn spawn_client_task<F>(&self, task: &'static str, fut: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.started(task);
+        let handle = tokio::spawn(fut);
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let result = handle.await;
+            supervisor.tasks.ended(task);
+            let reason = match result {
+                Ok(()) => String::from("task ended"),
+                Err(err) => err.to_string(),
+            };
+            if *supervisor.state.read() == NatsClientState::Closed {
+                return;
+            }
+            error!(target: supervisor.connection.log_target.as_str(), "Task {} failed => {}, closing client",
+                   task, reason);
+            supervisor.events.emit(ClientEvent::TaskFailed {
+                task: task.to_string(),
+                reason,
+                restarted: false,
+            });
+            supervisor.close();
+        });
+    }
+
+    /// Marks the client closed, drops the connection and ends all subscription streams.
+    pub(crate) fn close(&self) {
+        *self.state.write() = NatsClientState::Closed;
+        self.connection.close();
+        for (_, sink) in self.subs_map.write().drain() {
+            sink.close();
+        }
+        self.events.emit(ClientEvent::Closed);
+        if let Some(ref io_runtime) = self.io_runtime {
+            io_runtime.shutdown();
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::ops::ServerInfo;
+
+/// The cluster of the server a client is connected to, as announced in its INFO updates, see
+/// `NatsClient::topology`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Topology {
+    /// Id of the server connected to
+    pub server_id: String,
+    /// Name of its cluster, `None` for a standalone server
+    pub cluster: Option<String>,
+    /// JetStream domain of the server
+    pub domain: Option<String>,
+    /// Client urls of the servers of the cluster, sorted
+    pub members: Vec<String>,
+    /// The server connected to is in lame duck mode and about to shut down
+    pub lame_duck: bool,
+}
+
+impl Topology {
+    pub(crate) fn from_info(info: &ServerInfo) -> Self {
+        let mut members = info.connect_urls.clone();
+        members.sort();
+        members.dedup();
+        Topology {
+            server_id: info.server_id.clone(),
+            cluster: info.cluster.clone(),
+            domain: info.domain.clone(),
+            members,
+            lame_duck: info.ldm,
+        }
+    }
+}
+
+/// A change of the `Topology`, published as `ClientEvent::TopologyChanged` and on
+/// `NatsClient::topology_changes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopologyChange {
+    pub previous: Topology,
+    pub current: Topology,
+    /// Members that were not announced before
+    pub joined: Vec<String>,
+    /// Members no longer announced
+    pub left: Vec<String>,
+}
+
+impl TopologyChange {
+    /// The change from `previous` to `current`, `None` if nothing changed.
+    pub(crate) fn between(previous: Topology, current: Topology) -> Option<Self> {
+        if previous == current {
+            return None;
+        }
+        let joined = current.members.iter().filter(|url| !previous.members.contains(url)).cloned().collect();
+        let left = previous.members.iter().filter(|url| !current.members.contains(url)).cloned().collect();
+        Some(TopologyChange { previous, current, joined, left })
+    }
+}
+
+#[test]
+fn tracks_cluster_membership() {
+    let info = ServerInfo {
+        server_id: "N1".into(),
+        cluster: Some("east".into()),
+        connect_urls: vec!["10.0.0.2:4222".into(), "10.0.0.1:4222".into()],
+        ..ServerInfo::default()
+    };
+    let before = Topology::from_info(&info);
+    assert_eq!(before.members, vec!["10.0.0.1:4222", "10.0.0.2:4222"]);
+    assert_eq!(TopologyChange::between(before.clone(), before.clone()), None);
+
+    let upgraded = ServerInfo {
+        connect_urls: vec!["10.0.0.1:4222".into(), "10.0.0.3:4222".into()],
+        ..info
+    };
+    let change = TopologyChange::between(before, Topology::from_info(&upgraded)).unwrap();
+    assert_eq!(change.joined, vec!["10.0.0.3:4222"]);
+    assert_eq!(change.left, vec!["10.0.0.2:4222"]);
+}
@@ -0,0 +1,152 @@
+use crate::error::RatsioError;
+use serde::de::DeserializeOwned;
+use std::{marker::PhantomData, sync::Arc};
+
+/// A contract check run against the payload of messages published to, or received on,
+/// subjects matching the pattern it was registered with.
+pub trait PayloadValidator: Send + Sync {
+    /// Returns `Err(reason)` if `payload` does not satisfy the contract for `subject`.
+    fn validate(&self, subject: &str, payload: &[u8]) -> Result<(), String>;
+}
+
+impl<F> PayloadValidator for F
+where
+    F: Fn(&str, &[u8]) -> Result<(), String> + Send + Sync,
+{
+    fn validate(&self, subject: &str, payload: &[u8]) -> Result<(), String> {
+        (self)(subject, payload)
+    }
+}
+
+/// When a registered validator should run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValidateOn {
+    /// Before a message is sent, the publish fails with `RatsioError::PayloadValidationError`.
+    Publish,
+    /// Before a message is delivered to a subscription stream, invalid messages are dropped.
+    Receive,
+    /// Before a message is sent and before it is delivered, as `Publish` and `Receive` do.
+    Both,
+}
+
+impl ValidateOn {
+    fn on_publish(self) -> bool {
+        self != ValidateOn::Receive
+    }
+
+    fn on_receive(self) -> bool {
+        self != ValidateOn::Publish
+    }
+}
+
+/// Validates that payloads deserialize from JSON into `T`.
+pub struct JsonValidator<T> {
+    _schema: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> JsonValidator<T> {
+    pub fn new() -> Self {
+        JsonValidator { _schema: PhantomData }
+    }
+}
+
+impl<T: DeserializeOwned> Default for JsonValidator<T> {
+    fn default() -> Self {
+        JsonValidator::new()
+    }
+}
+
+impl<T: DeserializeOwned> PayloadValidator for JsonValidator<T> {
+    fn validate(&self, _subject: &str, payload: &[u8]) -> Result<(), String> {
+        serde_json::from_slice::<T>(payload)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Validates that payloads decode as the protobuf message `M`, including its required fields.
+#[cfg(feature = "protobuf")]
+pub struct ProtobufValidator<M> {
+    _descriptor: PhantomData<fn() -> M>,
+}
+
+#[cfg(feature = "protobuf")]
+impl<M: protobuf::Message> ProtobufValidator<M> {
+    pub fn new() -> Self {
+        ProtobufValidator { _descriptor: PhantomData }
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<M: protobuf::Message> Default for ProtobufValidator<M> {
+    fn default() -> Self {
+        ProtobufValidator::new()
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<M: protobuf::Message> PayloadValidator for ProtobufValidator<M> {
+    fn validate(&self, _subject: &str, payload: &[u8]) -> Result<(), String> {
+        protobuf::parse_from_bytes::<M>(payload)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Clone)]
+struct RegisteredValidator {
+    pattern: String,
+    on: ValidateOn,
+    validator: Arc<dyn PayloadValidator>,
+}
+
+/// Validators registered on a client, in registration order.
+#[derive(Clone, Default)]
+pub(crate) struct ValidatorRegistry {
+    validators: Vec<RegisteredValidator>,
+}
+
+impl ValidatorRegistry {
+    pub(crate) fn add(&mut self, pattern: String, on: ValidateOn, validator: Arc<dyn PayloadValidator>) {
+        self.validators.push(RegisteredValidator { pattern, on, validator });
+    }
+
+    pub(crate) fn remove(&mut self, pattern: &str) {
+        self.validators.retain(|v| v.pattern != pattern);
+    }
+
+    pub(crate) fn validate_publish(&self, subject: &str, payload: &[u8]) -> Result<(), RatsioError> {
+        self.validate(subject, payload, ValidateOn::on_publish)
+    }
+
+    pub(crate) fn validate_receive(&self, subject: &str, payload: &[u8]) -> Result<(), RatsioError> {
+        self.validate(subject, payload, ValidateOn::on_receive)
+    }
+
+    fn validate(&self, subject: &str, payload: &[u8], applies: fn(ValidateOn) -> bool) -> Result<(), RatsioError> {
+        self.validators.iter()
+            .filter(|v| applies(v.on) && crate::subject::matches(&v.pattern, subject))
+            .try_for_each(|v| v.validator.validate(subject, payload)
+                .map_err(|reason| RatsioError::PayloadValidationError(subject.to_string(), reason)))
+    }
+}
+
+#[test]
+fn validates_matching_subjects_only() {
+    let mut registry = ValidatorRegistry::default();
+    registry.add("orders.>".into(), ValidateOn::Both, Arc::new(JsonValidator::<serde_json::Value>::new()));
+    assert!(registry.validate_publish("orders.created", b"{\"id\":1}").is_ok());
+    assert!(registry.validate_publish("orders.created", b"not json").is_err());
+    assert!(registry.validate_publish("audit.created", b"not json").is_ok());
+}
+
+#[test]
+fn validates_by_direction() {
+    let mut registry = ValidatorRegistry::default();
+    let reject_all = |_: &str, _: &[u8]| -> Result<(), String> { Err(String::from("rejected")) };
+    registry.add("foo".into(), ValidateOn::Receive, Arc::new(reject_all));
+    assert!(registry.validate_publish("foo", b"bar").is_ok());
+    assert!(registry.validate_receive("foo", b"bar").is_err());
+    registry.remove("foo");
+    assert!(registry.validate_receive("foo", b"bar").is_ok());
+}
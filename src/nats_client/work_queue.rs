@@ -0,0 +1,408 @@
+//! Simple job distribution over core NATS, for deployments without JetStream. A
+//! `WorkQueueCoordinator` running on one client holds the jobs of a queue, producers enqueue
+//! them and consumers lease them through `WorkQueue` by request/reply. A leased job is invisible
+//! to other consumers until its visibility timeout passes, it is leased again after that unless
+//! it was acked, up to `max_attempts` times.
+//!
+//! The jobs only live in the memory of the coordinator, they are lost if it stops.
+//!
+//! ```no_run
+//! # async fn jobs(client: std::sync::Arc<ratsio::nats_client::NatsClient>)
+//! #     -> Result<(), ratsio::error::RatsioError> {
+//! use ratsio::nats_client::{WorkQueue, WorkQueueCoordinator, WorkQueueOptions};
+//! let _coordinator = WorkQueueCoordinator::start(client.clone(), "thumbnails", WorkQueueOptions::default()).await?;
+//! let queue = WorkQueue::new(client, "thumbnails")?;
+//! queue.enqueue(b"img-42.png").await?;
+//! if let Some(job) = queue.lease().await? {
+//!     // resize job.payload
+//!     queue.ack(&job).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::clock::SharedClock;
+use crate::error::{RatsioError, ServiceError};
+use crate::ops::{Message, Publish};
+use futures::{future, prelude::*};
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{NatsClient, NatsClientApi};
+
+/// Subjects of the work queues are `_RATSIO.wq.<queue>.<operation>`.
+pub const WORK_QUEUE_PREFIX: &str = "_RATSIO.wq";
+
+const ENQUEUE: &str = "enqueue";
+const LEASE: &str = "lease";
+const ACK: &str = "ack";
+const NACK: &str = "nack";
+
+/// Limits of the jobs of a `WorkQueueCoordinator`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkQueueOptions {
+    /// How long a leased job stays invisible to other consumers unless acked or nacked
+    pub visibility_timeout: Duration,
+    /// Leases of a job after which it is given up on instead of leased again
+    pub max_attempts: u32,
+    /// Most jobs held, leased ones included, enqueueing more fails. 0 for no limit.
+    pub max_jobs: usize,
+    /// Subject the payload of the jobs given up on is published to, dropped if `None`
+    pub dead_letter_subject: Option<String>,
+}
+
+impl Default for WorkQueueOptions {
+    fn default() -> Self {
+        WorkQueueOptions {
+            visibility_timeout: Duration::from_secs(30),
+            max_attempts: 5,
+            max_jobs: 0,
+            dead_letter_subject: None,
+        }
+    }
+}
+
+/// A job leased from a `WorkQueue`, to ack once done or nack to have it leased again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    pub id: u64,
+    /// 1 for the first lease of the job, higher for retries
+    pub attempt: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Job {
+    /// The reply to a lease, `<id> <attempt>\n` followed by the payload, empty if no job was ready.
+    fn decode(reply: &[u8]) -> Result<Option<Job>, RatsioError> {
+        if reply.is_empty() {
+            return Ok(None);
+        }
+        let malformed = || RatsioError::GenericError("malformed work queue lease".into());
+        let split = reply.iter().position(|byte| *byte == b'\n').ok_or_else(malformed)?;
+        let (id, attempt) = parse_token(&reply[..split]).ok_or_else(malformed)?;
+        Ok(Some(Job { id, attempt, payload: reply[split + 1..].to_vec() }))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut reply = self.token().into_bytes();
+        reply.push(b'\n');
+        reply.extend_from_slice(&self.payload);
+        reply
+    }
+
+    /// `<id> <attempt>`, naming the lease acks and nacks are about.
+    fn token(&self) -> String {
+        format!("{} {}", self.id, self.attempt)
+    }
+}
+
+fn parse_token(token: &[u8]) -> Option<(u64, u32)> {
+    let token = std::str::from_utf8(token).ok()?;
+    let mut parts = token.trim().splitn(2, ' ');
+    let id = parts.next()?.parse().ok()?;
+    let attempt = parts.next()?.parse().ok()?;
+    Some((id, attempt))
+}
+
+fn queue_subject(queue: &str, operation: &str) -> Result<String, RatsioError> {
+    if queue.contains(|c| c == '*' || c == '>') {
+        return Err(RatsioError::InvalidSubject(queue.to_string()));
+    }
+    let subject = format!("{}.{}.{}", WORK_QUEUE_PREFIX, queue, operation);
+    crate::subject::validate(&subject)?;
+    Ok(subject)
+}
+
+/// Producer and consumer of the jobs of a queue held by a `WorkQueueCoordinator`. Requests fail
+/// like `NatsClient::request`, with `RatsioError::NoResponders` if no coordinator runs.
+#[derive(Clone)]
+pub struct WorkQueue {
+    client: Arc<dyn NatsClientApi>,
+    queue: String,
+}
+
+impl std::fmt::Debug for WorkQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "WorkQueue({})", self.queue)
+    }
+}
+
+impl WorkQueue {
+    pub fn new<C: NatsClientApi + 'static>(client: Arc<C>, queue: &str) -> Result<Self, RatsioError> {
+        queue_subject(queue, ENQUEUE)?;
+        Ok(WorkQueue { client, queue: queue.to_string() })
+    }
+
+    /// Adds a job, resolving to its id once the coordinator holds it. Fails with a
+    /// `RatsioError::ServiceError` if the queue is full.
+    pub fn enqueue(&self, payload: &[u8]) -> impl Future<Output = Result<u64, RatsioError>> + Send {
+        self.call(ENQUEUE, payload.to_vec()).and_then(|reply| {
+            let id = std::str::from_utf8(&reply.payload).ok().and_then(|id| id.parse().ok());
+            future::ready(id.ok_or_else(|| RatsioError::GenericError("malformed work queue id".into())))
+        })
+    }
+
+    /// Leases the next ready job, `None` if there is none. The job must be acked or nacked
+    /// within the visibility timeout of the queue, it is leased to another consumer after that.
+    pub fn lease(&self) -> impl Future<Output = Result<Option<Job>, RatsioError>> + Send {
+        self.call(LEASE, Vec::new()).and_then(|reply| future::ready(Job::decode(&reply.payload)))
+    }
+
+    /// Removes a leased job from the queue. Fails with a `RatsioError::ServiceError` if the lease
+    /// expired in the meantime, the job may be processed by another consumer then.
+    pub fn ack(&self, job: &Job) -> impl Future<Output = Result<(), RatsioError>> + Send {
+        self.call(ACK, job.token().into_bytes()).map_ok(|_| ())
+    }
+
+    /// Gives a leased job back, to be leased again right away unless it ran out of attempts.
+    pub fn nack(&self, job: &Job) -> impl Future<Output = Result<(), RatsioError>> + Send {
+        self.call(NACK, job.token().into_bytes()).map_ok(|_| ())
+    }
+
+    fn call(&self, operation: &str, payload: Vec<u8>) -> impl Future<Output = Result<Message, RatsioError>> + Send {
+        let request = queue_subject(&self.queue, operation).map(|subject| self.client.request(subject, payload));
+        async move {
+            let reply = request?.await?;
+            match reply.service_error() {
+                Some(err) => Err(RatsioError::ServiceError(err)),
+                None => Ok(reply),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Lease {
+    job: Job,
+    expires: Instant,
+}
+
+/// The jobs of a queue: the ready ones in order, then the leased ones.
+#[derive(Debug)]
+struct QueueState {
+    options: WorkQueueOptions,
+    next_id: u64,
+    ready: VecDeque<Job>,
+    leased: HashMap<u64, Lease>,
+}
+
+impl QueueState {
+    fn new(options: WorkQueueOptions) -> Self {
+        QueueState {
+            options,
+            next_id: 1,
+            ready: VecDeque::new(),
+            leased: HashMap::new(),
+        }
+    }
+
+    fn enqueue(&mut self, payload: Vec<u8>) -> Result<u64, ServiceError> {
+        let max_jobs = self.options.max_jobs;
+        if max_jobs > 0 && self.ready.len() + self.leased.len() >= max_jobs {
+            return Err(ServiceError::new(503, &format!("work queue is full at {} jobs", max_jobs)));
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ready.push_back(Job { id, attempt: 0, payload });
+        Ok(id)
+    }
+
+    /// Leases the next ready job, once the expired leases were made visible again. Returns the
+    /// jobs that ran out of attempts meanwhile too.
+    fn lease(&mut self, now: Instant) -> (Option<Job>, Vec<Job>) {
+        let dead = self.expire(now);
+        let mut job = match self.ready.pop_front() {
+            Some(job) => job,
+            None => return (None, dead),
+        };
+        job.attempt += 1;
+        let expires = now + self.options.visibility_timeout;
+        self.leased.insert(job.id, Lease { job: job.clone(), expires });
+        (Some(job), dead)
+    }
+
+    /// Ends the lease of `token`, if it is still the current one of its job.
+    fn release(&mut self, token: &[u8]) -> Option<Job> {
+        let (id, attempt) = parse_token(token)?;
+        match self.leased.get(&id) {
+            Some(lease) if lease.job.attempt == attempt => self.leased.remove(&id).map(|lease| lease.job),
+            _ => None,
+        }
+    }
+
+    /// Puts a job back in front of the ready ones, `Err` with the job if it ran out of attempts.
+    fn retry(&mut self, job: Job) -> Result<(), Job> {
+        if job.attempt >= self.options.max_attempts {
+            return Err(job);
+        }
+        self.ready.push_front(job);
+        Ok(())
+    }
+
+    fn expire(&mut self, now: Instant) -> Vec<Job> {
+        let mut expired: Vec<u64> = self.leased.iter()
+            .filter(|(_, lease)| lease.expires <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        // Retried in the order they were enqueued.
+        expired.sort_unstable_by(|a, b| b.cmp(a));
+        let mut dead = Vec::new();
+        for id in expired {
+            let job = self.leased.remove(&id).expect("expired lease").job;
+            if let Err(job) = self.retry(job) {
+                dead.push(job);
+            }
+        }
+        dead
+    }
+}
+
+/// Holds the jobs of a queue and answers the requests of its `WorkQueue`s, until stopped. Run
+/// a single coordinator per queue, coordinators don't share their jobs.
+pub struct WorkQueueCoordinator {
+    client: Arc<NatsClient>,
+    queue: String,
+    state: Arc<Mutex<QueueState>>,
+    sids: Vec<String>,
+}
+
+impl std::fmt::Debug for WorkQueueCoordinator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "WorkQueueCoordinator({}, {:?})", self.queue, self.sids)
+    }
+}
+
+impl WorkQueueCoordinator {
+    /// Serves the queue `queue` on `client`.
+    pub async fn start(
+        client: Arc<NatsClient>,
+        queue: &str,
+        options: WorkQueueOptions,
+    ) -> Result<WorkQueueCoordinator, RatsioError> {
+        let state = Arc::new(Mutex::new(QueueState::new(options)));
+        let clock = client.opts.read().clock.clone();
+        let mut sids = Vec::new();
+        for &operation in &[ENQUEUE, LEASE, ACK, NACK] {
+            let subject = queue_subject(queue, operation)?;
+            let handler = Handler {
+                client: client.clone(),
+                state: state.clone(),
+                clock: clock.clone(),
+                operation,
+            };
+            let served = client.serve(subject, None, move |msg: Message| future::ready(handler.handle(msg))).await;
+            match served {
+                Ok(sid) => sids.push(sid),
+                Err(err) => {
+                    client.unsubscribe_many(&sids);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(WorkQueueCoordinator { client, queue: queue.to_string(), state, sids })
+    }
+
+    /// Jobs waiting to be leased.
+    pub fn ready(&self) -> usize {
+        self.state.lock().ready.len()
+    }
+
+    /// Jobs leased and neither acked nor nacked yet, including the expired leases not noticed yet.
+    pub fn leased(&self) -> usize {
+        self.state.lock().leased.len()
+    }
+
+    /// Stops answering the requests of the queue, its jobs are dropped.
+    pub fn stop(&self) {
+        self.client.unsubscribe_many(&self.sids);
+    }
+}
+
+struct Handler {
+    client: Arc<NatsClient>,
+    state: Arc<Mutex<QueueState>>,
+    clock: SharedClock,
+    operation: &'static str,
+}
+
+impl Handler {
+    fn handle(&self, msg: Message) -> Result<Vec<u8>, RatsioError> {
+        let mut state = self.state.lock();
+        let (reply, dead) = match self.operation {
+            ENQUEUE => (state.enqueue(msg.payload).map(|id| id.to_string().into_bytes()), Vec::new()),
+            LEASE => {
+                let (job, dead) = state.lease(self.clock.now());
+                (Ok(job.map(|job| job.encode()).unwrap_or_default()), dead)
+            }
+            ACK => (state.release(&msg.payload).map(|_| Vec::new()).ok_or_else(expired_lease), Vec::new()),
+            _ => match state.release(&msg.payload) {
+                Some(job) => (Ok(Vec::new()), state.retry(job).err().into_iter().collect()),
+                None => (Err(expired_lease()), Vec::new()),
+            },
+        };
+        drop(state);
+        self.dead_letter(dead);
+        reply.map_err(RatsioError::ServiceError)
+    }
+
+    fn dead_letter(&self, dead: Vec<Job>) {
+        for job in dead {
+            let subject = match self.state.lock().options.dead_letter_subject.clone() {
+                Some(subject) => subject,
+                None => {
+                    warn!(target: self.client.log_target(), "Dropping job {} after {} attempts", job.id, job.attempt);
+                    continue;
+                }
+            };
+            let published = self.client.publish(Publish {
+                subject,
+                payload: job.payload,
+                reply_to: None,
+                headers: None,
+            }).now_or_never();
+            if let Some(Err(err)) = published {
+                warn!(target: self.client.log_target(), "Unable to dead-letter job {} => {}", job.id, err);
+            }
+        }
+    }
+}
+
+fn expired_lease() -> ServiceError {
+    ServiceError::new(404, "no such lease, it expired or was released")
+}
+
+#[test]
+fn leases_expire_and_retry() {
+    let options = WorkQueueOptions {
+        visibility_timeout: Duration::from_secs(10),
+        max_attempts: 2,
+        max_jobs: 2,
+        ..WorkQueueOptions::default()
+    };
+    let mut state = QueueState::new(options);
+    let start = Instant::now();
+    assert_eq!(state.enqueue(b"a".to_vec()), Ok(1));
+    assert_eq!(state.enqueue(b"b".to_vec()), Ok(2));
+    assert!(state.enqueue(b"c".to_vec()).is_err(), "full");
+
+    let (a, _) = state.lease(start);
+    let a = a.unwrap();
+    assert_eq!((a.id, a.attempt), (1, 1));
+    assert_eq!(Job::decode(&a.encode()).unwrap(), Some(a.clone()));
+    let (b, _) = state.lease(start);
+    assert_eq!(state.release(b.unwrap().token().as_bytes()).map(|job| job.id), Some(2));
+
+    // The lease of `a` expires, it is leased again and the stale ack is refused.
+    let later = start + Duration::from_secs(11);
+    let (retried, dead) = state.lease(later);
+    assert_eq!((retried.clone().unwrap().attempt, dead.len()), (2, 0));
+    assert_eq!(state.release(a.token().as_bytes()), None);
+
+    let (none, dead) = state.lease(later + Duration::from_secs(11));
+    assert_eq!(none, None);
+    assert_eq!(dead.iter().map(|job| job.id).collect::<Vec<_>>(), vec![1], "out of attempts");
+    assert_eq!(Job::decode(b"").unwrap(), None);
+}
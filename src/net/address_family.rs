@@ -0,0 +1,50 @@
+use std::net::SocketAddr;
+
+/// Which IP family to try first when a server name resolves to both IPv4 and IPv6 addresses.
+/// Addresses of the other family are still tried when none of the preferred family is reachable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressFamily {
+    /// Try addresses in the order they were resolved
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+}
+
+impl Default for AddressFamily {
+    fn default() -> Self {
+        AddressFamily::Any
+    }
+}
+
+impl AddressFamily {
+    /// Moves the addresses of the preferred family to the front, keeping the resolved order otherwise.
+    pub(crate) fn order<T>(self, addrs: &mut Vec<(T, SocketAddr)>) {
+        match self {
+            AddressFamily::Any => {}
+            AddressFamily::PreferIpv4 => addrs.sort_by_key(|(_, addr)| !addr.is_ipv4()),
+            AddressFamily::PreferIpv6 => addrs.sort_by_key(|(_, addr)| !addr.is_ipv6()),
+        }
+    }
+}
+
+#[test]
+fn orders_preferred_family_first() {
+    let resolved = || -> Vec<(&'static str, SocketAddr)> {
+        vec![
+            ("a", "[::1]:4222".parse().unwrap()),
+            ("b", "127.0.0.1:4222".parse().unwrap()),
+            ("c", "[::2]:4222".parse().unwrap()),
+        ]
+    };
+    let names = |addrs: Vec<(&'static str, SocketAddr)>| addrs.into_iter().map(|(n, _)| n).collect::<Vec<_>>();
+
+    let mut addrs = resolved();
+    AddressFamily::PreferIpv4.order(&mut addrs);
+    assert_eq!(names(addrs), vec!["b", "a", "c"]);
+    let mut addrs = resolved();
+    AddressFamily::PreferIpv6.order(&mut addrs);
+    assert_eq!(names(addrs), vec!["a", "c", "b"]);
+    let mut addrs = resolved();
+    AddressFamily::Any.order(&mut addrs);
+    assert_eq!(names(addrs), vec!["a", "b", "c"]);
+}
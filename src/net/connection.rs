@@ -1,19 +1,27 @@
+use crate::clock::SharedClock;
 use crate::error::RatsioError;
 use crate::ops::Op;
 use futures::{
     future::{self, Either},
     prelude::*,
-    task::Poll,
+    ready,
+    task::{Context, Poll},
 };
 use parking_lot::RwLock;
 use std::{
     net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    pin::Pin,
     str::FromStr,
     sync::Arc,
+    time::Instant,
 };
 use super::connection_inner::NatsConnectionInner;
-use super::ReconnectHandler;
-use url::Url;
+use super::{
+    AddressFamily, ConnectionEventReason, ConnectionHistory, Identity, NatsUrl, ReconnectHandler, ServerList,
+    ServerPool, Standby, TlsIdentityProvider,
+};
+use rand::Rng;
 
 /// State of the raw connection
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -21,6 +29,25 @@ pub(crate) enum NatsConnectionState {
     Connected,
     Reconnecting,
     Disconnected,
+    /// Closed for good, no reconnects are attempted.
+    Closed,
+}
+
+/// Time to wait between connection attempts, `reconnect_timeout` plus a random jitter of up to
+/// `jitter` ms, or `jitter_tls` ms for TLS connections, so clients losing the same server spread out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ReconnectDelay {
+    pub(crate) reconnect_timeout: u64,
+    pub(crate) jitter: u64,
+    pub(crate) jitter_tls: u64,
+}
+
+impl ReconnectDelay {
+    pub(crate) fn next(&self, tls: bool) -> std::time::Duration {
+        let jitter = if tls { self.jitter_tls } else { self.jitter };
+        let jitter = if jitter > 0 { rand::thread_rng().gen_range(0, jitter + 1) } else { 0 };
+        std::time::Duration::from_millis(self.reconnect_timeout + jitter)
+    }
 }
 
 /// Represents a connection to a NATS server. Implements `Sink` and `Stream`
@@ -29,66 +56,94 @@ pub struct NatsConnection {
     /// indicates if the connection is made over TLS
     pub(crate) is_tls: bool,
     /// Inner dual `Stream`/`Sink` of the TCP connection
-    pub(crate) inner: Arc<RwLock<(Url, NatsConnectionInner)>>,
+    pub(crate) inner: Arc<RwLock<(NatsUrl, NatsConnectionInner)>>,
     /// Current state of the connection, and connect version.
     /// Version only increments on a successful reconnect.
     pub(crate) state: Arc<RwLock<(NatsConnectionState, u64)>>,
+    /// When data was last read from the server, used to detect stale connections.
+    pub(crate) last_read: Arc<RwLock<Instant>>,
 
     /// Reconnect trigger
     pub(crate) reconnect_handler: ReconnectHandler,
 
-    pub(crate) init_hosts: Vec<String>,
-    pub(crate) reconnect_hosts: RwLock<Vec<String>>,
-    pub(crate) reconnect_timeout: u64,
+    pub(crate) server_pool: RwLock<ServerPool>,
+    /// Where the server pool is kept between restarts, see `NatsClientOptions::server_pool_file`
+    pub(crate) server_pool_file: Option<PathBuf>,
+    /// Asked for the seed servers before every reconnect, if set
+    pub(crate) server_list: Option<ServerList>,
+    /// Asked for the client certificate before every TLS (re)connect, if set
+    pub(crate) tls_identity: Option<TlsIdentityProvider>,
+    /// Name to verify the server certificate against instead of the server host, if set
+    pub(crate) tls_hostname: Option<String>,
+    /// IP family to try first for servers resolving to both
+    pub(crate) address_family: AddressFamily,
+    pub(crate) reconnect_delay: RwLock<ReconnectDelay>,
+    /// Target of the log output for this connection, see `NatsClientOptions::log_label`
+    pub(crate) log_target: String,
+    /// Times `last_read` and the reconnect delays, see `NatsClientOptions::clock`
+    pub(crate) clock: SharedClock,
+    /// Recent connects and disconnects, see `NatsClient::connection_history`
+    pub(crate) history: ConnectionHistory,
+    /// Connection to switch to when this one is lost, see `NatsClientOptions::standby_connection`
+    pub(crate) standby: Standby,
 }
 
 pub struct NatsConnSinkStream {
     /// Inner dual `Stream`/`Sink` of the TCP connection
-    pub(crate) inner: Arc<RwLock<(Url, NatsConnectionInner)>>,
+    pub(crate) inner: Arc<RwLock<(NatsUrl, NatsConnectionInner)>>,
     /// Current state of the connection, and connect version.
     /// Version only increments on a successful reconnect.
     pub(crate) state: Arc<RwLock<(NatsConnectionState, u64)>>,
+    /// When data was last read from the server, updated on every received op.
+    pub(crate) last_read: Arc<RwLock<Instant>>,
 
-    /// Reconnect trigger
-    pub(crate) reconnect_trigger: Box<dyn Fn() -> () + Sync + Send>,
+    /// Reconnect trigger, with the reason the connection was lost
+    pub(crate) reconnect_trigger: Box<dyn Fn(&ConnectionEventReason) + Sync + Send>,
+    pub(crate) log_target: String,
+    pub(crate) clock: SharedClock,
+    pub(crate) history: ConnectionHistory,
 }
 
 
 impl NatsConnection {
     /// Connect to a raw TCP socket
     fn connect(addr: SocketAddr) -> impl Future<Output=Result<NatsConnectionInner, RatsioError>> {
-        NatsConnectionInner::connect_tcp(addr).map(move |result| {
-            debug!(target: "ratsio", "Got a socket successfully.");
-            result.map(|socket| socket.into())
-        })
+        NatsConnectionInner::connect_tcp(addr).map_ok(|socket| socket.into())
     }
 
     /// Connect to a TLS over TCP socket. Upgrade is performed automatically
-    fn connect_tls(host: String, addr: SocketAddr) -> impl Future<Output=Result<NatsConnectionInner, RatsioError>> {
+    #[cfg(feature = "tls")]
+    fn connect_tls(host: String, addr: SocketAddr, identity: Option<Identity>)
+                   -> impl Future<Output=Result<NatsConnectionInner, RatsioError>> {
         NatsConnectionInner::connect_tcp(addr)
-            .and_then(move |socket| {
-                debug!(target: "ratsio", "Got a socket successfully, upgrading to TLS");
-                NatsConnectionInner::upgrade_tcp_to_tls(host, socket)
-            })
-            .map(move |result| {
-                result.map(|socket| socket.into())
-            })
+            .and_then(move |socket| NatsConnectionInner::upgrade_tcp_to_tls(host, socket, identity))
+            .map_ok(|socket| socket.into())
     }
 
-    /// Tries to reconnect once to the server; Only used internally. Blocks polling during reconnecting
-    /// by forcing the object to return `Async::NotReady`/`AsyncSink::NotReady`
+    #[cfg(not(feature = "tls"))]
+    fn connect_tls(_host: String, _addr: SocketAddr, _identity: Option<Identity>)
+                   -> impl Future<Output=Result<NatsConnectionInner, RatsioError>> {
+        future::err(RatsioError::FeatureNotEnabled("tls".into()))
+    }
+
+    /// Tries to reconnect once to the server; Only used internally. While reconnecting the
+    /// `Sink`/`Stream` handed out for the previous connection are ended.
     pub(crate) fn trigger_reconnect(conn: Arc<Self>) {
-        trace!(target: "ratsio", "Trigger reconnection ");
+        let log_target = conn.log_target.as_str();
+        trace!(target: log_target, "Trigger reconnection ");
         let connect_version = conn.state.read().1;
         {
             let mut state_guard = conn.state.write();
-            if state_guard.0 == NatsConnectionState::Reconnecting {
+            if state_guard.0 == NatsConnectionState::Closed {
+                trace!(target: log_target, "Connection closed, not reconnecting");
+                return;
+            } else if state_guard.0 == NatsConnectionState::Reconnecting {
                 // Another thread is busy reconnecting...
-                trace!(target: "ratsio", "Already reconnection, nothing to do");
+                trace!(target: log_target, "Already reconnection, nothing to do");
                 return;
             } else if state_guard.0 == NatsConnectionState::Connected && state_guard.1 > connect_version {
                 // Another thread has already reconnected ...
-                trace!(target: "ratsio", "Another thread has reconnected, nothing to do");
+                trace!(target: log_target, "Another thread has reconnected, nothing to do");
                 return;
             } else {
                 let current_version = state_guard.1;
@@ -98,8 +153,58 @@ impl NatsConnection {
         NatsConnection::reconnect(conn);
     }
 
+    /// Closes the connection for good, the `Sink`/`Stream` handed out for it end.
+    pub(crate) fn close(&self) {
+        let mut state_guard = self.state.write();
+        *state_guard = (NatsConnectionState::Closed, state_guard.1);
+        self.standby.clear();
+    }
+
+    /// Whether there currently is a connection to a server.
+    pub(crate) fn is_connected(&self) -> bool {
+        self.state.read().0 == NatsConnectionState::Connected
+    }
+
+    /// Records an event for the server currently connected to, or last tried.
+    pub(crate) fn record_event(&self, reason: ConnectionEventReason) {
+        let server = self.inner.read().0.clone();
+        self.history.record(server, reason);
+    }
+
+    /// Records the servers tried and the one connected to in the pool, see `ServerPool::record_attempt`.
+    fn record_attempt(&self, servers: &[NatsUrl], connected: Option<&NatsUrl>) {
+        self.server_pool.write().record_attempt(servers, connected);
+        self.save_server_pool();
+    }
+
+    /// Writes the server pool to the `server_pool_file`, if set.
+    pub(crate) fn save_server_pool(&self) {
+        if let Some(ref path) = self.server_pool_file {
+            if let Err(err) = self.server_pool.read().save(path) {
+                warn!(target: self.log_target.as_str(), "Unable to save the server pool => {}", err);
+            }
+        }
+    }
+
+    /// Sets `TCP_NODELAY` on the current connection, see `NatsClientOptions::low_latency`.
+    pub(crate) fn set_nodelay(&self, nodelay: bool) {
+        if let Err(err) = self.inner.read().1.set_nodelay(nodelay) {
+            warn!(target: self.log_target.as_str(), "Unable to set TCP_NODELAY => {}", err);
+        }
+    }
+
+    /// Time since data was last read from the server.
+    pub(crate) fn read_idle(&self) -> std::time::Duration {
+        self.clock.since(*self.last_read.read())
+    }
+
     fn reconnect(conn: Arc<Self>) {
-        trace!(target: "ratsio", "Reconnecting");
+        trace!(target: conn.log_target.as_str(), "Reconnecting");
+        let is_tls = conn.is_tls;
+        let log_target = conn.log_target.clone();
+        let tls_identity = conn.tls_identity.clone();
+        let tls_hostname = conn.tls_hostname.clone();
+        let address_family = conn.address_family;
         {
             let mut state_guard = conn.state.write();
             if state_guard.0 == NatsConnectionState::Disconnected {
@@ -109,211 +214,316 @@ impl NatsConnection {
             }
         }
 
-        let cluster_addrs: Vec<_> = NatsConnection::parse_uris(&conn.reconnect_hosts.read());
-        trace!(target: "ratsio", "Retrying {:?}", &*conn.reconnect_hosts.read());
+        let standby = conn.standby.take(conn.clock.now());
+        let pool_conn = conn.clone();
+        let connected = async move {
+            if let Some(standby) = standby {
+                debug!(target: log_target.as_str(), "Switching to the standby connection to {}", standby.0.summary());
+                return Ok(standby);
+            }
+            if let Some(ref server_list) = pool_conn.server_list {
+                let seeds = pool_conn.server_pool.read().seeds().to_vec();
+                let seeds = server_list.resolve(seeds).await;
+                pool_conn.server_pool.write().set_seeds(seeds);
+            }
+            let servers = pool_conn.server_pool.read().servers();
+            trace!(target: log_target.as_str(), "Retrying {:?}", &servers);
+            let cluster_addrs: Vec<_> = NatsConnection::resolve_urls(&servers, address_family, &log_target);
+            let inner = NatsConnection::get_conn_inner(cluster_addrs, is_tls, tls_identity, tls_hostname, log_target).await;
+            pool_conn.record_attempt(&servers, inner.as_ref().ok().map(|(server, _)| server));
+            inner
+        };
 
-        tokio::spawn(NatsConnection::get_conn_inner(cluster_addrs, conn.is_tls)
+        tokio::spawn(connected
             .then(move |inner_result| {
-                let connect_version = (*conn.state.read()).1;
+                let (conn_state, connect_version) = *conn.state.read();
+                if conn_state == NatsConnectionState::Closed {
+                    return Either::Left(future::ready(()));
+                }
                 let retry_conn = conn.clone();
                 match inner_result {
                     Ok(new_inner) => {
                         *conn.inner.write() = new_inner;
+                        conn.record_event(ConnectionEventReason::Connected);
+                        *conn.last_read.write() = conn.clock.now();
                         *conn.state.write() = (NatsConnectionState::Connected, connect_version + 1);
                         let _ = conn.reconnect_handler.unbounded_send(conn.clone());
-                        debug!(target: "ratsio", "Got a connection");
-                        Either::Left(future::ok(()))
+                        debug!(target: conn.log_target.as_str(), "Got a connection");
+                        Either::Left(future::ready(()))
                     }
                     Err(err) => {
-                        error!(target: "ratsio", "Error reconnecting :: {:?}", err);
+                        error!(target: conn.log_target.as_str(), "Error reconnecting :: {:?}", err);
+                        conn.record_event(ConnectionEventReason::ReconnectFailed(err.to_string()));
                         *retry_conn.state.write() = (NatsConnectionState::Disconnected, connect_version);
                         //Rescedule another attempt
-                        let task = tokio::time::delay_for(std::time::Duration::from_millis(retry_conn.reconnect_timeout))
-                            .then(|_| async move  {
-                                NatsConnection::trigger_reconnect(retry_conn);
-                                Ok(())
-                            });
+                        let delay = retry_conn.reconnect_delay.read().next(is_tls || retry_conn.inner.read().0.tls);
+                        let task = retry_conn.clock.delay(delay)
+                            .map(move |_| NatsConnection::trigger_reconnect(retry_conn));
                         Either::Right(task)
                     }
                 }
             }));
     }
 
-    pub fn create_connection(reconnect_handler: ReconnectHandler, reconnect_timeout: u64,
-                             cluster_uris: &[String], tls_required: bool) -> impl Future<Output=NatsConnection> {
-        let cluster_addrs = NatsConnection::parse_uris(cluster_uris);
-        let init_hosts = cluster_uris.to_vec();
-        NatsConnection::get_conn_inner(cluster_addrs, tls_required)
-            .map(move |inner| {
-                NatsConnection {
-                    is_tls: tls_required,
-                    state: Arc::new(RwLock::new((NatsConnectionState::Connected, 0))),
-                    inner: Arc::new(RwLock::new(inner)),
-                    init_hosts: init_hosts.clone(),
-                    reconnect_hosts: RwLock::new(init_hosts),
-                    reconnect_handler,
-                    reconnect_timeout,
-                }
-            })
+    /// Connects to the first reachable server of the pool other than the current one and keeps the
+    /// connection as standby, unless a fresh one is held already or no standby is wanted.
+    pub(crate) async fn warm_standby(conn: Arc<Self>) {
+        if conn.standby.max_age().is_none() || !conn.is_connected() || conn.standby.is_fresh(conn.clock.now()) {
+            return;
+        }
+        let current = conn.inner.read().0.clone();
+        let servers: Vec<_> = conn.server_pool.read().servers().into_iter()
+            .filter(|server| *server != current)
+            .collect();
+        if servers.is_empty() {
+            trace!(target: conn.log_target.as_str(), "No other server to keep a standby connection to");
+            return;
+        }
+        let cluster_addrs = NatsConnection::resolve_urls(&servers, conn.address_family, &conn.log_target);
+        let standby = NatsConnection::get_conn_inner(cluster_addrs, conn.is_tls, conn.tls_identity.clone(),
+                                                     conn.tls_hostname.clone(), conn.log_target.clone()).await;
+        match standby {
+            Ok((server, inner)) => {
+                trace!(target: conn.log_target.as_str(), "Standby connection to {}", server.summary());
+                conn.standby.put(server, inner, conn.clock.now());
+            }
+            Err(err) => debug!(target: conn.log_target.as_str(), "Unable to make a standby connection => {}", err),
+        }
     }
 
-    pub fn parse_uris(cluster_uris: &[String]) -> Vec<(Url, SocketAddr)> {
-        cluster_uris.iter().map(|cluster_uri| {
-            let formatted_url = if cluster_uri.starts_with("nats://") {
-                cluster_uri.clone()
-            } else {
-                format!("nats://{}", cluster_uri)
+    /// Connects to the first reachable of `cluster_uris`, or of the servers supplied by `server_list`.
+    pub(crate) fn create_connection(reconnect_handler: ReconnectHandler, reconnect_delay: ReconnectDelay,
+                             cluster_uris: &[NatsUrl], server_list: Option<ServerList>,
+                             tls_required: bool, tls_identity: Option<TlsIdentityProvider>,
+                             tls_hostname: Option<String>, address_family: AddressFamily, log_target: String, clock: SharedClock,
+                             history: ConnectionHistory, server_pool_file: Option<PathBuf>)
+                             -> impl Future<Output=Result<NatsConnection, RatsioError>> {
+        let cluster_uris = cluster_uris.to_vec();
+        async move {
+            let seeds = match server_list {
+                Some(ref server_list) => server_list.resolve(cluster_uris).await,
+                None => cluster_uris,
             };
-            let node_url = Url::parse(&formatted_url);
-            match node_url {
-                Ok(node_url) =>
-                    match node_url.host_str() {
-                        Some(host) => {
-                            let host_and_port = format!("{}:{}", &host, node_url.port().unwrap_or(4222));
-                            match SocketAddr::from_str(&host_and_port) {
-                                Ok(sock_addr) => {
-                                    info!(" Resolved {} to {}", &host, &sock_addr);
-                                    vec!((node_url.clone(), sock_addr))
-                                }
-                                Err(_) => {
-                                    match host_and_port.to_socket_addrs() {
-                                        Ok(ips_iter) => ips_iter.map(|x| {
-                                            info!(" Resolved {} to {}", &host, &x);
-                                            (node_url.clone(), x)
-                                        }).collect::<Vec<_>>(),
-                                        Err(err) => {
-                                            error!("Unable resolve url => {} to ip address => {}", cluster_uri, err);
-                                            Vec::new()
-                                        }
-                                    }
-                                }
-                            }
+            let connection = NatsConnection::unconnected(reconnect_handler, reconnect_delay, seeds, server_list,
+                                                         tls_required, tls_identity.clone(), tls_hostname.clone(),
+                                                         address_family, log_target.clone(), clock, history,
+                                                         server_pool_file);
+            let servers = connection.server_pool.read().servers();
+            let cluster_addrs = NatsConnection::resolve_urls(&servers, address_family, &log_target);
+            let inner = NatsConnection::get_conn_inner(cluster_addrs, tls_required, tls_identity,
+                                                       tls_hostname, log_target).await;
+            connection.record_attempt(&servers, inner.as_ref().ok().map(|(server, _)| server));
+            let inner = inner?;
+            connection.history.record(inner.0.clone(), ConnectionEventReason::Connected);
+            *connection.inner.write() = inner;
+            *connection.state.write() = (NatsConnectionState::Connected, 0);
+            Ok(connection)
+        }
+    }
+
+    /// A connection to `seeds`, and the servers saved in `server_pool_file`, that is not made yet,
+    /// `Disconnected` until `trigger_reconnect` succeeds. See `NatsClientOptions::connect_lazily`.
+    pub(crate) fn unconnected(reconnect_handler: ReconnectHandler, reconnect_delay: ReconnectDelay,
+                              seeds: Vec<NatsUrl>, server_list: Option<ServerList>,
+                              tls_required: bool, tls_identity: Option<TlsIdentityProvider>,
+                              tls_hostname: Option<String>, address_family: AddressFamily, log_target: String,
+                              clock: SharedClock, history: ConnectionHistory,
+                              server_pool_file: Option<PathBuf>) -> NatsConnection {
+        let server_url = seeds.first().cloned().unwrap_or_else(|| NatsUrl::new("localhost", 4222));
+        let mut server_pool = ServerPool::new(seeds);
+        if let Some(ref path) = server_pool_file {
+            if let Err(err) = server_pool.load(path) {
+                warn!(target: log_target.as_str(), "Unable to load the server pool => {}", err);
+            }
+        }
+        NatsConnection {
+            is_tls: tls_required,
+            state: Arc::new(RwLock::new((NatsConnectionState::Disconnected, 0))),
+            inner: Arc::new(RwLock::new((server_url, NatsConnectionInner::NotConnected))),
+            last_read: Arc::new(RwLock::new(clock.now())),
+            server_pool: RwLock::new(server_pool),
+            server_pool_file,
+            server_list,
+            tls_identity,
+            tls_hostname,
+            address_family,
+            reconnect_handler,
+            reconnect_delay: RwLock::new(reconnect_delay),
+            log_target,
+            clock,
+            history,
+            standby: Standby::default(),
+        }
+    }
+
+    /// Resolves the urls in order, the addresses of each url are ordered by `address_family`.
+    pub(crate) fn resolve_urls(cluster_uris: &[NatsUrl], address_family: AddressFamily,
+                               log_target: &str) -> Vec<(NatsUrl, SocketAddr)> {
+        cluster_uris.iter().map(|node_url| {
+            let host_and_port = node_url.host_and_port();
+            match SocketAddr::from_str(&host_and_port) {
+                Ok(sock_addr) => {
+                    info!(target: log_target, " Resolved {} to {}", &node_url.host, &sock_addr);
+                    vec!((node_url.clone(), sock_addr))
+                }
+                Err(_) => {
+                    match host_and_port.to_socket_addrs() {
+                        Ok(ips_iter) => {
+                            let mut addrs = ips_iter.map(|x| {
+                                info!(target: log_target, " Resolved {} to {}", &node_url.host, &x);
+                                (node_url.clone(), x)
+                            }).collect::<Vec<_>>();
+                            address_family.order(&mut addrs);
+                            addrs
                         }
-                        _ => {
+                        Err(err) => {
+                            error!(target: log_target, "Unable resolve url => {} to ip address => {}", node_url.summary(), err);
                             Vec::new()
                         }
                     }
-                Err(err) => {
-                    error!("Unable to parse url => {} => {}", cluster_uri, err);
-                    Vec::new()
                 }
             }
         }).flatten().collect()
     }
 
-
-    fn get_conn_inner(cluster_addrs: Vec<(Url, SocketAddr)>, tls_required: bool)
-                      -> impl Future<Output=Result<(Url, NatsConnectionInner), RatsioError>> {
-        if cluster_addrs.is_empty() {
-            warn!("No addresses to connect to.");
-            return Either::Left(future::err(RatsioError::NoRouteToHostError));
-        }
-        fn get_conn_step(cluster_addrs: &[(Url, SocketAddr)], tls_required: bool)
-                         -> impl Future<Output=(Url, NatsConnectionInner)> {
+    /// Connects to the first reachable address, in order. The client certificate is fetched once
+    /// per attempt, before the first TLS connection. `tls_hostname` replaces the host of the urls
+    /// for SNI and certificate verification.
+    fn get_conn_inner(cluster_addrs: Vec<(NatsUrl, SocketAddr)>, tls_required: bool,
+                      tls_identity: Option<TlsIdentityProvider>, tls_hostname: Option<String>,
+                      log_target: String)
+                      -> impl Future<Output=Result<(NatsUrl, NatsConnectionInner), RatsioError>> {
+        async move {
             if cluster_addrs.is_empty() {
-                Either::Left(future::err::<(Url, NatsConnectionInner), RatsioError>(RatsioError::NoRouteToHostError))
-            } else {
-                Either::Right(future::ok(cluster_addrs[0].clone())
-                    .and_then(move |(node_url, node_addr)| {
-                        if tls_required {
-                            match node_url.host_str() {
-                                Some(host) => future::ok(Either::Right(NatsConnection::connect_tls(host.to_string(), node_addr)
-                                    .map(move |con| (node_url.clone(), con)))),
-                                None => future::err(RatsioError::NoRouteToHostError),
-                            }
-                        } else {
-                            future::ok(Either::Left(NatsConnection::connect(node_addr)
-                                .map(move |con| (node_url.clone(), con))))
+                warn!(target: log_target.as_str(), "No addresses to connect to.");
+                return Err(RatsioError::NoRouteToHostError);
+            }
+            let mut identity: Option<Identity> = None;
+            for (node_url, node_addr) in cluster_addrs {
+                let conn_result = if tls_required || node_url.tls {
+                    if identity.is_none() {
+                        if let Some(ref provider) = tls_identity {
+                            identity = Some(provider.identity()?);
                         }
-                    })
-                    .flatten())
+                    }
+                    let host = tls_hostname.clone().unwrap_or_else(|| node_url.host.clone());
+                    NatsConnection::connect_tls(host, node_addr, identity.clone()).await
+                } else {
+                    NatsConnection::connect(node_addr).await
+                };
+                match conn_result {
+                    Ok(inner) => {
+                        debug!(target: log_target.as_str(), "Connected to {}", node_url.summary());
+                        return Ok((node_url, inner));
+                    }
+                    Err(err @ RatsioError::FeatureNotEnabled(_)) => return Err(err),
+                    Err(err) => debug!(target: log_target.as_str(), "Unable to connect to {} => {}", node_url.summary(), err),
+                }
             }
+            Err(RatsioError::NoRouteToHostError)
+        }
+    }
+}
+
+impl NatsConnSinkStream {
+    fn is_connected(&self) -> bool {
+        self.state.read().0 == NatsConnectionState::Connected
+    }
+
+    /// Triggers a reconnect on connection errors, the error is passed on so the sending task ends,
+    /// a new `Sink` is created once reconnected.
+    fn check_sink_result(&self, result: Result<(), RatsioError>) -> Result<(), RatsioError> {
+        if let Err(ref err) = result {
+            error!(target: self.log_target.as_str(), "Error writing to connection => {}", err);
+            self.lost(ConnectionEventReason::IoError(err.to_string()));
+        }
+        result
+    }
+
+    /// Records why the connection was lost, once, and triggers a reconnect.
+    fn lost(&self, reason: ConnectionEventReason) {
+        if self.is_connected() {
+            let server = self.inner.read().0.clone();
+            self.history.record(server, reason.clone());
         }
-        Either::Right(loop_fn(cluster_addrs,
-                          move |cluster_addrs| {
-                              let rem_addrs = Vec::from(&cluster_addrs[1..]).clone();
-                              get_conn_step(&cluster_addrs[..], tls_required)
-                                  .then(move |inner| {
-                                      Ok(Loop::Break(inner))
-                                  })
-                                  .or_else(move |_err| {
-                                      if rem_addrs.is_empty() {
-                                          Err(RatsioError::NoRouteToHostError)
-                                      } else {
-                                          Ok(Loop::Continue(rem_addrs))
-                                      }
-                                  })
-                          }))
+        (*self.reconnect_trigger)(&reason);
     }
 }
 
 impl Sink<Op> for NatsConnSinkStream {
     type Error = RatsioError;
 
-    fn start_send(&mut self, item: Op) -> Result<(), Self::Error> {
-        if match self.state.try_read() {
-            Some(state) => (*state).0 != NatsConnectionState::Connected,
-            _ => true,
-        } {
-            return Ok(());
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if !self.is_connected() {
+            return Poll::Ready(Err(RatsioError::ServerDisconnected(None)));
         }
+        let result = ready!(Pin::new(&mut self.inner.write().1).poll_ready(cx));
+        Poll::Ready(self.check_sink_result(result))
+    }
 
-        if let Some(mut inner) = self.inner.try_write() {
-            match (*inner).1.start_send(item.clone()) {
-                Err(RatsioError::ServerDisconnected(_)) => {
-                    (*self.reconnect_trigger)();
-                    Ok(())
-                }
-                poll_res => poll_res,
-            }
-        } else {
-            Ok(())
+    fn start_send(self: Pin<&mut Self>, item: Op) -> Result<(), Self::Error> {
+        if !self.is_connected() {
+            return Err(RatsioError::ServerDisconnected(None));
         }
+        let result = Pin::new(&mut self.inner.write().1).start_send(item);
+        self.check_sink_result(result)
     }
 
-    fn poll_flush(&mut self) -> Poll<Result<(), Self::Error>> {
-        if match self.state.try_read() {
-            Some(state) => (*state).0 != NatsConnectionState::Connected,
-            _ => true,
-        } {
-            return Poll::Pending;
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if !self.is_connected() {
+            return Poll::Ready(Err(RatsioError::ServerDisconnected(None)));
         }
+        let result = ready!(Pin::new(&mut self.inner.write().1).poll_flush(cx));
+        Poll::Ready(self.check_sink_result(result))
+    }
 
-        if let Some(mut inner) = self.inner.try_write() {
-            match (*inner).1.poll_flush() {
-                Err(RatsioError::ServerDisconnected(_)) => {
-                    (*self.reconnect_trigger)();
-                    Poll::Pending
-                }
-                poll_res => poll_res,
-            }
-        } else {
-            Poll::Pending
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if !self.is_connected() {
+            return Poll::Ready(Ok(()));
         }
+        Pin::new(&mut self.inner.write().1).poll_close(cx)
     }
 }
 
 impl Stream for NatsConnSinkStream {
     type Item = Op;
 
-    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
-        if match self.state.try_read() {
-            Some(state) => (*state).0 != NatsConnectionState::Connected,
-            _ => true,
-        } {
-            return Poll::Pending;
+    /// Ends when the connection is lost, a new `Stream` is created once reconnected.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if !self.is_connected() {
+            return Poll::Ready(None);
         }
-
-        if let Some(mut inner) = self.inner.try_write() {
-            match (*inner).1.poll_next() {
-                Err(RatsioError::ServerDisconnected(_)) => {
-                    (*self.reconnect_trigger)();
-                    Poll::Pending
+        let next = ready!(Pin::new(&mut self.inner.write().1).poll_next(cx));
+        match next {
+            Some(Ok(op)) => {
+                *self.last_read.write() = self.clock.now();
+                Poll::Ready(Some(op))
+            }
+            Some(Err(err)) => {
+                error!(target: self.log_target.as_str(), "Error reading from connection => {}", err);
+                match err {
+                    RatsioError::ProtocolError(line) => self.lost(ConnectionEventReason::ProtocolError(line)),
+                    err => self.lost(ConnectionEventReason::IoError(err.to_string())),
                 }
-                poll_res => poll_res,
+                Poll::Ready(None)
+            }
+            None => {
+                warn!(target: self.log_target.as_str(), "Connection closed by the server");
+                self.lost(ConnectionEventReason::IoError(String::from("connection closed by the server")));
+                Poll::Ready(None)
             }
-        } else {
-            Poll::Pending
         }
     }
 }
+
+#[test]
+fn reconnect_delay_jitter() {
+    use std::time::Duration;
+
+    let delay = ReconnectDelay { reconnect_timeout: 100, jitter: 0, jitter_tls: 50 };
+    assert_eq!(delay.next(false), Duration::from_millis(100));
+    for _ in 0..100 {
+        let next = delay.next(true);
+        assert!(next >= Duration::from_millis(100) && next <= Duration::from_millis(150));
+    }
+}
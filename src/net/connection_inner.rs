@@ -1,8 +1,10 @@
 use crate::codec::OpCodec;
 use futures::{prelude::*, task::{Context, Poll}};
-use native_tls::TlsConnector as NativeTlsConnector;
+#[cfg(feature = "tls")]
+use native_tls::{Identity, TlsConnector as NativeTlsConnector};
 use std::{net::SocketAddr, pin::Pin};
 use tokio::net::TcpStream;
+#[cfg(feature = "tls")]
 use tokio_tls::{TlsConnector, TlsStream};
 use tokio_util::codec::{Decoder, Framed};
 use crate::error::*;
@@ -12,21 +14,64 @@ use crate::ops::Op;
 pub(crate) enum NatsConnectionInner {
     Tcp(Box<Framed<TcpStream, OpCodec>>),
 
+    #[cfg(feature = "tls")]
     Tls(Box<Framed<TlsStream<TcpStream>,OpCodec>>),
+
+    /// No connection was made yet, see `NatsClientOptions::connect_lazily`
+    NotConnected,
 }
 
 
 impl NatsConnectionInner {
     pub(crate) fn connect_tcp(addr: SocketAddr) -> impl Future<Output=Result<TcpStream, RatsioError>> {
         TcpStream::connect(addr)
-            .map_err(|err| RatsioError::from(err))
+            .map_err(RatsioError::from)
+    }
+
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        match self {
+            NatsConnectionInner::Tcp(framed) => Some(framed.get_ref()),
+            #[cfg(feature = "tls")]
+            NatsConnectionInner::Tls(framed) => Some(framed.get_ref().get_ref()),
+            NatsConnectionInner::NotConnected => None,
+        }
+    }
+
+    pub(crate) fn is_tls(&self) -> bool {
+        match self {
+            #[cfg(feature = "tls")]
+            NatsConnectionInner::Tls(_) => true,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        self.tcp_stream().and_then(|stream| stream.local_addr().ok())
+    }
+
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        self.tcp_stream().and_then(|stream| stream.peer_addr().ok())
     }
 
-    pub(crate) fn upgrade_tcp_to_tls(host: String, socket: TcpStream) -> impl Future<Output=Result<TlsStream<TcpStream>, RatsioError>>{
-        let tls_connector = NativeTlsConnector::builder().build().unwrap();
-        let tls_stream: TlsConnector = tls_connector.into();
-        tls_stream.connect(&host, socket)
-            .map(|result| result.map_err(|err| RatsioError::from(err)))
+    pub(crate) fn set_nodelay(&self, nodelay: bool) -> std::io::Result<()> {
+        match self.tcp_stream() {
+            Some(stream) => stream.set_nodelay(nodelay),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn upgrade_tcp_to_tls(host: String, socket: TcpStream, identity: Option<Identity>)
+                                     -> impl Future<Output=Result<TlsStream<TcpStream>, RatsioError>>{
+        async move {
+            let mut builder = NativeTlsConnector::builder();
+            if let Some(identity) = identity {
+                builder.identity(identity);
+            }
+            let tls_connector: TlsConnector = builder.build()?.into();
+            tls_connector.connect(&host, socket).await
+                .map_err(RatsioError::from)
+        }
     }
 }
 
@@ -36,6 +81,7 @@ impl From<TcpStream> for NatsConnectionInner {
     }
 }
 
+#[cfg(feature = "tls")]
 impl From<TlsStream<TcpStream>> for NatsConnectionInner {
     fn from(socket: TlsStream<TcpStream>) -> Self {
         NatsConnectionInner::Tls(Box::new(OpCodec::default().framed(socket)))
@@ -45,28 +91,52 @@ impl From<TlsStream<TcpStream>> for NatsConnectionInner {
 impl Sink<Op> for NatsConnectionInner {
     type Error = RatsioError;
 
-    fn start_send(&mut self, item: Op) -> Result<(), Self::Error> {
-        match self {
-            NatsConnectionInner::Tcp(framed) => framed.start_send(item),
-            NatsConnectionInner::Tls(framed) => framed.start_send(item),
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            NatsConnectionInner::Tcp(framed) => Pin::new(framed.as_mut()).poll_ready(cx),
+            #[cfg(feature = "tls")]
+            NatsConnectionInner::Tls(framed) => Pin::new(framed.as_mut()).poll_ready(cx),
+            NatsConnectionInner::NotConnected => Poll::Ready(Err(RatsioError::NotConnected)),
         }
     }
 
-    fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        match self {
-            NatsConnectionInner::Tcp(framed) => framed.poll_complete(),
-            NatsConnectionInner::Tls(framed) => framed.poll_complete(),
+    fn start_send(self: Pin<&mut Self>, item: Op) -> Result<(), Self::Error> {
+        match self.get_mut() {
+            NatsConnectionInner::Tcp(framed) => Pin::new(framed.as_mut()).start_send(item),
+            #[cfg(feature = "tls")]
+            NatsConnectionInner::Tls(framed) => Pin::new(framed.as_mut()).start_send(item),
+            NatsConnectionInner::NotConnected => Err(RatsioError::NotConnected),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            NatsConnectionInner::Tcp(framed) => Pin::new(framed.as_mut()).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            NatsConnectionInner::Tls(framed) => Pin::new(framed.as_mut()).poll_flush(cx),
+            NatsConnectionInner::NotConnected => Poll::Ready(Err(RatsioError::NotConnected)),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            NatsConnectionInner::Tcp(framed) => Pin::new(framed.as_mut()).poll_close(cx),
+            #[cfg(feature = "tls")]
+            NatsConnectionInner::Tls(framed) => Pin::new(framed.as_mut()).poll_close(cx),
+            NatsConnectionInner::NotConnected => Poll::Ready(Ok(())),
         }
     }
 }
 
 impl Stream for NatsConnectionInner {
-    type Item = Op;
+    type Item = Result<Op, RatsioError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        match self {
-            NatsConnectionInner::Tcp(framed) => framed.poll_next(),
-            NatsConnectionInner::Tls(framed) => framed.poll_next(),
+        match self.get_mut() {
+            NatsConnectionInner::Tcp(framed) => Pin::new(framed.as_mut()).poll_next(cx),
+            #[cfg(feature = "tls")]
+            NatsConnectionInner::Tls(framed) => Pin::new(framed.as_mut()).poll_next(cx),
+            NatsConnectionInner::NotConnected => Poll::Ready(None),
         }
     }
 }
@@ -0,0 +1,88 @@
+use super::NatsUrl;
+use parking_lot::Mutex;
+use std::{collections::VecDeque, sync::Arc, time::SystemTime};
+
+/// What happened to the connection, see `ConnectionEvent`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionEventReason {
+    /// A connection to the server was established
+    Connected,
+    /// Reading from or writing to the connection failed
+    IoError(String),
+    /// Pings went unanswered, or nothing was read for `read_timeout`
+    Stale,
+    /// A write did not complete within `write_timeout`, the server stopped reading
+    WriteTimeout,
+    /// The server announced it is about to shut down
+    LameDuck,
+    /// The server rejected the credentials of the client
+    Auth(String),
+    /// The server sent an op that could not be parsed, with the offending line
+    ProtocolError(String),
+    /// An internal task of the client failed, the connection was re-established
+    TaskFailed(String),
+    /// A reconnect attempt failed
+    ReconnectFailed(String),
+}
+
+/// An entry of `NatsClient::connection_history`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionEvent {
+    /// The server connected to, or whose connection was lost
+    pub server: NatsUrl,
+    pub at: SystemTime,
+    pub reason: ConnectionEventReason,
+}
+
+/// The latest `capacity` connection events, oldest first.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionHistory {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<ConnectionEvent>>>,
+}
+
+impl ConnectionHistory {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ConnectionHistory {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub(crate) fn record(&self, server: NatsUrl, reason: ConnectionEventReason) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(ConnectionEvent {
+            server,
+            at: SystemTime::now(),
+            reason,
+        });
+    }
+
+    pub(crate) fn events(&self) -> Vec<ConnectionEvent> {
+        self.events.lock().iter().cloned().collect()
+    }
+}
+
+#[test]
+fn keeps_the_latest_events() {
+    let history = ConnectionHistory::new(2);
+    let server = NatsUrl::new("a", 4222);
+    history.record(server.clone(), ConnectionEventReason::Connected);
+    history.record(server.clone(), ConnectionEventReason::Stale);
+    history.record(server.clone(), ConnectionEventReason::ReconnectFailed(String::from("refused")));
+    let reasons: Vec<_> = history.events().into_iter().map(|event| event.reason).collect();
+    assert_eq!(reasons, vec![
+        ConnectionEventReason::Stale,
+        ConnectionEventReason::ReconnectFailed(String::from("refused")),
+    ]);
+
+    let disabled = ConnectionHistory::new(0);
+    disabled.record(server, ConnectionEventReason::Connected);
+    assert!(disabled.events().is_empty());
+}
@@ -1,9 +1,25 @@
-use futures::channel::mpsc::UnboundedSender;
 use std::sync::Arc;
-pub(crate) use self::connection::{NatsConnSinkStream, NatsConnection};
+pub(crate) use self::connection::{NatsConnSinkStream, NatsConnection, ReconnectDelay};
+pub use self::address_family::AddressFamily;
+pub(crate) use self::history::ConnectionHistory;
+pub(crate) use self::queue::{counted, CountedReceiver, CountedSender, QueueDepth};
+pub use self::history::{ConnectionEvent, ConnectionEventReason};
+pub use self::nats_url::NatsUrl;
+pub(crate) use self::server_pool::ServerPool;
+pub use self::server_list::{ServerList, ServerListProvider};
+pub(crate) use self::standby::Standby;
+pub use self::tls_identity::{Identity, IdentityCallback, TlsIdentityProvider};
 
+mod address_family;
 pub(crate) mod connection;
 mod connection_inner;
+mod history;
+mod nats_url;
+mod queue;
+mod server_pool;
+mod server_list;
+mod standby;
+mod tls_identity;
 
 
-pub(crate) type ReconnectHandler = UnboundedSender<Arc<NatsConnection>>;
+pub(crate) type ReconnectHandler = CountedSender<Arc<NatsConnection>>;
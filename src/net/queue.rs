@@ -0,0 +1,101 @@
+use futures::{
+    channel::mpsc::{self, TrySendError, UnboundedReceiver, UnboundedSender},
+    ready,
+    task::{Context, Poll},
+    Stream, StreamExt,
+};
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Number of items queued in a channel and not taken from it yet.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct QueueDepth(Arc<AtomicUsize>);
+
+impl QueueDepth {
+    pub(crate) fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn queued(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn taken(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// An unbounded channel that keeps count of the items queued in it, see `NatsClient::runtime_metrics`.
+pub(crate) fn counted<T>() -> (CountedSender<T>, CountedReceiver<T>) {
+    let (tx, rx) = mpsc::unbounded();
+    let depth = QueueDepth::default();
+    (CountedSender { tx, depth: depth.clone() }, CountedReceiver { rx, depth })
+}
+
+#[derive(Debug)]
+pub(crate) struct CountedSender<T> {
+    tx: UnboundedSender<T>,
+    depth: QueueDepth,
+}
+
+impl<T> Clone for CountedSender<T> {
+    fn clone(&self) -> Self {
+        CountedSender {
+            tx: self.tx.clone(),
+            depth: self.depth.clone(),
+        }
+    }
+}
+
+impl<T> CountedSender<T> {
+    pub(crate) fn unbounded_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.depth.queued();
+        self.tx.unbounded_send(item).map_err(|err| {
+            self.depth.taken();
+            err
+        })
+    }
+
+    /// Items sent and not received yet.
+    pub(crate) fn depth(&self) -> usize {
+        self.depth.get()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CountedReceiver<T> {
+    rx: UnboundedReceiver<T>,
+    depth: QueueDepth,
+}
+
+impl<T> Stream for CountedReceiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let item = ready!(self.rx.poll_next_unpin(cx));
+        if item.is_some() {
+            self.depth.taken();
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[test]
+fn counts_queued_items() {
+    use futures::executor::block_on;
+
+    let (tx, mut rx) = counted();
+    tx.unbounded_send(1).unwrap();
+    tx.clone().unbounded_send(2).unwrap();
+    assert_eq!(tx.depth(), 2);
+    assert_eq!(block_on(rx.next()), Some(1));
+    assert_eq!(tx.depth(), 1);
+    drop(rx);
+    assert!(tx.unbounded_send(3).is_err());
+    assert_eq!(tx.depth(), 1);
+}
@@ -0,0 +1,70 @@
+use super::NatsUrl;
+use crate::error::RatsioError;
+use futures::future::{BoxFuture, FutureExt};
+use std::{fmt, future::Future, sync::Arc};
+
+/// Supplies the servers to connect to, e.g. from Consul or Kubernetes endpoints. Asked before
+/// every connect and reconnect attempt, the servers it returns replace the `cluster_uris`.
+pub trait ServerListProvider: Send + Sync {
+    fn servers(&self) -> BoxFuture<'static, Result<Vec<NatsUrl>, RatsioError>>;
+}
+
+impl<F, Fut> ServerListProvider for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Vec<NatsUrl>, RatsioError>> + Send + 'static,
+{
+    fn servers(&self) -> BoxFuture<'static, Result<Vec<NatsUrl>, RatsioError>> {
+        (self)().boxed()
+    }
+}
+
+/// A shareable `ServerListProvider`, see `NatsClientOptions::server_list`.
+#[derive(Clone)]
+pub struct ServerList(Arc<dyn ServerListProvider>);
+
+impl ServerList {
+    pub fn new<P: ServerListProvider + 'static>(provider: P) -> Self {
+        ServerList(Arc::new(provider))
+    }
+
+    /// The provided servers, `fallback` if the provider fails or returns none.
+    pub(crate) async fn resolve(&self, fallback: Vec<NatsUrl>) -> Vec<NatsUrl> {
+        match self.0.servers().await {
+            Ok(ref servers) if servers.is_empty() => {
+                warn!(target: "ratsio", "Server list provider returned no servers, using {:?}", &fallback);
+                fallback
+            }
+            Ok(servers) => servers,
+            Err(err) => {
+                error!(target: "ratsio", "Server list provider failed => {}, using {:?}", err, &fallback);
+                fallback
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ServerList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ServerList(provider)")
+    }
+}
+
+impl PartialEq for ServerList {
+    fn eq(&self, other: &ServerList) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[test]
+fn resolve_falls_back() {
+    use futures::executor::block_on;
+
+    let fallback = vec![NatsUrl::new("seed", 4222)];
+    let provided = ServerList::new(|| futures::future::ok::<_, RatsioError>(vec![NatsUrl::new("discovered", 4222)]));
+    assert_eq!(block_on(provided.resolve(fallback.clone()))[0].host, "discovered");
+    let empty = ServerList::new(|| futures::future::ok::<_, RatsioError>(Vec::new()));
+    assert_eq!(block_on(empty.resolve(fallback.clone())), fallback);
+    let failing = ServerList::new(|| futures::future::err::<Vec<NatsUrl>, _>(RatsioError::NoRouteToHostError));
+    assert_eq!(block_on(failing.resolve(fallback.clone())), fallback);
+}
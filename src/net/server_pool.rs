@@ -0,0 +1,201 @@
+use crate::error::RatsioError;
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use super::NatsUrl;
+
+/// How connecting to a server went lately.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct ServerHealth {
+    /// Failed connects since the last one that succeeded
+    pub(crate) failures: u32,
+    /// Seconds since the epoch of the last successful connect
+    pub(crate) last_connected: Option<u64>,
+}
+
+/// A server and its health as persisted in the server pool file.
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedServer {
+    url: NatsUrl,
+    #[serde(flatten)]
+    health: ServerHealth,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct PersistedPool {
+    servers: Vec<PersistedServer>,
+}
+
+/// The servers a connection may (re)connect to: the user supplied seed urls
+/// and the urls learned from the `connect_urls` of server INFO messages.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ServerPool {
+    seeds: Vec<NatsUrl>,
+    discovered: Vec<NatsUrl>,
+    /// Gossiped servers loaded from the server pool file, until the first INFO replaces them
+    remembered: Vec<NatsUrl>,
+    /// By `host:port`
+    health: HashMap<String, ServerHealth>,
+    /// Try the seeds before the gossiped servers
+    prefer_seeds: bool,
+}
+
+impl ServerPool {
+    pub(crate) fn new(seeds: Vec<NatsUrl>) -> Self {
+        ServerPool {
+            seeds,
+            discovered: Vec::new(),
+            remembered: Vec::new(),
+            health: HashMap::new(),
+            prefer_seeds: false,
+        }
+    }
+
+    pub(crate) fn seeds(&self) -> &[NatsUrl] {
+        &self.seeds
+    }
+
+    pub(crate) fn set_seeds(&mut self, seeds: Vec<NatsUrl>) {
+        self.seeds = seeds;
+    }
+
+    pub(crate) fn set_prefer_seeds(&mut self, prefer_seeds: bool) {
+        self.prefer_seeds = prefer_seeds;
+    }
+
+    /// Replaces the gossiped servers, `connect_urls` are `host:port` strings as sent in INFO.
+    pub(crate) fn set_discovered(&mut self, connect_urls: &[String]) {
+        self.discovered = NatsUrl::parse_all(connect_urls);
+        self.remembered.clear();
+    }
+
+    pub(crate) fn health(&self, url: &NatsUrl) -> Option<&ServerHealth> {
+        self.health.get(&url.host_and_port())
+    }
+
+    /// Records a connect attempt that tried `servers` in order and got through to `connected`, or to
+    /// none of them. The servers tried before the one connected to failed.
+    pub(crate) fn record_attempt(&mut self, servers: &[NatsUrl], connected: Option<&NatsUrl>) {
+        for server in servers {
+            let health = self.health.entry(server.host_and_port()).or_default();
+            if Some(server) == connected {
+                health.failures = 0;
+                health.last_connected = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+                return;
+            }
+            health.failures = health.failures.saturating_add(1);
+        }
+    }
+
+    /// Servers to try when reconnecting, gossiped servers first, then the seeds, or the other way
+    /// around when seeds are preferred. Servers that failed fewer connects in a row come first.
+    /// Gossiped servers that are seeds too keep the credentials of the seed url.
+    pub(crate) fn servers(&self) -> Vec<NatsUrl> {
+        let gossiped: Vec<_> = self.discovered.iter().chain(&self.remembered).cloned().collect();
+        let (first, then) = if self.prefer_seeds {
+            (&self.seeds, &gossiped)
+        } else {
+            (&gossiped, &self.seeds)
+        };
+        let mut servers = first.clone();
+        for url in then {
+            match servers.iter_mut().find(|s| s.host == url.host && s.port == url.port) {
+                Some(server) => {
+                    if server.username.is_none() && server.password.is_none() {
+                        server.username = url.username.clone();
+                        server.password = url.password.clone();
+                    }
+                }
+                None => servers.push(url.clone()),
+            }
+        }
+        servers.sort_by_key(|server| self.health(server).map_or(0, |health| health.failures));
+        servers
+    }
+
+    /// Adds the gossiped servers and the health saved by `save`, to reach the cluster through them
+    /// when the seeds are down. A missing file is an empty pool.
+    pub(crate) fn load(&mut self, path: &Path) -> Result<(), RatsioError> {
+        let json = match fs::read(path) {
+            Ok(json) => json,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let persisted: PersistedPool = serde_json::from_slice(&json)
+            .map_err(|err| RatsioError::ConfigError(format!("{} => {}", path.display(), err)))?;
+        for server in persisted.servers {
+            self.health.insert(server.url.host_and_port(), server.health);
+            if !self.remembered.contains(&server.url) {
+                self.remembered.push(server.url);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the gossiped servers with their health to `path`, replacing it.
+    pub(crate) fn save(&self, path: &Path) -> Result<(), RatsioError> {
+        let known = if self.discovered.is_empty() { &self.remembered } else { &self.discovered };
+        let persisted = PersistedPool {
+            servers: known.iter().map(|url| PersistedServer {
+                url: url.clone(),
+                health: self.health(url).cloned().unwrap_or_default(),
+            }).collect(),
+        };
+        let json = serde_json::to_vec_pretty(&persisted)
+            .map_err(|err| RatsioError::GenericError(format!("{} => {}", path.display(), err)))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn discovered_servers_keep_seed_credentials() {
+    let mut pool = ServerPool::new(NatsUrl::parse_all(&["nats://user:secret@a:4222"]));
+    pool.set_discovered(&[String::from("a:4222"), String::from("b:4222")]);
+    let servers = pool.servers();
+    assert_eq!(servers[0].username, Some(String::from("user")));
+    assert_eq!(servers[0].password, Some(String::from("secret")));
+    assert_eq!(servers[1].username, None);
+}
+
+#[test]
+fn servers_prefer_discovered_and_dedup() {
+    let mut pool = ServerPool::new(NatsUrl::parse_all(&["nats://a:4222", "nats://b:4222"]));
+    assert_eq!(pool.servers().len(), 2);
+    pool.set_discovered(&[String::from("c:4222"), String::from("a:4222")]);
+    let hosts: Vec<_> = pool.servers().into_iter().map(|s| s.host).collect();
+    assert_eq!(hosts, vec!["c", "a", "b"]);
+    pool.set_prefer_seeds(true);
+    let hosts: Vec<_> = pool.servers().into_iter().map(|s| s.host).collect();
+    assert_eq!(hosts, vec!["a", "b", "c"]);
+}
+
+#[test]
+fn persisted_pool_reaches_healthy_servers() {
+    let path = std::env::temp_dir().join(format!("ratsio-server-pool-{}.json", std::process::id()));
+    let mut pool = ServerPool::new(NatsUrl::parse_all(&["nats://seed:4222"]));
+    pool.set_discovered(&[String::from("b:4222"), String::from("c:4222")]);
+    let tried = pool.servers();
+    pool.record_attempt(&tried, Some(&tried[1]));
+    assert_eq!(pool.servers()[0].host, "c");
+    pool.save(&path).unwrap();
+
+    let mut restarted = ServerPool::new(NatsUrl::parse_all(&["nats://seed:4222"]));
+    restarted.load(&path).unwrap();
+    let hosts: Vec<_> = restarted.servers().into_iter().map(|s| s.host).collect();
+    assert_eq!(hosts, vec!["c", "seed", "b"]);
+    assert_eq!(restarted.health(&tried[0]).unwrap().failures, 1);
+    assert!(restarted.health(&tried[1]).unwrap().last_connected.is_some());
+    fs::remove_file(&path).unwrap();
+
+    let mut empty = ServerPool::default();
+    empty.load(&path).unwrap();
+    assert!(empty.servers().is_empty());
+}
@@ -0,0 +1,74 @@
+use super::connection_inner::NatsConnectionInner;
+use super::NatsUrl;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+/// A connection made ahead of time to another server of the pool, switched to instead of dialing
+/// when the current connection is lost, see `NatsClientOptions::standby_connection`.
+#[derive(Debug, Default)]
+pub(crate) struct Standby {
+    /// How long a standby connection is used, servers drop connections that don't send CONNECT
+    /// in time. `None` when no standby connection is kept.
+    max_age: Mutex<Option<Duration>>,
+    conn: Mutex<Option<(NatsUrl, NatsConnectionInner, Instant)>>,
+}
+
+impl Standby {
+    pub(crate) fn set_max_age(&self, max_age: Option<Duration>) {
+        *self.max_age.lock() = max_age;
+        if max_age.is_none() {
+            self.clear();
+        }
+    }
+
+    pub(crate) fn max_age(&self) -> Option<Duration> {
+        *self.max_age.lock()
+    }
+
+    pub(crate) fn put(&self, server: NatsUrl, inner: NatsConnectionInner, made_at: Instant) {
+        if self.max_age().is_some() {
+            *self.conn.lock() = Some((server, inner, made_at));
+        }
+    }
+
+    /// Whether a standby connection younger than `max_age` is held.
+    pub(crate) fn is_fresh(&self, now: Instant) -> bool {
+        let max_age = match self.max_age() {
+            Some(max_age) => max_age,
+            None => return false,
+        };
+        match *self.conn.lock() {
+            Some((_, _, made_at)) => now.saturating_duration_since(made_at) < max_age,
+            None => false,
+        }
+    }
+
+    /// The standby connection, unless it is `max_age` old and may have been dropped by the server.
+    pub(crate) fn take(&self, now: Instant) -> Option<(NatsUrl, NatsConnectionInner)> {
+        let fresh = self.is_fresh(now);
+        let standby = self.conn.lock().take();
+        standby.filter(|_| fresh).map(|(server, inner, _)| (server, inner))
+    }
+
+    pub(crate) fn clear(&self) {
+        self.conn.lock().take();
+    }
+}
+
+#[test]
+fn standby_expires() {
+    let standby = Standby::default();
+    let start = Instant::now();
+    let server = NatsUrl::new("b", 4222);
+    standby.put(server.clone(), NatsConnectionInner::NotConnected, start);
+    assert!(standby.take(start).is_none(), "kept while disabled");
+
+    standby.set_max_age(Some(Duration::from_millis(100)));
+    standby.put(server.clone(), NatsConnectionInner::NotConnected, start);
+    assert!(standby.is_fresh(start + Duration::from_millis(99)));
+    assert_eq!(standby.take(start + Duration::from_millis(99)).map(|(url, _)| url), Some(server.clone()));
+    assert!(standby.take(start).is_none(), "taken once");
+
+    standby.put(server, NatsConnectionInner::NotConnected, start);
+    assert!(standby.take(start + Duration::from_millis(100)).is_none());
+}
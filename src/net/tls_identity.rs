@@ -0,0 +1,66 @@
+use crate::error::RatsioError;
+#[cfg(feature = "tls")]
+pub use native_tls::Identity;
+#[cfg(feature = "tls")]
+use std::{fs, path::PathBuf};
+use std::{fmt, sync::Arc};
+
+/// Stands in for `native_tls::Identity` when built without the `tls` feature, it has no values
+/// so a `TlsIdentityProvider` can only fail.
+#[cfg(not(feature = "tls"))]
+#[derive(Clone, Debug)]
+pub enum Identity {}
+
+/// An alias representing the requirements for the client certificate callback function
+pub type IdentityCallback = Arc<dyn Fn() -> Result<Identity, RatsioError> + Send + Sync>;
+
+/// Supplies the client certificate and key for TLS connections. The callback is invoked for every
+/// connect and reconnect, so short-lived certificates can be rotated without recreating the client.
+#[derive(Clone)]
+pub struct TlsIdentityProvider {
+    callback: IdentityCallback,
+}
+
+impl TlsIdentityProvider {
+    pub fn new(callback: IdentityCallback) -> TlsIdentityProvider {
+        TlsIdentityProvider { callback }
+    }
+
+    /// Reads a PEM certificate (chain) and PKCS#8 PEM key from files, again on every (re)connect.
+    #[cfg(feature = "tls")]
+    pub fn from_pem_files<C: Into<PathBuf>, K: Into<PathBuf>>(cert: C, key: K) -> TlsIdentityProvider {
+        let cert = cert.into();
+        let key = key.into();
+        TlsIdentityProvider::new(Arc::new(move || {
+            let cert_pem = fs::read(&cert)?;
+            let key_pem = fs::read(&key)?;
+            Ok(Identity::from_pkcs8(&cert_pem, &key_pem)?)
+        }))
+    }
+
+    pub(crate) fn identity(&self) -> Result<Identity, RatsioError> {
+        (self.callback)()
+    }
+}
+
+impl fmt::Debug for TlsIdentityProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TlsIdentityProvider {{ callback: (func) }}")
+    }
+}
+
+impl PartialEq for TlsIdentityProvider {
+    fn eq(&self, other: &TlsIdentityProvider) -> bool {
+        Arc::ptr_eq(&self.callback, &other.callback)
+    }
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn pem_files_are_read_per_call() {
+    let provider = TlsIdentityProvider::from_pem_files("/nonexistent/cert.pem", "/nonexistent/key.pem");
+    match provider.identity() {
+        Err(RatsioError::IOError(_)) => {}
+        other => panic!("expected an IOError, got {:?}", other.map(|_| ())),
+    }
+}
@@ -1,5 +1,7 @@
 use crate::error::RatsioError;
+use crate::headers::Headers;
 use crate::nuid::NUID;
+use crate::redact::optional_secret;
 use bytes::{BufMut, Bytes, BytesMut};
 use std::collections::HashMap;
 use std::convert::From;
@@ -85,6 +87,12 @@ macro_rules! get_json_boolean {
 /// * tls_required: If this is set, then the client must perform the TLS/1.2 handshake. Note, this used to be ssl_required and has been updated along with the protocol from SSL to TLS.
 /// * tls_verify: If this is set, the client must provide a valid certificate during the TLS handshake.
 /// * connect_urls : An optional list of server urls that a client can connect to.
+/// * headers: If this is set, the server supports message headers (HPUB/HMSG, server version 2.2.0+).
+/// * ldm: If this is set, the server is in lame duck mode and about to shut down.
+/// * jetstream: If this is set, the server has JetStream enabled.
+/// * client_ip: The IP address of the client, as seen by the server.
+/// * cluster: The name of the cluster the server belongs to.
+/// * domain: The JetStream domain of the server.
 ///
 ///
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -104,6 +112,18 @@ pub struct ServerInfo {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub connect_urls: Vec<String>,
     pub nonce: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub headers: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub ldm: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub jetstream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
 }
 
 impl Default for ServerInfo {
@@ -122,6 +142,12 @@ impl Default for ServerInfo {
             tls_verify: false,
             connect_urls: Vec::new(),
             nonce: "".to_string(),
+            headers: false,
+            ldm: false,
+            jetstream: false,
+            client_ip: None,
+            cluster: None,
+            domain: None,
         }
     }
 }
@@ -154,6 +180,12 @@ impl From<JsonValue> for ServerInfo {
                     tls_verify: get_json_boolean!(obj, "tls_verify", false),
                     connect_urls,
                     nonce: get_json_string!(obj, "nonce"),
+                    headers: get_json_boolean!(obj, "headers", false),
+                    ldm: get_json_boolean!(obj, "ldm", false),
+                    jetstream: get_json_boolean!(obj, "jetstream", false),
+                    client_ip: get_json_opt_string!(obj, "client_ip"),
+                    cluster: get_json_opt_string!(obj, "cluster"),
+                    domain: get_json_opt_string!(obj, "domain"),
                 }
             }
             _ => ServerInfo::default(),
@@ -161,6 +193,49 @@ impl From<JsonValue> for ServerInfo {
     }
 }
 
+impl ServerInfo {
+    /// The protocol features the server announced in this INFO.
+    pub fn features(&self) -> ServerFeatures {
+        let mut features = ServerFeatures::NONE;
+        if self.proto >= 1 {
+            features = features | ServerFeatures::ECHO | ServerFeatures::ASYNC_INFO;
+        }
+        if self.headers {
+            features = features | ServerFeatures::HEADERS | ServerFeatures::NO_RESPONDERS;
+        }
+        features
+    }
+}
+
+/// Set of the protocol features a server supports, derived from its INFO by `ServerInfo::features`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServerFeatures(u8);
+
+impl ServerFeatures {
+    pub const NONE: ServerFeatures = ServerFeatures(0);
+    /// HPUB/HMSG, server 2.2.0+
+    pub const HEADERS: ServerFeatures = ServerFeatures(1);
+    /// Honors `echo: false` in CONNECT, protocol 1 (server 1.2.0+)
+    pub const ECHO: ServerFeatures = ServerFeatures(1 << 1);
+    /// Sends INFO updates of the cluster topology while connected, protocol 1
+    pub const ASYNC_INFO: ServerFeatures = ServerFeatures(1 << 2);
+    /// Answers requests without subscribers with a 503 status, needs headers
+    pub const NO_RESPONDERS: ServerFeatures = ServerFeatures(1 << 3);
+
+    /// Whether all features of `other` are in the set.
+    pub fn contains(self, other: ServerFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for ServerFeatures {
+    type Output = ServerFeatures;
+
+    fn bitor(self, other: ServerFeatures) -> ServerFeatures {
+        ServerFeatures(self.0 | other.0)
+    }
+}
+
 ///
 /// CONNECT {["option_name":option_value],...}
 ///
@@ -179,7 +254,12 @@ impl From<JsonValue> for ServerInfo {
 /// * sig: A signature produced from the nonce the server sent with its INFO message (if using JWT security)
 /// * protocol: optional int. Sending 0 (or absent) indicates client supports original protocol. Sending 1 indicates that the client supports dynamic reconfiguration of cluster topology changes by asynchronously receiving INFO messages with known servers it can reconnect to.
 /// * echo: Optional boolean. If set to true, the server (version 1.2.0+) will not send originating messages from this connection to its own subscriptions. Clients should set this to true only for server supporting this feature, which is when proto in the INFO protocol is set to at least 1.
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Builder)]
+/// * headers: Optional boolean. Indicates that the client supports message headers (HPUB/HMSG, server version 2.2.0+).
+/// * no_responders: Optional boolean. Requires headers, the server answers requests nobody is subscribed to
+///   with a 503 status message.
+///
+/// The credentials are redacted from the `Debug` output.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Builder)]
 #[serde()]
 #[builder(default)]
 pub struct Connect {
@@ -202,12 +282,56 @@ pub struct Connect {
     pub sig: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub jwt: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub headers: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub no_responders: bool,
 }
 
 impl Connect {
     pub fn builder() -> ConnectBuilder {
         ConnectBuilder::default()
     }
+
+    /// A one line description of the CONNECT, without credentials, for logs.
+    pub fn summary(&self) -> String {
+        let auth = if self.jwt.is_some() {
+            "jwt"
+        } else if self.auth_token.is_some() {
+            "token"
+        } else if self.user.is_some() {
+            "user and password"
+        } else {
+            "none"
+        };
+        format!(
+            "{} {} protocol {}, name: {:?}, auth: {}, verbose: {}, pedantic: {}, echo: {}, headers: {}",
+            self.lang, self.version, self.protocol, self.name, auth, self.verbose, self.pedantic, self.echo,
+            self.headers
+        )
+    }
+}
+
+impl std::fmt::Debug for Connect {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Connect")
+            .field("verbose", &self.verbose)
+            .field("pedantic", &self.pedantic)
+            .field("tls_required", &self.tls_required)
+            .field("auth_token", &optional_secret(&self.auth_token))
+            .field("user", &self.user)
+            .field("pass", &optional_secret(&self.pass))
+            .field("name", &self.name)
+            .field("lang", &self.lang)
+            .field("version", &self.version)
+            .field("protocol", &self.protocol)
+            .field("echo", &self.echo)
+            .field("sig", &optional_secret(&self.sig))
+            .field("jwt", &optional_secret(&self.jwt))
+            .field("headers", &self.headers)
+            .field("no_responders", &self.no_responders)
+            .finish()
+    }
 }
 impl Default for Connect {
     fn default() -> Self {
@@ -225,6 +349,8 @@ impl Default for Connect {
             echo: true,
             sig: None,
             jwt: None,
+            headers: false,
+            no_responders: false,
         }
     }
 }
@@ -246,6 +372,8 @@ impl From<JsonValue> for Connect {
                 echo: get_json_boolean!(obj, "tls_verify", true),
                 sig: get_json_opt_string!(obj, "sig"),
                 jwt: get_json_opt_string!(obj, "jwt"),
+                headers: get_json_boolean!(obj, "headers", false),
+                no_responders: get_json_boolean!(obj, "no_responders", false),
             },
             _ => Connect::default(),
         }
@@ -254,6 +382,7 @@ impl From<JsonValue> for Connect {
 
 /// MSG  protocol message is used to deliver an application message to the client.
 /// MSG <subject> <sid> [reply-to] <#bytes>\r\n[payload]\r\n
+/// HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>\r\n[headers][payload]\r\n
 ///
 /// where:
 ///
@@ -262,13 +391,59 @@ impl From<JsonValue> for Connect {
 /// * reply-to: The inbox subject on which the publisher is listening for responses
 /// * #bytes: Size of the payload in bytes
 /// * payload: The message payload data
+/// * headers: Message headers, only sent by servers supporting headers (2.2.0+)
 ///
-#[derive(Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct Message {
     pub subject: String,
     pub sid: String,
     pub reply_to: Option<String>,
     pub payload: Vec<u8>,
+    pub headers: Option<Headers>,
+}
+
+impl Message {
+    /// The deadline the requester attached in the `Nats-Deadline` header, handlers should
+    /// give up on work that cannot be answered before it.
+    pub fn deadline(&self) -> Option<::std::time::SystemTime> {
+        self.headers.as_ref().and_then(|headers| headers.deadline())
+    }
+
+    /// The error a service answered a request with, see `ServiceError`.
+    pub fn service_error(&self) -> Option<crate::error::ServiceError> {
+        self.headers.as_ref().and_then(|headers| headers.service_error())
+    }
+
+    /// The publish time a ratsio publisher stamped in the `Ratsio-Sent-At` header.
+    pub fn sent_at(&self) -> Option<::std::time::SystemTime> {
+        self.headers.as_ref().and_then(|headers| headers.sent_at())
+    }
+
+    /// Gives the payload buffer back to the `payload_pool`, once the message was handled.
+    pub fn recycle(self) {
+        crate::payload_pool::recycle(self.payload);
+    }
+
+    /// Takes the payload out of the message without copying it.
+    pub fn into_payload(self) -> Bytes {
+        Bytes::from(self.payload)
+    }
+
+    /// An `AsyncRead` over the payload, to stream large payloads into files or parsers.
+    pub fn payload_reader(self) -> crate::payload_reader::PayloadReader {
+        crate::payload_reader::PayloadReader::new(self.payload)
+    }
+
+    /// The payload as UTF-8 text.
+    pub fn as_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.payload)
+    }
+
+    /// Deserializes the JSON payload, `T` may borrow from the message.
+    pub fn json<'a, T: serde::Deserialize<'a>>(&'a self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.payload)
+    }
 }
 
 use ::std::fmt;
@@ -276,8 +451,8 @@ impl fmt::Debug for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Message {{ subject: {}, sid: {}, reply_to: {:?} }}",
-            self.subject, self.sid, self.reply_to
+            "Message {{ subject: {}, sid: {}, reply_to: {:?}, headers: {:?} }}",
+            self.subject, self.sid, self.reply_to, self.headers
         )
     }
 }
@@ -289,16 +464,63 @@ impl Default for Message {
             sid: String::from(""),
             reply_to: None,
             payload: Vec::new(),
+            headers: None,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Builder)]
+/// A received message shared by several local consumers without copying it, see
+/// `Subscription::split_broadcast_shared`. Reads go through `Deref`, `to_mut` copies the message
+/// only while other consumers still share it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedMessage(::std::sync::Arc<Message>);
+
+impl SharedMessage {
+    pub fn new(msg: Message) -> Self {
+        SharedMessage(::std::sync::Arc::new(msg))
+    }
+
+    /// Mutable access to the message, copied first if other consumers still share it.
+    pub fn to_mut(&mut self) -> &mut Message {
+        ::std::sync::Arc::make_mut(&mut self.0)
+    }
+
+    /// The message, copied only if other consumers still share it.
+    pub fn into_owned(self) -> Message {
+        ::std::sync::Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+
+    /// The payload, copied only if other consumers still share the message.
+    pub fn into_payload(self) -> Bytes {
+        self.into_owned().into_payload()
+    }
+}
+
+impl ::std::ops::Deref for SharedMessage {
+    type Target = Message;
+
+    fn deref(&self) -> &Message {
+        &self.0
+    }
+}
+
+impl From<Message> for SharedMessage {
+    fn from(msg: Message) -> Self {
+        SharedMessage::new(msg)
+    }
+}
+
+/// PUB <subject> [reply-to] <#bytes>\r\n[payload]\r\n
+/// or, when headers are set,
+/// HPUB <subject> [reply-to] <#header bytes> <#total bytes>\r\n[headers][payload]\r\n
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Builder)]
 #[builder(default)]
+#[serde(default)]
 pub struct Publish {
     pub subject: String,
     pub reply_to: Option<String>,
     pub payload: Vec<u8>,
+    pub headers: Option<Headers>,
 }
 
 impl Publish {
@@ -309,6 +531,12 @@ impl Publish {
     pub fn builder() -> PublishBuilder {
         PublishBuilder::default()
     }
+
+    /// The bytes the server checks against its `max_payload`, the header block included.
+    pub fn total_size(&self) -> usize {
+        let headers = self.headers.as_ref().filter(|headers| !headers.is_empty());
+        headers.map_or(0, |headers| headers.to_bytes().len()) + self.payload.len()
+    }
 }
 
 impl Default for Publish {
@@ -317,16 +545,55 @@ impl Default for Publish {
             subject: String::from(""),
             reply_to: None,
             payload: Vec::new(),
+            headers: None,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Builder)]
 #[builder(default)]
+#[serde(default)]
 pub struct Subscribe {
     pub subject: String,
     pub sid: String,
     pub queue_group: Option<String>,
+    /// Client side only, messages with a `Message::sent_at` older than this are dropped
+    /// before they are delivered. Messages without a sent-at header are always delivered.
+    pub max_age: Option<::std::time::Duration>,
+    /// Client side only, whether to subscribe again after a reconnect. `None` follows
+    /// `NatsClientOptions::subscribe_on_reconnect`.
+    pub resubscribe: Option<bool>,
+    /// Client side only, number of the latest messages kept for consumers attaching later
+    /// with `Subscription::fork`, 0 keeps none.
+    pub replay_buffer: usize,
+    /// Client side only, how many messages the subscription stream may fall behind and what
+    /// happens to messages beyond that. `None` buffers without bounds.
+    pub pending_limit: Option<PendingLimit>,
+    /// Client side only, unsubscribes once this long passed since subscribing, even if the
+    /// stream is still held. See also `Subscription::unsubscribe_after`.
+    pub ttl: Option<::std::time::Duration>,
+}
+
+/// What to do with messages for a subscription stream that is `PendingLimit::max_msgs` behind.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SlowConsumerPolicy {
+    /// Drop the message that just arrived
+    DropNewest,
+    /// Drop the oldest message not yet taken from the stream
+    DropOldest,
+    /// Stop reading from the connection until the stream caught up, this holds up all
+    /// subscriptions of the client, as well as the PONGs and INFOs of the server, for as long as
+    /// the stream is not read. The client reconnects once the pings time out and the server may
+    /// disconnect it as a slow consumer. Pausing the subscription lifts the block, see
+    /// `Subscription::pause`.
+    Block,
+}
+
+/// Bounds the messages buffered for a subscription stream, see `Subscribe::pending_limit`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PendingLimit {
+    pub max_msgs: usize,
+    pub policy: SlowConsumerPolicy,
 }
 
 impl Default for Subscribe {
@@ -335,6 +602,11 @@ impl Default for Subscribe {
             subject: String::from(""),
             sid: Subscribe::generate_sid(),
             queue_group: None,
+            max_age: None,
+            resubscribe: None,
+            replay_buffer: 0,
+            pending_limit: None,
+            ttl: None,
         }
     }
 }
@@ -348,8 +620,9 @@ impl Subscribe {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Builder)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Builder)]
 #[builder(default)]
+#[serde(default)]
 pub struct UnSubscribe {
     pub sid: String,
     pub max_msgs: Option<u32>,
@@ -382,7 +655,9 @@ PONG	Both	PONG keep-alive response
 +OK	Server	Acknowledges well-formed protocol message in verbose mode
 -ERR
 */
-#[derive(Debug, Clone, PartialEq)]
+/// Ops serialize with serde as `{"MSG": {...}}`, or `"PING"` for those without a body, so
+/// protocol traces can be kept as JSON fixtures.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Op {
     INFO(ServerInfo),
     CONNECT(Connect),
@@ -406,8 +681,41 @@ fn extend_bytes<'a>(dst: &'a mut BytesMut, s: &[u8]) {
     dst.put(s);
 }
 
+/// Writes `\t<#bytes>\r\n<payload>\r\n`, or `\t<#header bytes>\t<#total bytes>\r\n<headers><payload>\r\n`
+#[inline]
+fn extend_sizes_and_payload(dst: &mut BytesMut, header_bytes: Option<Vec<u8>>, payload: &[u8]) {
+    match header_bytes {
+        Some(header_bytes) => {
+            let total = header_bytes.len() + payload.len();
+            extend_bytes(dst, format!("\t{}\t{}\r\n", header_bytes.len(), total).as_bytes());
+            extend_bytes(dst, &header_bytes[..]);
+        }
+        None => {
+            extend_bytes(dst, format!("\t{}\r\n", payload.len()).as_bytes());
+        }
+    }
+    extend_bytes(dst, payload);
+    extend_bytes(dst, &b"\r\n"[..]);
+}
+
 impl Op {
+    /// Checks the subjects, reply subjects and queue groups of the op, see `subject::validate`.
+    pub fn validate(&self) -> Result<(), RatsioError> {
+        let (subject, extra) = match self {
+            Op::MSG(msg) => (&msg.subject, msg.reply_to.as_ref()),
+            Op::PUB(publish) => (&publish.subject, publish.reply_to.as_ref()),
+            Op::SUB(sub) => (&sub.subject, sub.queue_group.as_ref()),
+            _ => return Ok(()),
+        };
+        crate::subject::validate(subject)?;
+        match extra {
+            Some(extra) => crate::subject::validate(extra),
+            None => Ok(()),
+        }
+    }
+
     pub fn into_bytes(self) -> Result<Bytes, RatsioError> {
+        self.validate()?;
         match self {
             Op::INFO(info) => {
                 let prefix = &b"INFO\t"[..];
@@ -450,12 +758,13 @@ impl Op {
                 let re = Regex::new(r"[']").unwrap();
                 let cmd = format!("-ERR '{}'\r\n", re.replace_all(msg.as_str(), "\\'"));
                 let mut dst = BytesMut::with_capacity(cmd.len());
-                dst.put(&cmd);
+                dst.put(cmd.as_bytes());
                 Ok(dst.freeze())
             }
             Op::MSG(msg) => {
                 let mut dst = BytesMut::new();
-                extend_bytes(&mut dst, &b"MSG\t"[..]);
+                let header_bytes = msg.headers.filter(|h| !h.is_empty()).map(|h| h.to_bytes());
+                extend_bytes(&mut dst, if header_bytes.is_some() { &b"HMSG\t"[..] } else { &b"MSG\t"[..] });
                 extend_bytes(&mut dst, msg.subject.as_bytes());
                 extend_bytes(&mut dst, &b"\t"[..]);
                 extend_bytes(&mut dst, msg.sid.as_bytes());
@@ -463,25 +772,19 @@ impl Op {
                     extend_bytes(&mut dst, &b"\t"[..]);
                     extend_bytes(&mut dst, reply_to.as_bytes());
                 }
-                extend_bytes(&mut dst, format!("\t{}\r\n", msg.payload.len()).as_bytes());
-                extend_bytes(&mut dst, &msg.payload[..]);
-                extend_bytes(&mut dst, &b"\r\n"[..]);
+                extend_sizes_and_payload(&mut dst, header_bytes, &msg.payload[..]);
                 Ok(dst.freeze())
             }
             Op::PUB(publish) => {
                 let mut dst = BytesMut::new();
-                extend_bytes(&mut dst, &b"PUB\t"[..]);
+                let header_bytes = publish.headers.filter(|h| !h.is_empty()).map(|h| h.to_bytes());
+                extend_bytes(&mut dst, if header_bytes.is_some() { &b"HPUB\t"[..] } else { &b"PUB\t"[..] });
                 extend_bytes(&mut dst, publish.subject.as_bytes());
                 if let Some(reply_to) = publish.reply_to {
                     extend_bytes(&mut dst, &b"\t"[..]);
                     extend_bytes(&mut dst, reply_to.as_bytes());
                 }
-                extend_bytes(
-                    &mut dst,
-                    format!("\t{}\r\n", publish.payload.len()).as_bytes(),
-                );
-                extend_bytes(&mut dst, &publish.payload[..]);
-                extend_bytes(&mut dst, &b"\r\n"[..]);
+                extend_sizes_and_payload(&mut dst, header_bytes, &publish.payload[..]);
                 Ok(dst.freeze())
             }
             Op::SUB(sub) => {
@@ -517,6 +820,21 @@ impl Op {
     }
 }
 
+#[test]
+fn connect_debug_redacts_credentials() {
+    let connect = Connect {
+        user: Some("alice".into()),
+        pass: Some("s3cret".into()),
+        jwt: Some("eyJ0eXAi".into()),
+        name: Some("billing".into()),
+        ..Connect::default()
+    };
+    let debug = format!("{:?}", Op::CONNECT(connect.clone()));
+    assert!(debug.contains("alice") && !debug.contains("s3cret") && !debug.contains("eyJ0eXAi"));
+    assert!(connect.summary().contains("auth: jwt"));
+    assert!(!connect.summary().contains("s3cret"));
+}
+
 #[test]
 fn ser_ok() {
     match Op::OK.into_bytes() {
@@ -569,6 +887,8 @@ fn ser_connect() {
         echo: true,
         sig: None,
         jwt: None,
+        headers: false,
+        no_responders: false,
     })
     .into_bytes()
     {
@@ -590,6 +910,7 @@ fn ser_message() {
         sid: String::from("9"),
         reply_to: Some(String::from("INBOX.34")),
         payload: Vec::from(b"Hello World" as &[u8]),
+        headers: None,
     })
     .into_bytes()
     {
@@ -611,6 +932,7 @@ fn ser_message_no_reply() {
         sid: String::from("9"),
         reply_to: None,
         payload: Vec::from(b"Hello New World" as &[u8]),
+        headers: None,
     })
     .into_bytes()
     {
@@ -631,6 +953,7 @@ fn ser_publish() {
         subject: String::from("FRONT.DOOR"),
         reply_to: Some(String::from("INBOX.22")),
         payload: Vec::from(b"Knock Knock" as &[u8]),
+        headers: None,
     })
     .into_bytes()
     {
@@ -653,6 +976,7 @@ fn ser_publish_no_reply() {
         subject: String::from("FRONT.DOOR"),
         reply_to: None,
         payload: Vec::from(b"Knock Knock Again" as &[u8]),
+        headers: None,
     })
     .into_bytes()
     {
@@ -666,12 +990,38 @@ fn ser_publish_no_reply() {
     }
 }
 
+#[test]
+fn ser_publish_with_headers() {
+    let mut headers = Headers::new();
+    headers.insert("A", "1");
+    let publish = Publish {
+        subject: String::from("FRONT.DOOR"),
+        reply_to: Some(String::from("INBOX.22")),
+        payload: Vec::from(b"Knock Knock" as &[u8]),
+        headers: Some(headers),
+    };
+    assert_eq!(publish.total_size(), 29, "the header bytes count against max_payload");
+    assert_eq!(Publish { headers: Some(Headers::new()), ..publish.clone() }.total_size(), 11);
+    match Op::PUB(publish).into_bytes() {
+        Ok(b) => {
+            assert_eq!(
+                &b[..],
+                &b"HPUB\tFRONT.DOOR\tINBOX.22\t18\t29\r\nNATS/1.0\r\nA: 1\r\n\r\nKnock Knock\r\n"[..]
+            );
+        }
+        Err(_) => {
+            assert!(false);
+        }
+    }
+}
+
 #[test]
 fn ser_sub() {
     match Op::SUB(Subscribe {
         subject: String::from("BAR"),
         sid: String::from("44"),
         queue_group: Some(String::from("G1")),
+        ..Subscribe::default()
     })
     .into_bytes()
     {
@@ -691,6 +1041,7 @@ fn ser_sub_no_group() {
         subject: String::from("BAR"),
         sid: String::from("44"),
         queue_group: None,
+        ..Subscribe::default()
     })
     .into_bytes()
     {
@@ -739,3 +1090,67 @@ fn ser_unsub_no_max() {
         }
     }
 }
+
+#[test]
+fn message_payload_views() {
+    let msg = Message {
+        payload: Vec::from(&br#"{"name":"bar"}"#[..]),
+        ..Default::default()
+    };
+    assert_eq!(msg.as_str().unwrap(), r#"{"name":"bar"}"#);
+    let value: HashMap<&str, &str> = msg.json().unwrap();
+    assert_eq!(value["name"], "bar");
+    assert!(msg.json::<Vec<u8>>().is_err());
+    assert_eq!(msg.into_payload(), Bytes::from(&br#"{"name":"bar"}"#[..]));
+
+    let invalid = Message {
+        payload: vec![0xff],
+        ..Default::default()
+    };
+    assert!(invalid.as_str().is_err());
+}
+
+#[test]
+fn ops_roundtrip_through_json() {
+    let mut headers = Headers::new();
+    headers.insert("Trace-Id", "42");
+    let ops = vec![
+        Op::PING,
+        Op::ERR(String::from("'Unknown Protocol Operation'")),
+        Op::MSG(Message {
+            subject: String::from("orders.new"),
+            sid: String::from("1"),
+            reply_to: Some(String::from("INBOX.1")),
+            payload: b"hello".to_vec(),
+            headers: Some(headers),
+        }),
+        Op::SUB(Subscribe::builder()
+            .subject("orders.*".into())
+            .sid("1".into())
+            .max_age(Some(::std::time::Duration::from_secs(5)))
+            .build()
+            .unwrap()),
+        Op::UNSUB(UnSubscribe { sid: String::from("1"), max_msgs: Some(10) }),
+    ];
+    let json = serde_json::to_string(&ops).unwrap();
+    let replayed: Vec<Op> = serde_json::from_str(&json).unwrap();
+    assert_eq!(replayed, ops);
+
+    let publish: Publish = serde_json::from_str(r#"{"subject":"orders.new","payload":[104,105]}"#).unwrap();
+    assert_eq!(publish.payload, b"hi");
+    assert_eq!(publish.reply_to, None);
+}
+
+#[test]
+fn shared_messages_copy_on_write() {
+    let shared = SharedMessage::new(Message { payload: b"hello".to_vec(), ..Message::default() });
+    let mut other = shared.clone();
+    assert_eq!(shared.payload.as_ptr(), other.payload.as_ptr());
+    other.to_mut().payload.extend_from_slice(b" world");
+    assert_eq!(shared.payload, b"hello");
+    assert_eq!(other.payload, b"hello world");
+
+    let payload_ptr = shared.payload.as_ptr();
+    let owned = shared.into_owned();
+    assert_eq!(owned.payload.as_ptr(), payload_ptr);
+}
@@ -0,0 +1,174 @@
+//! Process wide pool of buffers the payloads of received messages are copied into, so small
+//! messages don't each need a fresh allocation. Buffers come back to the pool through
+//! `Message::recycle` or `recycle`, the pool is disabled until `configure` is called.
+//!
+//! ```
+//! ratsio::payload_pool::configure(ratsio::payload_pool::PayloadPoolOptions {
+//!     max_buffers: 4096,
+//!     buffer_size: 512,
+//! });
+//! ```
+use atomic_counter::{AtomicCounter, ConsistentCounter};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Sizing of the payload pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PayloadPoolOptions {
+    /// Most buffers kept in the pool, further recycled buffers are freed. 0 disables the pool.
+    pub max_buffers: usize,
+    /// Capacity of the pooled buffers, larger payloads are allocated as usual.
+    pub buffer_size: usize,
+}
+
+/// How well the pool served the received payloads, since it was configured.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PayloadPoolStats {
+    /// Payloads copied into a pooled buffer
+    pub hits: usize,
+    /// Payloads small enough for the pool that found it empty
+    pub misses: usize,
+    /// Buffers taken back into the pool
+    pub recycled: usize,
+    /// Buffers given back while the pool was full, or too small for it
+    pub discarded: usize,
+}
+
+impl PayloadPoolStats {
+    /// Share of the poolable payloads that got a pooled buffer, between 0 and 1.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+struct PayloadPool {
+    max_buffers: AtomicUsize,
+    buffer_size: AtomicUsize,
+    buffers: Mutex<Vec<Vec<u8>>>,
+    hits: ConsistentCounter,
+    misses: ConsistentCounter,
+    recycled: ConsistentCounter,
+    discarded: ConsistentCounter,
+}
+
+impl PayloadPool {
+    fn new() -> Self {
+        PayloadPool {
+            max_buffers: AtomicUsize::new(0),
+            buffer_size: AtomicUsize::new(0),
+            buffers: Mutex::new(Vec::new()),
+            hits: ConsistentCounter::new(0),
+            misses: ConsistentCounter::new(0),
+            recycled: ConsistentCounter::new(0),
+            discarded: ConsistentCounter::new(0),
+        }
+    }
+
+    fn configure(&self, options: PayloadPoolOptions) {
+        let mut buffers = self.buffers.lock();
+        buffers.clear();
+        buffers.shrink_to_fit();
+        self.max_buffers.store(options.max_buffers, Ordering::SeqCst);
+        self.buffer_size.store(options.buffer_size, Ordering::SeqCst);
+        self.hits.reset();
+        self.misses.reset();
+        self.recycled.reset();
+        self.discarded.reset();
+    }
+
+    fn stats(&self) -> PayloadPoolStats {
+        PayloadPoolStats {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+            recycled: self.recycled.get(),
+            discarded: self.discarded.get(),
+        }
+    }
+
+    fn recycle(&self, mut payload: Vec<u8>) {
+        let max_buffers = self.max_buffers.load(Ordering::Relaxed);
+        if max_buffers == 0 {
+            return;
+        }
+        if payload.capacity() < self.buffer_size.load(Ordering::Relaxed) {
+            self.discarded.inc();
+            return;
+        }
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < max_buffers {
+            payload.clear();
+            buffers.push(payload);
+            self.recycled.inc();
+        } else {
+            self.discarded.inc();
+        }
+    }
+
+    fn payload_from(&self, bytes: &[u8]) -> Vec<u8> {
+        let buffer_size = self.buffer_size.load(Ordering::Relaxed);
+        if self.max_buffers.load(Ordering::Relaxed) == 0 || bytes.len() > buffer_size {
+            return Vec::from(bytes);
+        }
+        let pooled = self.buffers.lock().pop();
+        let mut payload = match pooled {
+            Some(buffer) => {
+                self.hits.inc();
+                buffer
+            }
+            None => {
+                self.misses.inc();
+                Vec::with_capacity(buffer_size)
+            }
+        };
+        payload.extend_from_slice(bytes);
+        payload
+    }
+}
+
+lazy_static! {
+    static ref POOL: PayloadPool = PayloadPool::new();
+}
+
+/// Sets the sizing of the pool, emptying it and resetting its statistics.
+pub fn configure(options: PayloadPoolOptions) {
+    POOL.configure(options);
+}
+
+pub fn stats() -> PayloadPoolStats {
+    POOL.stats()
+}
+
+/// Gives a payload buffer back to the pool, once the message it came with was handled.
+pub fn recycle(payload: Vec<u8>) {
+    POOL.recycle(payload);
+}
+
+/// A copy of a received payload, in a pooled buffer if it is small enough.
+pub(crate) fn payload_from(bytes: &[u8]) -> Vec<u8> {
+    POOL.payload_from(bytes)
+}
+
+#[test]
+fn pooled_payloads() {
+    let pool = PayloadPool::new();
+    pool.configure(PayloadPoolOptions {
+        max_buffers: 1,
+        buffer_size: 16,
+    });
+    let first = pool.payload_from(b"hello");
+    assert_eq!(first, b"hello");
+    assert!(first.capacity() >= 16);
+    assert_eq!(pool.payload_from(&[0; 32]).capacity(), 32);
+    let second = pool.payload_from(b"world");
+    pool.recycle(first);
+    // Holds one buffer only.
+    pool.recycle(second);
+    assert_eq!(pool.payload_from(b"again"), b"again");
+
+    let stats = pool.stats();
+    assert_eq!((stats.hits, stats.misses, stats.recycled, stats.discarded), (1, 2, 1, 1));
+    assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+}
@@ -0,0 +1,120 @@
+//! `AsyncRead`s over received payloads, so large messages can be streamed into files or parsers
+//! without copying the payload again. Payloads read to the end go back to the `payload_pool`.
+//!
+//! ```
+//! use futures::executor::block_on;
+//! use ratsio::ops::Message;
+//! use tokio::io::AsyncReadExt;
+//!
+//! let msg = Message { payload: b"large".to_vec(), ..Message::default() };
+//! let mut text = String::new();
+//! block_on(msg.payload_reader().read_to_string(&mut text)).unwrap();
+//! assert_eq!(text, "large");
+//! ```
+use crate::ops::Message;
+use futures::{ready, Stream};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::AsyncRead;
+
+/// Reads the payload of one message, see `Message::payload_reader`.
+#[derive(Debug)]
+pub struct PayloadReader {
+    payload: Vec<u8>,
+    position: usize,
+}
+
+impl PayloadReader {
+    pub fn new(payload: Vec<u8>) -> Self {
+        PayloadReader { payload, position: 0 }
+    }
+
+    /// Number of payload bytes not read yet.
+    pub fn remaining(&self) -> usize {
+        self.payload.len() - self.position
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.remaining());
+        buf[..len].copy_from_slice(&self.payload[self.position..self.position + len]);
+        self.position += len;
+        if self.remaining() == 0 && len > 0 {
+            crate::payload_pool::recycle(std::mem::take(&mut self.payload));
+            self.position = 0;
+        }
+        len
+    }
+}
+
+impl AsyncRead for PayloadReader {
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(self.get_mut().read(buf)))
+    }
+}
+
+/// Reads the payloads of a stream of messages one after the other, as a single payload that
+/// ends with the stream. Useful for payloads published in several messages, such as a
+/// subscription to the chunks of a file.
+pub struct PayloadStreamReader<S> {
+    messages: S,
+    current: PayloadReader,
+    done: bool,
+}
+
+impl<S: Stream<Item = Message> + Unpin> PayloadStreamReader<S> {
+    pub fn new(messages: S) -> Self {
+        PayloadStreamReader {
+            messages,
+            current: PayloadReader::new(Vec::new()),
+            done: false,
+        }
+    }
+
+    /// The messages not read yet.
+    pub fn into_inner(self) -> S {
+        self.messages
+    }
+}
+
+impl<S: Stream<Item = Message> + Unpin> AsyncRead for PayloadStreamReader<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // Empty payloads are skipped, 0 bytes read means the end of the stream.
+        while this.current.remaining() == 0 && !this.done {
+            match ready!(Pin::new(&mut this.messages).poll_next(cx)) {
+                Some(msg) => this.current = msg.payload_reader(),
+                None => this.done = true,
+            }
+        }
+        Poll::Ready(Ok(this.current.read(buf)))
+    }
+}
+
+#[test]
+fn reads_payloads_in_order() {
+    use futures::{executor::block_on, stream};
+    use tokio::io::AsyncReadExt;
+
+    let mut reader = Message { payload: b"Hello World".to_vec(), ..Message::default() }.payload_reader();
+    let mut buf = [0; 5];
+    assert_eq!(block_on(reader.read(&mut buf)).unwrap(), 5);
+    assert_eq!(&buf, b"Hello");
+    assert_eq!(reader.remaining(), 6);
+    let mut rest = Vec::new();
+    block_on(reader.read_to_end(&mut rest)).unwrap();
+    assert_eq!(rest, b" World");
+
+    let chunks = vec![&b"chunk 1,"[..], b"", b" chunk 2"]
+        .into_iter()
+        .map(|chunk| Message { payload: chunk.to_vec(), ..Message::default() });
+    let mut reader = PayloadStreamReader::new(stream::iter(chunks));
+    let mut payload = String::new();
+    block_on(reader.read_to_string(&mut payload)).unwrap();
+    assert_eq!(payload, "chunk 1, chunk 2");
+}
@@ -16,24 +16,33 @@ pub use super::{
         NatsClient,
         NatsClientState,
         UriVec,
+        NatsUrl,
+        ServerList,
+        Subscription,
     },
-    error::RatsioError,
+    error::{RatsioError, ServiceError, SubscriptionError},
+    headers::Headers,
     ops::{
         Connect,
         Message,
         Op,
+        PendingLimit,
         Publish,
+        SharedMessage,
+        SlowConsumerPolicy,
         Subscribe,
         UnSubscribe,
     },
-    stan_client::{
-        StanClient,
-        StanOptions,
-        StanMessage,
-        StanSubscribe,
-        StartPosition,
-        SubscriptionHandler,
-        AsyncHandler,
-        SyncHandler,
-    },
+};
+
+#[cfg(feature = "streaming")]
+pub use super::stan_client::{
+    StanClient,
+    StanOptions,
+    StanMessage,
+    StanSubscribe,
+    StartPosition,
+    SubscriptionHandler,
+    AsyncHandler,
+    SyncHandler,
 };
@@ -1,5 +1,8 @@
+/// NATS Streaming protocol buffers
+#[cfg(feature = "streaming")]
 #[allow(clippy::module_inception)]
 pub mod protocol;
+#[cfg(feature = "streaming")]
 pub use self::protocol::*;
 
 
@@ -5,7 +5,9 @@ use nom::{AsChar, ErrorKind, InputTakeAtPosition, IResult,
 use std::collections::HashMap;
 use std::convert::From;
 
+use crate::headers::Headers;
 use crate::ops::*;
+use crate::payload_pool;
 
 #[allow(dead_code, clippy::needless_pass_by_value)]
 fn whitespace<'a, T>(input: T) -> IResult<T, T>
@@ -192,90 +194,174 @@ named!(
 );
 
 
-named!(text_token<String>, map!(take_till!(|x| {is_space(x) || is_newline(x)}),
-    |s| String::from_utf8(Vec::from(s)).unwrap().to_owned()));
+named!(text_token<String>, map_res!(take_till!(|x| {is_space(x) || is_newline(x)}),
+    |s| String::from_utf8(Vec::from(s))));
+
+/// The non-empty argument tokens of a protocol line.
+fn args(tokens: Vec<String>) -> Vec<String> {
+    tokens.into_iter().filter(|token| !token.is_empty()).collect()
+}
+
+/// `<subject> <sid> [reply-to] <#bytes>`, `None` if malformed.
+fn message_args(tokens: Vec<String>) -> Option<(usize, Message)> {
+    let mut tokens = args(tokens);
+    let size = tokens.pop()?.parse().ok()?;
+    let reply_to = match tokens.len() {
+        2 => None,
+        3 => tokens.pop(),
+        _ => return None,
+    };
+    let sid = tokens.pop()?;
+    let subject = tokens.pop()?;
+    Some((size, Message { subject, sid, reply_to, payload: Vec::new(), headers: None }))
+}
+
+/// `<subject> <sid> [reply-to] <#header bytes> <#total bytes>`, `None` if malformed.
+fn header_message_args(tokens: Vec<String>) -> Option<(usize, usize, Message)> {
+    let mut tokens = args(tokens);
+    let total_size: usize = tokens.pop()?.parse().ok()?;
+    let header_size: usize = tokens.pop()?.parse().ok()?;
+    if header_size > total_size {
+        return None;
+    }
+    let reply_to = match tokens.len() {
+        2 => None,
+        3 => tokens.pop(),
+        _ => return None,
+    };
+    let sid = tokens.pop()?;
+    let subject = tokens.pop()?;
+    Some((header_size, total_size, Message { subject, sid, reply_to, payload: Vec::new(), headers: None }))
+}
+
+/// `<subject> [reply-to] <#bytes>`, `None` if malformed.
+fn publish_args(tokens: Vec<String>) -> Option<(usize, Publish)> {
+    let mut tokens = args(tokens);
+    let size = tokens.pop()?.parse().ok()?;
+    let reply_to = match tokens.len() {
+        1 => None,
+        2 => tokens.pop(),
+        _ => return None,
+    };
+    let subject = tokens.pop()?;
+    Some((size, Publish { subject, reply_to, payload: Vec::new(), headers: None }))
+}
+
+/// `<subject> [reply-to] <#header bytes> <#total bytes>`, `None` if malformed.
+fn header_publish_args(tokens: Vec<String>) -> Option<(usize, usize, Publish)> {
+    let mut tokens = args(tokens);
+    let total_size: usize = tokens.pop()?.parse().ok()?;
+    let header_size: usize = tokens.pop()?.parse().ok()?;
+    if header_size > total_size {
+        return None;
+    }
+    let reply_to = match tokens.len() {
+        1 => None,
+        2 => tokens.pop(),
+        _ => return None,
+    };
+    let subject = tokens.pop()?;
+    Some((header_size, total_size, Publish { subject, reply_to, payload: Vec::new(), headers: None }))
+}
+
+/// `<subject> [queue group] <sid>`, `None` if malformed.
+fn subscribe_args(tokens: Vec<String>) -> Option<Subscribe> {
+    let mut tokens = args(tokens);
+    let sid = tokens.pop()?;
+    let queue_group = match tokens.len() {
+        1 => None,
+        2 => tokens.pop(),
+        _ => return None,
+    };
+    let subject = tokens.pop()?;
+    Some(Subscribe { subject, sid, queue_group, ..Subscribe::default() })
+}
+
+/// `<sid> [max_msgs]`, `None` if malformed. An unparsable `max_msgs` is ignored.
+fn un_subscribe_args(tokens: Vec<String>) -> Option<UnSubscribe> {
+    let tokens = args(tokens);
+    if tokens.len() > 2 {
+        return None;
+    }
+    let sid = tokens.get(0)?.to_owned();
+    let max_msgs = tokens.get(1).and_then(|max_msgs| max_msgs.parse().ok());
+    Some(UnSubscribe { sid, max_msgs })
+}
 
 
 //MSG <subject> <sid> [reply-to] <#bytes>\r\n[payload]\r\n
 named!(message<Message>, do_parse!(
-    item: map!(delimited!(leading_ws!(take_while!(is_space)), separated_list!(take_while!(is_space), text_token),
-        leading_space!(tag!("\r\n"))), |tokens| {
-            let token_len = tokens.len();
-            let size: usize =  tokens[token_len-1].parse().unwrap();
-            let subject = if token_len > 1 { tokens[0].to_owned() } else { String::from("") };
-            let sid =  if token_len > 2 { tokens[1].to_owned() } else {String::from("") };
-            let reply_to =  if token_len > 3 { Some(tokens[2].to_owned()) } else { None };
-            (size, Message{
-                subject, sid, reply_to, payload: Vec::new(),
-            })
-      })                  >>
+    item: map_opt!(delimited!(leading_ws!(take_while!(is_space)), separated_list!(take_while!(is_space), text_token),
+        leading_space!(tag!("\r\n"))), message_args) >>
     payload: take!(item.0) >>
     tag!("\r\n")          >>
     ( Message{
        subject: item.1.subject,
        sid: item.1.sid,
        reply_to: item.1.reply_to,
-       payload: Vec::from(payload),
+       payload: payload_pool::payload_from(payload),
+       headers: None,
+    })
+));
+
+
+//HMSG <subject> <sid> [reply-to] <#header bytes> <#total bytes>\r\n[headers][payload]\r\n
+named!(header_message<Message>, do_parse!(
+    item: map_opt!(delimited!(leading_ws!(take_while!(is_space)), separated_list!(take_while!(is_space), text_token),
+        leading_space!(tag!("\r\n"))), header_message_args) >>
+    payload: take!(item.1) >>
+    tag!("\r\n")          >>
+    ( Message{
+       subject: item.2.subject,
+       sid: item.2.sid,
+       reply_to: item.2.reply_to,
+       payload: payload_pool::payload_from(&payload[item.0..]),
+       headers: Some(Headers::from_bytes(&payload[..item.0])),
     })
 ));
 
 
 //PUB <subject> [reply-to] <#bytes>\r\n[payload]\r\n
 named!(publish<Publish>, do_parse!(
-    item: map!(delimited!(leading_ws!(take_while!(is_space)), separated_list!(take_while!(is_space), text_token),
-      leading_space!(tag!("\r\n"))), |tokens| {
-        let token_len = tokens.len();
-        let size: usize =  tokens[token_len-1].parse().unwrap();
-        let subject = if token_len > 1 { tokens[0].to_owned() } else { String::from("") };
-        let reply_to =  if token_len > 2 { Some(tokens[1].to_owned()) } else { None };
-       (size, Publish{
-            subject, reply_to, payload: Vec::new(),
-        })
-      })                      >>
+    item: map_opt!(delimited!(leading_ws!(take_while!(is_space)), separated_list!(take_while!(is_space), text_token),
+      leading_space!(tag!("\r\n"))), publish_args) >>
     payload: take!(item.0) >>
     tag!("\r\n")              >>
     (Publish{
         subject: item.1.subject,
         reply_to: item.1.reply_to,
         payload: Vec::from(payload),
+        headers: None,
+    })
+));
+
+
+//HPUB <subject> [reply-to] <#header bytes> <#total bytes>\r\n[headers][payload]\r\n
+named!(header_publish<Publish>, do_parse!(
+    item: map_opt!(delimited!(leading_ws!(take_while!(is_space)), separated_list!(take_while!(is_space), text_token),
+      leading_space!(tag!("\r\n"))), header_publish_args) >>
+    payload: take!(item.1) >>
+    tag!("\r\n")              >>
+    (Publish{
+        subject: item.2.subject,
+        reply_to: item.2.reply_to,
+        payload: Vec::from(&payload[item.0..]),
+        headers: Some(Headers::from_bytes(&payload[..item.0])),
     })
 ));
 
 
 //SUB <subject> [queue group] <sid>\r\n
 named!(subscribe<Subscribe>,
-   map!(delimited!(take_while!(is_space), separated_list!(take_while!(is_space), text_token),
-      leading_space!(tag!("\r\n"))), |tokens| {
-        let token_len = tokens.len();
-        let sid = tokens[token_len-1].to_owned();
-        let subject = if token_len > 1 { tokens[0].to_owned() } else { String::from("") };
-        let queue_group =  if token_len > 2 { Some(tokens[1].to_owned()) } else { None };
-        Subscribe{
-            subject, sid, queue_group,
-        }
-   })
+   map_opt!(delimited!(take_while!(is_space), separated_list!(take_while!(is_space), text_token),
+      leading_space!(tag!("\r\n"))), subscribe_args)
 );
 
 
 //UNSUB <sid> [max_msgs]
 named!(un_subscribe<UnSubscribe>,
-   map!(delimited!(take_while!(is_space), separated_list!(take_while!(is_space), text_token),
-      leading_space!(tag!("\r\n"))), |tokens| {
-        let token_len = tokens.len();
-        let sid = tokens[0].to_owned();
-        let max_msgs: Option<u32> = if token_len > 1 {
-          match tokens[1].parse() {
-            Ok(size) => Some(size),
-            _ => None,
-          }
-        }else{
-            None
-        };
-
-        UnSubscribe{
-            sid, max_msgs,
-        }
-   })
+   map_opt!(delimited!(take_while!(is_space), separated_list!(take_while!(is_space), text_token),
+      leading_space!(tag!("\r\n"))), un_subscribe_args)
 );
 
 
@@ -283,6 +369,7 @@ named!(
     pub operation<Op>,
     alt!(
           pair!(leading_ws!(tag_no_case!("MSG")), message) => { |(_, msg)| Op::MSG(msg) }
+        | pair!(leading_ws!(tag_no_case!("HMSG")), header_message) => { |(_, msg)| Op::MSG(msg) }
         | tuple!(leading_ws!(tag_no_case!("INFO")), object, leading_space!(tag!("\r\n"))) => { |(_, json_obj, _)|
             Op::INFO(ServerInfo::from(JsonValue::Object(json_obj)))}
         | tuple!(leading_ws!(tag_no_case!("CONNECT")), object, leading_space!(tag!("\r\n"))) => { |(_, json_obj, _)|
@@ -293,6 +380,7 @@ named!(
         | tuple!(leading_ws!(tag_no_case!("-ERR")), error_msg, leading_space!(tag!("\r\n"))) => { |(_, msg, _): (_, String, _)|
            Op::ERR(msg) }
         | pair!(leading_ws!(tag_no_case!("PUB")), publish) => { |(_, publish)| Op::PUB(publish) }
+        | pair!(leading_ws!(tag_no_case!("HPUB")), header_publish) => { |(_, publish)| Op::PUB(publish) }
         | pair!(leading_ws!(tag_no_case!("SUB")), subscribe) => { |(_, sub)| Op::SUB(sub) }
         | pair!(leading_ws!(tag_no_case!("UNSUB")), un_subscribe) => { |(_, un_sub)| Op::UNSUB(un_sub) }
         //Un parsed data.
@@ -1,4 +1,5 @@
 use super::parser::*;
+use crate::headers::Headers;
 use crate::ops::*;
 use std::collections::HashMap;
 
@@ -19,6 +20,7 @@ fn parse_pub() {
                         subject: String::from("FRONT.DOOR"),
                         reply_to: Some(String::from("INBOX.22")),
                         payload: Vec::from(b"Knock Knock" as &[u8]),
+                        headers: None,
                     })
                 )
             )
@@ -45,6 +47,7 @@ fn parse_pub_no_reply() {
                         subject: String::from("FRONT.DOOR"),
                         reply_to: None,
                         payload: Vec::from(b"Knock Knock" as &[u8]),
+                        headers: None,
                     })
                 )
             )
@@ -71,6 +74,7 @@ fn parse_sub() {
                         subject: String::from("BAR"),
                         sid: String::from("44"),
                         queue_group: Some(String::from("G1")),
+                        ..Subscribe::default()
                     })
                 )
             )
@@ -97,6 +101,7 @@ fn parse_sub_no_group() {
                         subject: String::from("FOO"),
                         sid: String::from("1"),
                         queue_group: None,
+                        ..Subscribe::default()
                     })
                 )
             )
@@ -174,6 +179,7 @@ fn parse_message() {
                         sid: String::from("9"),
                         reply_to: Some(String::from("INBOX.34")),
                         payload: Vec::from(b"Hello World" as &[u8]),
+                        headers: None,
                     })
                 )
             )
@@ -201,6 +207,7 @@ fn parse_message_no_reply() {
                         sid: String::from("9"),
                         reply_to: None,
                         payload: Vec::from(b"Hello World No Reply" as &[u8]),
+                        headers: None,
                     })
                 )
             )
@@ -212,6 +219,73 @@ fn parse_message_no_reply() {
     }
 }
 
+#[test]
+fn parse_header_message() {
+    let input: &[u8] = b"HMSG FOO.BAR 9 INBOX.34 34 45\r\nNATS/1.0\r\nFoodGroup: vegetable\r\n\r\nHello World\r\n\0";
+    let mut headers = Headers::new();
+    headers.insert("FoodGroup", "vegetable");
+    match operation(input) {
+        Ok(obj) => {
+            assert_eq!(
+                obj,
+                (
+                    b"\0" as &[u8],
+                    Op::MSG(Message {
+                        subject: String::from("FOO.BAR"),
+                        sid: String::from("9"),
+                        reply_to: Some(String::from("INBOX.34")),
+                        payload: Vec::from(b"Hello World" as &[u8]),
+                        headers: Some(headers),
+                    })
+                )
+            )
+        }
+        Err(err) => {
+            println!(" parse_header_message ~~~~~~~~~ ERROR {:?}", err);
+            assert!(false)
+        }
+    }
+}
+
+#[test]
+fn parse_header_message_no_reply() {
+    let input: &[u8] = b"HMSG FOO.BAR 9 16 27\r\nNATS/1.0 503\r\n\r\nHello World\r\n\0";
+    match operation(input) {
+        Ok((_, Op::MSG(msg))) => {
+            assert_eq!(msg.reply_to, None);
+            assert_eq!(msg.payload, Vec::from(b"Hello World" as &[u8]));
+            assert_eq!(msg.headers.and_then(|h| h.status()), Some(503));
+        }
+        other => {
+            println!(" parse_header_message_no_reply ~~~~~~~~~ ERROR {:?}", other);
+            assert!(false)
+        }
+    }
+}
+
+#[test]
+fn parse_malformed_arguments() {
+    let inputs: Vec<&[u8]> = vec![
+        b"MSG FOO.BAR 9 eleven\r\nHello World\r\n",
+        b"MSG FOO.BAR\r\nHello World\r\n",
+        b"MSG\r\n",
+        b"MSG FOO.BAR 9 INBOX.34 EXTRA 11\r\nHello World\r\n",
+        b"MSG \xff\xfe 9 11\r\nHello World\r\n",
+        b"HMSG FOO.BAR 9 45 34\r\nNATS/1.0\r\nFoodGroup: vegetable\r\n\r\nHello World\r\n",
+        b"HMSG FOO.BAR 27\r\nNATS/1.0 503\r\n\r\nHello World\r\n",
+        b"PUB 11\r\nKnock Knock\r\n",
+        b"HPUB FRONT.DOOR 30 3\r\nKnock Knock\r\n",
+        b"SUB 44\r\n",
+        b"UNSUB\r\n",
+    ];
+    for input in inputs {
+        match operation(input) {
+            Err(_) => {}
+            other => panic!("parsed malformed {:?} => {:?}", String::from_utf8_lossy(input), other),
+        }
+    }
+}
+
 #[test]
 fn parse_obj1() {
     let test = b"  { \"v\": 42,
@@ -263,6 +337,12 @@ fn parse_info_operation() {
                         tls_verify: false,
                         connect_urls: Vec::new(),
                         nonce: String::from(""),
+                        headers: false,
+                        ldm: false,
+                        jetstream: false,
+                        client_ip: None,
+                        cluster: None,
+                        domain: None,
                     })
                 )
             )
@@ -274,6 +354,29 @@ fn parse_info_operation() {
     }
 }
 
+#[test]
+fn parse_info_operation_v2() {
+    let input = concat!(
+        r#"INFO {"server_id":"NBX","version":"2.10.1","proto":1,"max_payload":1048576,"headers":true,"#,
+        r#""jetstream":true,"client_ip":"10.0.0.7","cluster":"east","domain":"hub","ldm":true}"#,
+        "\r\n"
+    );
+    match operation(input.as_bytes()) {
+        Ok((_, Op::INFO(info))) => {
+            assert_eq!(info.version, "2.10.1");
+            assert!(info.headers && info.jetstream && info.ldm);
+            assert_eq!(info.client_ip, Some(String::from("10.0.0.7")));
+            assert_eq!(info.cluster, Some(String::from("east")));
+            assert_eq!(info.domain, Some(String::from("hub")));
+            let features = info.features();
+            assert!(features.contains(ServerFeatures::HEADERS | ServerFeatures::NO_RESPONDERS));
+            assert!(features.contains(ServerFeatures::ASYNC_INFO));
+            assert!(!ServerInfo::default().features().contains(ServerFeatures::ECHO));
+        }
+        other => panic!("parse_info_operation_v2 => {:?}", other),
+    }
+}
+
 #[test]
 fn parse_connect_operation() {
     let input = format!(
@@ -305,6 +408,8 @@ fn parse_connect_operation() {
                         echo: true,
                         sig: None,
                         jwt: None,
+                        headers: false,
+                        no_responders: false,
                     })
                 )
             )
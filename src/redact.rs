@@ -0,0 +1,19 @@
+//! Helpers for the `Debug` impls and `summary` methods of the types holding credentials, so they
+//! are safe to log.
+
+/// Written in place of a secret.
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// `REDACTED` for a secret that is set, an empty string otherwise.
+pub(crate) fn secret(value: &str) -> &'static str {
+    if value.is_empty() {
+        ""
+    } else {
+        REDACTED
+    }
+}
+
+/// `REDACTED` for a secret that is set, `None` otherwise.
+pub(crate) fn optional_secret<T>(value: &Option<T>) -> Option<&'static str> {
+    value.as_ref().map(|_| REDACTED)
+}
@@ -0,0 +1,257 @@
+//! Process wide registry of named clients, so the crates of one application can share a
+//! connection per logical cluster instead of each opening its own.
+//!
+//! ```no_run
+//! # async fn shared() -> Result<(), ratsio::error::RatsioError> {
+//! use ratsio::nats_client::NatsClientOptions;
+//! use ratsio::ops::Publish;
+//!
+//! let orders = ratsio::registry::get_or_connect("orders", NatsClientOptions::default()).await?;
+//! orders.publish(Publish::builder().subject("orders.new".into()).build().unwrap()).await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::error::RatsioError;
+use crate::nats_client::{NatsClient, NatsClientOptions};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// What the registry does with a client once its last handle is dropped.
+trait Close: Send + Sync + 'static {
+    fn close(&self);
+}
+
+impl Close for NatsClient {
+    fn close(&self) {
+        NatsClient::close(self)
+    }
+}
+
+struct Entry<C> {
+    client: Arc<C>,
+    handles: usize,
+}
+
+/// The handle counting behind the registry, apart from how a client is connected.
+struct Registry<C> {
+    clients: Mutex<HashMap<String, Entry<C>>>,
+    /// Held per name while connecting, so concurrent calls for the same name don't both connect
+    connecting: Mutex<HashMap<String, Arc<futures::lock::Mutex<()>>>>,
+}
+
+impl<C> Default for Registry<C> {
+    fn default() -> Self {
+        Registry {
+            clients: Mutex::new(HashMap::new()),
+            connecting: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<C: Close> Registry<C> {
+    fn register(&'static self, name: &str) -> Option<Handle<C>> {
+        let mut clients = self.clients.lock();
+        let entry = clients.get_mut(name)?;
+        entry.handles += 1;
+        Some(Handle {
+            registry: self,
+            name: name.to_string(),
+            client: entry.client.clone(),
+        })
+    }
+
+    async fn get_or_connect<F, Fut>(&'static self, name: &str, connect: F) -> Result<Handle<C>, RatsioError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Arc<C>, RatsioError>>,
+    {
+        if let Some(handle) = self.register(name) {
+            return Ok(handle);
+        }
+        let connecting = self.connecting.lock().entry(name.to_string()).or_default().clone();
+        let result = {
+            let _connecting = connecting.lock().await;
+            self.connect(name, connect).await
+        };
+        let mut names = self.connecting.lock();
+        // Only the registry and this call hold it, nobody else is waiting for the name.
+        if Arc::strong_count(&connecting) == 2 {
+            names.remove(name);
+        }
+        result
+    }
+
+    async fn connect<F, Fut>(&'static self, name: &str, connect: F) -> Result<Handle<C>, RatsioError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Arc<C>, RatsioError>>,
+    {
+        if let Some(handle) = self.register(name) {
+            return Ok(handle);
+        }
+        let client = connect().await?;
+        // Registered with its first handle, so it can't be closed by a handle dropped meanwhile.
+        let mut clients = self.clients.lock();
+        clients.insert(name.to_string(), Entry { client: client.clone(), handles: 1 });
+        Ok(Handle {
+            registry: self,
+            name: name.to_string(),
+            client,
+        })
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.clients.lock().keys().cloned().collect()
+    }
+}
+
+struct Handle<C: Close> {
+    registry: &'static Registry<C>,
+    name: String,
+    client: Arc<C>,
+}
+
+impl<C: Close> Clone for Handle<C> {
+    fn clone(&self) -> Self {
+        if let Some(entry) = self.registry.clients.lock().get_mut(&self.name) {
+            entry.handles += 1;
+        }
+        Handle {
+            registry: self.registry,
+            name: self.name.clone(),
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<C: Close> Drop for Handle<C> {
+    fn drop(&mut self) {
+        let mut clients = self.registry.clients.lock();
+        let last = match clients.get_mut(&self.name) {
+            Some(entry) => {
+                entry.handles -= 1;
+                entry.handles == 0
+            }
+            None => false,
+        };
+        if last {
+            clients.remove(&self.name);
+            self.client.close();
+        }
+    }
+}
+
+lazy_static! {
+    static ref CLIENTS: Registry<NatsClient> = Registry::default();
+}
+
+/// A handle to a registered client. The client is closed and removed from the registry once
+/// the last handle to it is dropped.
+#[derive(Clone)]
+pub struct SharedClient(Handle<NatsClient>);
+
+impl SharedClient {
+    /// The name the client is registered with.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The client, for APIs that take an `Arc<NatsClient>`. It is closed along with the last
+    /// handle regardless of the copies handed out here.
+    pub fn client(&self) -> Arc<NatsClient> {
+        self.0.client.clone()
+    }
+}
+
+impl Deref for SharedClient {
+    type Target = NatsClient;
+
+    fn deref(&self) -> &NatsClient {
+        &self.0.client
+    }
+}
+
+/// The client registered as `name`, connecting it with `opts` if there is none yet. The options
+/// of an already registered client are not compared with `opts`.
+pub async fn get_or_connect(name: &str, opts: NatsClientOptions) -> Result<SharedClient, RatsioError> {
+    CLIENTS.get_or_connect(name, || NatsClient::connect(opts)).await.map(SharedClient)
+}
+
+/// The client registered as `name`, if any.
+pub fn get(name: &str) -> Option<SharedClient> {
+    CLIENTS.register(name).map(SharedClient)
+}
+
+/// Names of the registered clients.
+pub fn names() -> Vec<String> {
+    CLIENTS.names()
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct FakeClient {
+    closed: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl Close for FakeClient {
+    fn close(&self) {
+        self.closed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn closes_with_the_last_handle() {
+    use std::sync::atomic::Ordering;
+    let registry: &'static Registry<FakeClient> = Box::leak(Box::new(Registry::default()));
+    let client = Arc::new(FakeClient::default());
+    let connected = client.clone();
+    let first = futures::executor::block_on(registry.get_or_connect("orders", || async { Ok(connected) })).unwrap();
+    let second = first.clone();
+    let third = registry.register("orders").unwrap();
+    assert!(Arc::ptr_eq(&second.client, &client) && Arc::ptr_eq(&third.client, &client));
+
+    drop(first);
+    drop(third);
+    assert_eq!(client.closed.load(Ordering::SeqCst), 0);
+    assert_eq!(registry.names(), vec!["orders".to_string()]);
+
+    drop(second);
+    assert_eq!(client.closed.load(Ordering::SeqCst), 1);
+    assert!(registry.names().is_empty());
+    assert!(registry.register("orders").is_none());
+}
+
+#[test]
+fn connects_a_name_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let registry: &'static Registry<FakeClient> = Box::leak(Box::new(Registry::default()));
+    let connects = AtomicUsize::new(0);
+    let (ready_sender, ready) = futures::channel::oneshot::channel::<()>();
+    let slow = registry.get_or_connect("orders", || async {
+        connects.fetch_add(1, Ordering::SeqCst);
+        ready.await.unwrap();
+        Ok(Arc::new(FakeClient::default()))
+    });
+    let fast = registry.get_or_connect("orders", || async {
+        connects.fetch_add(1, Ordering::SeqCst);
+        Ok(Arc::new(FakeClient::default()))
+    });
+    // The slow connect holds the name's lock while the other call waits for it.
+    let (first, second, _) = futures::executor::block_on(futures::future::join3(slow, fast, async {
+        ready_sender.send(()).unwrap();
+    }));
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+    assert!(Arc::ptr_eq(&first.client, &second.client));
+    assert!(registry.connecting.lock().is_empty());
+
+    let client = first.client.clone();
+    drop(first);
+    drop(second);
+    assert_eq!(client.closed.load(Ordering::SeqCst), 1);
+    assert!(registry.names().is_empty());
+}
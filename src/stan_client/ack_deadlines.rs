@@ -0,0 +1,61 @@
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Duration};
+
+/// A message of a subscription is about to be redelivered by the server as it was not acked
+/// yet, see `StanSubscribe::ack_deadline_warning`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AckDeadlineApproaching {
+    pub subject: String,
+    pub sequence: u64,
+    /// Time left until the `ack_wait` of the subscription passes
+    pub remaining: Duration,
+}
+
+/// The messages of a subscription that were delivered and not acked yet.
+#[derive(Debug, Default)]
+pub(crate) struct AckDeadlines {
+    /// Subjects of the unacked messages, by sequence
+    unacked: Mutex<HashMap<u64, String>>,
+    listeners: Mutex<Vec<mpsc::UnboundedSender<AckDeadlineApproaching>>>,
+}
+
+impl AckDeadlines {
+    pub(crate) fn delivered(&self, subject: &str, sequence: u64) {
+        self.unacked.lock().insert(sequence, subject.to_string());
+    }
+
+    pub(crate) fn acked(&self, sequence: u64) {
+        self.unacked.lock().remove(&sequence);
+    }
+
+    pub(crate) fn listen(&self) -> mpsc::UnboundedReceiver<AckDeadlineApproaching> {
+        let (tx, rx) = mpsc::unbounded();
+        self.listeners.lock().push(tx);
+        rx
+    }
+
+    /// Tells the listeners about `sequence` if it is still unacked, listeners that went away are dropped.
+    pub(crate) fn warn(&self, sequence: u64, remaining: Duration) {
+        let subject = match self.unacked.lock().get(&sequence) {
+            Some(subject) => subject.clone(),
+            None => return,
+        };
+        let warning = AckDeadlineApproaching { subject, sequence, remaining };
+        self.listeners.lock().retain(|tx| tx.unbounded_send(warning.clone()).is_ok());
+    }
+}
+
+#[test]
+fn warns_about_unacked_messages() {
+    let deadlines = AckDeadlines::default();
+    let mut warnings = deadlines.listen();
+    deadlines.delivered("orders", 1);
+    deadlines.delivered("orders", 2);
+    deadlines.acked(1);
+    deadlines.warn(1, Duration::from_secs(5));
+    deadlines.warn(2, Duration::from_secs(5));
+    let warning = warnings.try_next().unwrap().unwrap();
+    assert_eq!((warning.subject.as_str(), warning.sequence), ("orders", 2));
+    assert!(warnings.try_next().is_err(), "acked messages are not warned about");
+}
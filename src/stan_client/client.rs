@@ -37,7 +37,7 @@ impl Into<ClientInfo> for ConnectResponse {
 impl StanClient {
     pub fn from_options(
         options: StanOptions,
-    ) -> impl Future<Output = Arc<Self>> {
+    ) -> impl Future<Output = Result<Arc<Self>, RatsioError>> {
         let id_generator = Arc::new(RwLock::new({
             let mut id_gen = NUID::new();
             id_gen.randomize_prefix();
@@ -53,7 +53,7 @@ impl StanClient {
         let mut nats_options = options.nats_options.clone();
         nats_options.name = client_id.clone();
         nats_options.subscribe_on_reconnect = false;
-        NatsClient::connect(nats_options.clone()).then(move |nats_client: Arc<NatsClient>| {
+        NatsClient::connect(nats_options.clone()).and_then(move |nats_client: Arc<NatsClient>| {
             debug!(target: "ratsio", "Got NATS client");
             let mut connect_request = ConnectRequest::new();
             connect_request.set_clientID(client_id.clone());
@@ -78,7 +78,7 @@ impl StanClient {
             //TODO add a timeout for cases where the STAN server does not reply.
             nats_client
                 .request(discover_subject, &connect_payload)
-                .map(move |response| {
+                .map_ok(move |response| {
                     let connect_response =
                         parse_from_bytes::<ConnectResponse>(&response.payload[..]).unwrap();
                     let client_info: ClientInfo = connect_response.clone().into();
@@ -114,7 +114,7 @@ impl StanClient {
                             .subscriptions
                             .write()
                             .remove(&sub_id[..]);
-                        Ok(())
+                        future::ready(())
                     }));
 
                     StanClient::register_reconnect_handler(
@@ -139,7 +139,7 @@ impl StanClient {
             close_request.set_clientID(stan_client.client_id.clone());
             let buf = ProtoMessage::write_to_bytes(&close_request).unwrap();
             info!(target: "ratsio", " STAN Reconnecting closing old connection");
-            let close_fut = nats_client.request(close_requests_subject.clone(), buf[..].into());
+            let close_fut = nats_client.request(close_requests_subject.clone(), &buf[..]);
 
             let conn_id = stan_client.id_generator.write().next();
             *stan_client.conn_id.write() = conn_id.clone().into_bytes();
@@ -165,7 +165,6 @@ impl StanClient {
             let recon_subs_stan_client = stan_client.clone();
             let recon_fut = stan_client.nats_client
                 .request(discover_subject.clone(), &buf)
-                .map_err(|_| ())
                 .and_then(move |response| {
                     info!(target: "ratsio", " STAN Reconnecting Response => {:?}", &response);
                     let connect_response = parse_from_bytes::<ConnectResponse>(
@@ -186,23 +185,19 @@ impl StanClient {
                             let err_subject = sub.cmd.subject.clone();
                             recon_subs_stan_client
                                 .subscribe_inner(sub.cmd.clone(), id.clone(), sub.handler.clone())
-                                .map(move |_| {
-                                    debug!(target: "ratsio", "Subject re-subscribed to => {}", subject)
-                                })
-                                .or_else(move |err| {
-                                    error!(target: "ratsio", "Error re-subscribing to => {} after reconnect {:?}", err_subject, err);
-                                    Ok(())
+                                .map(move |result| match result {
+                                    Ok(_) => debug!(target: "ratsio", "Subject re-subscribed to => {}", subject),
+                                    Err(err) => error!(target: "ratsio", "Error re-subscribing to => {} after reconnect {:?}", err_subject, err),
                                 })
                         }).collect::<Vec<_>>()
                     };
-                    future::join_all(subs_fut_list).map(|_| ())
+                    future::join_all(subs_fut_list).map(|_| Ok(()))
                 });
             tokio::spawn(close_fut.then(|_| {
                 info!(target: "ratsio", " STAN Reconnecting ...");
-                recon_fut.map_err(|err| {
-                    error!(target: "ratsio", "Error reconnecting to STAN: {:?}", err)
-                }).map(|_| {
-                    info!(target: "ratsio", " STAN Reconnecting Done, Ready!");
+                recon_fut.map(|result| match result {
+                    Ok(_) => info!(target: "ratsio", " STAN Reconnecting Done, Ready!"),
+                    Err(err) => error!(target: "ratsio", "Error reconnecting to STAN: {:?}", err),
                 })
             }));
         }));
@@ -223,8 +218,8 @@ impl StanClient {
             .build()
             .unwrap();
         tokio::spawn(nats_client.clone().subscribe(sub)
-            .and_then(|stream| {
-                stream
+            .then(move |result| match result {
+                Ok(stream) => Either::Left(stream
                     .for_each(move |msg| {
                         debug!(target: "ratsio", "HEARTBEAT {}", msg.subject);
                         if let Some(reply_to) = msg.reply_to {
@@ -241,20 +236,19 @@ impl StanClient {
                                 .payload(buf)
                                 .build().unwrap();
                             Either::Left(nats_client.publish(reply_publish)
-                                .map(|_| {
-                                    trace!(target: "ratsio", "HEARTBEAT -- heartbeat reply was sent");
-                                })
-                                .or_else(|err| {
-                                    error!(target: "ratsio", "Error replying to heartbeat {:?}", err);
-                                    Ok(())
+                                .map(|result| match result {
+                                    Ok(_) => trace!(target: "ratsio", "HEARTBEAT -- heartbeat reply was sent"),
+                                    Err(err) => error!(target: "ratsio", "Error replying to heartbeat {:?}", err),
                                 }))
                         } else {
-                            Either::Right(future::ok(()))
+                            Either::Right(future::ready(()))
                         }
-                    })
-            }).map_err(|err| {
-            error!(target: "ratsio", "Error in heartbeat stream {:?}", err);
-        }));
+                    })),
+                Err(err) => {
+                    error!(target: "ratsio", "Error in heartbeat stream {:?}", err);
+                    Either::Right(future::ready(()))
+                }
+            }));
     }
 
     fn sub_request_payload(&self, subscribe: &StanSubscribe, inbox: &str) -> Vec<u8> {
@@ -322,7 +316,7 @@ impl StanClient {
                     parse_from_bytes::<SubscriptionResponse>(&sub_response.payload[..]).unwrap();
 
                 let sub = Subscribe::builder().subject(inbox.clone()).build().unwrap();
-                subs_nats_client.subscribe(sub).then(move |stream| {
+                subs_nats_client.subscribe(sub).map_ok(move |stream| {
                     let subscription = Subscription {
                         subscription_id: subscription_id.clone(),
                         client_id: subs_client_id,
@@ -335,12 +329,13 @@ impl StanClient {
                         is_closed: AtomicBool::new(false),
                         unsub_tx,
                         handler,
+                        ack_deadlines: AckDeadlines::default(),
                     }
-                    .start(Box::new(stream));
+                    .start(stream);
                     subscriptions
                         .write()
                         .insert(subscription_id.clone(), subscription.clone());
-                    future::ok(subscription_id)
+                    subscription_id
                 })
             })
     }
@@ -398,7 +393,7 @@ impl StanClient {
         self.nats_client.publish(publ)
     }
 
-    pub fn close(&self) -> impl Future<Output = ()> {
+    pub fn close(&self) -> impl Future<Output = Result<(), RatsioError>> {
         let nats_client = self.nats_client.clone();
         let close_requests = self.client_info.read().close_requests.clone();
         let client_id = self.client_id.clone();
@@ -413,16 +408,15 @@ impl StanClient {
                 s.close()
             })
             .collect::<Vec<_>>();
-        future::join_all(subs_futures)
+        future::try_join_all(subs_futures)
             .map_err(|_| RatsioError::GenericError("Closing connection".into()))
             .and_then(move |_| {
                 let mut close_request = CloseRequest::new();
                 close_request.set_clientID(client_id);
                 let buf = ProtoMessage::write_to_bytes(&close_request).unwrap();
                 debug!(target: "ratsio", " STAN Shutting down ...");
-                nats_client.request(close_requests.clone(), buf[..].into())
+                nats_client.request(close_requests.clone(), &buf[..])
             })
-            .map(|_| debug!(target: "ratsio", "STAN Shutting down - DONE "))
-            .from_err()
+            .map_ok(|_| debug!(target: "ratsio", "STAN Shutting down - DONE "))
     }
 }
@@ -1,7 +1,7 @@
 use crate::nats_client::{NatsClient, NatsClientOptions};
 use futures::{
-    Future,
     channel::mpsc,
+    future::BoxFuture,
 };
 use crate::nuid::NUID;
 use parking_lot::RwLock;
@@ -11,10 +11,15 @@ use std::{
         Arc,
         atomic::AtomicBool,
     },
+    time::Duration,
 };
+mod ack_deadlines;
 mod client;
 mod subscription;
 
+pub use self::ack_deadlines::AckDeadlineApproaching;
+use self::ack_deadlines::AckDeadlines;
+
 // DefaultConnectWait is the default timeout used for the connect operation
 //const DEFAULT_CONNECT_WAIT: u64 = 2 * 60000;
 // DefaultDiscoverPrefix is the prefix subject used to connect to the NATS Streaming server
@@ -168,6 +173,11 @@ pub struct StanSubscribe {
     pub start_sequence: u64,
     pub start_time_delta: Option<i32>,
     pub manual_acks: bool,
+    /// Warn this long before the `ack_wait` of a message that was not acked yet passes, on the
+    /// stream of `Subscription::ack_deadlines`, so long running handlers notice before the server
+    /// redelivers the message. NATS Streaming has no way to extend the deadline, the handler can
+    /// only ack early or give up.
+    pub ack_deadline_warning: Option<Duration>,
 }
 
 impl StanSubscribe {
@@ -188,6 +198,7 @@ impl Default for StanSubscribe {
             start_sequence: 0,
             start_time_delta: None,
             manual_acks: false,
+            ack_deadline_warning: None,
         }
     }
 }
@@ -204,6 +215,8 @@ pub struct Subscription {
     unsub_tx: mpsc::UnboundedSender<String>,
     handler: Arc<SubscriptionHandler>,
     cmd: StanSubscribe,
+    /// Delivered messages not acked yet, with `StanSubscribe::ack_deadline_warning`
+    ack_deadlines: AckDeadlines,
 }
 
 pub struct StanClient {
@@ -239,7 +252,7 @@ pub struct ClientInfo {
     ping_requests: String,
 }
 
-pub struct AsyncHandler(pub Box<dyn Fn(StanMessage) -> Box<dyn Future<Output=()>+ Send + Sync> + Send + Sync>);
+pub struct AsyncHandler(pub Box<dyn Fn(StanMessage) -> BoxFuture<'static, ()> + Send + Sync>);
 
 pub struct SyncHandler(pub Box<dyn Fn(StanMessage) -> Result<(), ()> + Send + Sync>);
 
@@ -15,14 +15,18 @@ use std::{
         Arc,
         atomic::Ordering,
     },
+    time::Duration,
 };
 use super::*;
 
 impl Subscription {
-    pub(crate) fn start(self, stream: Box<dyn Stream<Item=Message> + Send + Sync>) -> Arc<Self> {
+    pub(crate) fn start<S>(self, stream: S) -> Arc<Self>
+        where S: Stream<Item=Message> + Send + 'static {
         let arc_self = Arc::new(self);
         let handler_subscr = arc_self.clone();
         let subs_nats_client = arc_self.nats_client.clone();
+        let clock = subs_nats_client.options().clock;
+        let ack_wait = Duration::from_secs(arc_self.cmd.ack_wait_in_secs.max(0) as u64);
 
         let subs_future = stream
             .for_each(move |nats_msg| {
@@ -36,23 +40,25 @@ impl Subscription {
                     sequence: msg.sequence,
                     redelivered: msg.redelivered,
                 };
-                if handler_subscr.clone().is_closed.load(Ordering::Relaxed) {
-                    future::ok(())
-                } else {
+                if !handler_subscr.is_closed.load(Ordering::Relaxed) {
+                    if let Some(warning) = handler_subscr.cmd.ack_deadline_warning {
+                        let sequence = stan_msg.sequence;
+                        handler_subscr.ack_deadlines.delivered(&stan_msg.subject, sequence);
+                        let warn_subscr = handler_subscr.clone();
+                        let remaining = warning.min(ack_wait);
+                        tokio::spawn(clock.delay(ack_wait - remaining)
+                            .map(move |_| warn_subscr.ack_deadlines.warn(sequence, remaining)));
+                    }
                     let _ = handler_subscr.handler.0(stan_msg, handler_subscr.clone(), subs_nats_client.clone()).map(|_| {
                         trace!(target: "ratsio", "Message handler completed");
                     });
-                    future::ok(())
                 }
-            })
-            .map_err(|err| error!(target: "ratsio", " STAN stream error  -> {}", err));
+                future::ready(())
+            });
 
-        tokio::spawn(subs_future.into_future()
+        tokio::spawn(subs_future
             .map(|_| {
                 debug!(target: "ratsio", "done with subscription");
-            })
-            .map_err(|_| {
-                debug!(target: "ratsio", "done with subscription with error  ");
             }));
         arc_self
     }
@@ -60,18 +66,18 @@ impl Subscription {
     ///
     /// The UnsubscribeRequest unsubcribes the connection from the specified subject.
     /// The inbox specified is the inbox returned from the NATS Streaming Server in the SubscriptionResponse.
-    pub fn unsubscribe(&self) -> impl Future<Item=(), Error=()> {
+    pub fn unsubscribe(&self) -> impl Future<Output=Result<(), RatsioError>> {
         self.unsub(self.unsub_requests.clone())
     }
 
     ///
     /// The UnsubscribeRequest closes subcribtion for specified subject.
     /// The inbox specified is the inbox returned from the NATS Streaming Server in the SubscriptionResponse.
-    pub fn close(&self) -> impl Future<Item=(), Error=()> {
+    pub fn close(&self) -> impl Future<Output=Result<(), RatsioError>> {
         self.unsub(self.close_requests.clone())
     }
 
-    fn unsub(&self, subject: String) -> impl Future<Item=(), Error=()> {
+    fn unsub(&self, subject: String) -> impl Future<Output=Result<(), RatsioError>> {
         let mut unsub_request = UnsubscribeRequest::new();
         unsub_request.set_clientID(self.client_id.clone());
         unsub_request.set_subject(self.cmd.subject.clone());
@@ -84,16 +90,31 @@ impl Subscription {
         let unsub_tx = self.unsub_tx.clone();
         let subscription_id = self.subscription_id.clone();
         self.nats_client.request(subject.clone(), &buf[..])
-            .map_err(|err| error!(target: "ratsio", " STAN Unsubscribe error => {}", err))
-            .and_then(move |_| {
-                info!(target: "ratsio", " STAN Unsubscribe for {} DONE", subject);
-                unsub_tx.unbounded_send(subscription_id)
-                    .into_future()
-                    .map_err(|_| ())
+            .map(move |result| match result {
+                Ok(_) => {
+                    info!(target: "ratsio", " STAN Unsubscribe for {} DONE", subject);
+                    unsub_tx.unbounded_send(subscription_id)
+                        .map_err(|_| RatsioError::InnerBrokenChain)
+                }
+                Err(err) => {
+                    error!(target: "ratsio", " STAN Unsubscribe error => {}", err);
+                    Err(err)
+                }
             })
     }
 
-    fn ack_message(&self, ack_inbox: String, subject: String, sequence: u64) -> impl Future<Item=(), Error=RatsioError> {
+    /// Acknowledges `msg`, for subscriptions with `StanSubscribe::manual_acks`.
+    pub fn ack(&self, msg: &StanMessage) -> impl Future<Output=Result<(), RatsioError>> {
+        self.ack_message(self.ack_inbox.clone(), msg.subject.clone(), msg.sequence)
+    }
+
+    /// Warnings about messages whose `ack_wait` is about to pass, see `StanSubscribe::ack_deadline_warning`.
+    pub fn ack_deadlines(&self) -> impl Stream<Item=AckDeadlineApproaching> {
+        self.ack_deadlines.listen()
+    }
+
+    fn ack_message(&self, ack_inbox: String, subject: String, sequence: u64) -> impl Future<Output=Result<(), RatsioError>> {
+        self.ack_deadlines.acked(sequence);
         let mut ack_request = Ack::new();
         ack_request.set_subject(subject);
         ack_request.set_sequence(sequence);
@@ -116,7 +137,9 @@ impl Into<SubscriptionHandler> for SyncHandler {
                     if !manual_acks {
                         tokio::spawn(
                             subscr.ack_message(ack_inbox, subject, sequence)
-                                .map_err(|err| error!(target: "ratsio", " STAN stream error -> {} ", err))
+                                .map(|result| if let Err(err) = result {
+                                    error!(target: "ratsio", " STAN stream error -> {} ", err)
+                                })
                         );
                     }
                 });
@@ -137,6 +160,7 @@ impl Into<SubscriptionHandler> for AsyncHandler {
                     .then(move |_| {
                         //stan_message.
                         if !manual_acks {
+                            subscr.ack_deadlines.acked(ack_sequence);
                             let mut ack_request = Ack::new();
                             ack_request.set_subject(ack_subject);
                             ack_request.set_sequence(ack_sequence);
@@ -145,11 +169,11 @@ impl Into<SubscriptionHandler> for AsyncHandler {
                                 .publish(Publish::builder()
                                     .payload(Vec::from(&buf[..]))
                                     .subject(ack_inbox).build().unwrap())
-                                .map_err(|err| {
+                                .map(|result| if let Err(err) = result {
                                     error!(" Error acknowledging message {}", err);
                                 }))
                         } else {
-                            Either::Right(future::ok(()))
+                            Either::Right(future::ready(()))
                         }
                     }));
                 Ok(())
@@ -0,0 +1,61 @@
+//! Helpers for working with NATS subjects and subject wildcards.
+//!
+//! Subjects are `.` separated tokens, a `*` token matches exactly one token and a trailing `>`
+//! token matches one or more tokens, e.g. `orders.*.created` or `logs.>`.
+
+use crate::error::RatsioError;
+
+/// Fails with `RatsioError::InvalidSubject` if `subject` can't be sent as a protocol argument:
+/// it is empty or contains whitespace or control characters, e.g. an embedded `\r\n`.
+pub fn validate(subject: &str) -> Result<(), RatsioError> {
+    if subject.is_empty() || subject.chars().any(|c| c.is_ascii_whitespace() || c.is_ascii_control()) {
+        Err(RatsioError::InvalidSubject(subject.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns true if `subject` is matched by the (possibly wildcarded) `pattern`.
+pub fn matches(pattern: &str, subject: &str) -> bool {
+    let mut pattern_tokens = pattern.split('.');
+    let mut subject_tokens = subject.split('.');
+    loop {
+        match (pattern_tokens.next(), subject_tokens.next()) {
+            (Some(">"), Some(_)) => return true,
+            (Some("*"), Some(_)) => continue,
+            (Some(p), Some(s)) if p == s => continue,
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[test]
+fn validates_protocol_arguments() {
+    assert!(validate("orders.*.created").is_ok());
+    assert!(validate("_INBOX.Ab9").is_ok());
+    for invalid in &["", "orders new", "orders\tnew", "orders\r\nPUB evil 0", "orders\n", "a\u{0}b"] {
+        match validate(invalid) {
+            Err(RatsioError::InvalidSubject(subject)) => assert_eq!(subject, *invalid),
+            other => panic!("{:?} was accepted => {:?}", invalid, other),
+        }
+    }
+}
+
+#[test]
+fn matches_literal() {
+    assert!(matches("foo.bar", "foo.bar"));
+    assert!(!matches("foo.bar", "foo.baz"));
+    assert!(!matches("foo.bar", "foo.bar.baz"));
+    assert!(!matches("foo.bar.baz", "foo.bar"));
+}
+
+#[test]
+fn matches_wildcards() {
+    assert!(matches("foo.*", "foo.bar"));
+    assert!(!matches("foo.*", "foo.bar.baz"));
+    assert!(matches("foo.*.baz", "foo.bar.baz"));
+    assert!(matches("foo.>", "foo.bar.baz"));
+    assert!(!matches("foo.>", "foo"));
+    assert!(matches(">", "foo"));
+}
@@ -0,0 +1,317 @@
+//! Fixtures for integration tests against a real server, only built with the `testing` feature.
+//!
+//! ```no_run
+//! use ratsio::nats_client::NatsClient;
+//! use ratsio::testing::NatsServerProcess;
+//!
+//! let server = NatsServerProcess::start().unwrap();
+//! let client = NatsClient::connect(server.client_options());
+//! ```
+use crate::error::RatsioError;
+use crate::nats_client::{NatsClientOptions, UriVec};
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Release downloaded when no `nats-server` binary is found.
+pub const DEFAULT_SERVER_VERSION: &str = "2.10.22";
+
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// TLS certificate of the server, PEM files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerTls {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    /// CA to verify client certificates with, clients must present one if set
+    pub ca_file: Option<PathBuf>,
+}
+
+/// How to start a server, see `NatsServerProcess::start_with`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NatsServerConfig {
+    /// Username and password clients must authenticate with
+    pub user: Option<(String, String)>,
+    /// Token clients must authenticate with
+    pub token: Option<String>,
+    /// Serve TLS only, the server expects the handshake before it sends INFO like ratsio does
+    pub tls: Option<ServerTls>,
+    pub jetstream: bool,
+    /// Appended to the generated config file
+    pub extra_config: String,
+}
+
+/// A `nats-server` process on a random local port, killed when dropped.
+///
+/// The binary is taken from `NATS_SERVER_BIN`, else `nats-server` on the path, else release
+/// `DEFAULT_SERVER_VERSION` is downloaded to `~/.cache/ratsio` with `curl` and `tar`.
+pub struct NatsServerProcess {
+    child: Child,
+    port: u16,
+    config: NatsServerConfig,
+    /// Holds the config file and JetStream store, removed when dropped
+    dir: PathBuf,
+}
+
+impl NatsServerProcess {
+    /// Starts a server without auth or TLS.
+    pub fn start() -> Result<Self, RatsioError> {
+        NatsServerProcess::start_with(NatsServerConfig::default())
+    }
+
+    pub fn start_with(config: NatsServerConfig) -> Result<Self, RatsioError> {
+        let binary = locate_server()?;
+        let dir = std::env::temp_dir().join(format!(
+            "ratsio-nats-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let port = free_port()?;
+        let config_file = dir.join("nats.conf");
+        std::fs::write(&config_file, render_config(&config, port, &dir))?;
+        let child = Command::new(&binary)
+            .arg("-c")
+            .arg(&config_file)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let server = NatsServerProcess { child, port, config, dir };
+        server.wait_ready()?;
+        Ok(server)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// `nats://` or `tls://` url of the server.
+    pub fn url(&self) -> String {
+        let scheme = if self.config.tls.is_some() { "tls" } else { "nats" };
+        format!("{}://127.0.0.1:{}", scheme, self.port)
+    }
+
+    /// Options connecting to this server, with its credentials. TLS servers need the client to
+    /// trust their certificate.
+    pub fn client_options(&self) -> NatsClientOptions {
+        let mut options = NatsClientOptions {
+            cluster_uris: UriVec::from(self.url()),
+            tls_required: self.config.tls.is_some(),
+            ..NatsClientOptions::default()
+        };
+        if let Some((ref user, ref pass)) = self.config.user {
+            options.username = user.clone();
+            options.password = pass.clone();
+        }
+        if let Some(ref token) = self.config.token {
+            options.auth_token = token.clone();
+        }
+        options
+    }
+
+    /// Kills the server, e.g. to test reconnects. Dropping it does the same.
+    pub fn stop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    /// Waits until the server greets a new connection with its INFO, or only accepts it for TLS servers.
+    fn wait_ready(&self) -> Result<(), RatsioError> {
+        let started = Instant::now();
+        while started.elapsed() < READY_TIMEOUT {
+            if let Ok(mut socket) = TcpStream::connect(("127.0.0.1", self.port)) {
+                if self.config.tls.is_some() {
+                    return Ok(());
+                }
+                let _ = socket.set_read_timeout(Some(Duration::from_secs(1)));
+                let mut buf = [0u8; 4];
+                if socket.read_exact(&mut buf).is_ok() && &buf == b"INFO" {
+                    return Ok(());
+                }
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Err(RatsioError::GenericError(format!(
+            "nats-server on port {} did not become ready",
+            self.port
+        )))
+    }
+}
+
+impl Drop for NatsServerProcess {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn free_port() -> Result<u16, RatsioError> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
+
+fn render_config(config: &NatsServerConfig, port: u16, dir: &Path) -> String {
+    let quoted = |path: &Path| format!("{:?}", path.display().to_string());
+    let mut conf = format!("host: \"127.0.0.1\"\nport: {}\n", port);
+    if let Some((ref user, ref pass)) = config.user {
+        conf.push_str(&format!("authorization {{\n  user: {:?}\n  password: {:?}\n}}\n", user, pass));
+    } else if let Some(ref token) = config.token {
+        conf.push_str(&format!("authorization {{\n  token: {:?}\n}}\n", token));
+    }
+    if let Some(ref tls) = config.tls {
+        conf.push_str(&format!(
+            "tls {{\n  cert_file: {}\n  key_file: {}\n  handshake_first: true\n",
+            quoted(&tls.cert_file),
+            quoted(&tls.key_file)
+        ));
+        if let Some(ref ca_file) = tls.ca_file {
+            conf.push_str(&format!("  ca_file: {}\n  verify: true\n", quoted(ca_file)));
+        }
+        conf.push_str("}\n");
+    }
+    if config.jetstream {
+        conf.push_str(&format!("jetstream {{\n  store_dir: {}\n}}\n", quoted(&dir.join("jetstream"))));
+    }
+    conf.push_str(&config.extra_config);
+    conf
+}
+
+/// `NATS_SERVER_BIN`, `nats-server` on the path, or a downloaded release.
+fn locate_server() -> Result<PathBuf, RatsioError> {
+    if let Ok(binary) = std::env::var("NATS_SERVER_BIN") {
+        return Ok(PathBuf::from(binary));
+    }
+    let on_path = Command::new("nats-server")
+        .arg("--version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+    if on_path {
+        return Ok(PathBuf::from("nats-server"));
+    }
+    download_server(DEFAULT_SERVER_VERSION)
+}
+
+/// Downloads release `version` to a cache dir only the user can write to, once. The archive is
+/// checked against the `SHA256SUMS` of the release before it is unpacked.
+fn download_server(version: &str) -> Result<PathBuf, RatsioError> {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    };
+    let release = format!("nats-server-v{}-{}-{}", version, os, arch);
+    let dir = cache_dir()?;
+    let binary = dir.join(&release).join("nats-server");
+    if binary.exists() {
+        return Ok(binary);
+    }
+    let releases = format!("https://github.com/nats-io/nats-server/releases/download/v{}", version);
+    let archive_name = format!("{}.tar.gz", release);
+    let archive = dir.join(&archive_name);
+    let sums = dir.join(format!("SHA256SUMS-v{}", version));
+    let download = |to: &Path, file: &str| {
+        run(Command::new("curl").arg("-sSfL").arg("-o").arg(to).arg(format!("{}/{}", releases, file)))
+    };
+    download(&sums, "SHA256SUMS")?;
+    download(&archive, &archive_name)?;
+    let expected = checksum(&std::fs::read_to_string(&sums)?, &archive_name)
+        .ok_or_else(|| RatsioError::GenericError(format!("No checksum for {} in SHA256SUMS", archive_name)))?;
+    let actual = sha256_hex(&std::fs::read(&archive)?);
+    let _ = std::fs::remove_file(&sums);
+    if actual != expected {
+        let _ = std::fs::remove_file(&archive);
+        return Err(RatsioError::GenericError(format!(
+            "Checksum of {} is {}, expected {}",
+            archive_name, actual, expected
+        )));
+    }
+    run(Command::new("tar").arg("xzf").arg(&archive).arg("-C").arg(&dir))?;
+    let _ = std::fs::remove_file(&archive);
+    Ok(binary)
+}
+
+/// `$XDG_CACHE_HOME/ratsio` or `~/.cache/ratsio`, private to the user.
+fn cache_dir() -> Result<PathBuf, RatsioError> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .ok_or_else(|| {
+            RatsioError::GenericError("No cache dir to download nats-server to, set NATS_SERVER_BIN".into())
+        })?;
+    let dir = base.join("ratsio");
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir)?;
+    Ok(dir)
+}
+
+/// The checksum of `file` in a `SHA256SUMS` listing.
+fn checksum<'a>(sums: &'a str, file: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some(sum), Some(name)) if name.trim_start_matches('*') == file => Some(sum),
+            _ => None,
+        }
+    })
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn run(command: &mut Command) -> Result<(), RatsioError> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(RatsioError::GenericError(format!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[test]
+fn renders_server_config() {
+    let config = NatsServerConfig {
+        user: Some(("alice".into(), "secret".into())),
+        jetstream: true,
+        ..NatsServerConfig::default()
+    };
+    let conf = render_config(&config, 4333, Path::new("/tmp/ratsio"));
+    assert!(conf.contains("port: 4333\n"));
+    assert!(conf.contains("user: \"alice\"\n  password: \"secret\"\n"));
+    assert!(conf.contains("store_dir: \"/tmp/ratsio/jetstream\""));
+    assert!(!conf.contains("tls"));
+}
+
+#[test]
+fn finds_release_checksums() {
+    let sums = "0a1b  nats-server-v2.10.22-linux-amd64.tar.gz\n\
+                2c3d *nats-server-v2.10.22-darwin-arm64.tar.gz\n";
+    assert_eq!(checksum(sums, "nats-server-v2.10.22-linux-amd64.tar.gz"), Some("0a1b"));
+    assert_eq!(checksum(sums, "nats-server-v2.10.22-darwin-arm64.tar.gz"), Some("2c3d"));
+    assert_eq!(checksum(sums, "nats-server-v2.10.22-linux-arm64.tar.gz"), None);
+    assert_eq!(
+        sha256_hex(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
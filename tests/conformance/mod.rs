@@ -0,0 +1,240 @@
+//! Runs `nats-server` containers for the conformance tests, needs a `docker` binary on the path.
+//!
+//! The server versions are read from `RATSIO_CONFORMANCE_VERSIONS`, a comma separated list of
+//! `nats` image tags, and default to every minor release from 2.2 to 2.10.
+
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const DEFAULT_VERSIONS: &[&str] = &["2.2", "2.3", "2.4", "2.5", "2.6", "2.7", "2.8", "2.9", "2.10"];
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub fn versions() -> Vec<String> {
+    match std::env::var("RATSIO_CONFORMANCE_VERSIONS") {
+        Ok(versions) => versions
+            .split(',')
+            .map(|version| version.trim().to_string())
+            .filter(|version| !version.is_empty())
+            .collect(),
+        Err(_) => DEFAULT_VERSIONS.iter().map(|version| version.to_string()).collect(),
+    }
+}
+
+/// Whether `version` is at least `major.minor`, versions that cannot be parsed (e.g. `latest`) are.
+pub fn at_least(version: &str, major: u32, minor: u32) -> bool {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(v_major)), Some(Ok(v_minor))) => (v_major, v_minor) >= (major, minor),
+        _ => true,
+    }
+}
+
+fn unique_name(kind: &str) -> String {
+    format!("ratsio-{}-{}-{}", kind, std::process::id(), NEXT_ID.fetch_add(1, Ordering::SeqCst))
+}
+
+fn docker(args: &[&str]) -> String {
+    let output = Command::new("docker")
+        .args(args)
+        .output()
+        .expect("Could not run docker");
+    assert!(
+        output.status.success(),
+        "docker {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("No free port")
+}
+
+/// A docker network for clustered servers, removed when dropped.
+pub struct Network {
+    pub name: String,
+}
+
+impl Network {
+    pub fn create() -> Network {
+        let name = unique_name("net");
+        docker(&["network", "create", &name]);
+        Network { name }
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        let _ = Command::new("docker").args(&["network", "rm", &self.name]).output();
+    }
+}
+
+/// How to start a server, see `NatsServer::start`.
+#[derive(Default)]
+pub struct ServerSpec<'a> {
+    pub network: Option<&'a Network>,
+    /// Host directory mounted read-only at `/etc/ratsio` in the container
+    pub volume: Option<&'a Path>,
+    pub args: Vec<String>,
+    /// The server expects a TLS handshake before it sends INFO
+    pub tls_first: bool,
+    /// Advertise the published host port to clients, so gossiped urls are reachable from the host
+    pub advertise: bool,
+}
+
+/// A running `nats-server` container, removed when dropped.
+pub struct NatsServer {
+    pub name: String,
+    pub version: String,
+    /// Host port the client port of the server is published on
+    pub port: u16,
+    tls_first: bool,
+}
+
+impl NatsServer {
+    pub fn start(version: &str, spec: ServerSpec) -> NatsServer {
+        let name = unique_name("nats");
+        let port = free_port();
+        let port_mapping = format!("{}:4222", port);
+        let image = format!("nats:{}", version);
+        let mut args = vec!["run", "-d", "--name", &name, "-p", &port_mapping];
+        let network = spec.network.map(|network| network.name.clone());
+        if let Some(ref network) = network {
+            args.extend(&["--network", network.as_str(), "--network-alias", name.as_str()]);
+        }
+        let volume = spec.volume.map(|dir| format!("{}:/etc/ratsio:ro", dir.display()));
+        if let Some(ref volume) = volume {
+            args.extend(&["-v", volume.as_str()]);
+        }
+        args.push(&image);
+        args.extend(spec.args.iter().map(String::as_str));
+        let advertise = format!("127.0.0.1:{}", port);
+        if spec.advertise {
+            args.extend(&["--client_advertise", advertise.as_str()]);
+        }
+        docker(&args);
+
+        let server = NatsServer {
+            name,
+            version: version.to_string(),
+            port,
+            tls_first: spec.tls_first,
+        };
+        server.wait_ready();
+        server
+    }
+
+    pub fn url(&self) -> String {
+        format!("127.0.0.1:{}", self.port)
+    }
+
+    /// Route url for other servers on the same network, if started with `--cluster`.
+    pub fn route_url(&self) -> String {
+        format!("nats://{}:6222", self.name)
+    }
+
+    pub fn restart(&self) {
+        docker(&["restart", "-t", "0", &self.name]);
+        self.wait_ready();
+    }
+
+    pub fn stop(&self) {
+        docker(&["stop", "-t", "0", &self.name]);
+    }
+
+    /// Waits until the server greets a new connection with its INFO, or only accepts it for
+    /// servers that expect TLS first.
+    fn wait_ready(&self) {
+        let started = Instant::now();
+        while started.elapsed() < READY_TIMEOUT {
+            if let Ok(mut socket) = TcpStream::connect(("127.0.0.1", self.port)) {
+                if self.tls_first {
+                    std::thread::sleep(Duration::from_millis(500));
+                    return;
+                }
+                let _ = socket.set_read_timeout(Some(Duration::from_secs(1)));
+                let mut buf = [0u8; 4];
+                if socket.read_exact(&mut buf).is_ok() && &buf == b"INFO" {
+                    return;
+                }
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        panic!("nats-server {} ({}) did not become ready", self.version, self.name);
+    }
+}
+
+impl Drop for NatsServer {
+    fn drop(&mut self) {
+        let _ = Command::new("docker").args(&["rm", "-f", &self.name]).output();
+    }
+}
+
+/// A self signed CA and a `localhost` server certificate, generated with the `openssl` binary.
+pub struct Certs {
+    pub dir: PathBuf,
+}
+
+impl Certs {
+    pub fn generate() -> Certs {
+        let dir = std::env::temp_dir().join(unique_name("certs"));
+        std::fs::create_dir_all(&dir).expect("Could not create certs dir");
+        let openssl = |args: &[&str]| {
+            let status = Command::new("openssl")
+                .current_dir(&dir)
+                .args(args)
+                .status()
+                .expect("Could not run openssl");
+            assert!(status.success(), "openssl {:?} failed", args);
+        };
+        openssl(&[
+            "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+            "-keyout", "ca-key.pem", "-out", "ca.pem", "-subj", "/CN=ratsio-test-ca",
+        ]);
+        openssl(&[
+            "req", "-newkey", "rsa:2048", "-nodes",
+            "-keyout", "server-key.pem", "-out", "server.csr", "-subj", "/CN=localhost",
+        ]);
+        std::fs::write(dir.join("san.ext"), "subjectAltName=DNS:localhost,IP:127.0.0.1\n")
+            .expect("Could not write san.ext");
+        openssl(&[
+            "x509", "-req", "-in", "server.csr", "-CA", "ca.pem", "-CAkey", "ca-key.pem",
+            "-CAcreateserial", "-days", "1", "-extfile", "san.ext", "-out", "server.pem",
+        ]);
+        // The server in the container runs as another user.
+        Command::new("chmod")
+            .args(&["-R", "a+rX"])
+            .arg(&dir)
+            .status()
+            .expect("Could not run chmod");
+        Certs { dir }
+    }
+
+    pub fn ca_file(&self) -> PathBuf {
+        self.dir.join("ca.pem")
+    }
+
+    /// Writes a server config enabling TLS with the generated certificate, returns its path in the container.
+    /// The client starts TLS before reading INFO, so the server is told to expect that.
+    pub fn write_tls_config(&self) -> String {
+        let config = "tls {\n  cert_file: \"/etc/ratsio/server.pem\"\n  key_file: \"/etc/ratsio/server-key.pem\"\n  handshake_first: true\n}\n";
+        std::fs::write(self.dir.join("tls.conf"), config).expect("Could not write tls.conf");
+        String::from("/etc/ratsio/tls.conf")
+    }
+}
+
+impl Drop for Certs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
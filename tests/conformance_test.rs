@@ -0,0 +1,315 @@
+//! Protocol conformance tests against dockerized nats-server releases, see `tests/conformance/mod.rs`.
+//!
+//! Only built with the `conformance` feature:
+//! `cargo test --features conformance --test conformance_test -- --test-threads=1`
+#![cfg(feature = "conformance")]
+
+#[macro_use]
+extern crate log;
+
+use futures::prelude::*;
+use ratsio::error::RatsioError;
+use ratsio::headers::Headers;
+use ratsio::nats_client::*;
+use ratsio::ops::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+mod common;
+mod conformance;
+
+#[cfg(feature = "tls")]
+use conformance::Certs;
+use conformance::{NatsServer, Network, ServerSpec};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+async fn connected(options: NatsClientOptions) -> Result<Arc<NatsClient>, RatsioError> {
+    let client = NatsClient::connect(options).await?;
+    wait_for_state(&client, NatsClientState::Connected).await?;
+    Ok(client)
+}
+
+async fn wait_for_state(client: &NatsClient, state: NatsClientState) -> Result<(), RatsioError> {
+    let waiting = async {
+        while client.get_state() != state {
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(TIMEOUT, waiting)
+        .await
+        .map_err(|_| RatsioError::GenericError(format!("client did not become {:?}", state)))
+}
+
+/// Subscribes to `subject`, publishes to it and waits for the message to come back.
+async fn round_trip(client: &NatsClient, subject: &str, headers: Option<Headers>) -> Result<Message, RatsioError> {
+    let mut stream = client
+        .subscribe(Subscribe::builder().subject(subject.into()).build().unwrap())
+        .await?;
+    client
+        .publish(Publish {
+            subject: subject.into(),
+            reply_to: None,
+            payload: Vec::from(&b"conformance"[..]),
+            headers,
+        })
+        .await?;
+    tokio::time::timeout(TIMEOUT, stream.next())
+        .await
+        .map_err(|_| RatsioError::RequestTimeout)?
+        .ok_or(RatsioError::InnerBrokenChain)
+}
+
+#[test]
+fn conformance_pub_sub_request() {
+    common::setup();
+    for version in conformance::versions() {
+        info!(target: "ratsio", "conformance_pub_sub_request against nats:{}", version);
+        let server = NatsServer::start(&version, ServerSpec::default());
+        let mut runtime = Runtime::new().unwrap();
+        let options = NatsClientOptions::builder().cluster_uris(server.url()).build().unwrap();
+        let result: Result<(), RatsioError> = runtime.block_on(async {
+            let client = connected(options).await?;
+            let msg = round_trip(&client, "conformance.pubsub", None).await?;
+            assert_eq!(msg.payload, Vec::from(&b"conformance"[..]));
+
+            client.serve("conformance.service".into(), None, |msg| future::ok(msg.payload)).await?;
+            let reply = client.request_timeout("conformance.service".into(), b"echo", TIMEOUT).await?;
+            assert_eq!(reply.payload, Vec::from(&b"echo"[..]));
+            Ok(())
+        });
+        assert!(result.is_ok(), "nats:{} => {:?}", version, result);
+    }
+}
+
+#[test]
+fn conformance_auth_modes() {
+    common::setup();
+    for version in conformance::versions() {
+        info!(target: "ratsio", "conformance_auth_modes against nats:{}", version);
+        let mut runtime = Runtime::new().unwrap();
+
+        let server = NatsServer::start(&version, ServerSpec {
+            args: args(&["--user", "ratsio", "--pass", "secret"]),
+            ..Default::default()
+        });
+        let options = NatsClientOptions::builder()
+            .username("ratsio")
+            .password("secret")
+            .cluster_uris(server.url())
+            .build()
+            .unwrap();
+        let result = runtime.block_on(async {
+            let client = connected(options).await?;
+            round_trip(&client, "conformance.auth.user", None).await
+        });
+        assert!(result.is_ok(), "nats:{} user/password => {:?}", version, result);
+
+        let url_credentials = format!("nats://ratsio:secret@{}", server.url());
+        let options = NatsClientOptions::builder().cluster_uris(url_credentials).build().unwrap();
+        let result = runtime.block_on(async {
+            let client = connected(options).await?;
+            round_trip(&client, "conformance.auth.url", None).await
+        });
+        assert!(result.is_ok(), "nats:{} url credentials => {:?}", version, result);
+
+        let options = NatsClientOptions::builder()
+            .username("ratsio")
+            .password("wrong")
+            .cluster_uris(server.url())
+            .build()
+            .unwrap();
+        let result = runtime.block_on(async {
+            let client = NatsClient::connect(options).await?;
+            round_trip(&client, "conformance.auth.wrong", None).await
+        });
+        assert!(result.is_err(), "nats:{} accepted a wrong password", version);
+        drop(server);
+
+        let server = NatsServer::start(&version, ServerSpec {
+            args: args(&["--auth", "token"]),
+            ..Default::default()
+        });
+        let options = NatsClientOptions::builder()
+            .auth_token("token")
+            .cluster_uris(server.url())
+            .build()
+            .unwrap();
+        let result = runtime.block_on(async {
+            let client = connected(options).await?;
+            round_trip(&client, "conformance.auth.token", None).await
+        });
+        assert!(result.is_ok(), "nats:{} token => {:?}", version, result);
+    }
+}
+
+#[test]
+fn conformance_headers() {
+    common::setup();
+    for version in conformance::versions() {
+        info!(target: "ratsio", "conformance_headers against nats:{}", version);
+        let server = NatsServer::start(&version, ServerSpec::default());
+        let mut runtime = Runtime::new().unwrap();
+        let options = NatsClientOptions::builder().cluster_uris(server.url()).build().unwrap();
+        let result = runtime.block_on(async {
+            let client = connected(options).await?;
+            let mut headers = Headers::new();
+            headers.insert("Ratsio-Test", "one");
+            headers.append("Ratsio-Test", "two");
+            round_trip(&client, "conformance.headers", Some(headers)).await
+        });
+        let msg = result.unwrap_or_else(|err| panic!("nats:{} => {:?}", version, err));
+        let headers = msg.headers.expect("headers were not delivered");
+        assert_eq!(headers.get_all("Ratsio-Test").collect::<Vec<_>>(), vec!["one", "two"]);
+    }
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn conformance_tls() {
+    common::setup();
+    let certs = Certs::generate();
+    // native-tls reads the trusted roots from here when built on openssl.
+    std::env::set_var("SSL_CERT_FILE", certs.ca_file());
+    let config = certs.write_tls_config();
+    for version in conformance::versions() {
+        // `handshake_first` is only understood by 2.10 servers, older ones send INFO before TLS.
+        if !conformance::at_least(&version, 2, 10) {
+            info!(target: "ratsio", "conformance_tls skipped for nats:{}", version);
+            continue;
+        }
+        info!(target: "ratsio", "conformance_tls against nats:{}", version);
+        let server = NatsServer::start(&version, ServerSpec {
+            volume: Some(&certs.dir),
+            args: args(&["--config", &config]),
+            tls_first: true,
+            ..Default::default()
+        });
+        let mut runtime = Runtime::new().unwrap();
+        let options = NatsClientOptions::builder()
+            .cluster_uris(format!("tls://localhost:{}", server.port))
+            .build()
+            .unwrap();
+        let result = runtime.block_on(async {
+            let client = connected(options).await?;
+            assert!(client.connection_info().tls);
+            round_trip(&client, "conformance.tls", None).await
+        });
+        assert!(result.is_ok(), "nats:{} => {:?}", version, result);
+    }
+}
+
+#[test]
+fn conformance_reconnect_on_server_restart() {
+    common::setup();
+    for version in conformance::versions() {
+        info!(target: "ratsio", "conformance_reconnect_on_server_restart against nats:{}", version);
+        let server = NatsServer::start(&version, ServerSpec::default());
+        let mut runtime = Runtime::new().unwrap();
+        let options = NatsClientOptions::builder()
+            .cluster_uris(server.url())
+            .reconnect_timeout(200u64)
+            .build()
+            .unwrap();
+        let result: Result<(), RatsioError> = runtime.block_on(async {
+            let client = connected(options).await?;
+            let mut stream = client
+                .subscribe(Subscribe::builder().subject("conformance.restart".into()).build().unwrap())
+                .await?;
+            let connect_version = client.connection_info().connect_version;
+
+            server.restart();
+            let reconnected = async {
+                while client.connection_info().connect_version == connect_version
+                    || client.get_state() != NatsClientState::Connected
+                {
+                    tokio::time::delay_for(Duration::from_millis(50)).await;
+                }
+            };
+            tokio::time::timeout(TIMEOUT, reconnected)
+                .await
+                .map_err(|_| RatsioError::CannotReconnectToServer)?;
+
+            client
+                .publish(Publish::builder()
+                    .subject("conformance.restart".into())
+                    .payload(Vec::from(&b"again"[..]))
+                    .build()
+                    .unwrap())
+                .await?;
+            let msg = tokio::time::timeout(TIMEOUT, stream.next())
+                .await
+                .map_err(|_| RatsioError::RequestTimeout)?
+                .ok_or(RatsioError::InnerBrokenChain)?;
+            assert_eq!(msg.payload, Vec::from(&b"again"[..]));
+            Ok(())
+        });
+        assert!(result.is_ok(), "nats:{} => {:?}", version, result);
+    }
+}
+
+#[test]
+fn conformance_cluster_topology() {
+    common::setup();
+    for version in conformance::versions() {
+        info!(target: "ratsio", "conformance_cluster_topology against nats:{}", version);
+        let network = Network::create();
+        let seed = NatsServer::start(&version, ServerSpec {
+            network: Some(&network),
+            args: args(&["--cluster_name", "ratsio", "--cluster", "nats://0.0.0.0:6222"]),
+            advertise: true,
+            ..Default::default()
+        });
+        let member = NatsServer::start(&version, ServerSpec {
+            network: Some(&network),
+            args: args(&[
+                "--cluster_name", "ratsio",
+                "--cluster", "nats://0.0.0.0:6222",
+                "--routes", &seed.route_url(),
+            ]),
+            advertise: true,
+            ..Default::default()
+        });
+
+        let mut runtime = Runtime::new().unwrap();
+        let options = NatsClientOptions::builder()
+            .cluster_uris(seed.url())
+            .reconnect_timeout(200u64)
+            .build()
+            .unwrap();
+        let result: Result<(), RatsioError> = runtime.block_on(async {
+            let client = connected(options).await?;
+            let mut raw_ops = client.raw_ops();
+            round_trip(&client, "conformance.cluster.seed", None).await?;
+
+            seed.stop();
+            let failed_over = async {
+                while client.connection_info().server_url.port != member.port
+                    || client.get_state() != NatsClientState::Connected
+                {
+                    tokio::time::delay_for(Duration::from_millis(50)).await;
+                }
+            };
+            tokio::time::timeout(TIMEOUT, failed_over)
+                .await
+                .map_err(|_| RatsioError::CannotReconnectToServer)?;
+            round_trip(&client, "conformance.cluster.member", None).await?;
+
+            let mut saw_info = false;
+            while let Ok(Some(op)) = tokio::time::timeout(Duration::from_millis(100), raw_ops.next()).await {
+                saw_info |= match op {
+                    Op::INFO(_) => true,
+                    _ => false,
+                };
+            }
+            assert!(saw_info, "no INFO received from the member server");
+            Ok(())
+        });
+        assert!(result.is_ok(), "nats:{} => {:?}", version, result);
+    }
+}
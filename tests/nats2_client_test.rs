@@ -1,7 +1,8 @@
+#![cfg(feature = "jwt")]
+
 #[macro_use]
 extern crate log;
 
-use futures::{prelude::*, sync::oneshot};
 use ratsio::nats_client::*;
 use tokio::runtime::Runtime;
 
@@ -26,19 +27,15 @@ fn test_nats2_connect() {
         .build()
         .unwrap();
 
-    let client = NatsClient::connect(options);
-    let (tx, rx) = oneshot::channel();
-    runtime.spawn(client.then(|r| tx.send(r).map_err(|e| panic!("Cannot send Result {:?}", e))));
-    let connection_result = rx.wait().expect("Cannot wait for a result");
+    let connection_result = runtime.block_on(NatsClient::connect(options));
     std::thread::sleep(std::time::Duration::from_millis(600));
-    let _ = runtime.shutdown_now().wait();
     info!(target: "ratsio", "can_connect::connection_result {:#?}", connection_result);
     assert!(connection_result.is_ok());
 }
 
 // Callback to sign the nonce. In real applications, key should be injected via environment
 // variables or pulled from a vault, etc.
-fn sign_nonce(nonce: &[u8]) -> Result<Vec<u8>, Box<std::error::Error>> {
+fn sign_nonce(nonce: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     info!(target: "ratsio", "Signing nonce!");
     let raw_nkey = "SUAGMSQYN72ENPO3NVRLNDIDF4LQ2SZXSV23CHCN3LGE44FQUG2YGEBMUI";
     let kp = nkeys::KeyPair::from_seed(raw_nkey).unwrap();
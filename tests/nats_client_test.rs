@@ -1,14 +1,12 @@
 #[macro_use]
 extern crate log;
 
-use futures::{prelude::*, sync::oneshot};
+use futures::prelude::*;
 use ratsio::error::RatsioError;
 use ratsio::nats_client::*;
 use ratsio::ops::*;
 use tokio::runtime::Runtime;
 
-use std::sync::Arc;
-
 mod common;
 
 #[test]
@@ -22,33 +20,22 @@ fn test_pub_sub() {
         .build()
         .unwrap();
 
-    let fut = NatsClient::connect(options).and_then(|client| {
-        client
+    let connection_result = runtime.block_on(async {
+        let client = NatsClient::connect(options).await?;
+        let mut stream = client
             .subscribe(Subscribe::builder().subject("foo".into()).build().unwrap())
-            .map_err(|_| RatsioError::InnerBrokenChain)
-            .and_then(move |stream| {
-                let _ = client
-                    .publish(
-                        Publish::builder()
-                            .subject("foo".into())
-                            .payload(Vec::from(&b"bar"[..]))
-                            .build()
-                            .unwrap(),
-                    )
-                    .wait();
-
-                stream
-                    .take(1)
-                    .into_future()
-                    .map(|(maybe_message, _)| maybe_message.unwrap())
-                    .map_err(|_| RatsioError::InnerBrokenChain)
-            })
+            .await?;
+        client
+            .publish(
+                Publish::builder()
+                    .subject("foo".into())
+                    .payload(Vec::from(&b"bar"[..]))
+                    .build()
+                    .unwrap(),
+            )
+            .await?;
+        stream.next().await.ok_or(RatsioError::InnerBrokenChain)
     });
-
-    let (tx, rx) = oneshot::channel();
-    runtime.spawn(fut.then(|r| tx.send(r).map_err(|e| panic!("Cannot send Result {:?}", e))));
-    let connection_result = rx.wait().expect("Cannot wait for a result");
-    let _ = runtime.shutdown_now().wait();
     info!(target: "ratsio", "can_sub_and_pub::connection_result {:#?}", connection_result);
     assert!(connection_result.is_ok());
     let msg = connection_result.unwrap();
@@ -64,12 +51,7 @@ fn test_connect() {
         .build()
         .unwrap();
 
-    let connection = NatsClient::connect(options);
-    let (tx, rx) = oneshot::channel();
-    runtime
-        .spawn(connection.then(|r| tx.send(r).map_err(|e| panic!("Cannot send Result {:?}", e))));
-    let connection_result = rx.wait().expect("Cannot wait for a result");
-    let _ = runtime.shutdown_now().wait();
+    let connection_result = runtime.block_on(NatsClient::connect(options));
     info!(target: "ratsio", "can_connect::connection_result {:#?}", connection_result);
     assert!(connection_result.is_ok());
 }
@@ -77,50 +59,69 @@ fn test_connect() {
 #[test]
 fn test_request() {
     common::setup();
-    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut runtime = Runtime::new().unwrap();
 
     let options = NatsClientOptions::builder()
         .cluster_uris(vec![String::from("127.0.0.1:4222")])
         .build()
         .unwrap();
 
-    let fut = NatsClient::connect(options)
-        //.and_then(|client| NatsClient::connect(&client))
-        .map(|client: Arc<NatsClient>| {
-            let sub = Subscribe::builder().subject("foo2".into()).build().unwrap();
-            let sender = client.sender.clone();
-            tokio::spawn(
-                client
-                    .subscribe(sub)
-                    .and_then(|stream| {
-                        stream.for_each(move |msg| {
-                            match msg.reply_to {
-                                Some(reply_to) => {
-                                    let publ = Publish::builder()
-                                        .subject(reply_to)
-                                        .payload(Vec::from(&b"bar"[..]))
-                                        .build()
-                                        .unwrap();
-                                    sender.read().send(Op::PUB(publ));
-                                }
-                                _ => {}
-                            };
-                            Ok(())
-                        })
-                    })
-                    .map_err(|_| ()),
-            );
-            client
-        })
-        .and_then(|client| client.request("foo2".into(), "foo".as_bytes()));
-
-    let (tx, rx) = oneshot::channel();
-    runtime.spawn(fut.then(|r| tx.send(r).map_err(|e| panic!("Cannot send Result {:?}", e))));
-    let connection_result = rx.wait().expect("Cannot wait for a result");
-    let _ = runtime.shutdown_now().wait();
+    let connection_result = runtime.block_on(async {
+        let client = NatsClient::connect(options).await?;
+        let sub = Subscribe::builder().subject("foo2".into()).build().unwrap();
+        let sender = client.sender.clone();
+        let stream = client.subscribe(sub).await?;
+        tokio::spawn(stream.for_each(move |msg| {
+            if let Some(reply_to) = msg.reply_to {
+                let publ = Publish::builder()
+                    .subject(reply_to)
+                    .payload(Vec::from(&b"bar"[..]))
+                    .build()
+                    .unwrap();
+                let _ = sender.read().send(Op::PUB(publ));
+            }
+            future::ready(())
+        }));
+        client.request("foo2".into(), "foo".as_bytes()).await
+    });
     info!(target: "ratsio", "can_request::connection_result {:#?}", connection_result);
     assert!(connection_result.is_ok());
     let msg = connection_result.unwrap();
     info!(target: "ratsio", "can_request::msg {:#?}", msg);
     assert_eq!(msg.payload, Vec::from(&b"bar"[..]));
 }
+
+#[test]
+fn test_subscribe_many() {
+    common::setup();
+    let mut runtime = Runtime::new().unwrap();
+
+    let options = NatsClientOptions::builder()
+        .cluster_uris("127.0.0.1:4222")
+        .build()
+        .unwrap();
+
+    let result = runtime.block_on(async {
+        let client = NatsClient::connect(options).await?;
+        let mut stream = client.subscribe_many(&["many.1", "many.2"]).await?;
+        for subject in &["many.1", "many.2"] {
+            client.publish(Publish {
+                subject: subject.to_string(),
+                payload: subject.as_bytes().to_vec(),
+                ..Publish::default()
+            }).await?;
+        }
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let (subject, msg) = stream.next().await.ok_or(RatsioError::InnerBrokenChain)?;
+            assert_eq!(subject.as_bytes(), &msg.payload[..]);
+            received.push(subject);
+        }
+        received.sort();
+        stream.unsubscribe();
+        Ok::<_, RatsioError>((received, stream.next().await))
+    });
+    let (received, after_unsubscribe) = result.unwrap();
+    assert_eq!(received, vec!["many.1", "many.2"]);
+    assert!(after_unsubscribe.is_none(), "the stream ends once unsubscribed");
+}